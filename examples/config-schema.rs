@@ -0,0 +1,8 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Prints the JSON Schema of the configuration, e.g. `cargo run --example config-schema --features schema`.
+
+fn main() {
+    println!("{}", otel_lib::schema::config_schema_json());
+}
@@ -4,9 +4,9 @@
 use clap::{arg, command, Parser};
 use log::{error, info};
 use opentelemetry::logs::Severity;
-use opentelemetry_sdk::metrics::data::Temporality;
 use otel_lib::{
-    config::{Attribute, Config, LogsExportTarget, MetricsExportTarget, PrometheusConfig},
+    config::{Attribute, Config, LogFilter, PrometheusConfig},
+    config_args::ConfigArgs,
     Otel,
 };
 
@@ -21,42 +21,34 @@ mod metrics;
 
 #[tokio::main]
 async fn main() {
-    // App expects 2 parameters. 1) '-n' that controls the number of iterations, and 2) '-o' to specify an otel compatible repo.
+    // App expects '-n' that controls the number of iterations, and optionally '--otel-endpoint' to specify an otel
+    // compatible repo.
     let args = Args::parse();
 
-    let prometheus_config = Some(PrometheusConfig { port: 9090 });
-    let (metrics_targets, logs_targets) = match args.otel_repo_url {
-        Some(url) => {
-            let metric_targets = vec![MetricsExportTarget {
-                url: url.clone(),
-                interval_secs: 1,
-                timeout: 5,
-                temporality: Some(Temporality::Cumulative),
-            }];
-            let logs_targets = vec![LogsExportTarget {
-                url,
-                interval_secs: 1,
-                timeout: 5,
-                export_severity: Some(Severity::Error),
-            }];
-            (Some(metric_targets), Some(logs_targets))
-        }
-        None => (None, None),
-    };
-
-    let config = Config {
+    let mut config = Config {
         emit_metrics_to_stdout: false,
-        metrics_export_targets: metrics_targets,
-        log_export_targets: logs_targets,
         level: "info,hyper=off".to_owned(),
         service_name: "sample-app".to_owned(),
         resource_attributes: Some(vec![Attribute {
             key: "resource_key1".to_owned(),
             value: "1".to_owned(),
         }]),
-        prometheus_config,
+        prometheus_config: Some(PrometheusConfig {
+            port: 9090,
+            ..PrometheusConfig::default()
+        }),
         ..Config::default()
     };
+    args.otel.apply(&mut config);
+    for metrics_target in config.metrics_export_targets.iter_mut().flatten() {
+        metrics_target.interval_secs = 1;
+    }
+    for logs_target in config.log_export_targets.iter_mut().flatten() {
+        logs_target.filter = LogFilter {
+            severity: Some(Severity::Error),
+            ..LogFilter::default()
+        };
+    }
 
     let otel_component = Otel::new(config);
     // Start the otel running task
@@ -89,7 +81,7 @@ async fn main() {
     });
 
     let _ = join!(instrumentation_task, otel_long_running_task);
-    otel_component.shutdown();
+    otel_component.shutdown().await;
 }
 
 #[derive(Parser, Debug)]
@@ -99,7 +91,6 @@ struct Args {
     #[arg(short, long, default_value_t = 1000)]
     pub num_iterations: u64,
 
-    /// Otel Repository URL
-    #[arg(short, long)]
-    pub otel_repo_url: Option<String>,
+    #[command(flatten)]
+    pub otel: ConfigArgs,
 }
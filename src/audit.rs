@@ -0,0 +1,186 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A dedicated audit log channel, so compliance logs don't mix policies with operational logs.
+//!
+//! Audit records are exported only to the audit targets, under a fixed `audit` scope. They bypass the log level,
+//! the targets' log filters and the governance caps, and are queued generously so that bursts are not dropped.
+
+use std::{
+    sync::{Arc, PoisonError, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use opentelemetry::{
+    logs::{AnyValue, LogRecord, LogRecordBuilder, Logger as _, LoggerProvider as _, Severity},
+    Key, KeyValue,
+};
+use opentelemetry_sdk::{
+    logs::{BatchConfigBuilder, BatchLogProcessor, Logger, LoggerProvider},
+    runtime, Resource,
+};
+
+use crate::{
+    config::Config,
+    identity::DeviceIdentity,
+    init::{InitComponent, InitReport},
+    limits::{self, LogPipelineLimits},
+    log_persistence,
+    log_queue::{DequeuingLogExporter, QueueLength, QueueLimitedLogProcessor},
+    log_routing::{RoutedLogProcessor, Router},
+    loggers::{build_target_exporter, connect_eagerly, target_exporter},
+    simple_log_processor::SimpleLogProcessor,
+    switch::{LogSwitch, SwitchableLogExporter, Switches},
+};
+
+/// The scope audit records are emitted under.
+const AUDIT_SCOPE: &str = "audit";
+
+/// The number of audit records queued per target before records are dropped.
+const AUDIT_QUEUE_SIZE: usize = 65536;
+
+/// The logger audit records are emitted to, set once the audit pipeline is initialized.
+static AUDIT_LOGGER: RwLock<Option<Logger>> = RwLock::new(None);
+
+/// Initialize the audit pipeline, if any audit targets are configured.
+pub(crate) fn init_audit(
+    config: &Config,
+    resource: Resource,
+    identity: Option<&Arc<DeviceIdentity>>,
+    switches: &Switches,
+    init_report: &mut InitReport,
+) -> Option<(LoggerProvider, Vec<LogPipelineLimits>)> {
+    let audit_targets = config.audit_targets.as_ref()?;
+    let mut pipeline_limits = Vec::new();
+    let mut logger_provider_builder = LoggerProvider::builder()
+        .with_config(opentelemetry_sdk::logs::Config::default().with_resource(resource));
+    let router = Arc::new(Router::new(&config.log_routes));
+    for audit_target in audit_targets {
+        if !audit_target.enabled {
+            init_report.disabled(InitComponent::AuditTarget, audit_target.label());
+            continue;
+        }
+        if let Err(e) = connect_eagerly(audit_target) {
+            // log error using eprintln as the logger framework is not setup yet!
+            eprintln!("{e} for audit target [{}]", audit_target.label());
+            init_report.failed(InitComponent::AuditTarget, Some(audit_target.label()), e);
+            continue;
+        }
+        let persistence = match log_persistence::open(audit_target) {
+            Ok(persistence) => persistence,
+            Err(e) => {
+                // log error using eprintln as the logger framework is not setup yet!
+                eprintln!("{e} for audit target [{}]", audit_target.label());
+                init_report.failed(InitComponent::AuditTarget, Some(audit_target.label()), e);
+                continue;
+            }
+        };
+        let switch = match build_target_exporter(audit_target, config) {
+            Ok(exporter) => {
+                init_report.initialized(InitComponent::AuditTarget, audit_target.label());
+                LogSwitch::new(audit_target.url.clone(), exporter)
+            }
+            Err(e) => {
+                // log error using eprintln as the logger framework is not setup yet!
+                eprintln!(
+                    "{e} for audit target [{}], retrying in the background",
+                    audit_target.label()
+                );
+                init_report.retrying(InitComponent::AuditTarget, audit_target.label(), e);
+                let switch = LogSwitch::pending(audit_target.url.clone(), audit_target.label());
+                switches.retry_logs(&switch, audit_target.clone(), config.clone());
+                switch
+            }
+        };
+        let exporter = target_exporter(
+            audit_target,
+            config,
+            SwitchableLogExporter::new(switch),
+            persistence,
+            identity,
+        );
+        if audit_target.simple {
+            pipeline_limits.push(LogPipelineLimits {
+                target: audit_target.label().to_owned(),
+                max_queue_size: 0,
+                max_export_batch_size: 1,
+                scheduled_delay: Duration::ZERO,
+                max_export_timeout: Duration::from_secs(audit_target.timeout),
+            });
+            logger_provider_builder =
+                logger_provider_builder.with_log_processor(RoutedLogProcessor::audit(
+                    SimpleLogProcessor::new(Box::new(exporter)),
+                    audit_target.label().to_owned(),
+                    router.clone(),
+                ));
+            continue;
+        }
+        let (max_queue_size, max_export_batch_size) = limits::batch_sizes(Some(AUDIT_QUEUE_SIZE));
+        pipeline_limits.push(LogPipelineLimits {
+            target: audit_target.label().to_owned(),
+            max_queue_size,
+            max_export_batch_size,
+            scheduled_delay: Duration::from_secs(audit_target.interval_secs),
+            max_export_timeout: Duration::from_secs(audit_target.timeout),
+        });
+        let queue = QueueLength::new(audit_target.label().to_owned(), max_queue_size);
+        let batch_log_processor = BatchLogProcessor::builder(
+            DequeuingLogExporter::new(exporter, queue.clone()),
+            runtime::Tokio,
+        )
+        .with_batch_config(
+            BatchConfigBuilder::default()
+                .with_max_queue_size(max_queue_size)
+                .with_max_export_batch_size(max_export_batch_size)
+                .with_scheduled_delay(Duration::from_secs(audit_target.interval_secs))
+                .with_max_export_timeout(Duration::from_secs(audit_target.timeout))
+                .build(),
+        )
+        .build();
+        logger_provider_builder =
+            logger_provider_builder.with_log_processor(RoutedLogProcessor::audit(
+                QueueLimitedLogProcessor::new(batch_log_processor, queue),
+                audit_target.label().to_owned(),
+                router.clone(),
+            ));
+    }
+
+    let logger_provider = logger_provider_builder.build();
+    *AUDIT_LOGGER.write().unwrap_or_else(PoisonError::into_inner) =
+        Some(logger_provider.versioned_logger(AUDIT_SCOPE, None, None, None));
+    Some((logger_provider, pipeline_limits))
+}
+
+/// Emit an audit record with `message` as its body. Records emitted before [`Otel`](crate::Otel) is initialized,
+/// or without audit targets configured, are dropped.
+pub fn audit(message: &str, attributes: &[KeyValue]) {
+    let audit_logger = AUDIT_LOGGER.read().unwrap_or_else(PoisonError::into_inner);
+    let Some(logger) = audit_logger.as_ref() else {
+        return;
+    };
+
+    let record_attributes: Vec<(Key, AnyValue)> = attributes
+        .iter()
+        .map(|attribute| (attribute.key.clone(), attribute.value.clone().into()))
+        .collect();
+    logger.emit(
+        LogRecordBuilder::new()
+            .with_severity_number(Severity::Info)
+            .with_severity_text(Severity::Info.name())
+            .with_timestamp(SystemTime::now())
+            .with_body(AnyValue::from(message.to_owned()))
+            .with_attributes(record_attributes)
+            .build(),
+    );
+}
+
+/// Emit a record of the `log` crate that is routed to audit targets. The audit targets it isn't routed to drop it.
+pub(crate) fn emit_routed(record: LogRecord) {
+    if let Some(logger) = AUDIT_LOGGER
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+    {
+        logger.emit(record);
+    }
+}
@@ -0,0 +1,54 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Enrichment of telemetry with baggage, so that request scoped labels such as a tenant or an experiment flow onto
+//! the telemetry of the request.
+//!
+//! The baggage entries listed in `baggage_attributes` in the config are copied from the current context onto log
+//! records and events as they are emitted. Metric attributes are fixed when a measurement is recorded, so
+//! measurements pick the entries up by recording with [`with_baggage`].
+
+use std::sync::{PoisonError, RwLock};
+
+use opentelemetry::{baggage::BaggageExt, logs::AnyValue, Context, Key, KeyValue};
+
+/// The keys of the baggage entries copied onto telemetry, set once [`Otel`](crate::Otel) is initialized.
+static BAGGAGE_KEYS: RwLock<Vec<Key>> = RwLock::new(Vec::new());
+
+pub(crate) fn set_keys(keys: &[String]) {
+    *BAGGAGE_KEYS.write().unwrap_or_else(PoisonError::into_inner) =
+        keys.iter().cloned().map(Key::from).collect();
+}
+
+/// The selected baggage entries of the current context.
+fn current_entries() -> Vec<KeyValue> {
+    let keys = BAGGAGE_KEYS.read().unwrap_or_else(PoisonError::into_inner);
+    if keys.is_empty() {
+        return Vec::new();
+    }
+    Context::map_current(|cx| {
+        let baggage = cx.baggage();
+        keys.iter()
+            .filter_map(|key| {
+                baggage
+                    .get(key.clone())
+                    .map(|value| KeyValue::new(key.clone(), value.clone()))
+            })
+            .collect()
+    })
+}
+
+/// The selected baggage entries of the current context, as log record attributes.
+pub(crate) fn log_attributes() -> Vec<(Key, AnyValue)> {
+    current_entries()
+        .into_iter()
+        .map(|entry| (entry.key, entry.value.into()))
+        .collect()
+}
+
+/// `attributes` followed by the selected baggage entries of the current context, for recording measurements.
+pub fn with_baggage(attributes: &[KeyValue]) -> Vec<KeyValue> {
+    let mut attributes = attributes.to_vec();
+    attributes.extend(current_entries());
+    attributes
+}
@@ -0,0 +1,72 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// The version of the telemetry stack, so fleets can audit which version is deployed where: the `otel_lib.build_info`
+// gauge, of value 1 with the version, the enabled features and the hash of the configuration as attributes, and a
+// record of the same logged at startup.
+//
+// The hash of the configuration is a 64 bit FNV-1a hash of its debug representation, which doesn't vary between
+// processes, so that devices running the same version with the same configuration report the same hash.
+
+use log::info;
+use opentelemetry::{global, KeyValue};
+
+use crate::config::Config;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// The features of the crate that are enabled.
+fn features() -> Vec<&'static str> {
+    let features = [
+        ("clap", cfg!(feature = "clap")),
+        ("max-level-info", cfg!(feature = "max-level-info")),
+        (
+            "release-max-level-info",
+            cfg!(feature = "release-max-level-info"),
+        ),
+        ("schema", cfg!(feature = "schema")),
+        ("sigv4", cfg!(feature = "sigv4")),
+        ("toml", cfg!(feature = "toml")),
+        ("tracing", cfg!(feature = "tracing")),
+        ("yaml", cfg!(feature = "yaml")),
+    ];
+    features
+        .into_iter()
+        .filter_map(|(feature, enabled)| enabled.then_some(feature))
+        .collect()
+}
+
+/// The hash of `config`, as 16 hex digits.
+fn config_hash(config: &Config) -> String {
+    let hash = format!("{config:?}")
+        .bytes()
+        .fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+        });
+    format!("{hash:016x}")
+}
+
+/// Create the `otel_lib.build_info` gauge with the global meter provider, and log the build info.
+pub(crate) fn register(config: &Config) {
+    let features = features().join(",");
+    let config_hash = config_hash(config);
+    info!(
+        version = VERSION, features = features.as_str(), config_hash = config_hash.as_str();
+        "otel-lib {VERSION} initialized with features [{features}] and configuration {config_hash}"
+    );
+    let attributes = [
+        KeyValue::new("version", VERSION),
+        KeyValue::new("features", features),
+        KeyValue::new("config_hash", config_hash),
+    ];
+    global::meter("otel-lib")
+        .u64_observable_gauge("otel_lib.build_info")
+        .with_description(
+            "The version, enabled features and configuration hash of otel-lib, with a value of 1",
+        )
+        .with_callback(move |observer| observer.observe(1, &attributes))
+        .init();
+}
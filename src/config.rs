@@ -1,30 +1,135 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
 use opentelemetry::logs::Severity;
+use opentelemetry_otlp::Protocol;
 use opentelemetry_sdk::metrics::data::Temporality;
 use serde::Deserialize;
 
-#[derive(Clone, Debug)]
+use crate::{config_file, config_serde, signing::RequestSigner};
+
+/// Shown in the debug representation instead of a credential.
+const REDACTED: &str = "<redacted>";
 
+#[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema), schemars(default))]
+#[serde(default)]
 /// Observability configuration
+#[allow(clippy::struct_excessive_bools)]
 pub struct Config {
+    /// set to false to turn telemetry off: `Otel::new` then neither installs globals nor starts tasks, and its
+    /// meters record nothing, so instrumentation can stay unconditional. See [`Config::disabled`].
+    pub enabled: bool,
     /// name of the component, for example "App"
     pub service_name: String,
+    /// namespace of the component, applied as the `service.namespace` resource attribute. Must not be empty or
+    /// contain whitespace.
+    pub service_namespace: Option<String>,
+    /// deployment environment, for example "production", applied as the `deployment.environment` resource attribute.
+    /// Must not be empty or contain whitespace.
+    pub deployment_environment: Option<String>,
+
+    /// Optional identifier of the application, for example "myapp/1.2.0", sent ahead of the name and version of this
+    /// library in the user agent of OTLP requests so that collector operators can identify traffic sources.
+    pub app_identifier: Option<String>,
 
     /// Optional resource attributes
     pub resource_attributes: Option<Vec<Attribute>>,
+    /// Optional file of the identity of the device in its fleet, applied as resource attributes that take precedence
+    /// over `resource_attributes`. The file is reloaded when it changes.
+    pub device_identity: Option<DeviceIdentityConfig>,
+    /// set to false to not detect the container the process runs in. When detected, its id and image name are applied
+    /// as the `container.id` and `container.image.name` resource attributes, unless set in `resource_attributes`.
+    pub detect_container: bool,
 
     /// Optional prometheus configuration if metrics are needed in Prometheus format as well as Otel.
     pub prometheus_config: Option<PrometheusConfig>,
+    /// Optional local OTLP receiver configuration, if metrics and logs from other processes on the host should be
+    /// forwarded to the export targets.
+    pub otlp_receiver_config: Option<OtlpReceiverConfig>,
     /// 0 or more metric export targets.
     pub metrics_export_targets: Option<Vec<MetricsExportTarget>>,
     /// 0 or more log export targets
     pub log_export_targets: Option<Vec<LogsExportTarget>>,
+    /// 0 or more audit log targets, which only receive records emitted with `audit::audit` and the records that
+    /// `log_routes` route to them. Their filter is ignored, as audit records are never filtered.
+    pub audit_targets: Option<Vec<LogsExportTarget>>,
+    /// 0 or more trace export targets, which receive the spans created with the tracers of
+    /// `opentelemetry::global::tracer`.
+    pub trace_export_targets: Option<Vec<TracesExportTarget>>,
+    /// 0 or more views that customize how matching instruments are aggregated. Views are matched in order and only
+    /// the first view that matches an instrument is applied to it.
+    pub metric_views: Option<Vec<MetricView>>,
     /// set to true if metrics should be emitted to stdout.
     pub emit_metrics_to_stdout: bool,
-    /// set to true if metrics should be emitted to stderr.
+    /// set to true to also emit the per-second rate of each cumulative counter over the last interval to stdout, as
+    /// a `<counter>.rate` gauge, so the output can be interpreted without a backend.
+    pub stdout_metric_rates: bool,
+    /// start time reported for cumulative metrics exported to the metrics export targets. Defaults to the SDK's,
+    /// which is when each instrument was first used.
+    pub start_time: Option<StartTime>,
+    /// set to true to log when cumulative counters appear to reset, to help diagnose sawtooth graphs.
+    /// Counters are checked once a minute.
+    pub detect_counter_resets: bool,
+    /// set to true if logs should be emitted to stderr.
     pub emit_logs_to_stderr: bool,
+    /// the records written to stderr, defaults to all records that pass `level`.
+    pub stderr_filter: LogFilter,
+    /// 0 or more routes that send the log records they match to specific sinks only. Routes are matched in order
+    /// and only the first route that matches a record applies to it. Records that match no route are written to
+    /// stderr and exported to the log targets.
+    pub log_routes: Vec<LogRoute>,
+    /// 0 or more rules that drop known-noisy records of the log crate, such as reconnect chatter of third-party
+    /// crates, before they are written to stderr or queued for export. A record is dropped if it matches any rule.
+    pub log_drop_rules: Vec<DropRule>,
+    /// set to true to report resource attributes, instrument names, units and attribute keys that violate the
+    /// naming rules of the OpenTelemetry semantic conventions as errors. Instruments are checked once a minute.
+    pub strict_semconv: bool,
+    /// set to true to fail the creation of instruments through the meters of `Otel` whose name was created before
+    /// with another kind or unit, instead of warning, for example as a CI gate.
+    pub strict_instruments: bool,
+    /// 0 or more latency SLOs whose good and bad events are counted from their histograms, as inputs of burn-rate
+    /// alerts.
+    pub latency_slos: Vec<LatencySlo>,
+    /// limits applied to the attributes of log records, as specified for the OpenTelemetry SDK.
+    pub log_record_limits: LogRecordLimits,
+    /// hard caps that protect shared devices from a single misbehaving component exhausting memory.
+    pub governance: Governance,
+    /// set to true to log each export attempt to the export targets with the batch size, serialized size, duration
+    /// and outcome, for troubleshooting. The logs are rate limited per target.
+    pub pipeline_debug: bool,
+    /// interval in seconds of the warnings that summarize the failures of an export target while it keeps failing,
+    /// after a warning for its first failure. 0 (the default) reports every failed export instead, as the SDK does.
+    /// Summaries replace the process-wide `opentelemetry::global` error handler, so that the summarized failures
+    /// aren't reported again: leave them off if the application installs its own handler.
+    pub export_failure_summary_secs: u64,
+    /// set to true to skip metrics exports that have no data points, so idle components with delta temporality
+    /// don't wake the network on every interval. Log batches are never exported empty.
+    pub skip_empty_exports: bool,
+    /// set to false to give each metrics export target its own reader, even if OTLP/gRPC targets receive identical
+    /// data, e.g. to rule the shared export out while troubleshooting a target. Sharing is on by default.
+    pub share_metrics_exports: bool,
+    /// set to true to allow adding metrics export targets at runtime with `Otel::add_metrics_target`. The metrics are
+    /// then also aggregated for those targets from startup, whether or not any is added.
+    pub runtime_metrics_targets: bool,
+    /// set to true to emit the events of spans as log records through the log pipeline once the spans end, for
+    /// backends that store logs but not traces. Requires `events::SpanEventsProcessor` on the tracer provider.
+    pub span_events_as_logs: bool,
+    /// keys of baggage entries copied from the current context onto log records and events, such as a tenant or an
+    /// experiment. Measurements pick them up when recorded with `baggage::with_baggage`.
+    pub baggage_attributes: Vec<String>,
+    /// Optional key of the attribute identifying the tenant of log records and data points, e.g. `tenant.id`, in
+    /// shared-agent deployments. The records and data points exported for each tenant, and their size, are counted
+    /// by the `otel_lib.exporter.tenant.records` and `otel_lib.exporter.tenant.size` metrics.
+    pub tenant_attribute: Option<String>,
     /// log level, specified as logging directives and controllable on a per-module basis
     pub level: String,
 }
@@ -32,34 +137,450 @@ pub struct Config {
 impl Default for Config {
     fn default() -> Self {
         Self {
+            enabled: true,
             service_name: "App".to_owned(),
+            service_namespace: None,
+            deployment_environment: None,
+            app_identifier: None,
             prometheus_config: None,
+            otlp_receiver_config: None,
             metrics_export_targets: None,
             log_export_targets: None,
+            audit_targets: None,
+            trace_export_targets: None,
+            metric_views: None,
             emit_metrics_to_stdout: false,
+            stdout_metric_rates: false,
+            start_time: None,
+            detect_counter_resets: false,
+            strict_semconv: false,
+            strict_instruments: false,
+            latency_slos: Vec::new(),
+            log_record_limits: LogRecordLimits::default(),
+            governance: Governance::default(),
+            pipeline_debug: false,
+            export_failure_summary_secs: 0,
+            skip_empty_exports: false,
+            share_metrics_exports: true,
+            runtime_metrics_targets: false,
+            span_events_as_logs: false,
+            baggage_attributes: Vec::new(),
+            tenant_attribute: None,
             emit_logs_to_stderr: true,
+            stderr_filter: LogFilter::default(),
+            log_routes: Vec::new(),
+            log_drop_rules: Vec::new(),
             level: "info".to_owned(),
             resource_attributes: None,
+            device_identity: None,
+            detect_container: true,
+        }
+    }
+}
+
+impl Config {
+    /// A configuration with telemetry turned off, e.g. for benchmarks or to toggle telemetry by configuration.
+    pub fn disabled() -> Self {
+        Config {
+            enabled: false,
+            ..Config::default()
+        }
+    }
+
+    /// Load the configuration from the file at `path`, in the format given by its extension: `.json`, and with the
+    /// `toml` and `yaml` features `.toml`, `.yaml` and `.yml`. Fields that are not set take their default values.
+    ///
+    /// The configuration can `include` fragment files by glob patterns relative to the directory of the file, e.g.
+    /// `include = ["targets.d/*.toml"]`, merged onto it in the order of the patterns and then of the paths they match.
+    /// Tables are merged field by field and the items of lists are appended, while a fragment that sets a field to a
+    /// different value than the configuration so far is an error. Fragments can't include other files. The
+    /// `profiles` and the secret references of [`Config::from_str`] apply once the fragments are merged.
+    ///
+    /// # Errors
+    /// * `ConfigError` - If the file or a fragment can't be read, its format is not supported, a fragment conflicts
+    ///   with the configuration, it is not a valid configuration, or a reference can't be resolved.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let mut config = config_file::read(path)?;
+        config_file::include(&mut config, path.parent().unwrap_or(Path::new("")))?;
+        Config::from_value(config)
+    }
+
+    /// Parse the configuration from `contents` in `format`. Fields that are not set take their default values.
+    ///
+    /// The configuration can define `profiles`, e.g. `dev` and `prod`, whose overrides are merged onto the rest of
+    /// the configuration when selected by the `OTEL_LIB_PROFILE` environment variable, or else by the `profile`
+    /// field. Tables are merged field by field, while any other value of a profile, lists included, replaces the
+    /// value it overrides. The patterns of `include`, as for [`Config::from_file`], are relative to the current
+    /// directory.
+    ///
+    /// Header values, tokens and passwords can be given as `env:NAME`, the value of the environment variable, or
+    /// `file:/run/secrets/x`, the contents of the file without a trailing newline, so that secrets don't live in the
+    /// configuration. The paths of keys, certificates and token files can be given as `env:NAME` too, and `file:`
+    /// is the path itself rather than the contents of the file. References are resolved when the configuration is
+    /// loaded, and only then: rotated secrets are picked up by loading the configuration again.
+    ///
+    /// # Errors
+    /// * `ConfigError` - If `contents` is not a valid configuration, a fragment can't be read or conflicts with the
+    ///   configuration, the selected profile is not defined, or a reference can't be resolved.
+    pub fn from_str(contents: &str, format: ConfigFormat) -> Result<Self, ConfigError> {
+        let mut config = config_file::parse(contents, format)?;
+        config_file::include(&mut config, Path::new(""))?;
+        Config::from_value(config)
+    }
+
+    fn from_value(config: serde_json::Value) -> Result<Self, ConfigError> {
+        let profile = env::var(config_file::PROFILE_ENV)
+            .ok()
+            .filter(|profile| !profile.is_empty());
+        let config = config_file::apply_profile(config, profile)?;
+        let mut config =
+            serde_json::from_value(config).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        config_file::resolve_secrets(&mut config)?;
+        Ok(config)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// The format of a configuration file.
+pub enum ConfigFormat {
+    /// JSON.
+    Json,
+    /// TOML, with the `toml` feature.
+    #[cfg(feature = "toml")]
+    Toml,
+    /// YAML, with the `yaml` feature.
+    #[cfg(feature = "yaml")]
+    Yaml,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The error of loading a configuration.
+pub enum ConfigError {
+    /// The file has an extension of a format that is not supported, or whose feature is not enabled.
+    UnsupportedFormat(PathBuf),
+    /// The file can't be read.
+    Read(PathBuf, String),
+    /// The contents are not a valid configuration.
+    Parse(String),
+    /// The selected profile is not defined.
+    UnknownProfile(String),
+    /// An included fragment file sets the field to a different value than the configuration.
+    Conflict(PathBuf, String),
+    /// The `env:` or `file:` reference of the field can't be resolved.
+    Secret(String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::UnsupportedFormat(path) => {
+                write!(f, "the format of {} is not supported", path.display())
+            }
+            ConfigError::Read(path, e) => write!(f, "unable to read {}: {e}", path.display()),
+            ConfigError::Parse(e) => write!(f, "invalid configuration: {e}"),
+            ConfigError::UnknownProfile(profile) => {
+                write!(f, "the profile {profile} is not defined")
+            }
+            ConfigError::Conflict(path, field) => write!(
+                f,
+                "{} sets {field} to a different value than the configuration",
+                path.display()
+            ),
+            ConfigError::Secret(field, e) => write!(f, "unable to resolve {field}: {e}"),
         }
     }
 }
 
+impl std::error::Error for ConfigError {}
+
 #[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// A JSON file with the identity of the device in its fleet, managed by fleet operators separately from the
+/// configuration of the application. Its `device_id`, `hardware_model` and `site` fields, all optional, are applied
+/// as the `device.id`, `device.model.identifier` and `device.site` resource attributes.
+pub struct DeviceIdentityConfig {
+    /// path of the file.
+    pub path: PathBuf,
+    /// how often the file is checked for changes, in seconds, as data is exported. Changes apply to the exports to
+    /// the export targets, while the prometheus end point keeps the identity read at initialization.
+    #[serde(default = "default_reload_interval_secs")]
+    #[cfg_attr(feature = "schema", schemars(default = "default_reload_interval_secs"))]
+    pub reload_interval_secs: u64,
+}
+
+impl DeviceIdentityConfig {
+    /// The identity file at `path`, checked for changes every minute.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        DeviceIdentityConfig {
+            path: path.into(),
+            reload_interval_secs: default_reload_interval_secs(),
+        }
+    }
+}
+
+fn default_reload_interval_secs() -> u64 {
+    60
+}
+
+#[derive(Clone, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 /// Prometheus configuration, which if specified results in an HTTP endpoint that can be used to get metrics
+#[allow(clippy::struct_excessive_bools)]
 pub struct PrometheusConfig {
+    /// The address to listen on for the HTTP end point, defaults to all interfaces.
+    #[serde(default = "default_bind_address")]
+    pub bind_address: IpAddr,
     /// The port for the HTTP end point
     pub port: u16,
+    /// Urls of co-located components' prometheus end points, whose metrics are served by this end point too.
+    /// Families that the sources share with this component or each other are merged, unless their types conflict,
+    /// in which case all but the first are dropped.
+    #[serde(default)]
+    pub scrape_sources: Vec<String>,
+    /// Names of histograms to also serve as precomputed quantile gauges named `<histogram>_quantile`, for dashboards
+    /// that can't run `histogram_quantile`. The `*` and `?` wildcards are supported. The quantiles are those of the
+    /// measurements recorded since the previous scrape of the end point, by any scraper, so that they follow the
+    /// current distribution rather than settle on that of the process lifetime. A series without measurements since
+    /// the previous scrape has no quantiles in the scrape.
+    #[serde(default)]
+    pub quantile_histograms: Vec<String>,
+    /// The quantiles to serve for `quantile_histograms`, defaults to p50, p90 and p99.
+    #[serde(default = "default_quantiles")]
+    pub quantiles: Vec<f64>,
+    /// set to true to gzip responses to scrapers that accept it, as announced by their `Accept-Encoding` header.
+    #[serde(default)]
+    pub gzip: bool,
+    /// Optional time in milliseconds to serve the encoded metrics from a cache, so scrapers hitting the end point
+    /// at the same time don't each encode the registries. Without a cache, every scrape collects the metrics, running
+    /// the callbacks of observable instruments, so the values are current. With a cache, this is the minimum time
+    /// between two collections.
+    #[serde(default)]
+    pub cache_ttl_millis: Option<u64>,
+    /// Optional maximum number of scrapes served at the same time. Further scrapes are rejected with
+    /// `503 Service Unavailable` rather than queued.
+    #[serde(default)]
+    pub max_concurrent_scrapes: Option<usize>,
+    /// Optional time in seconds after which a scrape is aborted with `408 Request Timeout`.
+    #[serde(default)]
+    pub request_timeout_secs: Option<u64>,
+    /// Optional maximum size in bytes of a request's headers and body. Larger requests are rejected.
+    #[serde(default)]
+    pub max_request_bytes: Option<usize>,
+    /// set to true to log each scrape at debug level, with the scraper's address, user agent and the response.
+    #[serde(default)]
+    pub log_scrapes: bool,
+    /// Additional end points, each serving a selection of the metrics, e.g. an exposed port that serves a safe
+    /// subset while `port` serves everything internally.
+    #[serde(default)]
+    pub listeners: Vec<PrometheusListener>,
+    /// Origins allowed to fetch the metrics from a browser, e.g. `http://dashboard.local:3000`, or `*` for any
+    /// origin. Cross-origin requests are not allowed if empty.
+    #[serde(default)]
+    pub cors_allowed_origins: Vec<String>,
+    /// set to true to add the standard security headers, such as `X-Content-Type-Options: nosniff`, to responses.
+    #[serde(default)]
+    pub security_headers: bool,
+    /// set to true to serve metrics without the unit suffix the exporter appends to their names, e.g. `_seconds`,
+    /// so the names match those exported to the OTLP targets, apart from the sanitization of invalid characters.
+    #[serde(default)]
+    pub without_unit_suffixes: bool,
+    /// set to true to serve counters without the `_total` suffix the exporter appends to their names.
+    #[serde(default)]
+    pub without_counter_suffixes: bool,
+    /// Optional certificate and private key to serve the end points over HTTPS rather than HTTP.
+    #[serde(default)]
+    pub tls: Option<PrometheusTls>,
+    /// Optional credentials scrapers must present with HTTP basic authentication.
+    #[serde(default)]
+    pub basic_auth: Option<BasicAuth>,
+    /// Optional token scrapers must present as `Authorization: Bearer <token>`. If `basic_auth` is also set, either
+    /// is accepted.
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+    /// set to true to also serve the `/healthz` and `/readyz` routes, which report the last successful export and
+    /// the consecutive failures of each export target as JSON.
+    #[serde(default)]
+    pub health_endpoints: bool,
+    /// The number of consecutive failed exports to a target after which `/readyz` responds with
+    /// `503 Service Unavailable`.
+    #[serde(default = "default_unready_after_failures")]
+    pub unready_after_failures: u32,
+}
+
+impl fmt::Debug for PrometheusConfig {
+    // The bearer token is a secret, and the configuration is logged.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let PrometheusConfig {
+            bind_address,
+            port,
+            scrape_sources,
+            quantile_histograms,
+            quantiles,
+            gzip,
+            cache_ttl_millis,
+            max_concurrent_scrapes,
+            request_timeout_secs,
+            max_request_bytes,
+            log_scrapes,
+            listeners,
+            cors_allowed_origins,
+            security_headers,
+            without_unit_suffixes,
+            without_counter_suffixes,
+            tls,
+            basic_auth,
+            bearer_token,
+            health_endpoints,
+            unready_after_failures,
+        } = self;
+        f.debug_struct("PrometheusConfig")
+            .field("bind_address", bind_address)
+            .field("port", port)
+            .field("scrape_sources", scrape_sources)
+            .field("quantile_histograms", quantile_histograms)
+            .field("quantiles", quantiles)
+            .field("gzip", gzip)
+            .field("cache_ttl_millis", cache_ttl_millis)
+            .field("max_concurrent_scrapes", max_concurrent_scrapes)
+            .field("request_timeout_secs", request_timeout_secs)
+            .field("max_request_bytes", max_request_bytes)
+            .field("log_scrapes", log_scrapes)
+            .field("listeners", listeners)
+            .field("cors_allowed_origins", cors_allowed_origins)
+            .field("security_headers", security_headers)
+            .field("without_unit_suffixes", without_unit_suffixes)
+            .field("without_counter_suffixes", without_counter_suffixes)
+            .field("tls", tls)
+            .field("basic_auth", basic_auth)
+            .field("bearer_token", &bearer_token.as_ref().map(|_| REDACTED))
+            .field("health_endpoints", health_endpoints)
+            .field("unready_after_failures", unready_after_failures)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// The certificate and private key of the prometheus end points, which serve HTTPS if they are set.
+pub struct PrometheusTls {
+    /// Path of a PEM file of the certificate chain of the server, starting with its own certificate.
+    pub cert_path: PathBuf,
+    /// Path of a PEM file of the private key of the server, in PKCS#1, PKCS#8 or SEC1 format.
+    pub key_path: PathBuf,
+}
+
+#[derive(Clone, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// Credentials of HTTP basic authentication.
+pub struct BasicAuth {
+    /// The user name.
+    pub username: String,
+    /// The password.
+    pub password: String,
+}
+
+impl fmt::Debug for BasicAuth {
+    // The password is a secret, and the configuration is logged.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BasicAuth")
+            .field("username", &self.username)
+            .field("password", &REDACTED)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// An additional prometheus end point, serving the metric families selected by its filters.
+pub struct PrometheusListener {
+    /// The address to listen on.
+    pub bind_address: IpAddr,
+    /// The port to listen on.
+    pub port: u16,
+    /// Names of the metric families to serve, all if empty. The `*` and `?` wildcards are supported.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Names of the metric families not to serve, taking precedence over `include`. The `*` and `?` wildcards are
+    /// supported.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+fn default_bind_address() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+}
+
+fn default_unready_after_failures() -> u32 {
+    3
+}
+
+fn default_quantiles() -> Vec<f64> {
+    vec![0.5, 0.9, 0.99]
 }
 
 impl Default for PrometheusConfig {
     fn default() -> Self {
-        PrometheusConfig { port: 9600 }
+        PrometheusConfig {
+            bind_address: default_bind_address(),
+            port: 9600,
+            scrape_sources: Vec::new(),
+            quantile_histograms: Vec::new(),
+            quantiles: default_quantiles(),
+            gzip: false,
+            cache_ttl_millis: None,
+            max_concurrent_scrapes: None,
+            request_timeout_secs: None,
+            max_request_bytes: None,
+            log_scrapes: false,
+            listeners: Vec::new(),
+            cors_allowed_origins: Vec::new(),
+            security_headers: false,
+            without_unit_suffixes: false,
+            without_counter_suffixes: false,
+            tls: None,
+            basic_auth: None,
+            bearer_token: None,
+            health_endpoints: false,
+            unready_after_failures: default_unready_after_failures(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema), schemars(default))]
+#[serde(default)]
+/// Local OTLP receiver configuration. Metrics and logs received over OTLP/gRPC are forwarded as-is to each enabled
+/// OTLP/gRPC export target, with log records filtered by the target's export severity.
+pub struct OtlpReceiverConfig {
+    /// The port to listen on. The receiver only accepts connections on the loopback interface.
+    pub port: u16,
+}
+
+impl Default for OtlpReceiverConfig {
+    fn default() -> Self {
+        OtlpReceiverConfig { port: 4317 }
     }
 }
 
 #[derive(Clone, Debug)]
+/// An export target of either signal, to switch to with [`Otel::switch_target`](crate::Otel::switch_target).
+pub enum ExportTarget {
+    /// a metrics export target.
+    Metrics(MetricsExportTarget),
+    /// a log export target.
+    Logs(LogsExportTarget),
+}
+
+#[derive(Clone, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema), schemars(default))]
+#[serde(default)]
 /// A Metrics export target definition
 pub struct MetricsExportTarget {
+    /// Optional name of the target, used to refer to it in diagnostics instead of the url.
+    pub name: Option<String>,
+    /// set to false to keep the target in the config without exporting to it.
+    pub enabled: bool,
     /// Address of the OTEL compatible repository
     pub url: String,
     /// How often to export, specified in seconds
@@ -67,23 +588,844 @@ pub struct MetricsExportTarget {
     /// export timeout - how long to wait before timing out on a push to the target.
     pub timeout: u64,
     /// export temporality preference, defaults to cumulative if not specified.
+    #[serde(deserialize_with = "config_serde::temporality")]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Option<crate::schema::Temporality>")
+    )]
     pub temporality: Option<Temporality>,
+    /// OTLP transport to use for the target, defaults to gRPC.
+    #[serde(deserialize_with = "config_serde::protocol")]
+    #[cfg_attr(feature = "schema", schemars(with = "crate::schema::Protocol"))]
+    pub protocol: Protocol,
+    /// set to true to fall back to OTLP/HTTP if the target turns out not to speak gRPC.
+    /// The protocol that worked on first contact is used from then on.
+    pub http_fallback: bool,
+    /// set to true to connect to the target during initialization, so that an unreachable or misconfigured
+    /// endpoint is reported at startup rather than on the first export. The target is skipped if the connection fails.
+    pub eager_connect: bool,
+    /// Optional capture of the serialized OTLP requests sent to the target, for inspecting protocol issues offline.
+    pub payload_capture: Option<PayloadCapture>,
+    /// Optional user agent of OTLP requests to the target, replacing the one derived from `Config::app_identifier`.
+    pub user_agent: Option<String>,
+    /// How the host of the target is resolved, defaults to the system resolver.
+    pub dns: DnsConfig,
+    /// Options of the sockets connecting to the target.
+    pub socket_options: SocketOptions,
+    /// optional maximum rate at which data is sent to the target, specified in bytes per second. Exports are paced
+    /// to the rate so that bursts of telemetry don't starve the other traffic of the device on low bandwidth links.
+    pub max_bytes_per_sec: Option<u64>,
+    /// Optional signer of the OTLP/HTTP requests to the target, for endpoints that require signed requests, such as
+    /// [`signing::SigV4Signer`](crate::signing) with the `sigv4` feature. OTLP/gRPC requests are not signed.
+    #[serde(skip)]
+    #[cfg_attr(feature = "schema", schemars(skip))]
+    pub signer: Option<Arc<dyn RequestSigner>>,
+    /// Optional path of a PEM or DER file of the certificate authorities trusted to verify the target, for `https`
+    /// urls. Defaults to the system's trusted certificate authorities.
+    pub ca_cert_path: Option<PathBuf>,
+    /// Optional directory of the certificate authorities trusted to verify the target, for `https` urls, each file
+    /// of which has PEM or DER encoded certificates, e.g. `/etc/ssl/certs`. They are trusted along with those of
+    /// `ca_cert_path`, instead of the system's. The file and the directory are checked for changes before each export,
+    /// and the target is reconnected with the new certificate authorities when they changed, as set by
+    /// `cert_change_policy`.
+    pub ca_cert_dir: Option<PathBuf>,
+    /// Optional path of a PEM or DER file of certificate revocation lists, for `https` urls. The certificates of the
+    /// target that are revoked are rejected, and so are those no list covers, so there must be a list for each
+    /// certificate authority of its chain. The file is checked for changes along with the certificate authority files.
+    pub crl_path: Option<PathBuf>,
+    /// Optional certificate and private key the target is authenticated to with, for `https` urls whose target
+    /// requires mutual TLS. They are checked for changes along with the certificate authority files, so certificates
+    /// rotated by another process, e.g. the SVIDs written by the SPIFFE helper, are used without restarting.
+    pub client_cert: Option<ClientCertificate>,
+    /// What happens when the certificate authority files of the target change, defaults to reconnecting the target.
+    pub cert_change_policy: CertChangePolicy,
+    /// Optional minimum version of TLS negotiated with the target, for `https` urls. Defaults to TLS 1.2.
+    pub min_tls_version: Option<TlsVersion>,
+    /// names of the cipher suites allowed with the target, for `https` urls, in order of preference, e.g.
+    /// `TLS13_AES_256_GCM_SHA384`. Defaults to all the cipher suites supported, which are all considered secure.
+    pub cipher_suites: Vec<String>,
+    /// headers added to the requests to the target, e.g. an API key, as gRPC metadata for OTLP/gRPC. Header names
+    /// are case insensitive.
+    pub headers: Vec<(String, String)>,
+    /// Optional file of a token added to the requests to the target, e.g. a bearer token that is rotated by another
+    /// process.
+    pub auth_token_file: Option<AuthTokenFile>,
+    /// when exports to the target are sent, defaults to always.
+    pub schedule: ExportSchedule,
+    /// Optional retries of the exports to the target that fail, e.g. while the collector restarts. Exports fail
+    /// without being retried by default. Targets with retries don't share a reader with other targets.
+    pub retry: Option<ExportRetry>,
 }
 
-#[derive(Clone, Debug)]
+impl fmt::Debug for MetricsExportTarget {
+    // The values of the headers are secrets, e.g. API keys, and the configuration is logged.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let MetricsExportTarget {
+            name,
+            enabled,
+            url,
+            interval_secs,
+            timeout,
+            temporality,
+            protocol,
+            http_fallback,
+            eager_connect,
+            payload_capture,
+            user_agent,
+            dns,
+            socket_options,
+            max_bytes_per_sec,
+            signer,
+            ca_cert_path,
+            ca_cert_dir,
+            crl_path,
+            client_cert,
+            cert_change_policy,
+            min_tls_version,
+            cipher_suites,
+            headers,
+            auth_token_file,
+            schedule,
+            retry,
+        } = self;
+        f.debug_struct("MetricsExportTarget")
+            .field("name", name)
+            .field("enabled", enabled)
+            .field("url", url)
+            .field("interval_secs", interval_secs)
+            .field("timeout", timeout)
+            .field("temporality", temporality)
+            .field("protocol", protocol)
+            .field("http_fallback", http_fallback)
+            .field("eager_connect", eager_connect)
+            .field("payload_capture", payload_capture)
+            .field("user_agent", user_agent)
+            .field("dns", dns)
+            .field("socket_options", socket_options)
+            .field("max_bytes_per_sec", max_bytes_per_sec)
+            .field("signer", signer)
+            .field("ca_cert_path", ca_cert_path)
+            .field("ca_cert_dir", ca_cert_dir)
+            .field("crl_path", crl_path)
+            .field("client_cert", client_cert)
+            .field("cert_change_policy", cert_change_policy)
+            .field("min_tls_version", min_tls_version)
+            .field("cipher_suites", cipher_suites)
+            .field(
+                "headers",
+                &headers.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            )
+            .field("auth_token_file", auth_token_file)
+            .field("schedule", schedule)
+            .field("retry", retry)
+            .finish()
+    }
+}
+
+impl MetricsExportTarget {
+    /// The name of the target if it has one, otherwise its url.
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.url)
+    }
+}
+
+impl Default for MetricsExportTarget {
+    fn default() -> Self {
+        Self {
+            name: None,
+            enabled: true,
+            url: "http://localhost:4317".to_owned(),
+            interval_secs: 60,
+            timeout: 30,
+            temporality: None,
+            protocol: Protocol::Grpc,
+            http_fallback: false,
+            eager_connect: false,
+            payload_capture: None,
+            user_agent: None,
+            dns: DnsConfig::default(),
+            socket_options: SocketOptions::default(),
+            max_bytes_per_sec: None,
+            signer: None,
+            ca_cert_path: None,
+            ca_cert_dir: None,
+            crl_path: None,
+            client_cert: None,
+            cert_change_policy: CertChangePolicy::default(),
+            min_tls_version: None,
+            cipher_suites: Vec::new(),
+            headers: Vec::new(),
+            auth_token_file: None,
+            schedule: ExportSchedule::default(),
+            retry: None,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema), schemars(default))]
+#[serde(default)]
+#[allow(clippy::struct_excessive_bools)]
 /// A Logs export target definition
 pub struct LogsExportTarget {
+    /// Optional name of the target, used to refer to it in diagnostics instead of the url.
+    pub name: Option<String>,
+    /// set to false to keep the target in the config without exporting to it.
+    pub enabled: bool,
     /// Address of the OTEL compatible repository
     pub url: String,
     /// How often to export, specified in seconds
     pub interval_secs: u64,
     /// export timeout - how long to wait before timing out on a push to the target.
     pub timeout: u64,
-    /// export severity - severity >= which to export
-    pub export_severity: Option<Severity>,
+    /// the records exported to the target, defaults to all records that pass `Config::level`.
+    pub filter: LogFilter,
+    /// OTLP transport to use for the target, defaults to gRPC.
+    #[serde(deserialize_with = "config_serde::protocol")]
+    #[cfg_attr(feature = "schema", schemars(with = "crate::schema::Protocol"))]
+    pub protocol: Protocol,
+    /// set to true to fall back to OTLP/HTTP if the target turns out not to speak gRPC.
+    /// The protocol that worked on first contact is used from then on.
+    pub http_fallback: bool,
+    /// set to true to connect to the target during initialization, so that an unreachable or misconfigured
+    /// endpoint is reported at startup rather than on the first export. The target is skipped if the connection fails.
+    pub eager_connect: bool,
+    /// Optional capture of the serialized OTLP requests sent to the target, for inspecting protocol issues offline.
+    pub payload_capture: Option<PayloadCapture>,
+    /// Optional user agent of OTLP requests to the target, replacing the one derived from `Config::app_identifier`.
+    pub user_agent: Option<String>,
+    /// How the host of the target is resolved, defaults to the system resolver.
+    pub dns: DnsConfig,
+    /// Options of the sockets connecting to the target.
+    pub socket_options: SocketOptions,
+    /// optional maximum rate at which data is sent to the target, specified in bytes per second. Exports are paced
+    /// to the rate so that bursts of telemetry don't starve the other traffic of the device on low bandwidth links.
+    pub max_bytes_per_sec: Option<u64>,
+    /// Optional signer of the OTLP/HTTP requests to the target, for endpoints that require signed requests, such as
+    /// [`signing::SigV4Signer`](crate::signing) with the `sigv4` feature. OTLP/gRPC requests are not signed.
+    #[serde(skip)]
+    #[cfg_attr(feature = "schema", schemars(skip))]
+    pub signer: Option<Arc<dyn RequestSigner>>,
+    /// Optional path of a PEM or DER file of the certificate authorities trusted to verify the target, for `https`
+    /// urls. Defaults to the system's trusted certificate authorities.
+    pub ca_cert_path: Option<PathBuf>,
+    /// Optional directory of the certificate authorities trusted to verify the target, for `https` urls, each file
+    /// of which has PEM or DER encoded certificates, e.g. `/etc/ssl/certs`. They are trusted along with those of
+    /// `ca_cert_path`, instead of the system's. The file and the directory are checked for changes before each export,
+    /// and the target is reconnected with the new certificate authorities when they changed, as set by
+    /// `cert_change_policy`.
+    pub ca_cert_dir: Option<PathBuf>,
+    /// Optional path of a PEM or DER file of certificate revocation lists, for `https` urls. The certificates of the
+    /// target that are revoked are rejected, and so are those no list covers, so there must be a list for each
+    /// certificate authority of its chain. The file is checked for changes along with the certificate authority files.
+    pub crl_path: Option<PathBuf>,
+    /// Optional certificate and private key the target is authenticated to with, for `https` urls whose target
+    /// requires mutual TLS. They are checked for changes along with the certificate authority files, so certificates
+    /// rotated by another process, e.g. the SVIDs written by the SPIFFE helper, are used without restarting.
+    pub client_cert: Option<ClientCertificate>,
+    /// What happens when the certificate authority files of the target change, defaults to reconnecting the target.
+    pub cert_change_policy: CertChangePolicy,
+    /// Optional minimum version of TLS negotiated with the target, for `https` urls. Defaults to TLS 1.2.
+    pub min_tls_version: Option<TlsVersion>,
+    /// names of the cipher suites allowed with the target, for `https` urls, in order of preference, e.g.
+    /// `TLS13_AES_256_GCM_SHA384`. Defaults to all the cipher suites supported, which are all considered secure.
+    pub cipher_suites: Vec<String>,
+    /// headers added to the requests to the target, e.g. an API key, as gRPC metadata for OTLP/gRPC. Header names
+    /// are case insensitive.
+    pub headers: Vec<(String, String)>,
+    /// Optional file of a token added to the requests to the target, e.g. a bearer token that is rotated by another
+    /// process.
+    pub auth_token_file: Option<AuthTokenFile>,
+    /// when exports to the target are sent, defaults to always.
+    pub schedule: ExportSchedule,
+    /// set to true to export each record synchronously as it is emitted instead of batching records in the
+    /// background, for short lived processes that emit few logs. `interval_secs` is ignored. Records must not be
+    /// emitted from a single threaded tokio runtime, as the export blocks the emitting thread.
+    pub simple: bool,
+    /// Optional retries of the exports to the target that fail, e.g. while the collector restarts. Exports fail
+    /// without being retried by default.
+    pub retry: Option<ExportRetry>,
+    /// Optional directory the batches that fail to export are persisted in, and replayed from in order once the
+    /// target is reachable again, including after a restart, for devices that lose connectivity for hours. `retry`
+    /// doesn't apply to targets with a persistence directory, and each target needs its own. The target is skipped
+    /// if the directory can't be created, or is the persistence directory of another target.
+    pub persistence_dir: Option<PathBuf>,
+    /// the maximum size of the batches persisted in `persistence_dir`, in bytes. The oldest batches are discarded
+    /// beyond it.
+    pub max_disk_bytes: u64,
+    /// Optional maximum age of the batches persisted in `persistence_dir`, in seconds. Older batches are discarded
+    /// rather than replayed, e.g. as the target would reject them as too old.
+    pub max_disk_age_secs: Option<u64>,
+    /// Optional path of a file of the base64 encoding of a 256 bit key, e.g. a mounted secret, to encrypt the batches
+    /// persisted in `persistence_dir` with AES-256-GCM. The target is skipped if the key can't be read, rather than
+    /// persisting batches in clear. Batches persisted in clear before are still replayed.
+    pub persistence_key_file: Option<PathBuf>,
 }
 
-#[derive(Clone, Debug)]
+impl fmt::Debug for LogsExportTarget {
+    // The values of the headers are secrets, e.g. API keys, and the configuration is logged.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let LogsExportTarget {
+            name,
+            enabled,
+            url,
+            interval_secs,
+            timeout,
+            filter,
+            protocol,
+            http_fallback,
+            eager_connect,
+            payload_capture,
+            user_agent,
+            dns,
+            socket_options,
+            max_bytes_per_sec,
+            signer,
+            ca_cert_path,
+            ca_cert_dir,
+            crl_path,
+            client_cert,
+            cert_change_policy,
+            min_tls_version,
+            cipher_suites,
+            headers,
+            auth_token_file,
+            schedule,
+            simple,
+            retry,
+            persistence_dir,
+            max_disk_bytes,
+            max_disk_age_secs,
+            persistence_key_file,
+        } = self;
+        f.debug_struct("LogsExportTarget")
+            .field("name", name)
+            .field("enabled", enabled)
+            .field("url", url)
+            .field("interval_secs", interval_secs)
+            .field("timeout", timeout)
+            .field("filter", filter)
+            .field("protocol", protocol)
+            .field("http_fallback", http_fallback)
+            .field("eager_connect", eager_connect)
+            .field("payload_capture", payload_capture)
+            .field("user_agent", user_agent)
+            .field("dns", dns)
+            .field("socket_options", socket_options)
+            .field("max_bytes_per_sec", max_bytes_per_sec)
+            .field("signer", signer)
+            .field("ca_cert_path", ca_cert_path)
+            .field("ca_cert_dir", ca_cert_dir)
+            .field("crl_path", crl_path)
+            .field("client_cert", client_cert)
+            .field("cert_change_policy", cert_change_policy)
+            .field("min_tls_version", min_tls_version)
+            .field("cipher_suites", cipher_suites)
+            .field(
+                "headers",
+                &headers.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            )
+            .field("auth_token_file", auth_token_file)
+            .field("schedule", schedule)
+            .field("simple", simple)
+            .field("retry", retry)
+            .field("persistence_dir", persistence_dir)
+            .field("max_disk_bytes", max_disk_bytes)
+            .field("max_disk_age_secs", max_disk_age_secs)
+            .field("persistence_key_file", persistence_key_file)
+            .finish()
+    }
+}
+
+impl LogsExportTarget {
+    /// The name of the target if it has one, otherwise its url.
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.url)
+    }
+}
+
+impl Default for LogsExportTarget {
+    fn default() -> Self {
+        Self {
+            name: None,
+            enabled: true,
+            url: "http://localhost:4317".to_owned(),
+            interval_secs: 1,
+            timeout: 30,
+            filter: LogFilter::default(),
+            protocol: Protocol::Grpc,
+            http_fallback: false,
+            eager_connect: false,
+            payload_capture: None,
+            user_agent: None,
+            dns: DnsConfig::default(),
+            socket_options: SocketOptions::default(),
+            max_bytes_per_sec: None,
+            signer: None,
+            ca_cert_path: None,
+            ca_cert_dir: None,
+            crl_path: None,
+            client_cert: None,
+            cert_change_policy: CertChangePolicy::default(),
+            min_tls_version: None,
+            cipher_suites: Vec::new(),
+            headers: Vec::new(),
+            auth_token_file: None,
+            schedule: ExportSchedule::default(),
+            simple: false,
+            retry: None,
+            persistence_dir: None,
+            max_disk_bytes: 64 * 1024 * 1024,
+            max_disk_age_secs: None,
+            persistence_key_file: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema), schemars(default))]
+#[serde(default)]
+/// A Traces export target definition. Spans are exported over OTLP/gRPC.
+pub struct TracesExportTarget {
+    /// Optional name of the target, used to refer to it in diagnostics instead of the url.
+    pub name: Option<String>,
+    /// set to false to keep the target in the config without exporting to it.
+    pub enabled: bool,
+    /// Address of the OTEL compatible repository
+    pub url: String,
+    /// How often to export, specified in seconds
+    pub interval_secs: u64,
+    /// export timeout - how long to wait before timing out on a push to the target.
+    pub timeout: u64,
+    /// the traces exported to the target, defaults to all traces.
+    pub sampler: TraceSampler,
+    /// Optional path of a PEM or DER file of the certificate authorities trusted to verify the target, for `https`
+    /// urls. Defaults to the system's trusted certificate authorities.
+    pub ca_cert_path: Option<PathBuf>,
+    /// Optional directory of the certificate authorities trusted to verify the target, for `https` urls, each file
+    /// of which has PEM or DER encoded certificates, e.g. `/etc/ssl/certs`. They are trusted along with those of
+    /// `ca_cert_path`, instead of the system's. The file and the directory are checked for changes before each export,
+    /// and the target is reconnected with the new certificate authorities when they changed, as set by
+    /// `cert_change_policy`.
+    pub ca_cert_dir: Option<PathBuf>,
+    /// Optional path of a PEM or DER file of certificate revocation lists, for `https` urls. The certificates of the
+    /// target that are revoked are rejected, and so are those no list covers, so there must be a list for each
+    /// certificate authority of its chain. The file is checked for changes along with the certificate authority files.
+    pub crl_path: Option<PathBuf>,
+    /// Optional certificate and private key the target is authenticated to with, for `https` urls whose target
+    /// requires mutual TLS. They are checked for changes along with the certificate authority files, so certificates
+    /// rotated by another process, e.g. the SVIDs written by the SPIFFE helper, are used without restarting.
+    pub client_cert: Option<ClientCertificate>,
+    /// What happens when the certificate authority files of the target change, defaults to reconnecting the target.
+    pub cert_change_policy: CertChangePolicy,
+    /// Optional minimum version of TLS negotiated with the target, for `https` urls. Defaults to TLS 1.2.
+    pub min_tls_version: Option<TlsVersion>,
+    /// names of the cipher suites allowed with the target, for `https` urls, in order of preference, e.g.
+    /// `TLS13_AES_256_GCM_SHA384`. Defaults to all the cipher suites supported, which are all considered secure.
+    pub cipher_suites: Vec<String>,
+}
+
+impl TracesExportTarget {
+    /// The name of the target if it has one, otherwise its url.
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.url)
+    }
+}
+
+impl Default for TracesExportTarget {
+    fn default() -> Self {
+        Self {
+            name: None,
+            enabled: true,
+            url: "http://localhost:4317".to_owned(),
+            interval_secs: 5,
+            timeout: 30,
+            sampler: TraceSampler::default(),
+            ca_cert_path: None,
+            ca_cert_dir: None,
+            crl_path: None,
+            client_cert: None,
+            cert_change_policy: CertChangePolicy::default(),
+            min_tls_version: None,
+            cipher_suites: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// What happens when the certificate authority files of an export target change, e.g. as a certificate authority is
+/// rotated.
+pub enum CertChangePolicy {
+    /// the target is reconnected with the new certificate authorities, without losing exports.
+    #[default]
+    Reconnect,
+    /// the files are not watched, the target keeps the certificate authorities it started with.
+    Ignore,
+    /// the process exits, for services whose supervisor restarts them to pick up new certificates.
+    Exit,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// A version of TLS.
+pub enum TlsVersion {
+    /// TLS 1.2.
+    #[serde(rename = "1.2")]
+    Tls12,
+    /// TLS 1.3.
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// The certificate and private key of the client, with which it authenticates to an export target over mutual TLS.
+pub struct ClientCertificate {
+    /// Path of a PEM file of the certificate chain of the client, starting with its own certificate.
+    pub cert_path: PathBuf,
+    /// Path of a PEM file of the private key of the client, in PKCS#1, PKCS#8 or SEC1 format.
+    pub key_path: PathBuf,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// A file of a token added to the requests to an export target, e.g. a bearer token. Leading and trailing whitespace
+/// of the token is ignored. If the file can't be read, the token read last is kept.
+pub struct AuthTokenFile {
+    /// path of the file.
+    pub path: PathBuf,
+    /// name of the header the token is sent in.
+    #[serde(default = "default_auth_token_header")]
+    #[cfg_attr(feature = "schema", schemars(default = "default_auth_token_header"))]
+    pub header: String,
+    /// optional scheme sent ahead of the token, e.g. `Bearer` for `authorization: Bearer <token>`.
+    #[serde(default = "default_auth_token_scheme")]
+    #[cfg_attr(feature = "schema", schemars(default = "default_auth_token_scheme"))]
+    pub scheme: Option<String>,
+    /// how often the file is read again, in seconds, as requests are sent.
+    #[serde(default = "default_reload_interval_secs")]
+    #[cfg_attr(feature = "schema", schemars(default = "default_reload_interval_secs"))]
+    pub reload_interval_secs: u64,
+}
+
+impl AuthTokenFile {
+    /// A bearer token read from `path` and sent as `authorization: Bearer <token>`, read again every minute.
+    pub fn bearer(path: impl Into<PathBuf>) -> Self {
+        AuthTokenFile {
+            path: path.into(),
+            header: default_auth_token_header(),
+            scheme: default_auth_token_scheme(),
+            reload_interval_secs: default_reload_interval_secs(),
+        }
+    }
+}
+
+fn default_auth_token_header() -> String {
+    "authorization".to_owned()
+}
+
+#[allow(clippy::unnecessary_wraps)]
+fn default_auth_token_scheme() -> Option<String> {
+    Some("Bearer".to_owned())
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// The traces exported to a trace export target.
+pub enum TraceSampler {
+    /// all traces.
+    #[default]
+    AlwaysOn,
+    /// no traces.
+    AlwaysOff,
+    /// the given fraction of traces, between 0 and 1. Traces are picked by their trace id, so that all services
+    /// sampling with the same ratio keep the same traces.
+    TraceIdRatio(f64),
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema), schemars(default))]
+#[serde(default)]
+/// When the exports to a target are sent. Exports are held in memory during quiet hours and sent along with the first
+/// export after them, for deployments where telemetry may only use the uplink at certain times of the day.
+pub struct ExportSchedule {
+    /// 0 or more windows of the day during which exports are held.
+    pub quiet_hours: Vec<QuietHours>,
+    /// offset from UTC of the time zone the quiet hours are specified in, in minutes, for example 60 for UTC+01:00.
+    /// Defaults to UTC.
+    pub utc_offset_minutes: i32,
+    /// the maximum number of exports held during quiet hours. The oldest exports are dropped beyond it.
+    pub max_held_exports: usize,
+}
+
+impl Default for ExportSchedule {
+    fn default() -> Self {
+        Self {
+            quiet_hours: Vec::new(),
+            utc_offset_minutes: 0,
+            max_held_exports: 10_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema), schemars(default))]
+#[serde(default)]
+/// How the failed exports to a target are retried. A failed export is buffered, and sent again with the exports that
+/// follow once the backoff elapsed, so retries don't block the pipeline. The backoff doubles with each failure in a
+/// row, and is reset by an export that succeeds.
+pub struct ExportRetry {
+    /// the maximum number of times an export is sent, including the first time, after which it is discarded.
+    pub max_attempts: u32,
+    /// the backoff after the first failure, in milliseconds.
+    pub initial_backoff_millis: u64,
+    /// the maximum backoff, in milliseconds.
+    pub max_backoff_millis: u64,
+    /// the fraction of the backoff, between 0 and 1, by which it is randomly shortened or lengthened, so that
+    /// devices that lost the collector at the same time don't retry all at once.
+    pub jitter: f64,
+    /// the maximum number of failed exports buffered for retry. The oldest exports are discarded beyond it.
+    pub max_buffered_batches: usize,
+}
+
+impl Default for ExportRetry {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_millis: 1000,
+            max_backoff_millis: 60_000,
+            jitter: 0.2,
+            max_buffered_batches: 100,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// A window of the day, which spans midnight if it ends before it starts.
+pub struct QuietHours {
+    /// start of the window, formatted as `HH:MM`.
+    pub start: String,
+    /// end of the window, formatted as `HH:MM`.
+    pub end: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema), schemars(default))]
+#[serde(default)]
+/// The log records written to a sink, such as stderr or an export target: the records that pass each of the parts of
+/// the filter that are set. Records are filtered before they are queued for the sink.
+pub struct LogFilter {
+    /// optional minimum severity of the records.
+    #[serde(deserialize_with = "config_serde::severity")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<crate::schema::Severity>"))]
+    pub severity: Option<Severity>,
+    /// optional logging directives in the format of `Config::level`, which records of the log crate must match by
+    /// level and module. Other records, such as events, are not subject to the directives.
+    pub directives: Option<String>,
+    /// attributes the records must have, with the given values.
+    pub attributes: Vec<Attribute>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// Sends the log records that match `filter` to `sinks` only, e.g. records of `security::*` to an audit target.
+pub struct LogRoute {
+    /// the records the route applies to.
+    pub filter: LogFilter,
+    /// the sinks the records are sent to, where they are still subject to the filters of the sinks.
+    pub sinks: Vec<LogSink>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// A sink log records can be routed to.
+pub enum LogSink {
+    /// stderr, if `Config::emit_logs_to_stderr` is set.
+    Stderr,
+    /// the log export target or audit target with this label, its name or url.
+    Target(String),
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema), schemars(default))]
+#[serde(default)]
+/// Drops the records of the log crate that match each of the regular expressions that are set. A rule without any
+/// regular expression doesn't drop any records. Dropped records are counted per rule, by the index of the rule, as
+/// the `otel_lib.logs.dropped_by_rule` metric.
+pub struct DropRule {
+    /// optional regular expression the target of the records must match, e.g. `^h2(::|$)`.
+    pub target: Option<String>,
+    /// optional regular expression the formatted message of the records must match.
+    pub body: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// A latency SLO of the values recorded into a histogram of the meters of `Otel`. Each recorded value counts an event
+/// of the `slo.events` counter, with the attributes of the value, `slo.name` and `slo.outcome`, which is `good` if the
+/// value is at most `target_latency` and `bad` otherwise. The objective is exported as the `slo.objective` gauge.
+pub struct LatencySlo {
+    /// the name of the SLO, e.g. `checkout-latency`.
+    pub name: String,
+    /// the full name of the histogram, including the prefix of its meter, e.g. `http.server.request.duration`.
+    pub histogram: String,
+    /// the latency of good events, in the unit of the histogram, e.g. 0.3 for 300ms in seconds.
+    pub target_latency: f64,
+    /// the target ratio of good events, between 0 and 1, e.g. 0.999.
+    pub objective: f64,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema), schemars(default))]
+#[serde(default)]
+/// Limits applied to the attributes of log records, which are silently truncated to fit. Audit records are not
+/// limited.
+pub struct LogRecordLimits {
+    /// maximum number of attributes per record, further attributes are dropped.
+    pub attribute_count_limit: Option<usize>,
+    /// maximum number of characters of string attribute values, including the strings in array values.
+    pub attribute_value_length_limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema), schemars(default))]
+#[serde(default)]
+/// Hard caps on the telemetry of a component. Exceeding a cap is reported as an error.
+pub struct Governance {
+    /// maximum number of instruments. Instruments created beyond the cap are dropped.
+    pub max_instruments: Option<usize>,
+    /// maximum number of attributes per log record. Attributes beyond the cap are dropped from the record.
+    pub max_attributes_per_record: Option<usize>,
+    /// maximum number of enabled export targets per signal. Targets beyond the cap are skipped.
+    pub max_export_targets: Option<usize>,
+}
+
+#[derive(Clone, Default, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema), schemars(default))]
+#[serde(default)]
+/// How the host of an export target is resolved, for devices whose system resolver is misconfigured.
+pub struct DnsConfig {
+    /// addresses of hosts, used instead of resolving the hosts.
+    pub static_hosts: HashMap<String, Vec<IpAddr>>,
+    /// DNS servers that resolve the other hosts, instead of the servers configured in resolv.conf.
+    pub name_servers: Vec<SocketAddr>,
+}
+
+impl fmt::Debug for DnsConfig {
+    // The hosts are sorted, so that equal configurations have the same representation.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DnsConfig")
+            .field(
+                "static_hosts",
+                &self.static_hosts.iter().collect::<BTreeMap<_, _>>(),
+            )
+            .field("name_servers", &self.name_servers)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema), schemars(default))]
+#[serde(default)]
+/// Options of the sockets connecting to an export target.
+pub struct SocketOptions {
+    /// optional TCP keepalive probing of idle connections, so that half-open connections are detected.
+    pub keepalive: Option<TcpKeepalive>,
+    /// optional DSCP value to mark packets with, so that the network can deprioritize the telemetry traffic, for
+    /// example 8 (CS1) for lower effort.
+    pub dscp: Option<u8>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// TCP keepalive probing of idle connections.
+pub struct TcpKeepalive {
+    /// how long a connection is idle before it is probed, specified in seconds.
+    pub idle_secs: u64,
+    /// how long to wait for a response to a probe before probing again, specified in seconds.
+    pub interval_secs: u64,
+    /// number of unanswered probes after which the connection is considered broken.
+    pub retries: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// Capture of the serialized OTLP requests sent to a target.
+pub struct PayloadCapture {
+    /// File the requests are written to, one line per request with its timestamp and the hex encoded protobuf
+    /// payload. The file is truncated on startup.
+    pub path: PathBuf,
+    /// Capturing stops once the file would grow beyond this size.
+    pub max_bytes: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// The start time of cumulative series, which tells backends when a series started counting from zero.
+pub enum StartTime {
+    /// The time the library was initialized, for every series.
+    ProcessStart,
+    /// The time each series was first exported to a target.
+    FirstObservation,
+    /// The time the library was first initialized, persisted to `path` and kept across restarts of the process.
+    Persisted {
+        /// File the start time is stored in, as nanoseconds since the unix epoch.
+        path: PathBuf,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// A view that customizes how the instruments it matches are aggregated, for all readers and exporters.
+pub struct MetricView {
+    /// Name of the instruments the view applies to. The `*` and `?` wildcards are supported, so a view for `*`
+    /// applies to every instrument not matched by an earlier view.
+    pub instrument_name: String,
+    /// Aggregation to use for histograms, defaults to the explicit bucket histogram if not specified.
+    pub histogram_aggregation: Option<HistogramAggregation>,
+    /// set to false to not record the min and max of histograms, which are then also left out of OTLP exports.
+    #[serde(default = "default_record_min_max")]
+    pub record_min_max: bool,
+    /// Optional bucket boundaries of explicit bucket histograms, in increasing order, instead of the SDK's defaults.
+    #[serde(default)]
+    pub bucket_boundaries: Option<Vec<f64>>,
+    /// Optional name to export the instrument with instead of its own. Only valid if `instrument_name` has no
+    /// wildcards, as the instruments it matches would otherwise be merged.
+    #[serde(default)]
+    pub rename: Option<String>,
+    /// Optional keys of the attributes to keep, the others being removed before aggregation to bound the cardinality
+    /// of the instrument.
+    #[serde(default)]
+    pub allowed_attribute_keys: Option<Vec<String>>,
+    /// set to true to drop the instruments the view matches, e.g. high cardinality instruments of a dependency.
+    #[serde(default)]
+    pub drop: bool,
+}
+
+fn default_record_min_max() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+/// How histogram measurements are aggregated.
+pub enum HistogramAggregation {
+    /// A histogram with fixed bucket boundaries.
+    ExplicitBucket,
+    /// A histogram with base-2 exponential buckets, whose resolution adapts to the range of the recorded values.
+    /// Exponential histograms are not served on the prometheus end point.
+    Base2Exponential {
+        /// The maximum number of buckets, 160 is a reasonable default.
+        max_size: u32,
+        /// The maximum resolution scale, between -10 and 20. 20 is a reasonable default.
+        max_scale: i8,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Attribute {
     pub key: String,
     pub value: String,
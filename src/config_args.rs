@@ -0,0 +1,56 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Common configuration flags, to flatten into the `clap::Parser` of an application. Available with the `clap`
+//! feature.
+
+use crate::config::{Config, LogsExportTarget, MetricsExportTarget, PrometheusConfig};
+
+/// Flags that override the corresponding settings of a [`Config`].
+#[derive(clap::Args, Clone, Debug, Default, PartialEq)]
+#[command(about = None, long_about = None)]
+pub struct ConfigArgs {
+    /// Url of an OTLP/gRPC collector to export metrics and logs to
+    #[arg(long, value_name = "URL")]
+    pub otel_endpoint: Option<String>,
+
+    /// Logging directives, e.g. "info,hyper=off"
+    #[arg(long, value_name = "DIRECTIVES")]
+    pub log_level: Option<String>,
+
+    /// Port to serve prometheus metrics on
+    #[arg(long, value_name = "PORT")]
+    pub prometheus_port: Option<u16>,
+}
+
+impl ConfigArgs {
+    /// Apply the flags that are set to `config`. The endpoint is added to the metrics and log export targets, with
+    /// the defaults of the targets, and the prometheus port enables the prometheus end point if `config` doesn't.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(url) = &self.otel_endpoint {
+            config
+                .metrics_export_targets
+                .get_or_insert_with(Vec::new)
+                .push(MetricsExportTarget {
+                    url: url.clone(),
+                    ..MetricsExportTarget::default()
+                });
+            config
+                .log_export_targets
+                .get_or_insert_with(Vec::new)
+                .push(LogsExportTarget {
+                    url: url.clone(),
+                    ..LogsExportTarget::default()
+                });
+        }
+        if let Some(level) = &self.log_level {
+            config.level.clone_from(level);
+        }
+        if let Some(port) = self.prometheus_port {
+            config
+                .prometheus_config
+                .get_or_insert_with(PrometheusConfig::default)
+                .port = port;
+        }
+    }
+}
@@ -0,0 +1,483 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Processing of a configuration file before it is deserialized into a `Config`. The file is parsed into a JSON value
+// whatever its format, the fragment files it includes are merged onto it, and then the overrides of the selected
+// profile. Fragments add to the configuration: tables are merged field by field and the items of lists are appended,
+// while a field set to two different values is a conflict, as fragments are typically managed by different teams and
+// neither of them should silently win. A profile overrides the configuration instead: tables are merged field by
+// field, while any other value of the profile, lists included, replaces the value of the base configuration.
+//
+// Once deserialized, the secrets of the configuration can be references rather than values, so that they don't live
+// in the file: `env:NAME` is the value of the environment variable, and `file:/run/secrets/x` the contents of the file
+// for header values, tokens and passwords. The paths of keys, certificates and token files are references to the
+// file already, so `env:NAME` is the path in the environment variable and `file:/run/secrets/x` the path itself,
+// rather than the contents of the file. References are resolved when the configuration is loaded, and only then:
+// nothing watches the variables or files, so rotated secrets are picked up by calling `Config::from_file` again.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use serde_json::Value;
+
+use crate::config::{AuthTokenFile, ClientCertificate, Config, ConfigError, ConfigFormat};
+
+const ENV_REFERENCE: &str = "env:";
+
+const FILE_REFERENCE: &str = "file:";
+
+/// The environment variable that selects the profile, over the `profile` field of the file.
+pub(crate) const PROFILE_ENV: &str = "OTEL_LIB_PROFILE";
+
+/// The configuration file at `path`, in the format given by its extension, as a JSON value.
+pub(crate) fn read(path: &Path) -> Result<Value, ConfigError> {
+    let format = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => ConfigFormat::Json,
+        #[cfg(feature = "toml")]
+        Some("toml") => ConfigFormat::Toml,
+        #[cfg(feature = "yaml")]
+        Some("yaml" | "yml") => ConfigFormat::Yaml,
+        _ => return Err(ConfigError::UnsupportedFormat(path.to_path_buf())),
+    };
+    let contents = fs::read_to_string(path)
+        .map_err(|e| ConfigError::Read(path.to_path_buf(), e.to_string()))?;
+    parse(&contents, format)
+}
+
+/// The contents of a configuration file in `format`, as a JSON value.
+pub(crate) fn parse(contents: &str, format: ConfigFormat) -> Result<Value, ConfigError> {
+    let value = match format {
+        ConfigFormat::Json => serde_json::from_str(contents).map_err(|e| e.to_string()),
+        #[cfg(feature = "toml")]
+        ConfigFormat::Toml => toml::from_str(contents).map_err(|e| e.to_string()),
+        #[cfg(feature = "yaml")]
+        ConfigFormat::Yaml => serde_yaml::from_str(contents).map_err(|e| e.to_string()),
+    };
+    value.map_err(ConfigError::Parse)
+}
+
+/// Remove the `include` field of `config`, and merge the fragment files matched by its patterns, relative to `dir`,
+/// onto it, in the order of the patterns and then of the paths they match.
+pub(crate) fn include(config: &mut Value, dir: &Path) -> Result<(), ConfigError> {
+    let Some(patterns) = config
+        .as_object_mut()
+        .and_then(|fields| fields.remove("include"))
+    else {
+        return Ok(());
+    };
+    let patterns: Vec<String> = serde_json::from_value(patterns)
+        .map_err(|e| ConfigError::Parse(format!("include must be a list of patterns: {e}")))?;
+    for pattern in patterns {
+        let pattern = dir.join(pattern);
+        let paths = glob::glob(&pattern.to_string_lossy()).map_err(|e| {
+            ConfigError::Parse(format!(
+                "invalid include pattern {}: {e}",
+                pattern.display()
+            ))
+        })?;
+        for path in paths {
+            let path =
+                path.map_err(|e| ConfigError::Read(e.path().to_path_buf(), e.error().to_string()))?;
+            let fragment = read(&path)?;
+            if fragment.get("include").is_some() {
+                return Err(ConfigError::Parse(format!(
+                    "{} can't include other files, as a fragment",
+                    path.display()
+                )));
+            }
+            merge_fragment(config, fragment, "")
+                .map_err(|field| ConfigError::Conflict(path, field))?;
+        }
+    }
+    Ok(())
+}
+
+/// Merge `fragment` onto `config`, the value of `field`, or return the path of a field they set to different values.
+fn merge_fragment(config: &mut Value, fragment: Value, field: &str) -> Result<(), String> {
+    match (config, fragment) {
+        (Value::Object(config), Value::Object(fragment)) => {
+            for (key, value) in fragment {
+                let field = if field.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{field}.{key}")
+                };
+                match config.get_mut(&key) {
+                    Some(config) => merge_fragment(config, value, &field)?,
+                    None => {
+                        config.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(config), Value::Array(fragment)) => config.extend(fragment),
+        (config, fragment) if *config == fragment => {}
+        _ => return Err(field.to_owned()),
+    }
+    Ok(())
+}
+
+/// Remove the `profile` and `profiles` fields of `config`, and merge the overrides of the profile named `selected`,
+/// or else by the `profile` field, onto it.
+pub(crate) fn apply_profile(
+    mut config: Value,
+    selected: Option<String>,
+) -> Result<Value, ConfigError> {
+    let Value::Object(fields) = &mut config else {
+        return Ok(config);
+    };
+    let profiles = fields.remove("profiles");
+    let name = match (selected, fields.remove("profile")) {
+        (Some(name), _) | (None, Some(Value::String(name))) => name,
+        (None, Some(profile)) => {
+            return Err(ConfigError::Parse(format!(
+                "the profile must be a name, not {profile}"
+            )))
+        }
+        (None, None) => return Ok(config),
+    };
+    let overrides = match profiles {
+        Some(Value::Object(mut profiles)) => profiles.remove(&name),
+        Some(profiles) => {
+            return Err(ConfigError::Parse(format!(
+                "the profiles must be a table of the profiles by name, not {profiles}"
+            )))
+        }
+        None => None,
+    };
+    let Some(overrides) = overrides else {
+        return Err(ConfigError::UnknownProfile(name));
+    };
+    merge_overrides(&mut config, overrides);
+    Ok(config)
+}
+
+fn merge_overrides(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(base) => merge_overrides(base, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// Resolve the `env:` and `file:` references of the header values, tokens, passwords, and the paths of keys,
+/// certificates and token files of `config`.
+pub(crate) fn resolve_secrets(config: &mut Config) -> Result<(), ConfigError> {
+    for (index, target) in config
+        .metrics_export_targets
+        .iter_mut()
+        .flatten()
+        .enumerate()
+    {
+        resolve_target_secrets(
+            &format!("metrics_export_targets[{index}]"),
+            &mut target.headers,
+            &mut target.ca_cert_path,
+            &mut target.ca_cert_dir,
+            &mut target.crl_path,
+            &mut target.client_cert,
+            &mut target.auth_token_file,
+        )?;
+    }
+    let log_targets = config.log_export_targets.iter_mut().flatten().enumerate();
+    let audit_targets = config.audit_targets.iter_mut().flatten().enumerate();
+    for (field, (index, target)) in log_targets
+        .map(|target| ("log_export_targets", target))
+        .chain(audit_targets.map(|target| ("audit_targets", target)))
+    {
+        let field = format!("{field}[{index}]");
+        resolve_target_secrets(
+            &field,
+            &mut target.headers,
+            &mut target.ca_cert_path,
+            &mut target.ca_cert_dir,
+            &mut target.crl_path,
+            &mut target.client_cert,
+            &mut target.auth_token_file,
+        )?;
+        if let Some(persistence_key_file) = &mut target.persistence_key_file {
+            resolve_path(persistence_key_file, || {
+                format!("{field}.persistence_key_file")
+            })?;
+        }
+    }
+    for (index, target) in config.trace_export_targets.iter_mut().flatten().enumerate() {
+        if let Some(ca_cert_path) = &mut target.ca_cert_path {
+            resolve_path(ca_cert_path, || {
+                format!("trace_export_targets[{index}].ca_cert_path")
+            })?;
+        }
+        if let Some(ca_cert_dir) = &mut target.ca_cert_dir {
+            resolve_path(ca_cert_dir, || {
+                format!("trace_export_targets[{index}].ca_cert_dir")
+            })?;
+        }
+        if let Some(crl_path) = &mut target.crl_path {
+            resolve_path(crl_path, || {
+                format!("trace_export_targets[{index}].crl_path")
+            })?;
+        }
+        if let Some(client_cert) = &mut target.client_cert {
+            resolve_client_cert(client_cert, &format!("trace_export_targets[{index}]"))?;
+        }
+    }
+    if let Some(prometheus_config) = &mut config.prometheus_config {
+        if let Some(tls) = &mut prometheus_config.tls {
+            resolve_path(&mut tls.cert_path, || {
+                "prometheus_config.tls.cert_path".to_owned()
+            })?;
+            resolve_path(&mut tls.key_path, || {
+                "prometheus_config.tls.key_path".to_owned()
+            })?;
+        }
+        if let Some(basic_auth) = &mut prometheus_config.basic_auth {
+            resolve_value(&mut basic_auth.password, || {
+                "prometheus_config.basic_auth.password".to_owned()
+            })?;
+        }
+        if let Some(bearer_token) = &mut prometheus_config.bearer_token {
+            resolve_value(bearer_token, || "prometheus_config.bearer_token".to_owned())?;
+        }
+    }
+    Ok(())
+}
+
+fn resolve_target_secrets(
+    field: &str,
+    headers: &mut [(String, String)],
+    ca_cert_path: &mut Option<PathBuf>,
+    ca_cert_dir: &mut Option<PathBuf>,
+    crl_path: &mut Option<PathBuf>,
+    client_cert: &mut Option<ClientCertificate>,
+    auth_token_file: &mut Option<AuthTokenFile>,
+) -> Result<(), ConfigError> {
+    for (name, value) in headers {
+        resolve_value(value, || format!("{field}.headers.{name}"))?;
+    }
+    if let Some(ca_cert_path) = ca_cert_path {
+        resolve_path(ca_cert_path, || format!("{field}.ca_cert_path"))?;
+    }
+    if let Some(ca_cert_dir) = ca_cert_dir {
+        resolve_path(ca_cert_dir, || format!("{field}.ca_cert_dir"))?;
+    }
+    if let Some(crl_path) = crl_path {
+        resolve_path(crl_path, || format!("{field}.crl_path"))?;
+    }
+    if let Some(client_cert) = client_cert {
+        resolve_client_cert(client_cert, field)?;
+    }
+    if let Some(auth_token_file) = auth_token_file {
+        resolve_path(&mut auth_token_file.path, || {
+            format!("{field}.auth_token_file.path")
+        })?;
+    }
+    Ok(())
+}
+
+fn resolve_client_cert(
+    client_cert: &mut ClientCertificate,
+    field: &str,
+) -> Result<(), ConfigError> {
+    resolve_path(&mut client_cert.cert_path, || {
+        format!("{field}.client_cert.cert_path")
+    })?;
+    resolve_path(&mut client_cert.key_path, || {
+        format!("{field}.client_cert.key_path")
+    })
+}
+
+/// Replace a reference by the value of the environment variable or the contents of the file, without a trailing
+/// newline.
+fn resolve_value(value: &mut String, field: impl FnOnce() -> String) -> Result<(), ConfigError> {
+    let resolved = if let Some(name) = value.strip_prefix(ENV_REFERENCE) {
+        env::var(name).map_err(|e| format!("{name}: {e}"))
+    } else if let Some(path) = value.strip_prefix(FILE_REFERENCE) {
+        fs::read_to_string(path)
+            .map(|contents| contents.trim_end_matches(['\r', '\n']).to_owned())
+            .map_err(|e| format!("{path}: {e}"))
+    } else {
+        return Ok(());
+    };
+    *value = resolved.map_err(|e| ConfigError::Secret(field(), e))?;
+    Ok(())
+}
+
+/// Replace a reference by the value of the environment variable or the path of the file. The file is not read, as
+/// the path is what the field expects: `file:/run/secrets/x` is `/run/secrets/x`.
+fn resolve_path(path: &mut PathBuf, field: impl FnOnce() -> String) -> Result<(), ConfigError> {
+    let Some(reference) = path.to_str() else {
+        return Ok(());
+    };
+    if let Some(name) = reference.strip_prefix(ENV_REFERENCE) {
+        let Some(resolved) = env::var_os(name) else {
+            return Err(ConfigError::Secret(
+                field(),
+                format!("{name}: {}", env::VarError::NotPresent),
+            ));
+        };
+        *path = resolved.into();
+    } else if let Some(resolved) = reference.strip_prefix(FILE_REFERENCE) {
+        *path = resolved.into();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// A directory of fragment files, removed when dropped.
+    struct Fragments(PathBuf);
+
+    impl Fragments {
+        fn new(name: &str, files: &[(&str, Value)]) -> Self {
+            let dir = env::temp_dir().join(format!("otel-lib-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            for (file, contents) in files {
+                fs::write(dir.join(file), contents.to_string()).unwrap();
+            }
+            Fragments(dir)
+        }
+    }
+
+    impl Drop for Fragments {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn with_profiles() -> Value {
+        json!({
+            "service_name": "app",
+            "profile": "dev",
+            "prometheus_config": { "port": 9600, "gzip": true },
+            "metrics_export_targets": [{ "url": "http://collector:4317" }],
+            "profiles": {
+                "dev": { "prometheus_config": { "port": 9601 } },
+                "prod": { "metrics_export_targets": [{ "url": "https://prod:4317" }] },
+            },
+        })
+    }
+
+    #[test]
+    fn profile_tables_are_merged_field_by_field() {
+        let config = apply_profile(with_profiles(), None).unwrap();
+        assert_eq!(
+            config,
+            json!({
+                "service_name": "app",
+                "prometheus_config": { "port": 9601, "gzip": true },
+                "metrics_export_targets": [{ "url": "http://collector:4317" }],
+            })
+        );
+    }
+
+    #[test]
+    fn profile_lists_replace_the_base_lists() {
+        let config = apply_profile(with_profiles(), Some("prod".to_owned())).unwrap();
+        assert_eq!(
+            config["metrics_export_targets"],
+            json!([{ "url": "https://prod:4317" }])
+        );
+        assert_eq!(config["prometheus_config"]["port"], json!(9600));
+    }
+
+    #[test]
+    fn unknown_profile_is_an_error() {
+        assert_eq!(
+            apply_profile(with_profiles(), Some("test".to_owned())),
+            Err(ConfigError::UnknownProfile("test".to_owned()))
+        );
+        assert_eq!(
+            apply_profile(json!({ "profile": "dev" }), None),
+            Err(ConfigError::UnknownProfile("dev".to_owned()))
+        );
+    }
+
+    #[test]
+    fn without_profile_the_profiles_are_removed() {
+        let mut config = with_profiles();
+        config.as_object_mut().unwrap().remove("profile");
+        let config = apply_profile(config, None).unwrap();
+        assert!(config.get("profiles").is_none());
+        assert_eq!(config["prometheus_config"]["port"], json!(9600));
+    }
+
+    #[test]
+    fn fragments_merge_tables_and_append_lists() {
+        let fragments = Fragments::new(
+            "merge",
+            &[
+                (
+                    "a.json",
+                    json!({
+                        "metrics_export_targets": [{ "url": "http://a:4317" }],
+                        "prometheus_config": { "gzip": true },
+                    }),
+                ),
+                (
+                    "b.json",
+                    json!({
+                        "service_name": "app",
+                        "metrics_export_targets": [{ "url": "http://b:4317" }],
+                    }),
+                ),
+            ],
+        );
+        let mut config = json!({
+            "include": ["*.json"],
+            "service_name": "app",
+            "prometheus_config": { "port": 9600 },
+        });
+        include(&mut config, &fragments.0).unwrap();
+        assert_eq!(
+            config,
+            json!({
+                "service_name": "app",
+                "prometheus_config": { "port": 9600, "gzip": true },
+                "metrics_export_targets": [{ "url": "http://a:4317" }, { "url": "http://b:4317" }],
+            })
+        );
+    }
+
+    #[test]
+    fn fragment_setting_a_different_value_is_a_conflict() {
+        let fragments = Fragments::new(
+            "conflict",
+            &[("a.json", json!({ "prometheus_config": { "port": 9601 } }))],
+        );
+        let mut config = json!({ "include": ["*.json"], "prometheus_config": { "port": 9600 } });
+        assert_eq!(
+            include(&mut config, &fragments.0),
+            Err(ConfigError::Conflict(
+                fragments.0.join("a.json"),
+                "prometheus_config.port".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn fragments_cant_include_other_files() {
+        let fragments = Fragments::new("nested", &[("a.json", json!({ "include": ["b.json"] }))]);
+        let mut config = json!({ "include": ["*.json"] });
+        assert!(matches!(
+            include(&mut config, &fragments.0),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Deserialization of the types of other crates used in the configuration, from the string forms of the OpenTelemetry
+// specification that the JSON Schema of the `schema` feature describes: `grpc` or `http/protobuf` for protocols,
+// `cumulative` or `delta` for temporalities, and the lowercase short names of severities, e.g. `warn` or `error2`.
+
+use opentelemetry::logs::Severity;
+use opentelemetry_otlp::Protocol;
+use opentelemetry_sdk::metrics::data::Temporality;
+use serde::{de::Error, Deserialize, Deserializer};
+
+const PROTOCOLS: &[&str] = &["grpc", "http/protobuf"];
+
+const TEMPORALITIES: &[&str] = &["cumulative", "delta"];
+
+const SEVERITIES: [Severity; 24] = [
+    Severity::Trace,
+    Severity::Trace2,
+    Severity::Trace3,
+    Severity::Trace4,
+    Severity::Debug,
+    Severity::Debug2,
+    Severity::Debug3,
+    Severity::Debug4,
+    Severity::Info,
+    Severity::Info2,
+    Severity::Info3,
+    Severity::Info4,
+    Severity::Warn,
+    Severity::Warn2,
+    Severity::Warn3,
+    Severity::Warn4,
+    Severity::Error,
+    Severity::Error2,
+    Severity::Error3,
+    Severity::Error4,
+    Severity::Fatal,
+    Severity::Fatal2,
+    Severity::Fatal3,
+    Severity::Fatal4,
+];
+
+pub(crate) fn protocol<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Protocol, D::Error> {
+    let protocol = String::deserialize(deserializer)?;
+    match protocol.as_str() {
+        "grpc" => Ok(Protocol::Grpc),
+        "http/protobuf" => Ok(Protocol::HttpBinary),
+        _ => Err(D::Error::unknown_variant(&protocol, PROTOCOLS)),
+    }
+}
+
+pub(crate) fn temporality<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Temporality>, D::Error> {
+    let Some(temporality) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    match temporality.as_str() {
+        "cumulative" => Ok(Some(Temporality::Cumulative)),
+        "delta" => Ok(Some(Temporality::Delta)),
+        _ => Err(D::Error::unknown_variant(&temporality, TEMPORALITIES)),
+    }
+}
+
+pub(crate) fn severity<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Severity>, D::Error> {
+    let Some(severity) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    SEVERITIES
+        .into_iter()
+        .find(|candidate| candidate.name().eq_ignore_ascii_case(&severity))
+        .map(Some)
+        .ok_or_else(|| {
+            D::Error::custom(format!(
+                "unknown severity `{severity}`, expected e.g. `info` or `warn2`"
+            ))
+        })
+}
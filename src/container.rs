@@ -0,0 +1,101 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Detection of the container the process runs in, for the `container.*` resource attributes.
+//
+// The container id is the 64 hex digit id that container runtimes put in the cgroup paths of the process with cgroup
+// v1, e.g. `/docker/<id>` or `/kubepods/.../cri-containerd-<id>.scope`. With cgroup v2 the paths are usually hidden
+// by a cgroup namespace, so the id is looked up in the mounts of the files the runtime provides instead, e.g.
+// `/var/lib/docker/containers/<id>/hostname`. The image name is taken from the `CONTAINER_IMAGE` environment
+// variable if set, e.g. by a Kubernetes manifest, or from the `.containerenv` file of podman.
+
+use std::{env, fs, path::Path};
+
+use opentelemetry::KeyValue;
+
+const CONTAINER_ID_KEY: &str = "container.id";
+const CONTAINER_IMAGE_NAME_KEY: &str = "container.image.name";
+
+/// The environment variable the image name of the container is read from.
+const CONTAINER_IMAGE_ENV: &str = "CONTAINER_IMAGE";
+
+/// The length of the ids of containers.
+const CONTAINER_ID_LENGTH: usize = 64;
+
+/// The resource attributes of the container the process runs in, none if it doesn't run in a container.
+pub(crate) fn detect() -> Vec<KeyValue> {
+    let container_id = read("/proc/self/cgroup")
+        .as_deref()
+        .and_then(cgroup_container_id)
+        .or_else(|| {
+            read("/proc/self/mountinfo")
+                .as_deref()
+                .and_then(mounted_container_id)
+        });
+    let image_name = env::var(CONTAINER_IMAGE_ENV)
+        .ok()
+        .filter(|image| !image.is_empty())
+        .or_else(|| read("/run/.containerenv").as_deref().and_then(podman_image))
+        .map(|image| image_name(&image).to_owned());
+    let in_container = container_id.is_some()
+        || Path::new("/.dockerenv").exists()
+        || Path::new("/run/.containerenv").exists();
+    if !in_container {
+        return Vec::new();
+    }
+    [
+        (CONTAINER_ID_KEY, container_id),
+        (CONTAINER_IMAGE_NAME_KEY, image_name),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some(KeyValue::new(key, value?)))
+    .collect()
+}
+
+fn read(path: &str) -> Option<String> {
+    fs::read_to_string(path).ok()
+}
+
+fn is_container_id(id: &str) -> bool {
+    id.len() == CONTAINER_ID_LENGTH && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// The first container id in the cgroup paths of `/proc/self/cgroup`.
+fn cgroup_container_id(cgroup: &str) -> Option<String> {
+    cgroup.lines().find_map(|line| {
+        line.split(['/', '-', '.', ':'])
+            .find(|part| is_container_id(part))
+            .map(str::to_owned)
+    })
+}
+
+/// The first container id in the mounted paths of `/proc/self/mountinfo` that follows a `containers/` directory. Other
+/// ids, such as those of the layers of the image, are ignored.
+fn mounted_container_id(mountinfo: &str) -> Option<String> {
+    mountinfo.lines().find_map(|line| {
+        line.match_indices("containers/")
+            .find_map(|(index, directory)| {
+                let id = line[index + directory.len()..].split('/').next()?;
+                is_container_id(id).then(|| id.to_owned())
+            })
+    })
+}
+
+/// The image of the `.containerenv` file of podman, from its `image="<image>"` line.
+fn podman_image(contents: &str) -> Option<String> {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix("image="))
+        .map(|image| image.trim_matches('"').to_owned())
+        .filter(|image| !image.is_empty())
+}
+
+/// The name of `image` without its tag or digest, e.g. `docker.io/library/nginx` for `docker.io/library/nginx:1.25`.
+fn image_name(image: &str) -> &str {
+    let image = image.split_once('@').map_or(image, |(name, _)| name);
+    let name_start = image.rfind('/').map_or(0, |slash| slash + 1);
+    match image[name_start..].rfind(':') {
+        Some(colon) => &image[..name_start + colon],
+        None => image,
+    }
+}
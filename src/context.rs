@@ -0,0 +1,73 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Propagation of the OpenTelemetry [`Context`] across task boundaries.
+//!
+//! The current context is thread local, so it is lost when work moves to another task. These helpers carry it
+//! along, so that log records and spans of background tasks stay correlated with the work that started them.
+
+use std::future::Future;
+
+use opentelemetry::{trace::FutureExt as _, Context};
+use tokio::{
+    sync::mpsc::{self, error::SendError},
+    task::JoinHandle,
+};
+
+/// Spawn `future` on the tokio runtime with the current context attached, so that it is current whenever the
+/// future is polled.
+pub fn spawn_instrumented<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(future.with_current_context())
+}
+
+/// Create a bounded channel that carries the sender's current context along with each value.
+pub fn channel<T>(buffer: usize) -> (Sender<T>, Receiver<T>) {
+    let (sender, receiver) = mpsc::channel(buffer);
+    (Sender { inner: sender }, Receiver { inner: receiver })
+}
+
+/// The sending half of a [`channel`].
+#[derive(Debug)]
+pub struct Sender<T> {
+    inner: mpsc::Sender<(T, Context)>,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    /// Send `value` along with the current context, waiting for capacity if the channel is full.
+    ///
+    /// # Errors
+    /// * `SendError` - If the receiver was dropped, with the value that was not sent
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.inner
+            .send((value, Context::current()))
+            .await
+            .map_err(|SendError((value, _))| SendError(value))
+    }
+}
+
+/// The receiving half of a [`channel`].
+#[derive(Debug)]
+pub struct Receiver<T> {
+    inner: mpsc::Receiver<(T, Context)>,
+}
+
+impl<T> Receiver<T> {
+    /// Receive the next value and the context it was sent with, or `None` once all senders were dropped. Attach
+    /// the context with [`Context::attach`], or to a future with [`opentelemetry::trace::FutureExt::with_context`],
+    /// to continue the sender's work under it.
+    pub async fn recv(&mut self) -> Option<(T, Context)> {
+        self.inner.recv().await
+    }
+}
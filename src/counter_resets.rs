@@ -0,0 +1,150 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use log::{info, warn};
+use opentelemetry::metrics::Result as MetricsResult;
+use opentelemetry_sdk::{
+    metrics::{
+        data::{ResourceMetrics, Sum, Temporality},
+        exporter::PushMetricsExporter,
+        reader::{AggregationSelector, DefaultAggregationSelector, TemporalitySelector},
+        Aggregation, InstrumentKind,
+    },
+    AttributeSet,
+};
+
+/// The last observation of a counter series.
+struct Observation {
+    start_time: Option<SystemTime>,
+    value: f64,
+}
+
+/// A metrics "exporter" that exports nothing, but logs when a cumulative counter appears to reset, to help
+/// diagnose sawtooth graphs.
+///
+/// A series whose start time moves forward was dropped and re-created, which is what attribute churn looks like.
+/// A series that decreases without a new start time is an observable counter whose source reset. Resets caused by
+/// a restart of this process can only be seen by the backend, so the start time of this process' series is logged
+/// once to tell them apart.
+pub(crate) struct CounterResetDetector {
+    observations: Mutex<HashMap<(String, AttributeSet), Observation>>,
+}
+
+impl CounterResetDetector {
+    pub(crate) fn new() -> Self {
+        info!(
+            "cumulative counters of this process start at start_time_unix_nano {}, a reset with this start time is a \
+             restart of the process",
+            unix_nanos(Some(SystemTime::now()))
+        );
+        CounterResetDetector {
+            observations: Mutex::default(),
+        }
+    }
+
+    fn observe<T: Copy>(
+        observations: &mut HashMap<(String, AttributeSet), Observation>,
+        name: &str,
+        sum: &Sum<T>,
+        to_f64: impl Fn(T) -> f64,
+    ) {
+        if !sum.is_monotonic || sum.temporality != Temporality::Cumulative {
+            return;
+        }
+
+        for data_point in &sum.data_points {
+            let observation = Observation {
+                start_time: data_point.start_time,
+                value: to_f64(data_point.value),
+            };
+            let key = (name.to_owned(), data_point.attributes.clone());
+            if let Some(previous) = observations.get(&key) {
+                if previous.start_time != observation.start_time {
+                    warn!(
+                        "counter {}{{{}}} was reset from {} to {}, its series was dropped and re-created \
+                         (start_time_unix_nano {} -> {}), which usually means attribute churn",
+                        name,
+                        format_attributes(&data_point.attributes),
+                        previous.value,
+                        observation.value,
+                        unix_nanos(previous.start_time),
+                        unix_nanos(observation.start_time)
+                    );
+                } else if observation.value < previous.value {
+                    warn!(
+                        "counter {}{{{}}} decreased from {} to {} without a new start time \
+                         (start_time_unix_nano {}), the source of the observable counter likely reset",
+                        name,
+                        format_attributes(&data_point.attributes),
+                        previous.value,
+                        observation.value,
+                        unix_nanos(observation.start_time)
+                    );
+                }
+            }
+            observations.insert(key, observation);
+        }
+    }
+}
+
+impl TemporalitySelector for CounterResetDetector {
+    fn temporality(&self, _kind: InstrumentKind) -> Temporality {
+        Temporality::Cumulative
+    }
+}
+
+impl AggregationSelector for CounterResetDetector {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        DefaultAggregationSelector::new().aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl PushMetricsExporter for CounterResetDetector {
+    #[allow(clippy::cast_precision_loss)]
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        let mut observations = self
+            .observations
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for scope_metrics in &metrics.scope_metrics {
+            for metric in &scope_metrics.metrics {
+                let data = metric.data.as_any();
+                if let Some(sum) = data.downcast_ref::<Sum<u64>>() {
+                    Self::observe(&mut observations, &metric.name, sum, |value| value as f64);
+                } else if let Some(sum) = data.downcast_ref::<Sum<f64>>() {
+                    Self::observe(&mut observations, &metric.name, sum, |value| value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        Ok(())
+    }
+}
+
+fn format_attributes(attributes: &AttributeSet) -> String {
+    attributes
+        .iter()
+        .map(|(key, value)| format!("{key}=\"{value}\""))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn unix_nanos(time: Option<SystemTime>) -> u128 {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_nanos())
+}
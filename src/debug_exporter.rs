@@ -0,0 +1,379 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Exporters that help troubleshoot the export to a target.
+//
+// With pipeline debugging enabled, every export attempt is logged with the target, the size of the batch, its
+// serialized size, how long it took and whether it succeeded. The logs are rate limited per target so that a short
+// export interval does not flood them. With payload capture enabled, the serialized OTLP requests are additionally
+// written to a file, so protocol issues can be inspected offline.
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, Write as _},
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant, SystemTime},
+};
+
+use async_trait::async_trait;
+use humantime::format_rfc3339_millis;
+use log::{info, warn};
+use opentelemetry::{
+    logs::{LogError, LogResult},
+    metrics::{MetricsError, Result as MetricsResult},
+};
+use opentelemetry_proto::tonic::collector::{
+    logs::v1::ExportLogsServiceRequest, metrics::v1::ExportMetricsServiceRequest,
+};
+use opentelemetry_sdk::{
+    export::logs::{LogData, LogExporter},
+    metrics::{
+        data::{ResourceMetrics, Temporality},
+        exporter::PushMetricsExporter,
+        reader::{AggregationSelector, TemporalitySelector},
+        Aggregation, InstrumentKind,
+    },
+};
+use prost::Message;
+
+use crate::{
+    config::PayloadCapture,
+    export_failures::{ExportFailures, SummarizedError},
+    exporter_metrics, target_health,
+};
+
+/// The window over which export attempts are rate limited.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+/// The number of export attempts logged per target in each window.
+const RATE_LIMIT_ATTEMPTS: u32 = 10;
+
+struct RateLimitWindow {
+    start: Instant,
+    logged: u32,
+    suppressed: u32,
+}
+
+/// Limits the number of export attempts logged per window.
+pub(crate) struct RateLimiter {
+    window: Mutex<RateLimitWindow>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new() -> Self {
+        RateLimiter {
+            window: Mutex::new(RateLimitWindow {
+                start: Instant::now(),
+                logged: 0,
+                suppressed: 0,
+            }),
+        }
+    }
+
+    /// Returns the number of attempts suppressed in the previous window if this attempt may be logged.
+    fn acquire(&self) -> Option<u32> {
+        let mut window = self.window.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut suppressed = 0;
+        if window.start.elapsed() >= RATE_LIMIT_WINDOW {
+            suppressed = window.suppressed;
+            *window = RateLimitWindow {
+                start: Instant::now(),
+                logged: 0,
+                suppressed: 0,
+            };
+        }
+        if window.logged < RATE_LIMIT_ATTEMPTS {
+            window.logged += 1;
+            Some(suppressed)
+        } else {
+            window.suppressed += 1;
+            None
+        }
+    }
+}
+
+impl std::fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RateLimiter").finish_non_exhaustive()
+    }
+}
+
+/// Log an export attempt, if the rate limit allows it.
+pub(crate) fn log_attempt<E: std::fmt::Debug>(
+    rate_limiter: &RateLimiter,
+    signal: &str,
+    target: &str,
+    batch: &str,
+    bytes: usize,
+    duration: Duration,
+    result: &Result<(), E>,
+) {
+    let Some(suppressed) = rate_limiter.acquire() else {
+        return;
+    };
+    let outcome = match result {
+        Ok(()) => "succeeded".to_owned(),
+        Err(e) => format!("failed due to {e:?}"),
+    };
+    let suppressed = if suppressed > 0 {
+        format!(" ({suppressed} earlier attempts not logged)")
+    } else {
+        String::new()
+    };
+    info!(
+        "{signal} export to {target} of {batch}, {bytes} bytes, took {duration:?} and {outcome}{suppressed}"
+    );
+}
+
+/// Describe the size of a metrics batch.
+pub(crate) fn metrics_batch(metrics: &ResourceMetrics) -> String {
+    format!(
+        "{} metrics in {} scopes",
+        metrics
+            .scope_metrics
+            .iter()
+            .map(|scope_metrics| scope_metrics.metrics.len())
+            .sum::<usize>(),
+        metrics.scope_metrics.len()
+    )
+}
+
+/// The file serialized requests to a target are captured to.
+#[derive(Debug)]
+pub(crate) struct CaptureFile {
+    target: String,
+    file: File,
+    written: u64,
+    max_bytes: u64,
+}
+
+impl CaptureFile {
+    /// Create or truncate the capture file for `target`.
+    pub(crate) fn create(target: String, capture: &PayloadCapture) -> io::Result<Self> {
+        Ok(CaptureFile {
+            target,
+            file: File::create(&capture.path)?,
+            written: 0,
+            max_bytes: capture.max_bytes,
+        })
+    }
+
+    /// Write a request as a timestamped line of hex encoded protobuf, unless that would exceed the size cap.
+    pub(crate) fn write(&mut self, request: &[u8]) {
+        if self.written >= self.max_bytes {
+            return;
+        }
+        let mut line = format!("{} ", format_rfc3339_millis(SystemTime::now()));
+        for byte in request {
+            let _ = write!(line, "{byte:02x}");
+        }
+        line.push('\n');
+        let len = line.len() as u64;
+        if self.written + len > self.max_bytes {
+            warn!(
+                "payload capture for {} reached its size cap, no further requests are captured",
+                self.target
+            );
+            self.written = self.max_bytes;
+            return;
+        }
+        match self.file.write_all(line.as_bytes()) {
+            Ok(()) => self.written += len,
+            Err(e) => {
+                warn!(
+                    "stopping payload capture for {} due to: {:?}",
+                    self.target, e
+                );
+                self.written = self.max_bytes;
+            }
+        }
+    }
+}
+
+/// A metrics exporter that logs each export attempt when pipeline debugging is enabled, captures the serialized
+/// requests when payload capture is enabled, summarizes failed exports if `failures` is set, and records the outcome
+/// and duration of each export as self-diagnostics metrics.
+pub(crate) struct DebugMetricsExporter<E> {
+    inner: E,
+    target: String,
+    rate_limiter: Option<RateLimiter>,
+    capture: Option<Mutex<CaptureFile>>,
+    failures: Option<ExportFailures>,
+}
+
+impl<E> DebugMetricsExporter<E> {
+    /// Create a new exporter. Export attempts are only logged if `enabled` is true.
+    pub(crate) fn new(
+        inner: E,
+        target: String,
+        enabled: bool,
+        capture: Option<CaptureFile>,
+        failures: Option<ExportFailures>,
+    ) -> Self {
+        target_health::register("metrics", &target);
+        DebugMetricsExporter {
+            inner,
+            target,
+            rate_limiter: enabled.then(RateLimiter::new),
+            capture: capture.map(Mutex::new),
+            failures,
+        }
+    }
+}
+
+impl<E: TemporalitySelector> TemporalitySelector for DebugMetricsExporter<E> {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.inner.temporality(kind)
+    }
+}
+
+impl<E: AggregationSelector> AggregationSelector for DebugMetricsExporter<E> {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.inner.aggregation(kind)
+    }
+}
+
+impl<E: PushMetricsExporter> DebugMetricsExporter<E> {
+    /// Export `metrics`, logging and capturing the attempt as configured.
+    async fn export_logged(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        if self.rate_limiter.is_none() && self.capture.is_none() {
+            return self.inner.export(metrics).await;
+        }
+
+        let request = ExportMetricsServiceRequest::from(&*metrics);
+        if let Some(capture) = &self.capture {
+            capture
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .write(&request.encode_to_vec());
+        }
+        let Some(rate_limiter) = &self.rate_limiter else {
+            return self.inner.export(metrics).await;
+        };
+
+        let batch = metrics_batch(metrics);
+        let bytes = request.encoded_len();
+        let start = Instant::now();
+        let result = self.inner.export(metrics).await;
+        log_attempt(
+            rate_limiter,
+            "metrics",
+            &self.target,
+            &batch,
+            bytes,
+            start.elapsed(),
+            &result,
+        );
+        result
+    }
+}
+
+#[async_trait]
+impl<E: PushMetricsExporter> PushMetricsExporter for DebugMetricsExporter<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        let start = Instant::now();
+        let result = self.export_logged(metrics).await;
+        exporter_metrics::record_export("metrics", &self.target, start, &result);
+        match &self.failures {
+            Some(failures) => {
+                failures.report(&result);
+                result.map_err(|e| MetricsError::from(SummarizedError::new(&e)))
+            }
+            None => result,
+        }
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        self.inner.force_flush().await
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// A log exporter that logs each export attempt when pipeline debugging is enabled, captures the serialized
+/// requests when payload capture is enabled, summarizes failed exports if `failures` is set, and records the outcome
+/// and duration of each export as self-diagnostics metrics.
+#[derive(Debug)]
+pub(crate) struct DebugLogExporter<E> {
+    inner: E,
+    target: String,
+    rate_limiter: Option<RateLimiter>,
+    capture: Option<CaptureFile>,
+    failures: Option<ExportFailures>,
+}
+
+impl<E> DebugLogExporter<E> {
+    /// Create a new exporter. Export attempts are only logged if `enabled` is true.
+    pub(crate) fn new(
+        inner: E,
+        target: String,
+        enabled: bool,
+        capture: Option<CaptureFile>,
+        failures: Option<ExportFailures>,
+    ) -> Self {
+        target_health::register("logs", &target);
+        DebugLogExporter {
+            inner,
+            target,
+            rate_limiter: enabled.then(RateLimiter::new),
+            capture,
+            failures,
+        }
+    }
+}
+
+impl<E: LogExporter> DebugLogExporter<E> {
+    /// Export `batch`, logging and capturing the attempt as configured.
+    async fn export_logged(&mut self, batch: Vec<LogData>) -> LogResult<()> {
+        if self.rate_limiter.is_none() && self.capture.is_none() {
+            return self.inner.export(batch).await;
+        }
+
+        let request = ExportLogsServiceRequest {
+            resource_logs: batch.iter().cloned().map(Into::into).collect(),
+        };
+        if let Some(capture) = &mut self.capture {
+            capture.write(&request.encode_to_vec());
+        }
+        let Some(rate_limiter) = &self.rate_limiter else {
+            return self.inner.export(batch).await;
+        };
+
+        let records = format!("{} records", batch.len());
+        let bytes = request.encoded_len();
+        let start = Instant::now();
+        let result = self.inner.export(batch).await;
+        log_attempt(
+            rate_limiter,
+            "logs",
+            &self.target,
+            &records,
+            bytes,
+            start.elapsed(),
+            &result,
+        );
+        result
+    }
+}
+
+#[async_trait]
+impl<E: LogExporter> LogExporter for DebugLogExporter<E> {
+    async fn export(&mut self, batch: Vec<LogData>) -> LogResult<()> {
+        let start = Instant::now();
+        let result = self.export_logged(batch).await;
+        exporter_metrics::record_export("logs", &self.target, start, &result);
+        match &self.failures {
+            Some(failures) => {
+                failures.report(&result);
+                result.map_err(|e| LogError::from(SummarizedError::new(&e)))
+            }
+            None => result,
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Encryption of the telemetry buffered on disk, for logs that may not be persisted in clear on shared hardware.
+//
+// Batches are sealed with AES-256-GCM, with a random nonce stored ahead of the ciphertext, under a 256 bit key read
+// from a file of its base64 encoding, e.g. a mounted secret generated with `openssl rand -base64 32`.
+
+use std::{fmt, fs, io, path::Path};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+
+/// The data authenticated with each batch, so batches sealed for another purpose with the same key are rejected.
+const AAD: &[u8] = b"otel-lib disk buffer";
+
+/// The key of the batches of a buffer.
+pub(crate) struct Cipher {
+    key: LessSafeKey,
+    random: SystemRandom,
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cipher").finish_non_exhaustive()
+    }
+}
+
+impl Cipher {
+    /// Read the key from `path`, which contains its base64 encoding.
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let encoded = fs::read_to_string(path)
+            .map_err(|e| format!("unable to read key file {} due to {e:?}", path.display()))?;
+        let key = STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| format!("invalid key in {}: {e}", path.display()))?;
+        let key = UnboundKey::new(&AES_256_GCM, &key).map_err(|_| {
+            format!(
+                "invalid key in {}: expected 32 bytes, found {}",
+                path.display(),
+                key.len()
+            )
+        })?;
+        Ok(Cipher {
+            key: LessSafeKey::new(key),
+            random: SystemRandom::new(),
+        })
+    }
+
+    /// Encrypt `batch`, prefixed with its nonce.
+    pub(crate) fn seal(&self, batch: &[u8]) -> io::Result<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        self.random
+            .fill(&mut nonce)
+            .map_err(|_| io::Error::other("unable to generate a nonce"))?;
+        let mut sealed = Vec::with_capacity(NONCE_LEN + batch.len() + AES_256_GCM.tag_len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(batch);
+        let tag = self
+            .key
+            .seal_in_place_separate_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(AAD),
+                &mut sealed[NONCE_LEN..],
+            )
+            .map_err(|_| io::Error::other("unable to encrypt the batch"))?;
+        sealed.extend_from_slice(tag.as_ref());
+        Ok(sealed)
+    }
+
+    /// Decrypt the batch `sealed` by [`Cipher::seal`]. The batch is `InvalidData` if it was sealed with another key
+    /// or altered.
+    pub(crate) fn open(&self, mut sealed: Vec<u8>) -> io::Result<Vec<u8>> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "unable to decrypt the batch");
+        if sealed.len() < NONCE_LEN {
+            return Err(invalid());
+        }
+        let mut ciphertext = sealed.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&sealed).map_err(|_| invalid())?;
+        let len = self
+            .key
+            .open_in_place(nonce, Aad::from(AAD), &mut ciphertext)
+            .map_err(|_| invalid())?
+            .len();
+        ciphertext.truncate(len);
+        Ok(ciphertext)
+    }
+}
@@ -0,0 +1,552 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Retention of the telemetry buffered on disk, so the buffers of a device stay within their quotas without external
+// cleanup jobs.
+//
+// A buffer is a directory of files, one per batch, named by a sequence number. Opening it scans the directory:
+// batches that were being written as the process exited, and empty batches left by a power loss, are removed, and
+// the quotas are applied to the batches that remain, in case they were lowered since they were written. Beyond its
+// maximum size, the oldest batches of a buffer are discarded, and so are the batches older than its maximum age.
+// Discarded batches are counted as the `otel_lib.exporter.discarded` metric, and the size and number of the batches
+// of each buffer are reported as the `otel_lib.disk_buffer.usage` and `otel_lib.disk_buffer.batches` gauges.
+//
+// Each file starts with a header of the length and the CRC-32 of the batch, and is synced to the disk before it is
+// renamed into place, so a batch truncated or garbled by a power loss is detected when it is read, and discarded as
+// corrupted rather than replayed. The batches of a buffer with a key are encrypted, see `disk_encryption`, and have
+// their own magic number, so a buffer replays the batches written before it had a key, and discards as corrupted the
+// encrypted batches it can't decrypt.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, PoisonError, RwLock, Weak,
+    },
+    time::{Duration, SystemTime},
+};
+
+use log::warn;
+use opentelemetry::{global, metrics::Unit, KeyValue};
+
+use crate::{
+    disk_encryption::Cipher,
+    exporter_metrics::{self, Discard},
+    global_logger,
+};
+
+/// The extension of the files of the batches.
+const BATCH_EXTENSION: &str = "otlp";
+
+/// The extension of a batch being written, renamed once it is complete.
+const PARTIAL_EXTENSION: &str = "partial";
+
+/// The magic number at the start of the files of the batches, followed by the length of the batch and its CRC-32,
+/// little-endian.
+const MAGIC: &[u8; 4] = b"OTB1";
+
+/// The magic number of the files of encrypted batches.
+const ENCRYPTED_MAGIC: &[u8; 4] = b"OTE1";
+
+/// The size of the header of the files of the batches.
+const HEADER_LEN: usize = 16;
+
+/// The usage of the buffers, until they are dropped.
+static BUFFERS: RwLock<Vec<(String, Weak<DiskUsage>)>> = RwLock::new(Vec::new());
+
+/// The size and number of the batches of a buffer, observed by the gauges.
+#[derive(Debug)]
+struct DiskUsage {
+    signal: &'static str,
+    /// The directory of the buffer, which no other buffer may use while it's open.
+    dir: PathBuf,
+    bytes: AtomicU64,
+    batches: AtomicU64,
+}
+
+/// A batch of a buffer.
+#[derive(Debug)]
+struct Batch {
+    sequence: u64,
+    size: u64,
+    written: SystemTime,
+}
+
+/// The quotas of a buffer.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Quotas {
+    /// The maximum size of the batches, in bytes.
+    pub(crate) max_bytes: u64,
+    /// The maximum age of the batches, if they expire.
+    pub(crate) max_age: Option<Duration>,
+}
+
+/// The batches of a target buffered in a directory, within the quotas of the target.
+#[derive(Debug)]
+pub(crate) struct DiskBuffer {
+    signal: &'static str,
+    target: String,
+    dir: PathBuf,
+    quotas: Quotas,
+    /// The batches, oldest first.
+    batches: VecDeque<Batch>,
+    next_sequence: u64,
+    usage: Arc<DiskUsage>,
+    discarded: usize,
+    cipher: Option<Cipher>,
+}
+
+impl DiskBuffer {
+    /// Open the buffer of `signal` of `target` in `dir`, creating the directory if needed, with the batches written
+    /// to it before. The batches are encrypted with `cipher`, if set. The directory can't be the buffer of two
+    /// targets, which would replay and remove each other's batches.
+    pub(crate) fn open(
+        signal: &'static str,
+        target: &str,
+        dir: &Path,
+        quotas: Quotas,
+        cipher: Option<Cipher>,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let usage = Arc::new(DiskUsage {
+            signal,
+            dir: dir.canonicalize()?,
+            bytes: AtomicU64::new(0),
+            batches: AtomicU64::new(0),
+        });
+        let mut buffers = BUFFERS.write().unwrap_or_else(PoisonError::into_inner);
+        buffers.retain(|(_, usage)| usage.strong_count() > 0);
+        if let Some((other, _)) = buffers
+            .iter()
+            .find(|(_, other)| other.upgrade().is_some_and(|other| other.dir == usage.dir))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("already the buffer of {other}"),
+            ));
+        }
+        buffers.push((target.to_owned(), Arc::downgrade(&usage)));
+        drop(buffers);
+
+        let mut batches = Vec::new();
+        let mut removed = 0;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let extension = path.extension().and_then(|extension| extension.to_str());
+            if extension == Some(PARTIAL_EXTENSION) {
+                // A batch that was being written as the process exited.
+                let _ = fs::remove_file(&path);
+                removed += 1;
+                continue;
+            }
+            if extension != Some(BATCH_EXTENSION) {
+                continue;
+            }
+            let Some(sequence) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+            else {
+                continue;
+            };
+            let metadata = fs::metadata(&path)?;
+            if metadata.len() == 0 {
+                // A batch whose content was lost, as the file system persisted the rename but not the data.
+                let _ = fs::remove_file(&path);
+                removed += 1;
+                continue;
+            }
+            batches.push(Batch {
+                sequence,
+                size: metadata.len(),
+                written: metadata.modified().unwrap_or_else(|_| SystemTime::now()),
+            });
+        }
+        batches.sort_unstable_by_key(|batch| batch.sequence);
+
+        let mut buffer = DiskBuffer {
+            signal,
+            target: target.to_owned(),
+            dir: dir.to_owned(),
+            quotas,
+            next_sequence: batches.last().map_or(0, |batch| batch.sequence + 1),
+            batches: batches.into(),
+            usage,
+            discarded: 0,
+            cipher,
+        };
+        buffer.expire();
+        while buffer.bytes() > quotas.max_bytes {
+            buffer.remove_oldest();
+            buffer.discard(Discard::DiskFull);
+        }
+        buffer.update_usage();
+        let discarded = buffer.take_discarded();
+        if removed > 0 || discarded > 0 {
+            let message = format!(
+                "removed {removed} incomplete batches and {discarded} batches beyond the quotas from the buffer of \
+                 {target} in {}",
+                dir.display()
+            );
+            // The buffers of the log targets configured at startup are opened before the logger.
+            if global_logger::is_installed() {
+                warn!("{message}");
+            } else {
+                // log error using eprintln as the logger framework is not setup yet!
+                eprintln!("{message}");
+            }
+        }
+        Ok(buffer)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    fn bytes(&self) -> u64 {
+        self.batches.iter().map(|batch| batch.size).sum()
+    }
+
+    fn path(&self, sequence: u64, extension: &str) -> PathBuf {
+        self.dir.join(format!("{sequence:020}.{extension}"))
+    }
+
+    /// Write `batch` after the other batches, discarding the oldest ones beyond the maximum size.
+    pub(crate) fn write(&mut self, batch: &[u8]) {
+        self.expire();
+        let sealed = match &self.cipher {
+            Some(cipher) => match cipher.seal(batch) {
+                Ok(sealed) => Some(sealed),
+                Err(e) => {
+                    warn!(
+                        "unable to buffer a failed export to {} due to {e:?}",
+                        self.target
+                    );
+                    self.discard(Discard::DiskFull);
+                    return;
+                }
+            },
+            None => None,
+        };
+        let (magic, batch) = match &sealed {
+            Some(sealed) => (*ENCRYPTED_MAGIC, sealed.as_slice()),
+            None => (*MAGIC, batch),
+        };
+        let size = (HEADER_LEN + batch.len()) as u64;
+        if size > self.quotas.max_bytes {
+            self.discard(Discard::DiskFull);
+            return;
+        }
+        while self.bytes() + size > self.quotas.max_bytes {
+            self.remove_oldest();
+            self.discard(Discard::DiskFull);
+        }
+        let sequence = self.next_sequence;
+        let partial = self.path(sequence, PARTIAL_EXTENSION);
+        if let Err(e) = write_synced(&partial, magic, batch)
+            .and_then(|()| fs::rename(&partial, self.path(sequence, BATCH_EXTENSION)))
+            .and_then(|()| File::open(&self.dir)?.sync_all())
+        {
+            warn!(
+                "unable to buffer a failed export to {} in {} due to {e:?}",
+                self.target,
+                self.dir.display()
+            );
+            let _ = fs::remove_file(&partial);
+            self.discard(Discard::DiskFull);
+            return;
+        }
+        self.next_sequence += 1;
+        self.batches.push_back(Batch {
+            sequence,
+            size,
+            written: SystemTime::now(),
+        });
+        self.update_usage();
+    }
+
+    /// Read the oldest batch that hasn't expired, if any. The batch is `InvalidData` if it doesn't match its checksum,
+    /// or is encrypted and can't be decrypted.
+    pub(crate) fn oldest(&mut self) -> Option<io::Result<Vec<u8>>> {
+        self.expire();
+        let batch = self.batches.front()?;
+        Some(
+            fs::read(self.path(batch.sequence, BATCH_EXTENSION))
+                .and_then(verify)
+                .and_then(|(encrypted, batch)| match (encrypted, &self.cipher) {
+                    (false, _) => Ok(batch),
+                    (true, Some(cipher)) => cipher.open(batch),
+                    (true, None) => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "encrypted batch without key",
+                    )),
+                }),
+        )
+    }
+
+    /// Discard the oldest batch, as it is corrupted. Unlike the batches discarded by the quotas, it's not counted by
+    /// [`DiskBuffer::take_discarded`], as the reader reports it.
+    pub(crate) fn discard_oldest(&mut self) {
+        self.remove_oldest();
+        exporter_metrics::record_discarded(self.signal, &self.target, Discard::Corrupted);
+    }
+
+    /// Remove the oldest batch, once it is exported or can't be read.
+    pub(crate) fn remove_oldest(&mut self) {
+        if let Some(batch) = self.batches.pop_front() {
+            let _ = fs::remove_file(self.path(batch.sequence, BATCH_EXTENSION));
+            self.update_usage();
+        }
+    }
+
+    /// Discard the batches older than the maximum age.
+    fn expire(&mut self) {
+        let Some(max_age) = self.quotas.max_age else {
+            return;
+        };
+        while self.batches.front().is_some_and(|batch| {
+            batch
+                .written
+                .elapsed()
+                .is_ok_and(|elapsed| elapsed > max_age)
+        }) {
+            self.remove_oldest();
+            self.discard(Discard::Expired);
+        }
+    }
+
+    fn discard(&mut self, discard: Discard) {
+        self.discarded += 1;
+        exporter_metrics::record_discarded(self.signal, &self.target, discard);
+    }
+
+    /// The number of batches discarded since the last call.
+    pub(crate) fn take_discarded(&mut self) -> usize {
+        std::mem::take(&mut self.discarded)
+    }
+
+    fn update_usage(&self) {
+        self.usage.bytes.store(self.bytes(), Ordering::Relaxed);
+        self.usage
+            .batches
+            .store(self.batches.len() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Run `f`, which reads or writes the files of a buffer, on a blocking thread of the runtime, so that syncing the
+/// files doesn't stall the other tasks of its worker. Without a runtime, e.g. as a simple processor exports on the
+/// emitting thread, `f` runs in place.
+pub(crate) async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> io::Result<T> {
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => runtime.spawn_blocking(f).await.map_err(io::Error::other),
+        Err(_) => Ok(f()),
+    }
+}
+
+/// Write `batch` with its header starting with `magic` to `path`, and sync it to the disk.
+fn write_synced(path: &Path, magic: [u8; 4], batch: &[u8]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    let mut header = [0; HEADER_LEN];
+    header[..4].copy_from_slice(&magic);
+    header[4..12].copy_from_slice(&(batch.len() as u64).to_le_bytes());
+    header[12..].copy_from_slice(&crc32fast::hash(batch).to_le_bytes());
+    file.write_all(&header)?;
+    file.write_all(batch)?;
+    file.sync_all()
+}
+
+/// The batch of the file `content`, if it matches the length and checksum of its header, and whether it's encrypted.
+fn verify(mut content: Vec<u8>) -> io::Result<(bool, Vec<u8>)> {
+    let corrupted = |reason: &str| Err(io::Error::new(io::ErrorKind::InvalidData, reason));
+    if content.len() < HEADER_LEN {
+        return corrupted("missing header");
+    }
+    let encrypted = match &content[..4] {
+        magic if magic == MAGIC => false,
+        magic if magic == ENCRYPTED_MAGIC => true,
+        _ => return corrupted("missing header"),
+    };
+    let length = u64::from_le_bytes(content[4..12].try_into().unwrap_or_default());
+    let checksum = u32::from_le_bytes(content[12..HEADER_LEN].try_into().unwrap_or_default());
+    let batch = content.split_off(HEADER_LEN);
+    if batch.len() as u64 != length {
+        return corrupted("truncated batch");
+    }
+    if crc32fast::hash(&batch) != checksum {
+        return corrupted("checksum mismatch");
+    }
+    Ok((encrypted, batch))
+}
+
+/// Create the gauges of the usage of the buffers with the global meter provider, once it is set.
+pub(crate) fn register_metrics() {
+    let meter = global::meter("otel-lib");
+    let observe = |value: fn(&DiskUsage) -> u64| {
+        move |observer: &dyn opentelemetry::metrics::AsyncInstrument<u64>| {
+            let buffers = BUFFERS.read().unwrap_or_else(PoisonError::into_inner);
+            for (target, usage) in buffers.iter() {
+                if let Some(usage) = usage.upgrade() {
+                    observer.observe(
+                        value(&usage),
+                        &[
+                            KeyValue::new("signal", usage.signal),
+                            KeyValue::new("target", target.clone()),
+                        ],
+                    );
+                }
+            }
+        }
+    };
+    meter
+        .u64_observable_gauge("otel_lib.disk_buffer.usage")
+        .with_description("Size of the failed exports buffered on disk, by target")
+        .with_unit(Unit::new("By"))
+        .with_callback(observe(|usage| usage.bytes.load(Ordering::Relaxed)))
+        .init();
+    meter
+        .u64_observable_gauge("otel_lib.disk_buffer.batches")
+        .with_description("Number of failed exports buffered on disk, by target")
+        .with_unit(Unit::new("{batch}"))
+        .with_callback(observe(|usage| usage.batches.load(Ordering::Relaxed)))
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory for a buffer, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("otel-lib-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const QUOTAS: Quotas = Quotas {
+        max_bytes: 1024,
+        max_age: None,
+    };
+
+    fn open(dir: &TempDir, quotas: Quotas) -> DiskBuffer {
+        DiskBuffer::open("logs", "target", &dir.0, quotas, None).unwrap()
+    }
+
+    #[test]
+    fn batches_are_read_back_in_order_after_reopening() {
+        let dir = TempDir::new("reopen");
+        let mut buffer = open(&dir, QUOTAS);
+        buffer.write(b"first");
+        buffer.write(b"second");
+        drop(buffer);
+
+        let mut buffer = open(&dir, QUOTAS);
+        assert_eq!(buffer.oldest().unwrap().unwrap(), b"first");
+        buffer.remove_oldest();
+        assert_eq!(buffer.oldest().unwrap().unwrap(), b"second");
+        buffer.remove_oldest();
+        assert!(buffer.oldest().is_none());
+    }
+
+    #[test]
+    fn truncated_or_garbled_batches_are_invalid() {
+        let mut content = Vec::new();
+        content.extend_from_slice(MAGIC);
+        content.extend_from_slice(&5_u64.to_le_bytes());
+        content.extend_from_slice(&crc32fast::hash(b"batch").to_le_bytes());
+        content.extend_from_slice(b"batch");
+        assert_eq!(verify(content.clone()).unwrap(), (false, b"batch".to_vec()));
+
+        let invalid = |content: Vec<u8>| verify(content).unwrap_err().kind();
+        assert_eq!(
+            invalid(content[..content.len() - 1].to_vec()),
+            io::ErrorKind::InvalidData
+        );
+        let mut garbled = content.clone();
+        *garbled.last_mut().unwrap() ^= 1;
+        assert_eq!(invalid(garbled), io::ErrorKind::InvalidData);
+        let mut unknown = content.clone();
+        unknown[0] = b'X';
+        assert_eq!(invalid(unknown), io::ErrorKind::InvalidData);
+        assert_eq!(invalid(content[..8].to_vec()), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn oldest_batches_are_evicted_beyond_the_maximum_size() {
+        let dir = TempDir::new("quota");
+        // Room for two batches of 32 bytes with their headers.
+        let quotas = Quotas {
+            max_bytes: 2 * (HEADER_LEN as u64 + 32),
+            max_age: None,
+        };
+        let mut buffer = open(&dir, quotas);
+        for byte in [1, 2, 3] {
+            buffer.write(&[byte; 32]);
+        }
+        assert_eq!(buffer.batches.len(), 2);
+        assert_eq!(buffer.take_discarded(), 1);
+        assert_eq!(buffer.oldest().unwrap().unwrap(), [2; 32]);
+
+        // A batch larger than the quota is discarded rather than evicting the others.
+        buffer.write(&[4; 128]);
+        assert_eq!(buffer.batches.len(), 2);
+        assert_eq!(buffer.take_discarded(), 1);
+        drop(buffer);
+
+        // Lowered quotas apply to the batches written before.
+        let mut buffer = open(
+            &dir,
+            Quotas {
+                max_bytes: HEADER_LEN as u64 + 32,
+                max_age: None,
+            },
+        );
+        assert_eq!(buffer.batches.len(), 1);
+        assert_eq!(buffer.oldest().unwrap().unwrap(), [3; 32]);
+    }
+
+    #[test]
+    fn partial_and_empty_batches_are_removed_on_open() {
+        let dir = TempDir::new("partial");
+        let mut buffer = open(&dir, QUOTAS);
+        buffer.write(b"complete");
+        drop(buffer);
+        fs::write(
+            dir.0.join(format!("{:020}.{PARTIAL_EXTENSION}", 1)),
+            b"half",
+        )
+        .unwrap();
+        fs::write(dir.0.join(format!("{:020}.{BATCH_EXTENSION}", 2)), b"").unwrap();
+
+        let mut buffer = open(&dir, QUOTAS);
+        assert_eq!(buffer.batches.len(), 1);
+        assert_eq!(buffer.oldest().unwrap().unwrap(), b"complete");
+        assert_eq!(fs::read_dir(&dir.0).unwrap().count(), 1);
+        // The sequence continues after the batches that remain.
+        assert_eq!(buffer.next_sequence, 1);
+    }
+
+    #[test]
+    fn a_directory_is_the_buffer_of_one_target() {
+        let dir = TempDir::new("shared");
+        let buffer = open(&dir, QUOTAS);
+        let e = DiskBuffer::open("logs", "other", &dir.0, QUOTAS, None).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        drop(buffer);
+        assert!(DiskBuffer::open("logs", "other", &dir.0, QUOTAS, None).is_ok());
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Structured events, for state changes and audit trails that shouldn't be parsed out of free text.
+//!
+//! Events are emitted as OTLP log records with the `event.name` attribute set, per the Events semantic convention,
+//! and are batched and exported through the same processors as text logs. They are emitted with the `Info`
+//! severity, so log export targets whose filter has a stricter severity don't receive them.
+//!
+//! With `span_events_as_logs` set in the config, the events of spans are emitted the same way once the spans end,
+//! for backends that store logs but not traces. The records keep the events' attributes and timestamps and carry
+//! the trace context of their span. Add a [`SpanEventsProcessor`] to the tracer provider the spans are created with.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        PoisonError, RwLock,
+    },
+    time::SystemTime,
+};
+
+use opentelemetry::{
+    logs::{AnyValue, LogRecordBuilder, Logger as _, Severity},
+    trace::{SpanContext, TraceResult},
+    Context, Key, KeyValue,
+};
+use opentelemetry_sdk::{
+    export::trace::SpanData,
+    logs::Logger,
+    trace::{Span, SpanProcessor},
+};
+
+use crate::baggage;
+
+const EVENT_NAME_KEY: &str = "event.name";
+
+/// The logger events are emitted to, set once the log pipeline is initialized.
+static EVENT_LOGGER: RwLock<Option<Logger>> = RwLock::new(None);
+
+/// Whether span events are emitted as log records.
+static SPAN_EVENTS_AS_LOGS: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn set_logger(logger: Logger, span_events_as_logs: bool) {
+    *EVENT_LOGGER.write().unwrap_or_else(PoisonError::into_inner) = Some(logger);
+    SPAN_EVENTS_AS_LOGS.store(span_events_as_logs, Ordering::Relaxed);
+}
+
+/// Emit an event called `name` with `attributes`. Events emitted before [`Otel`](crate::Otel) is initialized are
+/// dropped.
+pub fn emit_event(name: &str, attributes: &[KeyValue]) {
+    emit(name, attributes, SystemTime::now(), None);
+}
+
+fn emit(
+    name: &str,
+    attributes: &[KeyValue],
+    timestamp: SystemTime,
+    span_context: Option<&SpanContext>,
+) {
+    let event_logger = EVENT_LOGGER.read().unwrap_or_else(PoisonError::into_inner);
+    let Some(logger) = event_logger.as_ref() else {
+        return;
+    };
+
+    let mut record_attributes: Vec<(Key, AnyValue)> = Vec::with_capacity(attributes.len() + 1);
+    record_attributes.push((Key::from_static_str(EVENT_NAME_KEY), name.to_owned().into()));
+    for attribute in attributes {
+        record_attributes.push((attribute.key.clone(), attribute.value.clone().into()));
+    }
+    record_attributes.extend(baggage::log_attributes());
+    let mut record = LogRecordBuilder::new()
+        .with_severity_number(Severity::Info)
+        .with_severity_text(Severity::Info.name())
+        .with_timestamp(timestamp)
+        .with_attributes(record_attributes);
+    if let Some(span_context) = span_context {
+        record = record.with_span_context(span_context);
+    }
+    logger.emit(record.build());
+}
+
+/// A span processor that emits the events of ended spans as log records, if `span_events_as_logs` is set in the
+/// config.
+#[derive(Debug, Default)]
+pub struct SpanEventsProcessor {}
+
+impl SpanEventsProcessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SpanProcessor for SpanEventsProcessor {
+    fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        if !SPAN_EVENTS_AS_LOGS.load(Ordering::Relaxed) {
+            return;
+        }
+        for event in span.events.iter() {
+            emit(
+                &event.name,
+                &event.attributes,
+                event.timestamp,
+                Some(&span.span_context),
+            );
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        Ok(())
+    }
+}
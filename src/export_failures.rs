@@ -0,0 +1,154 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Summaries of sustained export failures.
+//
+// The SDK reports every failed export to the global error handler, which writes it to stderr, so a collector that
+// is down for an hour floods the logs with one error per target and interval. Instead, the first failure of a target
+// is logged as a warning, followed by a summary of the attempts every summary interval while the target keeps
+// failing, and a note once it recovers. The failures are then returned to the SDK as `SummarizedError`s, which the
+// error handler installed by `Otel` ignores.
+
+use std::{
+    error::Error,
+    fmt,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use humantime::format_duration;
+use log::{info, warn};
+use opentelemetry::{
+    global, logs::LogError, metrics::MetricsError, trace::TraceError, ExportError,
+};
+
+/// The exporter name of the errors that were already summarized.
+const SUMMARIZED_EXPORTER: &str = "otel-lib";
+
+/// An export failure already reported by the summaries of its target.
+#[derive(Debug)]
+pub(crate) struct SummarizedError(String);
+
+impl SummarizedError {
+    pub(crate) fn new(e: &impl fmt::Debug) -> Self {
+        SummarizedError(format!("{e:?}"))
+    }
+}
+
+impl fmt::Display for SummarizedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for SummarizedError {}
+
+impl ExportError for SummarizedError {
+    fn exporter_name(&self) -> &'static str {
+        SUMMARIZED_EXPORTER
+    }
+}
+
+struct FailureState {
+    /// When the current run of failures started, if the target is failing.
+    failing_since: Option<Instant>,
+    attempts: u64,
+    last_summary: Instant,
+}
+
+/// The export failures of a target, summarized every `interval` while they last.
+pub(crate) struct ExportFailures {
+    signal: &'static str,
+    target: String,
+    interval: Duration,
+    state: Mutex<FailureState>,
+}
+
+impl ExportFailures {
+    /// Summarize the failures of the `signal` exports to `target` every `interval_secs`, or `None` if summaries are
+    /// disabled with an interval of 0.
+    pub(crate) fn new(signal: &'static str, target: String, interval_secs: u64) -> Option<Self> {
+        (interval_secs > 0).then(|| ExportFailures {
+            signal,
+            target,
+            interval: Duration::from_secs(interval_secs),
+            state: Mutex::new(FailureState {
+                failing_since: None,
+                attempts: 0,
+                last_summary: Instant::now(),
+            }),
+        })
+    }
+
+    /// Record the outcome of an export. Its error should then be returned as a [`SummarizedError`].
+    pub(crate) fn report<E: fmt::Debug>(&self, result: &Result<(), E>) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let (signal, target) = (self.signal, &self.target);
+        let e = match result {
+            Ok(()) => {
+                if let Some(failing_since) = state.failing_since.take() {
+                    info!(
+                        "{signal} export to {target} recovered after {} ({} failed attempts)",
+                        format_duration(whole_secs(failing_since.elapsed())),
+                        state.attempts
+                    );
+                    state.attempts = 0;
+                }
+                return;
+            }
+            Err(e) => e,
+        };
+        state.attempts += 1;
+        match state.failing_since {
+            None => {
+                state.failing_since = Some(Instant::now());
+                state.last_summary = Instant::now();
+                warn!(
+                    "{signal} export to {target} failed due to {e:?}, further failures are summarized every {}",
+                    format_duration(self.interval)
+                );
+            }
+            Some(failing_since) if state.last_summary.elapsed() >= self.interval => {
+                state.last_summary = Instant::now();
+                warn!(
+                    "{signal} export to {target} failing for {} ({} attempts), last due to {e:?}",
+                    format_duration(whole_secs(failing_since.elapsed())),
+                    state.attempts
+                );
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+impl fmt::Debug for ExportFailures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExportFailures")
+            .field("signal", &self.signal)
+            .field("target", &self.target)
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+fn whole_secs(duration: Duration) -> Duration {
+    Duration::from_secs(duration.as_secs())
+}
+
+/// Install a global error handler that ignores the export failures already summarized, and writes other errors to
+/// stderr like the default handler of the SDK.
+pub(crate) fn install_error_handler() {
+    let result = global::set_error_handler(|error| match error {
+        global::Error::Metric(MetricsError::ExportErr(e))
+        | global::Error::Log(LogError::ExportFailed(e))
+        | global::Error::Trace(TraceError::ExportFailed(e))
+            if e.exporter_name() == SUMMARIZED_EXPORTER => {}
+        global::Error::Metric(e) => eprintln!("OpenTelemetry metrics error occurred. {e}"),
+        global::Error::Log(e) => eprintln!("OpenTelemetry log error occurred. {e}"),
+        global::Error::Trace(e) => eprintln!("OpenTelemetry trace error occurred. {e}"),
+        e => eprintln!("OpenTelemetry error occurred. {e}"),
+    });
+    if let Err(e) = result {
+        eprintln!("unable to install the otel error handler: {e:?}");
+    }
+}
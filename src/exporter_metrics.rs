@@ -0,0 +1,226 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Self-diagnostics of the export pipelines, so a target that is down is visible on the prometheus end point and the
+// other targets rather than only in the logs. Every export to a target is counted by outcome and timed, retries,
+// records dropped as the queue of a target is full and failed exports discarded rather than retried are counted, all
+// with the signal and the label of the target.
+//
+// Exporters are built before the meter provider is set, so the instruments are created once it is, and exports
+// before that are not recorded.
+
+use std::{
+    fmt,
+    sync::{PoisonError, RwLock},
+    time::Instant,
+};
+
+use opentelemetry::{
+    global,
+    metrics::{Counter, Histogram, Unit},
+    KeyValue,
+};
+
+use crate::target_health;
+
+const SIGNAL_KEY: &str = "signal";
+const TARGET_KEY: &str = "target";
+const OUTCOME_KEY: &str = "outcome";
+const REASON_KEY: &str = "reason";
+const TENANT_KEY: &str = "tenant";
+
+/// The instruments of the export pipelines, set once the meter provider is set.
+static INSTRUMENTS: RwLock<Option<Instruments>> = RwLock::new(None);
+
+struct Instruments {
+    exports: Counter<u64>,
+    duration: Histogram<f64>,
+    retries: Counter<u64>,
+    dropped: Counter<u64>,
+    discarded: Counter<u64>,
+    tenant_records: Counter<u64>,
+    tenant_bytes: Counter<u64>,
+}
+
+/// Why an export to a target was retried.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Retry {
+    /// the exporter of the target failed to build, and building it is retried.
+    Build,
+    /// the target doesn't speak OTLP/gRPC, and the export is retried over OTLP/HTTP.
+    ProtocolFallback,
+    /// the export failed, and is sent again after a backoff.
+    Export,
+}
+
+impl fmt::Display for Retry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Retry::Build => "build",
+            Retry::ProtocolFallback => "protocol_fallback",
+            Retry::Export => "export",
+        })
+    }
+}
+
+/// Why a failed export was discarded rather than retried.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Discard {
+    /// the buffer of failed exports of the target is full, and the export is the oldest in it.
+    BufferFull,
+    /// the export was sent the maximum number of times.
+    MaxAttempts,
+    /// the persistence directory of the target is full, and the export is the oldest in it, or it couldn't be
+    /// written.
+    DiskFull,
+    /// the export was buffered on disk for longer than the maximum age of the buffer.
+    Expired,
+    /// the export was buffered on disk, and doesn't match its checksum when read, e.g. after a power loss.
+    Corrupted,
+}
+
+impl fmt::Display for Discard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Discard::BufferFull => "buffer_full",
+            Discard::MaxAttempts => "max_attempts",
+            Discard::DiskFull => "disk_full",
+            Discard::Expired => "expired",
+            Discard::Corrupted => "corrupted",
+        })
+    }
+}
+
+/// Create the instruments with the global meter provider, once it is set.
+pub(crate) fn register_metrics() {
+    let meter = global::meter("otel-lib");
+    let instruments = Instruments {
+        exports: meter
+            .u64_counter("otel_lib.exporter.exports")
+            .with_description("Number of exports to the export targets, by outcome")
+            .with_unit(Unit::new("{export}"))
+            .init(),
+        duration: meter
+            .f64_histogram("otel_lib.exporter.duration")
+            .with_description("Time taken by the exports to the export targets")
+            .with_unit(Unit::new("s"))
+            .init(),
+        retries: meter
+            .u64_counter("otel_lib.exporter.retries")
+            .with_description("Number of retries of the export targets, by reason")
+            .with_unit(Unit::new("{retry}"))
+            .init(),
+        dropped: meter
+            .u64_counter("otel_lib.exporter.dropped")
+            .with_description(
+                "Number of log records dropped as the queue of their export target is full",
+            )
+            .with_unit(Unit::new("{record}"))
+            .init(),
+        discarded: meter
+            .u64_counter("otel_lib.exporter.discarded")
+            .with_description(
+                "Number of failed exports to the export targets discarded rather than retried, by reason",
+            )
+            .with_unit(Unit::new("{export}"))
+            .init(),
+        tenant_records: meter
+            .u64_counter("otel_lib.exporter.tenant.records")
+            .with_description(
+                "Number of log records and data points exported to the export targets, by tenant",
+            )
+            .with_unit(Unit::new("{record}"))
+            .init(),
+        tenant_bytes: meter
+            .u64_counter("otel_lib.exporter.tenant.size")
+            .with_description(
+                "Serialized size of the log records and data points exported to the export targets, by tenant",
+            )
+            .with_unit(Unit::new("By"))
+            .init(),
+    };
+    *INSTRUMENTS.write().unwrap_or_else(PoisonError::into_inner) = Some(instruments);
+}
+
+fn attributes(signal: &'static str, target: &str) -> [KeyValue; 2] {
+    [
+        KeyValue::new(SIGNAL_KEY, signal),
+        KeyValue::new(TARGET_KEY, target.to_owned()),
+    ]
+}
+
+/// Record an export of `signal` to `target` started at `start`, with its `result`.
+pub(crate) fn record_export<E>(
+    signal: &'static str,
+    target: &str,
+    start: Instant,
+    result: &Result<(), E>,
+) {
+    target_health::record(signal, target, result.is_ok());
+    let instruments = INSTRUMENTS.read().unwrap_or_else(PoisonError::into_inner);
+    let Some(instruments) = instruments.as_ref() else {
+        return;
+    };
+    let [signal, target] = attributes(signal, target);
+    let outcome = if result.is_ok() { "success" } else { "failure" };
+    instruments.duration.record(
+        start.elapsed().as_secs_f64(),
+        &[signal.clone(), target.clone()],
+    );
+    instruments
+        .exports
+        .add(1, &[signal, target, KeyValue::new(OUTCOME_KEY, outcome)]);
+}
+
+/// Record a retry of `target` of `signal`.
+pub(crate) fn record_retry(signal: &'static str, target: &str, retry: Retry) {
+    let instruments = INSTRUMENTS.read().unwrap_or_else(PoisonError::into_inner);
+    if let Some(instruments) = instruments.as_ref() {
+        let [signal, target] = attributes(signal, target);
+        instruments.retries.add(
+            1,
+            &[signal, target, KeyValue::new(REASON_KEY, retry.to_string())],
+        );
+    }
+}
+
+/// Record a log record dropped as the queue of `target` is full.
+pub(crate) fn record_dropped(target: &str) {
+    let instruments = INSTRUMENTS.read().unwrap_or_else(PoisonError::into_inner);
+    if let Some(instruments) = instruments.as_ref() {
+        instruments.dropped.add(1, &attributes("logs", target));
+    }
+}
+
+/// Record a failed export of `signal` to `target` that was discarded rather than retried.
+pub(crate) fn record_discarded(signal: &'static str, target: &str, discard: Discard) {
+    let instruments = INSTRUMENTS.read().unwrap_or_else(PoisonError::into_inner);
+    if let Some(instruments) = instruments.as_ref() {
+        let [signal, target] = attributes(signal, target);
+        instruments.discarded.add(
+            1,
+            &[
+                signal,
+                target,
+                KeyValue::new(REASON_KEY, discard.to_string()),
+            ],
+        );
+    }
+}
+
+/// Record the `records` of `tenant`, of `bytes` in total, exported to `target` of `signal`.
+pub(crate) fn record_tenant_export(
+    signal: &'static str,
+    target: &str,
+    tenant: &str,
+    records: u64,
+    bytes: u64,
+) {
+    let instruments = INSTRUMENTS.read().unwrap_or_else(PoisonError::into_inner);
+    if let Some(instruments) = instruments.as_ref() {
+        let [signal, target] = attributes(signal, target);
+        let attributes = [signal, target, KeyValue::new(TENANT_KEY, tenant.to_owned())];
+        instruments.tenant_records.add(records, &attributes);
+        instruments.tenant_bytes.add(bytes, &attributes);
+    }
+}
@@ -0,0 +1,243 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// The logger installed for the `log` crate.
+//
+// The `log` crate only allows a logger to be installed once, so the logger installed here delegates to whichever
+// stage of initialization the process is in. Before the configuration is available, records are written to stderr
+// and the first of them are buffered. Once the OTLP pipeline is initialized, the logger is upgraded in place and
+// the buffered records are replayed into the pipeline.
+
+use std::{
+    sync::{Mutex, PoisonError, RwLock},
+    time::SystemTime,
+};
+
+use env_filter::Filter;
+use log::{
+    kv::{self, Key, Value, VisitSource},
+    warn, Level, Log, Metadata, Record, SetLoggerError,
+};
+use opentelemetry_sdk::logs::{Logger, LoggerProvider};
+
+use crate::{
+    loggers::{host_name, OtelLogBridge},
+    syslog_writer::{self, SyslogOptions},
+};
+
+/// The number of early records buffered for replay by default.
+pub(crate) const EARLY_RECORDS_CAPACITY: usize = 1024;
+
+/// A record logged before the OTLP pipeline was initialized.
+struct EarlyRecord {
+    level: Level,
+    target: String,
+    message: String,
+    /// The key-values of the record, formatted, as their types can't be kept.
+    key_values: Vec<(String, String)>,
+    timestamp: SystemTime,
+}
+
+/// Collects the key-values of a record, formatted.
+struct FormattedKeyValues(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for FormattedKeyValues {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+}
+
+enum LoggerState {
+    Uninitialized,
+    Early {
+        filter: Filter,
+        service_name: String,
+        host_name: String,
+        records: Mutex<EarlyRecords>,
+    },
+    Ready {
+        filter: Filter,
+        bridge: OtelLogBridge<LoggerProvider, Logger>,
+    },
+}
+
+/// The first records logged before the OTLP pipeline was initialized.
+#[derive(Default)]
+struct EarlyRecords {
+    records: Vec<EarlyRecord>,
+    capacity: usize,
+    dropped: usize,
+}
+
+struct GlobalLogger {
+    state: RwLock<LoggerState>,
+}
+
+static LOGGER: GlobalLogger = GlobalLogger {
+    state: RwLock::new(LoggerState::Uninitialized),
+};
+
+impl Log for GlobalLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        if metadata.level() > log::STATIC_MAX_LEVEL {
+            return false;
+        }
+        match &*self.state.read().unwrap_or_else(PoisonError::into_inner) {
+            LoggerState::Uninitialized => false,
+            LoggerState::Early { filter, .. } | LoggerState::Ready { filter, .. } => {
+                filter.enabled(metadata)
+            }
+        }
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        // Compiled out with the `max-level-*` features for the levels they strip.
+        if record.level() > log::STATIC_MAX_LEVEL {
+            return;
+        }
+        match &*self.state.read().unwrap_or_else(PoisonError::into_inner) {
+            LoggerState::Uninitialized => {}
+            LoggerState::Early {
+                filter,
+                service_name,
+                host_name,
+                records,
+            } => {
+                if !filter.matches(record) {
+                    return;
+                }
+                let timestamp = SystemTime::now();
+                syslog_writer::write_syslog_format(
+                    record,
+                    &SyslogOptions {
+                        service_name,
+                        host_name,
+                        timestamp,
+                        trace_id: syslog_writer::current_trace_id(),
+                    },
+                );
+                let mut records = records.lock().unwrap_or_else(PoisonError::into_inner);
+                if records.records.len() == records.capacity {
+                    records.dropped += 1;
+                    return;
+                }
+                let mut key_values = FormattedKeyValues(Vec::new());
+                // Collecting the key-values can't fail.
+                let _ = record.key_values().visit(&mut key_values);
+                records.records.push(EarlyRecord {
+                    level: record.level(),
+                    target: record.target().to_owned(),
+                    message: record.args().to_string(),
+                    key_values: key_values.0,
+                    timestamp,
+                });
+            }
+            LoggerState::Ready { filter, bridge } => {
+                if filter.matches(record) {
+                    bridge.log(record);
+                } else {
+                    bridge.filtered();
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install a logger that writes records matching `level` to stderr until the OTLP pipeline is initialized, buffering
+/// the first `capacity` of them for replay.
+pub(crate) fn preinit_stderr(level: &str, capacity: usize) -> Result<(), SetLoggerError> {
+    let filter = env_filter::Builder::new().parse(level).build();
+    let level_filter = filter.filter();
+    let mut state = LOGGER.state.write().unwrap_or_else(PoisonError::into_inner);
+    log::set_logger(&LOGGER)?;
+    *state = LoggerState::Early {
+        filter,
+        service_name: std::env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.file_name()?.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "App".to_owned()),
+        host_name: host_name(),
+        records: Mutex::new(EarlyRecords {
+            capacity,
+            ..EarlyRecords::default()
+        }),
+    };
+    log::set_max_level(level_filter.min(log::STATIC_MAX_LEVEL));
+    Ok(())
+}
+
+/// Install `bridge` as the logger, upgrading the early stderr logger in place if there is one. Records buffered by
+/// the early logger are replayed into the OTLP pipeline, they were already written to stderr. If the buffer
+/// overflowed, the number of records that were not replayed is reported once the logger is upgraded.
+pub(crate) fn install(
+    filter: Filter,
+    bridge: OtelLogBridge<LoggerProvider, Logger>,
+) -> Result<(), SetLoggerError> {
+    let level_filter = filter.filter();
+    let mut state = LOGGER.state.write().unwrap_or_else(PoisonError::into_inner);
+    let mut dropped = 0;
+    match &mut *state {
+        LoggerState::Early { records, .. } => {
+            let records = std::mem::take(records.get_mut().unwrap_or_else(PoisonError::into_inner));
+            dropped = records.dropped;
+            for record in records.records {
+                let key_values: Vec<(&str, &str)> = record
+                    .key_values
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str()))
+                    .collect();
+                bridge.replay(
+                    &Record::builder()
+                        .level(record.level)
+                        .target(&record.target)
+                        .args(format_args!("{}", record.message))
+                        .key_values(&key_values.as_slice())
+                        .build(),
+                    record.timestamp,
+                );
+            }
+        }
+        LoggerState::Uninitialized | LoggerState::Ready { .. } => log::set_logger(&LOGGER)?,
+    }
+    *state = LoggerState::Ready { filter, bridge };
+    drop(state);
+    log::set_max_level(level_filter.min(log::STATIC_MAX_LEVEL));
+
+    if dropped > 0 {
+        warn!("{dropped} records logged before initialization were not replayed as the early record buffer was full");
+    }
+    Ok(())
+}
+
+/// Whether a logger is installed, the early stderr logger or the OTLP bridge. Until then, records of the `log` crate
+/// are lost, so errors are reported on stderr instead.
+pub(crate) fn is_installed() -> bool {
+    !matches!(
+        *LOGGER.state.read().unwrap_or_else(PoisonError::into_inner),
+        LoggerState::Uninitialized
+    )
+}
+
+/// Replace the filter of the installed logger, whether it is the early stderr logger or the OTLP bridge. Returns
+/// false if no logger is installed.
+pub(crate) fn set_filter(filter: Filter) -> bool {
+    let level_filter = filter.filter();
+    let mut state = LOGGER.state.write().unwrap_or_else(PoisonError::into_inner);
+    match &mut *state {
+        LoggerState::Uninitialized => return false,
+        LoggerState::Early {
+            filter: current, ..
+        }
+        | LoggerState::Ready {
+            filter: current, ..
+        } => {
+            *current = filter;
+        }
+    }
+    drop(state);
+    log::set_max_level(level_filter.min(log::STATIC_MAX_LEVEL));
+    true
+}
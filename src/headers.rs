@@ -0,0 +1,183 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Headers added to the requests to an export target, for backends that require an API key or a bearer token.
+//
+// OTLP/gRPC requests carry them as metadata and OTLP/HTTP requests as headers, ahead of signing. The token of a token
+// file is read again at most every reload interval, as requests are sent, so tokens rotated by another process are
+// picked up without a restart.
+
+use std::{
+    fs,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use hyper_0_14::header::{HeaderName, HeaderValue};
+use log::warn;
+use opentelemetry_http::{Bytes, HttpClient, HttpError, Request, Response};
+use tonic::{
+    metadata::{AsciiMetadataKey, AsciiMetadataValue, MetadataMap},
+    Status,
+};
+
+use crate::config::AuthTokenFile;
+
+struct TokenState {
+    /// The value of the header, if the token was read.
+    value: Option<String>,
+    last_read: Option<Instant>,
+}
+
+/// A token read from a file.
+struct Token {
+    file: AuthTokenFile,
+    state: Mutex<TokenState>,
+}
+
+impl Token {
+    /// The header and value of the token, read again from the file if due.
+    fn header(&self, target: &str) -> Option<(String, String)> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let due = state.last_read.is_none_or(|last_read| {
+            last_read.elapsed() >= Duration::from_secs(self.file.reload_interval_secs)
+        });
+        if due {
+            state.last_read = Some(Instant::now());
+            match fs::read_to_string(&self.file.path) {
+                Ok(token) => {
+                    let token = token.trim();
+                    state.value = Some(match &self.file.scheme {
+                        Some(scheme) => format!("{scheme} {token}"),
+                        None => token.to_owned(),
+                    });
+                }
+                Err(e) => warn!(
+                    "unable to read the auth token of {target} from {}: {e}",
+                    self.file.path.display()
+                ),
+            }
+        }
+        let value = state.value.clone()?;
+        Some((self.file.header.clone(), value))
+    }
+}
+
+/// The headers added to the requests to an export target.
+pub(crate) struct ExportHeaders {
+    target: String,
+    headers: Vec<(String, String)>,
+    token: Option<Token>,
+}
+
+impl ExportHeaders {
+    /// The `headers` and the token of `auth_token_file` of `target`, or `None` if there are neither.
+    pub(crate) fn new(
+        target: &str,
+        headers: &[(String, String)],
+        auth_token_file: Option<&AuthTokenFile>,
+    ) -> Option<Arc<Self>> {
+        if headers.is_empty() && auth_token_file.is_none() {
+            return None;
+        }
+        Some(Arc::new(ExportHeaders {
+            target: target.to_owned(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_ascii_lowercase(), value.clone()))
+                .collect(),
+            token: auth_token_file.map(|file| Token {
+                file: AuthTokenFile {
+                    header: file.header.to_ascii_lowercase(),
+                    ..file.clone()
+                },
+                state: Mutex::new(TokenState {
+                    value: None,
+                    last_read: None,
+                }),
+            }),
+        }))
+    }
+
+    /// The headers to add to a request.
+    fn current(&self) -> impl Iterator<Item = (String, String)> + '_ {
+        self.headers.iter().cloned().chain(
+            self.token
+                .as_ref()
+                .and_then(|token| token.header(&self.target)),
+        )
+    }
+
+    /// Add the headers to the metadata of an OTLP/gRPC request.
+    pub(crate) fn apply(&self, metadata: &mut MetadataMap) -> Result<(), String> {
+        for (name, value) in self.current() {
+            let key: AsciiMetadataKey = name
+                .parse()
+                .map_err(|_| format!("invalid header name {name} for {}", self.target))?;
+            let value: AsciiMetadataValue = value
+                .parse()
+                .map_err(|_| format!("invalid value of header {name} for {}", self.target))?;
+            metadata.insert(key, value);
+        }
+        Ok(())
+    }
+
+    /// An interceptor of OTLP/gRPC exporters that adds the headers to their requests. Its signature is set by tonic.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn interceptor(
+        self: Arc<Self>,
+    ) -> impl FnMut(tonic::Request<()>) -> Result<tonic::Request<()>, Status> + Clone + Send + Sync
+    {
+        move |mut request| {
+            self.apply(request.metadata_mut())
+                .map_err(Status::invalid_argument)?;
+            Ok(request)
+        }
+    }
+}
+
+/// An HTTP client that adds the headers of an export target to the requests of an exporter, if it has any.
+#[derive(Debug)]
+pub(crate) struct HeadersClient<C> {
+    inner: C,
+    headers: Option<Arc<ExportHeaders>>,
+}
+
+impl<C> HeadersClient<C> {
+    pub(crate) fn new(inner: C, headers: Option<Arc<ExportHeaders>>) -> Self {
+        HeadersClient { inner, headers }
+    }
+}
+
+#[async_trait]
+impl<C: HttpClient> HttpClient for HeadersClient<C> {
+    async fn send(&self, mut request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+        if let Some(headers) = &self.headers {
+            for (name, value) in headers.current() {
+                request.headers_mut().insert(
+                    HeaderName::from_bytes(name.as_bytes())?,
+                    HeaderValue::from_str(&value)?,
+                );
+            }
+        }
+        self.inner.send(request).await
+    }
+}
+
+impl std::fmt::Debug for ExportHeaders {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // The values are secrets.
+        f.debug_struct("ExportHeaders")
+            .field("target", &self.target)
+            .field(
+                "headers",
+                &self
+                    .headers
+                    .iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+            )
+            .finish_non_exhaustive()
+    }
+}
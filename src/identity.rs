@@ -0,0 +1,248 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// The identity of the device in its fleet, read from a file that fleet operators manage separately from the
+// configuration of the application.
+//
+// The resource of the providers is fixed when they are built, so the identity is applied to the resource of each
+// export instead. The file is checked for changes at most every reload interval, as data is exported, and a file that
+// can't be read or parsed leaves the previous identity in place. The prometheus end point serves the identity read at
+// initialization.
+
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant, SystemTime},
+};
+
+use async_trait::async_trait;
+use futures_util::future::BoxFuture;
+use log::{info, warn};
+use opentelemetry::{logs::LogResult, metrics::Result as MetricsResult, KeyValue};
+use opentelemetry_sdk::{
+    export::{
+        logs::{LogData, LogExporter},
+        trace::{ExportResult, SpanData, SpanExporter},
+    },
+    metrics::{
+        data::{ResourceMetrics, Temporality},
+        exporter::PushMetricsExporter,
+        reader::{AggregationSelector, TemporalitySelector},
+        Aggregation, InstrumentKind,
+    },
+    Resource,
+};
+use serde::Deserialize;
+
+use crate::config::DeviceIdentityConfig;
+
+/// The contents of the identity file. Unknown fields are ignored.
+#[derive(Debug, Deserialize)]
+struct IdentityFile {
+    device_id: Option<String>,
+    hardware_model: Option<String>,
+    site: Option<String>,
+}
+
+impl IdentityFile {
+    fn attributes(self) -> Vec<KeyValue> {
+        [
+            ("device.id", self.device_id),
+            ("device.model.identifier", self.hardware_model),
+            ("device.site", self.site),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some(KeyValue::new(key, value?)))
+        .collect()
+    }
+}
+
+#[derive(Debug)]
+struct IdentityState {
+    resource: Resource,
+    /// The modification time of the file the identity was last read from.
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+/// The resource of the component merged with the identity of the device, which takes precedence.
+#[derive(Debug)]
+pub(crate) struct DeviceIdentity {
+    path: PathBuf,
+    reload_interval: Duration,
+    base: Resource,
+    state: Mutex<IdentityState>,
+}
+
+impl DeviceIdentity {
+    /// Read the identity file of `config` and merge it into `base`. A missing or invalid file is reported, and
+    /// `base` is used until the file is fixed.
+    pub(crate) fn new(config: &DeviceIdentityConfig, base: Resource) -> Self {
+        let resource = match read_identity(&config.path) {
+            Ok(attributes) => base.merge(&Resource::new(attributes)),
+            Err(e) => {
+                // log error using eprintln as the logger framework is not setup yet!
+                eprintln!(
+                    "unable to read device identity from {}: {e}",
+                    config.path.display()
+                );
+                base.clone()
+            }
+        };
+        DeviceIdentity {
+            path: config.path.clone(),
+            reload_interval: Duration::from_secs(config.reload_interval_secs),
+            base,
+            state: Mutex::new(IdentityState {
+                resource,
+                modified: modified(&config.path),
+                last_check: Instant::now(),
+            }),
+        }
+    }
+
+    /// The current resource, after reloading the identity file if it is due for a check and has changed.
+    pub(crate) fn resource(&self) -> Resource {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if state.last_check.elapsed() >= self.reload_interval {
+            state.last_check = Instant::now();
+            let modified = modified(&self.path);
+            if modified != state.modified {
+                // A file that can't be read is reported once, until it changes again.
+                state.modified = modified;
+                match read_identity(&self.path) {
+                    Ok(attributes) => {
+                        state.resource = self.base.merge(&Resource::new(attributes));
+                        info!("reloaded device identity from {}", self.path.display());
+                    }
+                    Err(e) => warn!(
+                        "keeping the previous device identity as {} can't be read: {e}",
+                        self.path.display()
+                    ),
+                }
+            }
+        }
+        state.resource.clone()
+    }
+}
+
+/// The modification time of the file at `path`, or `None` if it doesn't exist.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// The resource attributes of the identity file at `path`.
+fn read_identity(path: &Path) -> Result<Vec<KeyValue>, String> {
+    let contents = fs::read(path).map_err(|e| e.to_string())?;
+    let identity: IdentityFile = serde_json::from_slice(&contents).map_err(|e| e.to_string())?;
+    Ok(identity.attributes())
+}
+
+/// A metrics exporter that applies the device identity, if any, to the resource of its exports.
+pub(crate) struct IdentityMetricsExporter<E> {
+    inner: E,
+    identity: Option<Arc<DeviceIdentity>>,
+}
+
+impl<E> IdentityMetricsExporter<E> {
+    pub(crate) fn new(inner: E, identity: Option<Arc<DeviceIdentity>>) -> Self {
+        IdentityMetricsExporter { inner, identity }
+    }
+}
+
+impl<E: TemporalitySelector> TemporalitySelector for IdentityMetricsExporter<E> {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.inner.temporality(kind)
+    }
+}
+
+impl<E: AggregationSelector> AggregationSelector for IdentityMetricsExporter<E> {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.inner.aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl<E: PushMetricsExporter> PushMetricsExporter for IdentityMetricsExporter<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        if let Some(identity) = &self.identity {
+            metrics.resource = identity.resource();
+        }
+        self.inner.export(metrics).await
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        self.inner.force_flush().await
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// A log exporter that applies the device identity, if any, to the resource of its records.
+#[derive(Debug)]
+pub(crate) struct IdentityLogExporter<E> {
+    inner: E,
+    identity: Option<Arc<DeviceIdentity>>,
+}
+
+impl<E> IdentityLogExporter<E> {
+    pub(crate) fn new(inner: E, identity: Option<Arc<DeviceIdentity>>) -> Self {
+        IdentityLogExporter { inner, identity }
+    }
+}
+
+#[async_trait]
+impl<E: LogExporter> LogExporter for IdentityLogExporter<E> {
+    async fn export(&mut self, mut batch: Vec<LogData>) -> LogResult<()> {
+        if let Some(identity) = &self.identity {
+            let resource = identity.resource();
+            for log_data in &mut batch {
+                log_data.resource = Cow::Owned(resource.clone());
+            }
+        }
+        self.inner.export(batch).await
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+}
+
+/// A span exporter that applies the device identity, if any, to the resource of its spans.
+#[derive(Debug)]
+pub(crate) struct IdentitySpanExporter<E> {
+    inner: E,
+    identity: Option<Arc<DeviceIdentity>>,
+}
+
+impl<E> IdentitySpanExporter<E> {
+    pub(crate) fn new(inner: E, identity: Option<Arc<DeviceIdentity>>) -> Self {
+        IdentitySpanExporter { inner, identity }
+    }
+}
+
+impl<E: SpanExporter> SpanExporter for IdentitySpanExporter<E> {
+    fn export(&mut self, mut batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        if let Some(identity) = &self.identity {
+            let resource = identity.resource();
+            for span_data in &mut batch {
+                span_data.resource = Cow::Owned(resource.clone());
+            }
+        }
+        self.inner.export(batch)
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+
+    fn force_flush(&mut self) -> BoxFuture<'static, ExportResult> {
+        self.inner.force_flush()
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The outcome of initializing [`Otel`](crate::Otel).
+//!
+//! [`Otel::new`](crate::Otel::new) skips the parts of the configuration that fail to initialize, such as a target
+//! whose exporter can't be built, and reports them on stderr. [`Otel::try_new`](crate::Otel::try_new) and
+//! [`Otel::builder`](crate::Otel::builder) report them as [`InitFailure`]s instead, so production services can
+//! refuse to start with a broken telemetry configuration. Either way, the [`InitReport`] of
+//! [`Otel::init_report`](crate::Otel::init_report) has the outcome of every configured target, so orchestration can
+//! decide whether a partially initialized pipeline is acceptable.
+
+use std::fmt;
+
+/// What to do when a part of the configuration fails to initialize.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InitPolicy {
+    /// Initialize what can be initialized. The failures are available from
+    /// [`Otel::init_report`](crate::Otel::init_report).
+    #[default]
+    BestEffort,
+    /// Fail the initialization if any part of the configuration fails to initialize.
+    FailFast,
+}
+
+/// A part of the configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InitComponent {
+    /// the global logger, which can only be installed once per process.
+    Logger,
+    /// the prometheus end point.
+    Prometheus,
+    /// a metrics export target.
+    MetricsTarget,
+    /// a log export target.
+    LogTarget,
+    /// an audit target.
+    AuditTarget,
+    /// a trace export target.
+    TraceTarget,
+}
+
+impl fmt::Display for InitComponent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InitComponent::Logger => "logger",
+            InitComponent::Prometheus => "prometheus end point",
+            InitComponent::MetricsTarget => "metrics target",
+            InitComponent::LogTarget => "log target",
+            InitComponent::AuditTarget => "audit target",
+            InitComponent::TraceTarget => "trace target",
+        })
+    }
+}
+
+/// A part of the configuration that failed to initialize, and was skipped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InitFailure {
+    /// the part of the configuration.
+    pub component: InitComponent,
+    /// the label of the target, for targets.
+    pub target: Option<String>,
+    /// why it failed.
+    pub reason: String,
+}
+
+impl fmt::Display for InitFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.target {
+            Some(target) => write!(f, "{} [{target}]: {}", self.component, self.reason),
+            None => write!(f, "{}: {}", self.component, self.reason),
+        }
+    }
+}
+
+/// What became of a configured target.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TargetOutcome {
+    /// the target exports.
+    Initialized,
+    /// the target is disabled in the configuration.
+    Disabled,
+    /// the target was skipped by the configuration, e.g. as the maximum number of export targets is reached.
+    Skipped(String),
+    /// the target failed to initialize, and was skipped.
+    Failed(String),
+    /// the exporter of the target failed to build, and is retried in the background. The target exports once it is
+    /// built.
+    Retrying(String),
+}
+
+/// The outcome of initializing a configured target.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TargetInit {
+    /// the kind of target.
+    pub component: InitComponent,
+    /// the label of the target.
+    pub target: String,
+    /// what became of it.
+    pub outcome: TargetOutcome,
+}
+
+impl fmt::Display for TargetInit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}]: ", self.component, self.target)?;
+        match &self.outcome {
+            TargetOutcome::Initialized => f.write_str("initialized"),
+            TargetOutcome::Disabled => f.write_str("disabled"),
+            TargetOutcome::Skipped(reason) => write!(f, "skipped as {reason}"),
+            TargetOutcome::Failed(reason) => write!(f, "failed: {reason}"),
+            TargetOutcome::Retrying(reason) => write!(f, "retrying as it failed: {reason}"),
+        }
+    }
+}
+
+/// The error of initializing [`Otel`](crate::Otel) with [`InitPolicy::FailFast`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OtelError {
+    failures: Vec<InitFailure>,
+}
+
+impl OtelError {
+    pub(crate) fn new(failures: Vec<InitFailure>) -> Self {
+        OtelError { failures }
+    }
+
+    /// The parts of the configuration that failed to initialize.
+    pub fn failures(&self) -> &[InitFailure] {
+        &self.failures
+    }
+}
+
+impl fmt::Display for OtelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "otel failed to initialize")?;
+        for (index, failure) in self.failures.iter().enumerate() {
+            let separator = if index == 0 { ": " } else { "; " };
+            write!(f, "{separator}{failure}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for OtelError {}
+
+/// The outcome of initializing [`Otel`](crate::Otel): the outcome of each configured target, grouped by kind of
+/// target, and the parts of the configuration that failed to initialize.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InitReport {
+    targets: Vec<TargetInit>,
+    failures: Vec<InitFailure>,
+}
+
+impl InitReport {
+    /// The outcome of each configured target.
+    pub fn targets(&self) -> &[TargetInit] {
+        &self.targets
+    }
+
+    /// The parts of the configuration that failed to initialize and were skipped, including the failed targets.
+    pub fn failures(&self) -> &[InitFailure] {
+        &self.failures
+    }
+
+    /// Whether the whole configuration initialized, i.e. nothing failed.
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Report that `component`, and `target` for targets, failed to initialize due to `reason`.
+    pub(crate) fn failed(
+        &mut self,
+        component: InitComponent,
+        target: Option<&str>,
+        reason: impl fmt::Display,
+    ) {
+        let reason = reason.to_string();
+        if let Some(target) = target {
+            self.add_target(component, target, TargetOutcome::Failed(reason.clone()));
+        }
+        self.failures.push(InitFailure {
+            component,
+            target: target.map(str::to_owned),
+            reason,
+        });
+    }
+
+    /// Report that the exporter of `target` failed to build due to `reason`, and is retried in the background.
+    pub(crate) fn retrying(
+        &mut self,
+        component: InitComponent,
+        target: &str,
+        reason: impl fmt::Display,
+    ) {
+        let reason = reason.to_string();
+        self.add_target(component, target, TargetOutcome::Retrying(reason.clone()));
+        self.failures.push(InitFailure {
+            component,
+            target: Some(target.to_owned()),
+            reason,
+        });
+    }
+
+    /// Report that `target` was initialized.
+    pub(crate) fn initialized(&mut self, component: InitComponent, target: &str) {
+        self.add_target(component, target, TargetOutcome::Initialized);
+    }
+
+    /// Report that `target` is disabled.
+    pub(crate) fn disabled(&mut self, component: InitComponent, target: &str) {
+        self.add_target(component, target, TargetOutcome::Disabled);
+    }
+
+    /// Report that `target` was skipped as `reason`.
+    pub(crate) fn skipped(
+        &mut self,
+        component: InitComponent,
+        target: &str,
+        reason: impl fmt::Display,
+    ) {
+        self.add_target(
+            component,
+            target,
+            TargetOutcome::Skipped(reason.to_string()),
+        );
+    }
+
+    fn add_target(&mut self, component: InitComponent, target: &str, outcome: TargetOutcome) {
+        self.targets.push(TargetInit {
+            component,
+            target: target.to_owned(),
+            outcome,
+        });
+    }
+}
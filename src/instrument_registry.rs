@@ -0,0 +1,74 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+};
+
+use log::warn;
+use opentelemetry::metrics::{MetricsError, Result, Unit};
+
+/// The kind and unit an instrument was first created with.
+#[derive(Debug, PartialEq)]
+struct Registration {
+    kind: &'static str,
+    unit: String,
+}
+
+/// The instruments created through the meters of [`Otel`](crate::Otel), to detect the same name being created with
+/// conflicting kinds or units, which silently breaks the metric of that name.
+#[derive(Debug, Default)]
+pub(crate) struct InstrumentRegistry {
+    instruments: Mutex<HashMap<String, Registration>>,
+    strict: bool,
+}
+
+impl InstrumentRegistry {
+    /// Create a registry that warns of conflicts, or fails the creation of conflicting instruments if `strict`.
+    pub(crate) fn new(strict: bool) -> Self {
+        InstrumentRegistry {
+            instruments: Mutex::default(),
+            strict,
+        }
+    }
+
+    /// Register the creation of the instrument `name` of `kind`, e.g. `u64_counter`. Creating an instrument again
+    /// with the same kind and unit is fine.
+    ///
+    /// # Errors
+    /// * `MetricsError::Other` - If the instrument was created with another kind or unit before, in strict mode.
+    pub(crate) fn register(
+        &self,
+        name: &str,
+        kind: &'static str,
+        unit: Option<&Unit>,
+    ) -> Result<()> {
+        let registration = Registration {
+            kind,
+            unit: unit
+                .map(|unit| unit.as_str().to_owned())
+                .unwrap_or_default(),
+        };
+        let mut instruments = self
+            .instruments
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let Some(existing) = instruments.get(name) else {
+            instruments.insert(name.to_owned(), registration);
+            return Ok(());
+        };
+        if *existing == registration {
+            return Ok(());
+        }
+        let conflict = format!(
+            "instrument {name} is created as a {} with unit \"{}\", but was created as a {} with unit \"{}\" before",
+            registration.kind, registration.unit, existing.kind, existing.unit
+        );
+        if self.strict {
+            return Err(MetricsError::Other(conflict));
+        }
+        warn!("{conflict}");
+        Ok(())
+    }
+}
@@ -1,79 +1,748 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-use std::time::Duration;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, PoisonError, RwLock},
+    time::{Duration, SystemTime},
+};
 
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
 
-use hyper::StatusCode;
-use opentelemetry::{global, KeyValue};
+use futures_util::{
+    future::{join, join_all},
+    stream::{FuturesUnordered, StreamExt},
+};
+use http_body_util::{BodyExt, Empty};
+use hyper::{body::Bytes, server::conn::http1, StatusCode};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::{TokioExecutor, TokioIo},
+    server::graceful::GracefulShutdown,
+    service::TowerToHyperService,
+};
+use opentelemetry::{
+    global,
+    logs::Severity,
+    metrics::{Meter, MeterProvider as _, MetricsError},
+    KeyValue,
+};
 
-use axum::{http, Extension};
+use axum::{
+    error_handling::HandleErrorLayer,
+    extract::{ConnectInfo, Request},
+    http,
+    middleware::Next,
+    response::IntoResponse,
+    Extension, Json,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tower::{limit::GlobalConcurrencyLimitLayer, timeout::TimeoutLayer, ServiceExt};
 
-use opentelemetry_otlp::{ExportConfig, Protocol, WithExportConfig};
+use opentelemetry_otlp::{
+    ExportConfig, MetricsExporter, Protocol, WithExportConfig, OTEL_EXPORTER_OTLP_METRICS_ENDPOINT,
+    OTEL_EXPORTER_OTLP_METRICS_TIMEOUT,
+};
 use opentelemetry_sdk::{
     logs::LoggerProvider,
     metrics::{
         data::Temporality,
+        exporter::PushMetricsExporter,
         reader::{DefaultAggregationSelector, DefaultTemporalitySelector, TemporalitySelector},
         InstrumentKind, PeriodicReader, SdkMeterProvider,
     },
-    runtime, Resource,
+    runtime,
+    trace::TracerProvider,
+    Resource,
 };
 use opentelemetry_stdout::MetricsExporterBuilder;
-use prometheus::{Encoder, Registry, TextEncoder};
+use prometheus::{proto::MetricFamily, Encoder, Registry, TextEncoder};
 
-use self::config::Config;
+use self::{
+    config::{
+        Config, ExportTarget, LogFilter, LogsExportTarget, MetricsExportTarget, PrometheusConfig,
+    },
+    counter_resets::CounterResetDetector,
+    debug_exporter::{CaptureFile, DebugMetricsExporter},
+    export_failures::ExportFailures,
+    headers::ExportHeaders,
+    identity::{DeviceIdentity, IdentityMetricsExporter},
+    init::{InitComponent, InitFailure, InitPolicy, InitReport, OtelError},
+    instrument_registry::InstrumentRegistry,
+    limits::{Limits, MetricsPipelineLimits},
+    log_bridge_metrics::LogBridgeMetrics,
+    log_drop::DropRules,
+    log_level::{LogLevelError, LogLevelHandle},
+    otlp_receiver::OtlpReceiver,
+    prometheus_filter::MetricFilter,
+    prometheus_payload::{accepts_gzip, Payload, PayloadCache},
+    prometheus_quantiles::QuantileEmulation,
+    protocol::ProtocolMetricsExporter,
+    resource_scope::{scope_resource, MetricsScopes, ResourceScope, ScopedMetricsExporter},
+    retry::RetryMetricsExporter,
+    run::RunHandle,
+    runtime_targets::{MetricsReaders, RuntimeTargetError, RuntimeTargets},
+    schedule::ScheduledMetricsExporter,
+    scrape_metrics::ScrapeMetrics,
+    semconv::SemconvChecker,
+    server_headers::HeaderPolicy,
+    server_security::ScrapeAuth,
+    shared_export::{SharedMetricsExporter, SharedTarget, SharedTargets},
+    skip_empty::SkipEmptyMetricsExporter,
+    slo::LatencySlos,
+    start_time::{resolve_start_time, StartTimeMetricsExporter, StartTimeSource},
+    stdout_rates::RateMetricsExporter,
+    switch::{MetricsSwitch, SwitchTargetError, SwitchableMetricsExporter, Switches},
+    tenant_accounting::TenantMetricsExporter,
+    transport::CaReloading,
+};
 
+pub mod audit;
+pub mod baggage;
+mod build_info;
 pub mod config;
-mod filtered_log_processor;
+#[cfg(feature = "clap")]
+pub mod config_args;
+mod config_file;
+mod config_serde;
+mod container;
+pub mod context;
+mod counter_resets;
+mod debug_exporter;
+mod disk_encryption;
+mod disk_retention;
+pub mod events;
+mod export_failures;
+mod exporter_metrics;
+mod global_logger;
+mod headers;
+mod identity;
+pub mod init;
+mod instrument_registry;
+pub mod limits;
+mod log_bridge_metrics;
+mod log_drop;
+mod log_filter;
+pub mod log_level;
+mod log_limits;
+mod log_persistence;
+mod log_queue;
+mod log_routing;
 pub mod loggers;
+pub mod metrics;
+mod otlp_receiver;
+mod pacing;
+mod prometheus_filter;
+mod prometheus_payload;
+mod prometheus_quantiles;
+mod prometheus_text;
+mod protocol;
+pub mod resource_scope;
+mod retry;
+pub mod run;
+pub mod runtime_targets;
+mod schedule;
+#[cfg(feature = "schema")]
+pub mod schema;
+mod scrape_metrics;
+mod semconv;
+mod server_headers;
+mod server_security;
+mod shared_export;
+pub mod signing;
+mod simple_log_processor;
+mod skip_empty;
+mod slo;
+mod start_time;
+mod stdout_rates;
+pub mod switch;
 pub mod syslog_writer;
+mod target_health;
+mod tenant_accounting;
+mod tracers;
+#[cfg(feature = "tracing")]
+pub mod tracing_layer;
+mod tracked_meter;
+mod transport;
+mod views;
 
-pub(crate) const SERVICE_NAME_KEY: &str = "service.name";
+const SERVICE_NAME_KEY: &str = "service.name";
+const SERVICE_NAMESPACE_KEY: &str = "service.namespace";
+const DEPLOYMENT_ENVIRONMENT_KEY: &str = "deployment.environment";
+
+/// Timeout for fetching metrics from a scrape source while serving a scrape.
+const SCRAPE_SOURCE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often cumulative counters are checked for resets, if enabled.
+const COUNTER_RESET_DETECTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often instruments are checked against the semantic conventions, if enabled.
+const SEMCONV_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long the prometheus end points wait for scrapers to complete the TLS handshake, if served over HTTPS.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
 struct PrometheusRegistry {
     registry: Registry,
-    port: u16,
+    listeners: Vec<Listener>,
+    sibling_registries: Arc<RwLock<Vec<Registry>>>,
+    scrape_sources: Vec<String>,
+    quantiles: Arc<QuantileEmulation>,
+    gzip: bool,
+    server_options: ServerOptions,
+}
+
+/// An end point serving the metrics of the prometheus registry, or a selection of them.
+struct Listener {
+    address: SocketAddr,
+    filter: Option<Arc<MetricFilter>>,
+    cache: Option<Arc<PayloadCache>>,
+}
+
+/// Protections of the prometheus end point against misbehaving scrapers, its diagnostics and response headers.
+#[derive(Clone)]
+struct ServerOptions {
+    max_concurrent_scrapes: Option<usize>,
+    request_timeout: Option<Duration>,
+    max_request_bytes: Option<usize>,
+    log_scrapes: bool,
+    headers: Arc<HeaderPolicy>,
+    auth: Option<Arc<ScrapeAuth>>,
+    tls: Option<TlsAcceptor>,
+    /// The number of consecutive failures after which targets are unready, if the health routes are served.
+    unready_after_failures: Option<u32>,
+}
+
+/// State shared with the handler of the prometheus end point.
+#[derive(Clone)]
+struct MetricsEndpoint {
+    registry: Registry,
+    sibling_registries: Arc<RwLock<Vec<Registry>>>,
+    scrape_sources: Arc<Vec<String>>,
+    quantiles: Arc<QuantileEmulation>,
+    gzip: bool,
+    filter: Option<Arc<MetricFilter>>,
+    cache: Option<Arc<PayloadCache>>,
+    client: Client<HttpConnector, Empty<Bytes>>,
 }
 
 pub struct Otel {
     registry: Option<PrometheusRegistry>,
+    otlp_receiver: Option<OtlpReceiver>,
     meter_provider: SdkMeterProvider,
+    instruments: Arc<InstrumentRegistry>,
+    slos: Arc<LatencySlos>,
     logger_provider: Option<LoggerProvider>,
+    tracer_provider: Option<TracerProvider>,
+    audit_provider: Option<LoggerProvider>,
+    limits: Limits,
+    log_level: LogLevelHandle,
+    config: Config,
+    resource: Resource,
+    switches: Switches,
+    runtime_targets: RuntimeTargets,
+    init_report: InitReport,
+    run_handle: RunHandle,
+    metrics_scopes: MetricsScopes,
+}
+
+/// Builds an [`Otel`], reporting the parts of the configuration that fail to initialize according to its
+/// [`InitPolicy`].
+#[derive(Default)]
+pub struct OtelBuilder {
+    config: Config,
+    policy: InitPolicy,
+}
+
+impl OtelBuilder {
+    /// Initialize with `config` instead of the default configuration.
+    #[must_use]
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// What to do when a part of the configuration fails to initialize, [`InitPolicy::BestEffort`] by default.
+    #[must_use]
+    pub fn with_policy(mut self, policy: InitPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Initialize `Otel`.
+    ///
+    /// # Errors
+    /// * `OtelError` - With [`InitPolicy::FailFast`], if any part of the configuration failed to initialize. What was
+    ///   initialized is shut down, but the global logger, which can only be installed once, stays installed.
+    pub fn build(self) -> Result<Otel, OtelError> {
+        let otel = Otel::init(self.config);
+        if self.policy == InitPolicy::FailFast && !otel.init_report.is_complete() {
+            let failures = otel.init_report.failures().to_vec();
+            otel.run_handle.stop();
+            otel.providers().shutdown();
+            return Err(OtelError::new(failures));
+        }
+        Ok(otel)
+    }
 }
 
 impl Otel {
+    /// Initialize with `config`. Parts of the configuration that fail to initialize, such as a target whose exporter
+    /// can't be built, are reported on stderr or in the logs and skipped; see [`Otel::init_report`].
     pub fn new(config: Config) -> Otel {
-        let logger_provider = match loggers::init_logs(config.clone()) {
-            Ok(logger_provider) => Some(logger_provider),
+        Otel::init(config)
+    }
+
+    /// Initialize with `config`, failing if any part of the configuration fails to initialize.
+    ///
+    /// # Errors
+    /// * `OtelError` - With the parts of the configuration that failed to initialize, and why.
+    pub fn try_new(config: Config) -> Result<Otel, OtelError> {
+        Otel::builder()
+            .with_config(config)
+            .with_policy(InitPolicy::FailFast)
+            .build()
+    }
+
+    /// A builder to initialize `Otel` with a configuration and an [`InitPolicy`].
+    pub fn builder() -> OtelBuilder {
+        OtelBuilder::default()
+    }
+
+    fn init(config: Config) -> Otel {
+        if !config.enabled {
+            return Otel::noop(config);
+        }
+        let mut init_report = InitReport::default();
+        let resource = build_resource(&config);
+        let identity = config.device_identity.as_ref().map(|device_identity| {
+            Arc::new(DeviceIdentity::new(device_identity, resource.clone()))
+        });
+        let resource = identity
+            .as_ref()
+            .map_or(resource, |identity| identity.resource());
+        if config.export_failure_summary_secs > 0 {
+            export_failures::install_error_handler();
+        }
+        baggage::set_keys(&config.baggage_attributes);
+        let run_handle = RunHandle::default();
+        let mut switches = Switches::new(run_handle.subscribe());
+        let mut limits = Limits {
+            log_record_limits: config.log_record_limits,
+            governance: config.governance.clone(),
+            ..Limits::default()
+        };
+        let audit_provider = audit::init_audit(
+            &config,
+            resource.clone(),
+            identity.as_ref(),
+            &switches,
+            &mut init_report,
+        )
+        .map(|(audit_provider, audit_limits)| {
+            limits.audit_targets = audit_limits;
+            audit_provider
+        });
+        let drop_rules = Arc::new(DropRules::new(&config.log_drop_rules));
+        let bridge_metrics = Arc::new(LogBridgeMetrics::default());
+        let mut log_targets = None;
+        let logger_provider = match loggers::init_logs(
+            config.clone(),
+            resource.clone(),
+            identity.as_ref(),
+            drop_rules.clone(),
+            bridge_metrics.clone(),
+            &mut switches,
+            &mut init_report,
+        ) {
+            Ok((logger_provider, log_limits, targets)) => {
+                limits.log_targets = log_limits;
+                log_targets = Some(targets);
+                Some(logger_provider)
+            }
             Err(e) => {
                 warn!("unable to initialize otel logger as another library has already initialized a global logger:{:?}",e);
+                init_report.failed(InitComponent::Logger, None, e);
                 None
             }
         };
 
-        let (registry, meter_provider) = init_metrics(config);
+        if config.strict_semconv {
+            semconv::check_resource(&resource);
+        }
+
+        let otlp_receiver = config
+            .otlp_receiver_config
+            .as_ref()
+            .map(|otlp_receiver_config| OtlpReceiver::new(otlp_receiver_config.port, &config));
+
+        let metrics_scopes = MetricsScopes::default();
+        let start_time_source = config
+            .start_time
+            .as_ref()
+            .map(|start_time| resolve_start_time(start_time, SystemTime::now()));
+        let metrics_readers = config
+            .runtime_metrics_targets
+            .then(|| Arc::new(MetricsReaders::new()));
+        let (registry, meter_provider, metrics_limits) = init_metrics(
+            &config,
+            resource.clone(),
+            identity.as_ref(),
+            &mut switches,
+            &metrics_scopes,
+            start_time_source,
+            metrics_readers.as_deref(),
+            &mut init_report,
+        );
+        limits.metrics_targets = metrics_limits;
+        let tracer_provider = tracers::init_traces(
+            &config,
+            resource.clone(),
+            identity.as_ref(),
+            &mut init_report,
+        );
+        drop_rules.register_metrics();
+        bridge_metrics.register_metrics();
+        exporter_metrics::register_metrics();
+        disk_retention::register_metrics();
+        build_info::register(&config);
+        let runtime_targets = RuntimeTargets::new(
+            config.clone(),
+            identity,
+            start_time_source,
+            metrics_readers,
+            log_targets,
+            (limits.metrics_targets.len(), limits.log_targets.len()),
+        );
         Otel {
             registry,
+            otlp_receiver,
             meter_provider,
+            instruments: Arc::new(InstrumentRegistry::new(config.strict_instruments)),
+            slos: Arc::new(LatencySlos::new(&config.latency_slos)),
             logger_provider,
+            tracer_provider,
+            audit_provider,
+            limits,
+            log_level: LogLevelHandle::new(config.level.clone()),
+            config,
+            resource,
+            switches,
+            runtime_targets,
+            init_report,
+            run_handle,
+            metrics_scopes,
         }
     }
 
-    /// Long running tasks for otel propagation.
+    /// An `Otel` whose APIs are no-ops, for a disabled configuration. Its meter provider has no readers, so the
+    /// instruments of its meters don't aggregate, and nothing is installed globally.
+    fn noop(config: Config) -> Otel {
+        Otel {
+            registry: None,
+            otlp_receiver: None,
+            meter_provider: SdkMeterProvider::builder().build(),
+            instruments: Arc::new(InstrumentRegistry::new(config.strict_instruments)),
+            slos: Arc::default(),
+            logger_provider: None,
+            tracer_provider: None,
+            audit_provider: None,
+            limits: Limits::default(),
+            log_level: LogLevelHandle::new(config.level.clone()),
+            runtime_targets: RuntimeTargets::new(config.clone(), None, None, None, None, (0, 0)),
+            config,
+            resource: Resource::empty(),
+            switches: Switches::default(),
+            init_report: InitReport::default(),
+            run_handle: RunHandle::default(),
+            metrics_scopes: MetricsScopes::default(),
+        }
+    }
+
+    /// Initialize with defaults suited to small tools: metrics and logs are exported to the OTLP/gRPC collector at
+    /// `otlp_endpoint` every second, metrics with cumulative temporality and logs of warn severity and above, and
+    /// logs are written to stderr. Use [`Otel::new`] with a full [`Config`] for anything else.
+    pub fn quickstart(service_name: &str, otlp_endpoint: &str) -> Otel {
+        Otel::new(Config {
+            service_name: service_name.to_owned(),
+            metrics_export_targets: Some(vec![MetricsExportTarget {
+                url: otlp_endpoint.to_owned(),
+                interval_secs: 1,
+                temporality: Some(Temporality::Cumulative),
+                ..MetricsExportTarget::default()
+            }]),
+            log_export_targets: Some(vec![LogsExportTarget {
+                url: otlp_endpoint.to_owned(),
+                interval_secs: 1,
+                filter: LogFilter {
+                    severity: Some(Severity::Warn),
+                    ..LogFilter::default()
+                },
+                ..LogsExportTarget::default()
+            }]),
+            emit_logs_to_stderr: true,
+            ..Config::default()
+        })
+    }
+
+    /// A meter named `name`. Instruments created with the same name but another kind or unit through the meters of
+    /// `Otel` are reported, as they break the metric of that name, or fail to be created with `strict_instruments`.
+    pub fn meter(&self, name: &str) -> Meter {
+        tracked_meter::tracked_meter(
+            self.meter_provider.meter(name.to_owned()),
+            self.instruments.clone(),
+            self.slos.clone(),
+            None,
+        )
+    }
+
+    /// A secondary resource within the component, e.g. the twin of an embedded device, with the resource of the
+    /// component overridden by `attributes`. The metrics and logs of its meters and loggers are exported with that
+    /// resource through the metrics and log export targets; see [`resource_scope`] for the details.
+    pub fn resource_scope(&self, attributes: &[KeyValue]) -> ResourceScope {
+        ResourceScope::new(
+            scope_resource(&self.resource, attributes),
+            &self.metrics_scopes,
+            self.logger_provider.clone(),
+            self.instruments.clone(),
+            self.slos.clone(),
+        )
+    }
+
+    /// A meter whose instruments are named with `prefix`, e.g. `myapp.subsys.requests` for an instrument created
+    /// as `requests` with the prefix `myapp.subsys`, so that the components of large codebases don't need to spell
+    /// out unique names. The prefix is also the name of the meter. Instruments are checked like those of
+    /// [`Otel::meter`].
+    pub fn meter_with_prefix(&self, prefix: &str) -> Meter {
+        tracked_meter::tracked_meter(
+            self.meter_provider.meter(prefix.to_owned()),
+            self.instruments.clone(),
+            self.slos.clone(),
+            Some(prefix),
+        )
+    }
+
+    /// Switch the metrics or log export target that exports to `old_url` over to `new_target`, e.g. to migrate to
+    /// another collector without losing telemetry. The new target must accept an empty export before it takes over.
+    /// Exports in flight to the old target are awaited before it is torn down, and data queued in the meantime is
+    /// exported to the new target. The pipeline keeps the interval, temporality, export severity and batching of the
+    /// old target. Audit targets can't be switched, and the OTLP receiver keeps forwarding to the configured targets.
+    ///
+    /// # Errors
+    /// * `SwitchTargetError` - If no target exports to `old_url`, or the new target can't be built or exported to.
+    ///   The old target keeps exporting in that case.
+    pub async fn switch_target(
+        &self,
+        old_url: &str,
+        new_target: ExportTarget,
+    ) -> Result<(), SwitchTargetError> {
+        self.switches
+            .switch(&self.config, &self.resource, old_url, new_target)
+            .await
+    }
+
+    /// Add `export_target` as a metrics export target at runtime, e.g. to point telemetry at a new collector when a
+    /// device is re-provisioned. Requires `runtime_metrics_targets` in the configuration. The target exports the
+    /// metrics aggregated since startup on its interval, with its temporality, schedule and retries, until it is
+    /// removed with [`Otel::remove_target`]. The metrics of resource scopes are not exported to it.
+    ///
+    /// # Errors
+    /// * `RuntimeTargetError` - If `runtime_metrics_targets` is not set, the target is disabled, a target added at
+    ///   runtime already exports metrics to its url, the maximum number of export targets is reached, or its exporter
+    ///   can't be built.
+    pub async fn add_metrics_target(
+        &self,
+        export_target: MetricsExportTarget,
+    ) -> Result<(), RuntimeTargetError> {
+        self.runtime_targets.add_metrics(export_target).await
+    }
+
+    /// Add `export_target` as a log export target at runtime. Its processor joins those of the configured log targets,
+    /// so it receives the records emitted from then on according to its filter and the log routes, until it is
+    /// removed with [`Otel::remove_target`].
+    ///
+    /// # Errors
+    /// * `RuntimeTargetError` - If the log pipeline is not installed, the target is disabled, a target added at
+    ///   runtime already exports logs to its url, the maximum number of export targets is reached, or its exporter
+    ///   can't be built.
+    pub async fn add_logs_target(
+        &self,
+        export_target: LogsExportTarget,
+    ) -> Result<(), RuntimeTargetError> {
+        self.runtime_targets.add_logs(export_target).await
+    }
+
+    /// Remove the metrics and log targets added at runtime that export to `url`. Returns once they exported the
+    /// metrics collected and the records queued for them, and their exporters are shut down. Configured targets can't
+    /// be removed, but can be moved to another collector with [`Otel::switch_target`].
+    ///
+    /// # Errors
+    /// * `RuntimeTargetError` - If no target added at runtime exports to `url`.
+    pub async fn remove_target(&self, url: &str) -> Result<(), RuntimeTargetError> {
+        self.runtime_targets.remove(url).await
+    }
+
+    /// The parts of the configuration that failed to initialize and were skipped.
+    pub fn init_failures(&self) -> &[InitFailure] {
+        self.init_report.failures()
+    }
+
+    /// The outcome of each configured target, and the parts of the configuration that failed to initialize, so
+    /// orchestration can decide whether a partially initialized pipeline is acceptable.
+    pub fn init_report(&self) -> &InitReport {
+        &self.init_report
+    }
+
+    /// The effective queue sizes, batch sizes, intervals, timeouts and limits of the pipelines, after applying the
+    /// configuration and the `OTEL_*` environment variables.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// A handle to the log level, to change the logging directives of `Config::level` at runtime, e.g. to turn on
+    /// debug logging for a misbehaving module without restarting the process.
+    pub fn log_level(&self) -> LogLevelHandle {
+        self.log_level.clone()
+    }
+
+    /// Replace the logging directives of `Config::level`, see [`LogLevelHandle::set_level`].
+    ///
+    /// # Errors
+    /// * `LogLevelError` - If `directives` are invalid, or the logger of this library is not installed.
+    pub fn set_level(&self, directives: &str) -> Result<(), LogLevelError> {
+        self.log_level.set_level(directives)
+    }
+
+    /// Install a minimal logger that writes records matching `level` to stderr, for logging before the
+    /// configuration is available. `Otel::new` later upgrades it in place and replays the first 1024 early records
+    /// into the log export targets, so startup errors aren't lost.
+    ///
+    /// # Errors
+    /// * `log::SetLoggerError` - If another logger has already been installed
+    pub fn preinit_stderr(level: &str) -> Result<(), log::SetLoggerError> {
+        global_logger::preinit_stderr(level, global_logger::EARLY_RECORDS_CAPACITY)
+    }
+
+    /// Like [`Otel::preinit_stderr`], but buffers the first `max_early_records` early records for replay.
+    ///
+    /// # Errors
+    /// * `log::SetLoggerError` - If another logger has already been installed
+    pub fn preinit_stderr_with_capacity(
+        level: &str,
+        max_early_records: usize,
+    ) -> Result<(), log::SetLoggerError> {
+        global_logger::preinit_stderr(level, max_early_records)
+    }
+
+    /// Long running tasks for otel propagation: the prometheus end points and the OTLP receiver. They run until
+    /// stopped with the handle of [`Otel::run_handle`] or by [`Otel::shutdown`].
     pub async fn run(&self) {
+        if self.run_handle.is_stopped() {
+            return;
+        }
+        let _guard = self.run_handle.enter();
+        let prometheus = async {
+            if let Some(prometheus_registry) = &self.registry {
+                let client = Client::builder(TokioExecutor::new()).build_http();
+                join_all(prometheus_registry.listeners.iter().map(|listener| async {
+                    if let Err(e) = httpserver_init(
+                        listener.address,
+                        prometheus_registry.server_options.clone(),
+                        MetricsEndpoint {
+                            registry: prometheus_registry.registry.clone(),
+                            sibling_registries: prometheus_registry.sibling_registries.clone(),
+                            scrape_sources: Arc::new(prometheus_registry.scrape_sources.clone()),
+                            quantiles: prometheus_registry.quantiles.clone(),
+                            gzip: prometheus_registry.gzip,
+                            filter: listener.filter.clone(),
+                            cache: listener.cache.clone(),
+                            client: client.clone(),
+                        },
+                        self.run_handle.subscribe(),
+                    )
+                    .await
+                    {
+                        error!(
+                            "unable to serve prometheus endpoint on {} due to: {:?}",
+                            listener.address, e
+                        );
+                    }
+                }))
+                .await;
+            }
+        };
+        let otlp_receiver = async {
+            if let Some(otlp_receiver) = &self.otlp_receiver {
+                if let Err(e) = otlp_receiver.run(self.run_handle.subscribe()).await {
+                    error!("otlp receiver failed due to: {:?}", e);
+                }
+            }
+        };
+        join(prometheus, otlp_receiver).await;
+    }
+
+    /// A handle to stop the tasks of [`Otel::run`] and await their termination.
+    pub fn run_handle(&self) -> RunHandle {
+        self.run_handle.clone()
+    }
+
+    /// Add a registry of a co-located component whose metrics are served alongside this component's metrics
+    /// on the prometheus end point. Metric families that exist in several registries are merged.
+    pub fn add_scrape_registry(&self, registry: Registry) {
         if let Some(prometheus_registry) = &self.registry {
-            let _ = httpserver_init(
-                prometheus_registry.port,
-                prometheus_registry.registry.clone(),
-            )
-            .await;
+            prometheus_registry
+                .sibling_registries
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(registry);
+        } else {
+            warn!("ignoring scrape registry as prometheus is not enabled");
         }
     }
 
-    /// Graceful shutdown that flushes any pending metrics and logs to the exporter.
-    pub fn shutdown(&self) {
+    /// Graceful shutdown that stops the tasks of [`Otel::run`] and awaits their termination, so the prometheus end
+    /// points stop once the scrapes in progress are served, removes the targets added at runtime, then flushes any
+    /// pending metrics, spans and logs to the exporters. Flushing blocks on the exports, so it runs on the blocking
+    /// threads of the runtime.
+    pub async fn shutdown(&self) {
+        self.run_handle.stop();
+        self.run_handle.stopped().await;
+        self.runtime_targets.shutdown().await;
+        let providers = self.providers();
+        if let Err(e) = tokio::task::spawn_blocking(move || providers.shutdown()).await {
+            warn!("encountered error while shutting down providers: {:?}", e);
+        }
+    }
+
+    fn providers(&self) -> Providers {
+        Providers {
+            meter_provider: self.meter_provider.clone(),
+            tracer_provider: self.tracer_provider.clone(),
+            logger_providers: self
+                .logger_provider
+                .iter()
+                .chain(&self.audit_provider)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// The providers of the pipelines, to flush and shut them down.
+struct Providers {
+    meter_provider: SdkMeterProvider,
+    tracer_provider: Option<TracerProvider>,
+    logger_providers: Vec<LoggerProvider>,
+}
+
+impl Providers {
+    fn shutdown(self) {
         if let Err(metrics_error) = self.meter_provider.force_flush() {
             warn!(
                 "ecountered error while flushing metrics: {:?}",
@@ -87,7 +756,17 @@ impl Otel {
             );
         }
 
-        if let Some(mut logger_provider) = self.logger_provider.clone() {
+        if let Some(tracer_provider) = &self.tracer_provider {
+            for result in tracer_provider.force_flush() {
+                if let Err(trace_error) = result {
+                    warn!("encountered error while flushing spans: {:?}", trace_error);
+                }
+            }
+            // The processors are shut down once the provider is no longer used.
+            global::shutdown_tracer_provider();
+        }
+
+        for mut logger_provider in self.logger_providers {
             logger_provider.force_flush();
             logger_provider.try_shutdown();
         }
@@ -96,7 +775,6 @@ impl Otel {
 
 #[derive(Default, Debug)]
 /// A temporality selector that returns Delta for all instruments
-
 pub(crate) struct DeltaTemporalitySelector {}
 
 impl DeltaTemporalitySelector {
@@ -112,37 +790,124 @@ impl TemporalitySelector for DeltaTemporalitySelector {
     }
 }
 
-/// Initialize metrics based on passed in config.
-/// This function will setup metrics exporters, create a Prometheus registry if enabled,
-/// setup the stdout metrics writer if enabled, and initializes STATIC Metrics.
-///
-/// Returns the Prometheus Registry or None if Prometheus was disabled.
+/// Build the resource describing the component, shared by metrics and logs.
 ///
-fn init_metrics(config: Config) -> (Option<PrometheusRegistry>, SdkMeterProvider) {
-    let mut keys = vec![KeyValue::new(SERVICE_NAME_KEY, config.service_name.clone())];
-    if let Some(resource_attributes) = config.resource_attributes {
+/// The service identity fields take precedence over resource attributes with the same key, which take precedence over
+/// the detected container attributes. Invalid identity fields are reported and left out.
+fn build_resource(config: &Config) -> Resource {
+    let mut keys = Vec::new();
+    if config.detect_container {
+        keys.extend(container::detect());
+    }
+    keys.push(KeyValue::new(SERVICE_NAME_KEY, config.service_name.clone()));
+    if let Some(resource_attributes) = &config.resource_attributes {
         for attribute in resource_attributes {
-            keys.push(KeyValue::new(attribute.key, attribute.value));
+            keys.push(KeyValue::new(
+                attribute.key.clone(),
+                attribute.value.clone(),
+            ));
+        }
+    }
+    for (key, value) in [
+        (SERVICE_NAMESPACE_KEY, &config.service_namespace),
+        (DEPLOYMENT_ENVIRONMENT_KEY, &config.deployment_environment),
+    ] {
+        let Some(value) = value else {
+            continue;
+        };
+        if value.is_empty() || value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            // log error using eprintln as the logger framework is not setup yet!
+            eprintln!("ignoring {key} {value:?} as it is empty or contains whitespace");
+            continue;
         }
+        keys.push(KeyValue::new(key, value.clone()));
     }
-    let mut meter_provider_builder = SdkMeterProvider::builder().with_resource(Resource::new(keys));
+    Resource::new(keys)
+}
+
+/// Initialize metrics based on passed in config.
+/// This function will setup metrics exporters, create a Prometheus registry if enabled,
+/// setup the stdout metrics writer if enabled, and initializes STATIC Metrics.
+///
+/// Returns the Prometheus Registry or None if Prometheus was disabled, and the settings of the export targets.
+fn init_metrics(
+    config: &Config,
+    resource: Resource,
+    identity: Option<&Arc<DeviceIdentity>>,
+    switches: &mut Switches,
+    scopes: &MetricsScopes,
+    start_time_source: Option<StartTimeSource>,
+    runtime_readers: Option<&MetricsReaders>,
+    init_report: &mut InitReport,
+) -> (
+    Option<PrometheusRegistry>,
+    SdkMeterProvider,
+    Vec<MetricsPipelineLimits>,
+) {
+    let mut pipeline_limits = Vec::new();
+    let mut meter_provider_builder = SdkMeterProvider::builder().with_resource(resource);
 
     // Setup Prometheus Registry if configured
-    let prometheus_registry = if let Some(prometheus_config) = config.prometheus_config {
+    let prometheus_registry = if let Some(prometheus_config) = &config.prometheus_config {
         let registry = prometheus::Registry::new();
-        match opentelemetry_prometheus::exporter()
-            .with_registry(registry.clone())
-            .build()
-        {
-            Ok(exporter) => {
+        let mut exporter_builder =
+            opentelemetry_prometheus::exporter().with_registry(registry.clone());
+        if prometheus_config.without_unit_suffixes {
+            exporter_builder = exporter_builder.without_units();
+        }
+        if prometheus_config.without_counter_suffixes {
+            exporter_builder = exporter_builder.without_counter_suffixes();
+        }
+        // The end points are not served at all rather than over plain HTTP if their certificate can't be loaded.
+        let tls = prometheus_config
+            .tls
+            .as_ref()
+            .map(server_security::tls_acceptor)
+            .transpose();
+        match tls.and_then(|tls| {
+            exporter_builder
+                .build()
+                .map(|exporter| (exporter, tls))
+                .map_err(|e| format!("{e:?}"))
+        }) {
+            Ok((exporter, tls)) => {
                 meter_provider_builder = meter_provider_builder.with_reader(exporter);
                 Some(PrometheusRegistry {
                     registry,
-                    port: prometheus_config.port,
+                    listeners: prometheus_listeners(prometheus_config),
+                    sibling_registries: Arc::default(),
+                    scrape_sources: prometheus_config.scrape_sources.clone(),
+                    quantiles: Arc::new(QuantileEmulation::new(
+                        &prometheus_config.quantile_histograms,
+                        &prometheus_config.quantiles,
+                    )),
+                    gzip: prometheus_config.gzip,
+                    server_options: ServerOptions {
+                        max_concurrent_scrapes: prometheus_config.max_concurrent_scrapes,
+                        request_timeout: prometheus_config
+                            .request_timeout_secs
+                            .map(Duration::from_secs),
+                        max_request_bytes: prometheus_config.max_request_bytes,
+                        log_scrapes: prometheus_config.log_scrapes,
+                        headers: Arc::new(HeaderPolicy::new(
+                            prometheus_config.cors_allowed_origins.clone(),
+                            prometheus_config.security_headers,
+                        )),
+                        auth: ScrapeAuth::new(
+                            prometheus_config.basic_auth.as_ref(),
+                            prometheus_config.bearer_token.as_deref(),
+                        )
+                        .map(Arc::new),
+                        tls,
+                        unready_after_failures: prometheus_config
+                            .health_endpoints
+                            .then_some(prometheus_config.unready_after_failures),
+                    },
                 })
             }
             Err(e) => {
-                error!("unable to setup prometheus endpoint due to: {:?}", e);
+                error!("unable to setup prometheus endpoint due to: {}", e);
+                init_report.failed(InitComponent::Prometheus, None, e);
                 None
             }
         }
@@ -150,50 +915,208 @@ fn init_metrics(config: Config) -> (Option<PrometheusRegistry>, SdkMeterProvider
         None
     };
 
+    if config.metric_views.is_some() || config.governance.max_instruments.is_some() {
+        meter_provider_builder = meter_provider_builder.with_view(views::build_view(
+            config.metric_views.clone().unwrap_or_default(),
+            config.governance.max_instruments,
+        ));
+    }
+
     // Add Metrics Exporters
-    if let Some(export_targets_list) = config.metrics_export_targets {
+    if let Some(export_targets_list) = &config.metrics_export_targets {
+        let mut targets = Vec::new();
+        let mut index = 0;
         for export_target in export_targets_list {
-            let export_config = ExportConfig {
-                endpoint: export_target.url.clone(),
-                timeout: Duration::from_secs(export_target.timeout),
-                protocol: Protocol::Grpc,
-            };
-
-            let temporality_selector: Box<dyn TemporalitySelector> =
-                if let Some(temporality) = export_target.temporality {
-                    match temporality {
-                        Temporality::Delta => Box::new(DeltaTemporalitySelector::new()),
-                        _ => Box::new(DefaultTemporalitySelector::new()),
-                    }
-                } else {
-                    Box::new(DefaultTemporalitySelector::new())
-                };
+            if !export_target.enabled {
+                init_report.disabled(InitComponent::MetricsTarget, export_target.label());
+                continue;
+            }
+            index += 1;
+            if let Some(max_export_targets) = config.governance.max_export_targets {
+                if index > max_export_targets {
+                    error!(
+                        "skipping metrics target {} as the maximum of {} export targets is reached",
+                        export_target.label(),
+                        max_export_targets
+                    );
+                    init_report.skipped(
+                        InitComponent::MetricsTarget,
+                        export_target.label(),
+                        format!("the maximum of {max_export_targets} export targets is reached"),
+                    );
+                    continue;
+                }
+            }
 
-            let exporter = match opentelemetry_otlp::new_exporter()
-                .tonic()
-                .with_export_config(export_config)
-                .build_metrics_exporter(
-                    // TODO: Make this also part of config?
-                    Box::new(DefaultAggregationSelector::new()),
-                    temporality_selector,
+            if export_target.eager_connect {
+                if let Err(e) = transport::connect_eagerly(
+                    &export_target.url,
+                    eager_connect_protocol(export_target.protocol, export_target.http_fallback),
+                    Duration::from_secs(export_target.timeout),
+                    transport::Connector::new(
+                        &export_target.dns,
+                        &export_target.socket_options,
+                        export_target.max_bytes_per_sec,
+                    ),
+                    &transport::TlsOptions::from(export_target),
                 ) {
-                Ok(exporter) => exporter,
-                Err(e) => {
                     error!(
-                        "unable to set export to {} due to {:?}",
-                        export_target.url, e
+                        "unable to connect to {} due to {:?}",
+                        export_target.label(),
+                        e
+                    );
+                    init_report.failed(
+                        InitComponent::MetricsTarget,
+                        Some(export_target.label()),
+                        format!("unable to connect: {e:?}"),
                     );
                     continue;
                 }
-            };
+            }
+            targets.push(export_target);
+        }
+
+        // Targets that receive identical data share a reader, so the data is serialized once.
+        let sharing_supported = config.share_metrics_exports && shared_export::sharing_supported();
+        let mut groups: Vec<Vec<&MetricsExportTarget>> = Vec::new();
+        for export_target in targets {
+            let group = groups
+                .iter_mut()
+                .find(|group| sharing_supported && receive_identical_data(group[0], export_target));
+            match group {
+                Some(group) => group.push(export_target),
+                None => groups.push(vec![export_target]),
+            }
+        }
 
-            let reader = PeriodicReader::builder(exporter, runtime::Tokio)
-                .with_interval(Duration::from_secs(export_target.interval_secs))
-                .build();
+        // A target whose exporter can't be built yet doesn't share a reader, so that its build is retried in the
+        // background like that of the targets with their own reader.
+        let mut readers: Vec<(Vec<&MetricsExportTarget>, Vec<SharedTarget>)> = Vec::new();
+        for group in groups {
+            if group.len() == 1 {
+                readers.push((group, Vec::new()));
+                continue;
+            }
+            let mut sharing = Vec::new();
+            let mut shared_targets = Vec::new();
+            for export_target in group {
+                match build_shared_target(export_target, config) {
+                    Ok(shared_target) => {
+                        sharing.push(export_target);
+                        shared_targets.push(shared_target);
+                    }
+                    Err(e) => {
+                        error!(
+                            "unable to share a reader with {} due to {:?}, using its own reader",
+                            export_target.label(),
+                            e
+                        );
+                        readers.push((vec![export_target], Vec::new()));
+                    }
+                }
+            }
+            if !sharing.is_empty() {
+                readers.push((sharing, shared_targets));
+            }
+        }
+
+        for (group, shared_targets) in readers {
+            let interval = Duration::from_secs(group[0].interval_secs);
+            let reader = if shared_targets.is_empty() {
+                let export_target = group[0];
+                let switch = match build_switchable_exporter(export_target, config) {
+                    Ok(exporter) => {
+                        init_report
+                            .initialized(InitComponent::MetricsTarget, export_target.label());
+                        MetricsSwitch::new(export_target.url.clone(), exporter)
+                    }
+                    Err(e) => {
+                        error!(
+                            "unable to set export to {} due to {:?}, retrying in the background",
+                            export_target.label(),
+                            e
+                        );
+                        init_report.retrying(
+                            InitComponent::MetricsTarget,
+                            export_target.label(),
+                            e,
+                        );
+                        let switch = MetricsSwitch::pending(
+                            export_target.url.clone(),
+                            export_target.label(),
+                        );
+                        switches.retry_metrics(&switch, (*export_target).clone(), config.clone());
+                        switch
+                    }
+                };
+                switches.add_metrics(switch.clone());
+                let exporter = IdentityMetricsExporter::new(
+                    ScopedMetricsExporter::new(
+                        target_pipeline_exporter(
+                            SwitchableMetricsExporter::new(
+                                switch,
+                                temporality_selector(export_target.temporality),
+                            ),
+                            export_target,
+                            config,
+                            start_time_source,
+                        ),
+                        scopes,
+                        export_target.temporality,
+                    ),
+                    identity.cloned(),
+                );
+                pipeline_limits.push(target_limits(export_target));
+                PeriodicReader::builder(exporter, runtime::Tokio)
+                    .with_interval(interval)
+                    .build()
+            } else {
+                for export_target in &group {
+                    init_report.initialized(InitComponent::MetricsTarget, export_target.label());
+                    pipeline_limits.push(target_limits(export_target));
+                }
+                let shared_targets = SharedTargets::new(shared_targets);
+                switches.add_shared_metrics(shared_targets.clone());
+                let exporter = IdentityMetricsExporter::new(
+                    ScopedMetricsExporter::new(
+                        SkipEmptyMetricsExporter::new(
+                            StartTimeMetricsExporter::new(
+                                TenantMetricsExporter::new(
+                                    SharedMetricsExporter::new(
+                                        shared_targets,
+                                        temporality_selector(group[0].temporality),
+                                    ),
+                                    config.tenant_attribute.as_deref(),
+                                    group
+                                        .iter()
+                                        .map(|export_target| export_target.label().to_owned())
+                                        .collect(),
+                                ),
+                                start_time_source,
+                            ),
+                            config.skip_empty_exports,
+                        ),
+                        scopes,
+                        group[0].temporality,
+                    ),
+                    identity.cloned(),
+                );
+                PeriodicReader::builder(exporter, runtime::Tokio)
+                    .with_interval(interval)
+                    .build()
+            };
             meter_provider_builder = meter_provider_builder.with_reader(reader);
         }
     }
 
+    // The metrics targets added at runtime collect their metrics from these readers.
+    for reader in runtime_readers
+        .into_iter()
+        .flat_map(MetricsReaders::readers)
+    {
+        meter_provider_builder = meter_provider_builder.with_reader(reader);
+    }
+
     if config.emit_metrics_to_stdout {
         let exporter = MetricsExporterBuilder::default()
             .with_encoder(|writer, data| {
@@ -204,54 +1127,683 @@ fn init_metrics(config: Config) -> (Option<PrometheusRegistry>, SdkMeterProvider
             })
             .build();
 
-        let reader = PeriodicReader::builder(exporter, runtime::Tokio).build();
+        let reader = if config.stdout_metric_rates {
+            PeriodicReader::builder(RateMetricsExporter::new(exporter), runtime::Tokio).build()
+        } else {
+            PeriodicReader::builder(exporter, runtime::Tokio).build()
+        };
+        meter_provider_builder = meter_provider_builder.with_reader(reader);
+    }
+
+    if config.detect_counter_resets {
+        let reader = PeriodicReader::builder(CounterResetDetector::new(), runtime::Tokio)
+            .with_interval(COUNTER_RESET_DETECTION_INTERVAL)
+            .build();
+        meter_provider_builder = meter_provider_builder.with_reader(reader);
+    }
+
+    if config.strict_semconv {
+        let reader = PeriodicReader::builder(SemconvChecker::new(), runtime::Tokio)
+            .with_interval(SEMCONV_CHECK_INTERVAL)
+            .build();
         meter_provider_builder = meter_provider_builder.with_reader(reader);
     }
 
     let meter_provider = meter_provider_builder.build();
     global::set_meter_provider(meter_provider.clone());
 
-    (prometheus_registry, meter_provider)
+    (prometheus_registry, meter_provider, pipeline_limits)
+}
+
+/// The end points of the prometheus registry: `port` serving all metrics, and the configured additional listeners.
+fn prometheus_listeners(prometheus_config: &PrometheusConfig) -> Vec<Listener> {
+    let cache = || {
+        prometheus_config
+            .cache_ttl_millis
+            .map(|ttl| Arc::new(PayloadCache::new(Duration::from_millis(ttl))))
+    };
+    let mut listeners = vec![Listener {
+        address: SocketAddr::new(prometheus_config.bind_address, prometheus_config.port),
+        filter: None,
+        cache: cache(),
+    }];
+    listeners.extend(prometheus_config.listeners.iter().map(|listener| Listener {
+        address: SocketAddr::new(listener.bind_address, listener.port),
+        filter: Some(Arc::new(MetricFilter::new(
+            &listener.include,
+            &listener.exclude,
+        ))),
+        cache: cache(),
+    }));
+    listeners
+}
+
+/// The protocol to use when eagerly connecting to a target. A target that may fall back to OTLP/HTTP
+/// is only required to accept connections, as the protocol is negotiated on the first export.
+pub(crate) fn eager_connect_protocol(protocol: Protocol, http_fallback: bool) -> Protocol {
+    if http_fallback {
+        Protocol::HttpBinary
+    } else {
+        protocol
+    }
+}
+
+/// Returns true if `a` and `b` receive identical data, so that they can share a reader. Only OTLP/gRPC targets
+/// without fallback share readers.
+fn receive_identical_data(a: &MetricsExportTarget, b: &MetricsExportTarget) -> bool {
+    shareable(a)
+        && shareable(b)
+        && a.interval_secs == b.interval_secs
+        && (a.temporality == Some(Temporality::Delta))
+            == (b.temporality == Some(Temporality::Delta))
+}
+
+/// Returns true if `export_target` may share a reader with other targets.
+pub(crate) fn shareable(export_target: &MetricsExportTarget) -> bool {
+    export_target.protocol == Protocol::Grpc
+        && !export_target.http_fallback
+        && export_target.schedule.quiet_hours.is_empty()
+        && export_target.retry.is_none()
+}
+
+/// The effective settings of `export_target`.
+fn target_limits(export_target: &MetricsExportTarget) -> MetricsPipelineLimits {
+    MetricsPipelineLimits {
+        target: export_target.label().to_owned(),
+        interval: Duration::from_secs(export_target.interval_secs),
+        export_timeout: Duration::from_secs(export_target.timeout),
+    }
+}
+
+/// The exporter of the pipeline of `export_target`, which exports with `exporter` on the schedule of the target and
+/// retries failed exports.
+pub(crate) fn target_pipeline_exporter<E: PushMetricsExporter>(
+    exporter: E,
+    export_target: &MetricsExportTarget,
+    config: &Config,
+    start_time_source: Option<StartTimeSource>,
+) -> impl PushMetricsExporter {
+    ScheduledMetricsExporter::new(
+        RetryMetricsExporter::new(
+            SkipEmptyMetricsExporter::new(
+                StartTimeMetricsExporter::new(
+                    TenantMetricsExporter::new(
+                        exporter,
+                        config.tenant_attribute.as_deref(),
+                        vec![export_target.label().to_owned()],
+                    ),
+                    start_time_source,
+                ),
+                config.skip_empty_exports,
+            ),
+            export_target.label(),
+            export_target.retry.as_ref(),
+        ),
+        export_target.label(),
+        &export_target.schedule,
+    )
+}
+
+/// Build the exporter for `export_target`, which falls back to OTLP/HTTP if configured to, and is rebuilt when the
+/// certificate authorities of the target change.
+fn build_target_exporter(
+    export_target: &MetricsExportTarget,
+    user_agent: &str,
+) -> opentelemetry::metrics::Result<CaReloading<Arc<ProtocolMetricsExporter>>> {
+    CaReloading::new(
+        "metrics",
+        export_target.label(),
+        &transport::TlsOptions::from(export_target),
+        {
+            let export_target = export_target.clone();
+            let user_agent = user_agent.to_owned();
+            move || build_protocol_exporter(&export_target, &user_agent).map(Arc::new)
+        },
+    )
+}
+
+/// Build the exporter for `export_target` with the certificate authorities of the target as they are now.
+fn build_protocol_exporter(
+    export_target: &MetricsExportTarget,
+    user_agent: &str,
+) -> opentelemetry::metrics::Result<ProtocolMetricsExporter> {
+    let primary = build_metrics_exporter(export_target, export_target.protocol, user_agent)?;
+    let fallback = if export_target.http_fallback && export_target.protocol == Protocol::Grpc {
+        Some(build_metrics_exporter(
+            export_target,
+            Protocol::HttpBinary,
+            user_agent,
+        )?)
+    } else {
+        None
+    };
+    Ok(ProtocolMetricsExporter::new(
+        export_target.label().to_owned(),
+        primary,
+        fallback,
+    ))
+}
+
+/// Build the exporter of `export_target` that sits behind its switch.
+pub(crate) fn build_switchable_exporter(
+    export_target: &MetricsExportTarget,
+    config: &Config,
+) -> opentelemetry::metrics::Result<DebugMetricsExporter<CaReloading<Arc<ProtocolMetricsExporter>>>>
+{
+    let exporter = build_target_exporter(
+        export_target,
+        &transport::user_agent(
+            export_target.user_agent.as_deref(),
+            config.app_identifier.as_deref(),
+        ),
+    )?;
+    Ok(DebugMetricsExporter::new(
+        exporter,
+        export_target.label().to_owned(),
+        config.pipeline_debug,
+        create_capture(export_target),
+        ExportFailures::new(
+            "metrics",
+            export_target.label().to_owned(),
+            config.export_failure_summary_secs,
+        ),
+    ))
+}
+
+/// Build `export_target` as a target of a shared reader.
+pub(crate) fn build_shared_target(
+    export_target: &MetricsExportTarget,
+    config: &Config,
+) -> Result<SharedTarget, transport::ConnectError> {
+    SharedTarget::new(
+        export_target.label().to_owned(),
+        &export_target.url,
+        Duration::from_secs(export_target.timeout),
+        &transport::user_agent(
+            export_target.user_agent.as_deref(),
+            config.app_identifier.as_deref(),
+        ),
+        config.pipeline_debug,
+        create_capture(export_target),
+        ExportFailures::new(
+            "metrics",
+            export_target.label().to_owned(),
+            config.export_failure_summary_secs,
+        ),
+        ExportHeaders::new(
+            export_target.label(),
+            &export_target.headers,
+            export_target.auth_token_file.as_ref(),
+        ),
+        transport::Connector::new(
+            &export_target.dns,
+            &export_target.socket_options,
+            export_target.max_bytes_per_sec,
+        ),
+        &transport::TlsOptions::from(export_target),
+    )
+}
+
+/// Create the payload capture file of `export_target`, if it captures payloads.
+fn create_capture(export_target: &MetricsExportTarget) -> Option<CaptureFile> {
+    let capture = export_target.payload_capture.as_ref()?;
+    CaptureFile::create(export_target.label().to_owned(), capture)
+        .map_err(|e| {
+            error!(
+                "unable to capture payloads for {} to {} due to {:?}",
+                export_target.label(),
+                capture.path.display(),
+                e
+            );
+        })
+        .ok()
+}
+
+/// The temporality selector for a target configured with `temporality`.
+fn temporality_selector(temporality: Option<Temporality>) -> Box<dyn TemporalitySelector> {
+    match temporality {
+        Some(Temporality::Delta) => Box::new(DeltaTemporalitySelector::new()),
+        _ => Box::new(DefaultTemporalitySelector::new()),
+    }
+}
+
+/// Build an OTLP metrics exporter for `export_target` that talks `protocol`.
+fn build_metrics_exporter(
+    export_target: &MetricsExportTarget,
+    protocol: Protocol,
+    user_agent: &str,
+) -> opentelemetry::metrics::Result<MetricsExporter> {
+    let export_config = ExportConfig {
+        endpoint: export_target.url.clone(),
+        timeout: Duration::from_secs(export_target.timeout),
+        protocol,
+    };
+
+    let temporality_selector = temporality_selector(export_target.temporality);
+    let headers = ExportHeaders::new(
+        export_target.label(),
+        &export_target.headers,
+        export_target.auth_token_file.as_ref(),
+    );
+
+    // TODO: Make the aggregation selector also part of config?
+    match protocol {
+        Protocol::Grpc => {
+            let mut builder = opentelemetry_otlp::new_exporter().tonic().with_channel(
+                transport::grpc_channel(
+                    &export_config.endpoint,
+                    export_config.timeout,
+                    user_agent,
+                    OTEL_EXPORTER_OTLP_METRICS_ENDPOINT,
+                    OTEL_EXPORTER_OTLP_METRICS_TIMEOUT,
+                    transport::Connector::new(
+                        &export_target.dns,
+                        &export_target.socket_options,
+                        export_target.max_bytes_per_sec,
+                    ),
+                    &transport::TlsOptions::from(export_target),
+                )
+                .map_err(|e| MetricsError::Other(e.to_string()))?,
+            );
+            if let Some(headers) = headers {
+                builder = builder.with_interceptor(headers.interceptor());
+            }
+            builder
+                .with_export_config(export_config)
+                .build_metrics_exporter(
+                    Box::new(DefaultAggregationSelector::new()),
+                    temporality_selector,
+                )
+        }
+        Protocol::HttpBinary => opentelemetry_otlp::new_exporter()
+            .http()
+            .with_http_client(transport::http_client(
+                transport::Connector::new(
+                    &export_target.dns,
+                    &export_target.socket_options,
+                    export_target.max_bytes_per_sec,
+                ),
+                transport::http_tls(
+                    &export_config.endpoint,
+                    &transport::TlsOptions::from(export_target),
+                )
+                .map_err(|e| MetricsError::Other(e.to_string()))?,
+                export_config.timeout,
+                export_target.signer.clone(),
+                headers,
+            ))
+            .with_export_config(export_config)
+            .with_headers(HashMap::from([(
+                http::header::USER_AGENT.as_str().to_owned(),
+                user_agent.to_owned(),
+            )]))
+            .build_metrics_exporter(
+                Box::new(DefaultAggregationSelector::new()),
+                temporality_selector,
+            ),
+    }
 }
 
 /// Setup the http server for the prometheus end point
 ///
 /// # Arguments
-/// * `http_port` - The port to listen on for http requests
-/// * `registry` - The prometheus registry that contains the metrics
+/// * `address` - The address to listen on for http requests
+/// * `server_options` - The limits protecting the end point against misbehaving scrapers, its diagnostics and
+///   response headers
+/// * `endpoint` - The prometheus registries and scrape sources that contain the metrics
+///
+/// * `shutdown` - Set to true to stop accepting connections and return once the open connections are served
 ///
 /// # Errors
-/// * `hyper::Error` - If the http server fails to start
-async fn httpserver_init(http_port: u16, registry: Registry) -> Result<(), hyper::Error> {
+/// * `std::io::Error` - If the http server fails to bind to `address`
+async fn httpserver_init(
+    address: SocketAddr,
+    server_options: ServerOptions,
+    endpoint: MetricsEndpoint,
+    mut shutdown: watch::Receiver<bool>,
+) -> std::io::Result<()> {
     info!("initializing prometheus metrics endpoint");
-    let router = axum::Router::new()
+    let middleware = tower::ServiceBuilder::new()
+        .layer(HandleErrorLayer::new(handle_server_error))
+        .option_layer(
+            server_options
+                .max_concurrent_scrapes
+                .map(|max_concurrent_scrapes| {
+                    tower::ServiceBuilder::new()
+                        .load_shed()
+                        .layer(GlobalConcurrencyLimitLayer::new(max_concurrent_scrapes))
+                }),
+        )
+        .option_layer(server_options.request_timeout.map(TimeoutLayer::new));
+    let mut router = axum::Router::new()
         .route("/metrics", axum::routing::get(metrics_handler))
-        .layer(Extension(registry));
-    axum::Server::bind(&([0u8; 4], http_port).into())
-        .serve(router.into_make_service())
-        .await
+        .layer(middleware)
+        .layer(Extension(endpoint));
+    if let Some(max_request_bytes) = server_options.max_request_bytes {
+        router = router.layer(axum::middleware::from_fn(
+            move |request: Request, next: Next| {
+                limit_request_size(request, next, max_request_bytes)
+            },
+        ));
+    }
+    let scrape_metrics = Arc::new(ScrapeMetrics::new(
+        address.port(),
+        server_options.log_scrapes,
+    ));
+    router = router.layer(axum::middleware::from_fn(
+        move |request: Request, next: Next| {
+            let scrape_metrics = scrape_metrics.clone();
+            async move { scrape_metrics.observe(request, next).await }
+        },
+    ));
+    if let Some(unready_after_failures) = server_options.unready_after_failures {
+        // The probes are not counted as scrapes.
+        router = router
+            .route(
+                "/healthz",
+                axum::routing::get(move || async move {
+                    Json(target_health::report(unready_after_failures).1)
+                }),
+            )
+            .route(
+                "/readyz",
+                axum::routing::get(move || async move {
+                    let (ready, report) = target_health::report(unready_after_failures);
+                    let status = if ready {
+                        StatusCode::OK
+                    } else {
+                        StatusCode::SERVICE_UNAVAILABLE
+                    };
+                    (status, Json(report))
+                }),
+            );
+    }
+    if let Some(auth) = server_options.auth {
+        router = router.layer(axum::middleware::from_fn(
+            move |request: Request, next: Next| {
+                let auth = auth.clone();
+                async move { auth.apply(request, next).await }
+            },
+        ));
+    }
+    let headers = server_options.headers;
+    router = router.layer(axum::middleware::from_fn(
+        move |request: Request, next: Next| {
+            let headers = headers.clone();
+            async move { headers.apply(request, next).await }
+        },
+    ));
+
+    let listener = TcpListener::bind(address).await?;
+    let mut builder = http1::Builder::new();
+    if let Some(max_request_bytes) = server_options.max_request_bytes {
+        // hyper doesn't accept buffers smaller than 8KiB.
+        builder.max_buf_size(max_request_bytes.max(8192));
+    }
+    let graceful = GracefulShutdown::new();
+    let mut handshakes = FuturesUnordered::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => match &server_options.tls {
+                    // The handshake is awaited here rather than in the task serving the connection, so that the
+                    // connection is watched for the graceful shutdown once it completes.
+                    Some(tls) => handshakes.push(tls_handshake(tls.clone(), stream, peer)),
+                    None => serve_connection(&builder, &graceful, &router, stream, peer),
+                },
+                Err(e) => warn!("unable to accept connection on {} due to: {:?}", address, e),
+            },
+            Some((handshake, peer)) = handshakes.next() => match handshake {
+                Ok(stream) => serve_connection(&builder, &graceful, &router, stream, peer),
+                Err(e) => debug!("tls handshake with {} failed due to: {:?}", peer, e),
+            },
+            _ = shutdown.wait_for(|shutdown| *shutdown) => break,
+        }
+    }
+    info!("shutting down prometheus metrics endpoint on {address}");
+    drop(listener);
+    graceful.shutdown().await;
+    Ok(())
+}
+
+/// Serve the requests of `stream`, accepted from `peer`, with `router` until it's closed or `graceful` shuts down.
+fn serve_connection<S>(
+    builder: &http1::Builder,
+    graceful: &GracefulShutdown,
+    router: &axum::Router,
+    stream: S,
+    peer: SocketAddr,
+) where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let service = router.clone().map_request(move |mut request: Request<_>| {
+        request.extensions_mut().insert(ConnectInfo(peer));
+        request
+    });
+    let connection = graceful
+        .watch(builder.serve_connection(TokioIo::new(stream), TowerToHyperService::new(service)));
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!("connection from {} failed due to: {:?}", peer, e);
+        }
+    });
+}
+
+/// Accept the TLS connection of `stream` from `peer`, giving up after `TLS_HANDSHAKE_TIMEOUT`.
+async fn tls_handshake(
+    tls: TlsAcceptor,
+    stream: TcpStream,
+    peer: SocketAddr,
+) -> (std::io::Result<TlsStream<TcpStream>>, SocketAddr) {
+    let handshake = match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+        Ok(handshake) => handshake,
+        Err(e) => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, e)),
+    };
+    (handshake, peer)
+}
+
+/// Map the errors of the end point's middleware to responses.
+async fn handle_server_error(error: tower::BoxError) -> (StatusCode, String) {
+    if error.is::<tower::timeout::error::Elapsed>() {
+        (StatusCode::REQUEST_TIMEOUT, "scrape timed out".to_owned())
+    } else if error.is::<tower::load_shed::error::Overloaded>() {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "too many concurrent scrapes".to_owned(),
+        )
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
+    }
+}
+
+/// Reject requests whose body is announced to be larger than `max_request_bytes`.
+async fn limit_request_size(
+    request: Request,
+    next: Next,
+    max_request_bytes: usize,
+) -> axum::response::Response {
+    let content_length = request
+        .headers()
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|content_length| content_length.to_str().ok())
+        .and_then(|content_length| content_length.parse::<usize>().ok());
+    if content_length.is_some_and(|content_length| content_length > max_request_bytes) {
+        return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+    }
+    next.run(request).await
 }
 
 async fn metrics_handler(
-    Extension(data): Extension<Registry>,
-) -> axum::response::Result<impl axum::response::IntoResponse> {
+    Extension(endpoint): Extension<MetricsEndpoint>,
+    headers: http::HeaderMap,
+) -> axum::response::Response {
+    let gzip = endpoint.gzip
+        && headers
+            .get(http::header::ACCEPT_ENCODING)
+            .and_then(|accept_encoding| accept_encoding.to_str().ok())
+            .is_some_and(accepts_gzip);
+    let body = match &endpoint.cache {
+        Some(cache) => cache.body(gzip, encode_metrics(&endpoint)).await,
+        None => match encode_metrics(&endpoint).await {
+            Ok(text) => Payload::new(text)
+                .body(gzip)
+                .map_err(prometheus::Error::from),
+            Err(e) => Err(e),
+        },
+    };
+    let body = match body {
+        Ok(body) => body,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [(http::header::CONTENT_TYPE, "text".to_string())],
+                e.to_string(),
+            )
+                .into_response();
+        }
+    };
+
+    let mut response = (
+        StatusCode::OK,
+        [(
+            http::header::CONTENT_TYPE,
+            TextEncoder::new().format_type().to_owned(),
+        )],
+        body,
+    )
+        .into_response();
+    if endpoint.gzip {
+        response.headers_mut().insert(
+            http::header::VARY,
+            http::HeaderValue::from_static("accept-encoding"),
+        );
+    }
+    if gzip {
+        response.headers_mut().insert(
+            http::header::CONTENT_ENCODING,
+            http::HeaderValue::from_static("gzip"),
+        );
+    }
+    response
+}
+
+/// Encode the metrics of the registries and the scrape sources in the prometheus text format.
+async fn encode_metrics(endpoint: &MetricsEndpoint) -> Result<Vec<u8>, prometheus::Error> {
     let mut buffer = vec![];
     let encoder = TextEncoder::new();
-    let metric_families = data.gather();
-    match encoder.encode(&metric_families, &mut buffer) {
-        Ok(()) => {
-            let content_type = encoder.format_type().to_owned();
-            let body = String::from_utf8_lossy(&buffer).into_owned();
-            Ok((
-                StatusCode::OK,
-                [(http::header::CONTENT_TYPE, content_type)],
-                body,
-            ))
+    let mut metric_families = endpoint.registry.gather();
+    for registry in endpoint
+        .sibling_registries
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+    {
+        metric_families.extend(registry.gather());
+    }
+    let scrapes = endpoint
+        .scrape_sources
+        .iter()
+        .map(|source| fetch_scrape_source(&endpoint.client, source));
+    for (source, scrape) in endpoint.scrape_sources.iter().zip(join_all(scrapes).await) {
+        match scrape.and_then(|body| Ok(prometheus_text::parse(std::str::from_utf8(&body)?)?)) {
+            Ok(families) => metric_families.extend(families),
+            Err(e) => warn!("unable to scrape {} due to: {:?}", source, e),
+        }
+    }
+
+    let mut metric_families = merge_metric_families(metric_families);
+    let quantile_families = endpoint.quantiles.metric_families(&metric_families);
+    metric_families.extend(quantile_families);
+    if let Some(filter) = &endpoint.filter {
+        filter.filter_families(&mut metric_families);
+    }
+    encoder.encode(&metric_families, &mut buffer)?;
+    Ok(buffer)
+}
+
+/// Merge metric families with the same name, as the text format requires each family to appear only once.
+/// Families whose type conflicts with an earlier family of the same name are dropped.
+fn merge_metric_families(metric_families: Vec<MetricFamily>) -> Vec<MetricFamily> {
+    let mut merged: Vec<MetricFamily> = Vec::with_capacity(metric_families.len());
+    for mut metric_family in metric_families {
+        match merged
+            .iter_mut()
+            .find(|merged_family| merged_family.get_name() == metric_family.get_name())
+        {
+            Some(merged_family)
+                if merged_family.get_field_type() == metric_family.get_field_type() =>
+            {
+                for metric in metric_family.take_metric() {
+                    merged_family.mut_metric().push(metric);
+                }
+            }
+            Some(_) => warn!(
+                "dropping metric family {} as its type conflicts with another registry or scrape source",
+                metric_family.get_name()
+            ),
+            None => merged.push(metric_family),
         }
-        Err(e) => Ok((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            [(http::header::CONTENT_TYPE, "text".to_string())],
-            e.to_string(),
-        )),
+    }
+    merged
+}
+
+/// Fetch the metrics served by a scrape source in the prometheus text format.
+async fn fetch_scrape_source(
+    client: &Client<HttpConnector, Empty<Bytes>>,
+    url: &str,
+) -> Result<Bytes, Box<dyn std::error::Error + Send + Sync>> {
+    let uri: http::Uri = url.parse()?;
+    let response = tokio::time::timeout(SCRAPE_SOURCE_TIMEOUT, client.get(uri)).await??;
+    if !response.status().is_success() {
+        return Err(format!("unexpected status {}", response.status()).into());
+    }
+    Ok(response.into_body().collect().await?.to_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::proto::{Counter, Gauge, Metric, MetricType};
+
+    use super::*;
+
+    fn family(name: &str, metric_type: MetricType, value: f64) -> MetricFamily {
+        let mut metric = Metric::default();
+        if metric_type == MetricType::COUNTER {
+            let mut counter = Counter::default();
+            counter.set_value(value);
+            metric.set_counter(counter);
+        } else {
+            let mut gauge = Gauge::default();
+            gauge.set_value(value);
+            metric.set_gauge(gauge);
+        }
+        let mut family = MetricFamily::default();
+        family.set_name(name.to_owned());
+        family.set_field_type(metric_type);
+        family.mut_metric().push(metric);
+        family
+    }
+
+    #[test]
+    fn families_with_the_same_name_are_merged() {
+        let merged = merge_metric_families(vec![
+            family("requests", MetricType::COUNTER, 1.0),
+            family("memory", MetricType::GAUGE, 2.0),
+            family("requests", MetricType::COUNTER, 3.0),
+        ]);
+        let names: Vec<_> = merged.iter().map(MetricFamily::get_name).collect();
+        assert_eq!(names, ["requests", "memory"]);
+        assert_eq!(merged[0].get_metric().len(), 2);
+    }
+
+    #[test]
+    fn families_with_conflicting_types_are_dropped() {
+        let merged = merge_metric_families(vec![
+            family("requests", MetricType::COUNTER, 1.0),
+            family("requests", MetricType::GAUGE, 2.0),
+        ]);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].get_field_type(), MetricType::COUNTER);
+        assert_eq!(merged[0].get_metric().len(), 1);
     }
 }
@@ -0,0 +1,78 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The effective settings of the export pipelines, so operators can verify that the configuration and the
+//! `OTEL_*` environment variables took effect.
+
+use std::time::Duration;
+
+use crate::config::{Governance, LogRecordLimits};
+
+/// Environment variable overriding the maximum queue size of batched log targets.
+const OTEL_BLRP_MAX_QUEUE_SIZE: &str = "OTEL_BLRP_MAX_QUEUE_SIZE";
+/// Default maximum queue size of batched log targets.
+const OTEL_BLRP_MAX_QUEUE_SIZE_DEFAULT: usize = 2_048;
+/// Environment variable overriding the maximum batch size of batched log targets.
+const OTEL_BLRP_MAX_EXPORT_BATCH_SIZE: &str = "OTEL_BLRP_MAX_EXPORT_BATCH_SIZE";
+/// Default maximum batch size of batched log targets.
+const OTEL_BLRP_MAX_EXPORT_BATCH_SIZE_DEFAULT: usize = 512;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+/// The effective settings of all pipelines. Targets that are disabled, or that failed to initialize, are not listed.
+pub struct Limits {
+    /// settings of the metrics export targets.
+    pub metrics_targets: Vec<MetricsPipelineLimits>,
+    /// settings of the log export targets.
+    pub log_targets: Vec<LogPipelineLimits>,
+    /// settings of the audit targets.
+    pub audit_targets: Vec<LogPipelineLimits>,
+    /// limits applied to the attributes of log records.
+    pub log_record_limits: LogRecordLimits,
+    /// hard caps on the telemetry of the component.
+    pub governance: Governance,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// The effective settings of a metrics export target.
+pub struct MetricsPipelineLimits {
+    /// label of the target, its name or url.
+    pub target: String,
+    /// interval between two exports.
+    pub interval: Duration,
+    /// timeout of an export.
+    pub export_timeout: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// The effective settings of a log export target.
+pub struct LogPipelineLimits {
+    /// label of the target, its name or url.
+    pub target: String,
+    /// maximum number of records queued before records are dropped.
+    pub max_queue_size: usize,
+    /// maximum number of records per export.
+    pub max_export_batch_size: usize,
+    /// delay between two exports.
+    pub scheduled_delay: Duration,
+    /// timeout of an export.
+    pub max_export_timeout: Duration,
+}
+
+/// The queue and batch sizes of batched log targets with a queue of `max_queue_size` records, or the queue size
+/// given by the environment. As in the SDK, the batch size is capped at the queue size.
+pub(crate) fn batch_sizes(max_queue_size: Option<usize>) -> (usize, usize) {
+    let max_queue_size = max_queue_size
+        .unwrap_or_else(|| env_or(OTEL_BLRP_MAX_QUEUE_SIZE, OTEL_BLRP_MAX_QUEUE_SIZE_DEFAULT));
+    let max_export_batch_size = env_or(
+        OTEL_BLRP_MAX_EXPORT_BATCH_SIZE,
+        OTEL_BLRP_MAX_EXPORT_BATCH_SIZE_DEFAULT,
+    );
+    (max_queue_size, max_export_batch_size.min(max_queue_size))
+}
+
+fn env_or(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
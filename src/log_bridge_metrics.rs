@@ -0,0 +1,98 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::Duration,
+};
+
+use opentelemetry::{
+    global,
+    metrics::{Histogram, Unit},
+};
+
+/// Self-telemetry of the bridge of the `log` crate, to quantify the overhead of logging.
+///
+/// The bridge is installed before the meter provider is set, so records are counted and reported by observable
+/// counters, and formatting times are only recorded once the metrics are registered.
+#[derive(Debug, Default)]
+pub(crate) struct LogBridgeMetrics {
+    received: AtomicU64,
+    filtered: AtomicU64,
+    formatted: AtomicU64,
+    format_duration: OnceLock<Histogram<f64>>,
+}
+
+impl LogBridgeMetrics {
+    /// Count a record received by the bridge.
+    pub(crate) fn received(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a record received by the bridge and filtered out by the level filter.
+    pub(crate) fn filtered(&self) {
+        self.received();
+        self.filtered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a record formatted for stderr in `duration`.
+    pub(crate) fn formatted(&self, duration: Duration) {
+        self.formatted.fetch_add(1, Ordering::Relaxed);
+        if let Some(format_duration) = self.format_duration.get() {
+            format_duration.record(duration.as_secs_f64(), &[]);
+        }
+    }
+
+    /// Create the instruments of the bridge, once the meter provider is set.
+    pub(crate) fn register_metrics(self: &Arc<Self>) {
+        let meter = global::meter("otel-lib");
+        let counters = [
+            (
+                "otel_lib.logs.bridge.received",
+                "Number of log records received by the log bridge",
+                Self::received_count as fn(&Self) -> u64,
+            ),
+            (
+                "otel_lib.logs.bridge.filtered",
+                "Number of log records filtered out by the level filter of the log bridge",
+                Self::filtered_count,
+            ),
+            (
+                "otel_lib.logs.bridge.formatted",
+                "Number of log records formatted for stderr by the log bridge",
+                Self::formatted_count,
+            ),
+        ];
+        for (name, description, count) in counters {
+            let metrics = self.clone();
+            meter
+                .u64_observable_counter(name)
+                .with_description(description)
+                .with_unit(Unit::new("{record}"))
+                .with_callback(move |observer| observer.observe(count(&metrics), &[]))
+                .init();
+        }
+        let _ = self.format_duration.set(
+            meter
+                .f64_histogram("otel_lib.logs.bridge.format_duration")
+                .with_description("Time taken by the log bridge to format a log record for stderr")
+                .with_unit(Unit::new("s"))
+                .init(),
+        );
+    }
+
+    fn received_count(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    fn filtered_count(&self) -> u64 {
+        self.filtered.load(Ordering::Relaxed)
+    }
+
+    fn formatted_count(&self) -> u64 {
+        self.formatted.load(Ordering::Relaxed)
+    }
+}
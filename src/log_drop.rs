@@ -0,0 +1,99 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use opentelemetry::{global, metrics::Unit, KeyValue};
+use regex::Regex;
+
+use crate::config::DropRule;
+
+const DROP_RULE_KEY: &str = "otel_lib.drop_rule";
+
+/// The [`DropRule`]s of the config, which drop known-noisy records of the `log` crate before they are written to
+/// stderr or queued for export, with the number of records each rule dropped.
+#[derive(Debug, Default)]
+pub(crate) struct DropRules {
+    rules: Vec<CompiledRule>,
+}
+
+#[derive(Debug)]
+struct CompiledRule {
+    target: Option<Regex>,
+    body: Option<Regex>,
+    dropped: AtomicU64,
+}
+
+impl DropRules {
+    /// Compile `rules`. Rules with an invalid regular expression are reported and don't drop any records.
+    pub(crate) fn new(rules: &[DropRule]) -> Self {
+        DropRules {
+            rules: rules
+                .iter()
+                .enumerate()
+                .map(|(index, rule)| {
+                    let compile = |regex: &Option<String>| regex.as_deref().map(Regex::new).transpose();
+                    let (target, body) = match (compile(&rule.target), compile(&rule.body)) {
+                        (Ok(target), Ok(body)) => (target, body),
+                        (Err(e), _) | (_, Err(e)) => {
+                            eprintln!("ignoring log drop rule {index} with an invalid regular expression: {e}");
+                            (None, None)
+                        }
+                    };
+                    CompiledRule {
+                        target,
+                        body,
+                        dropped: AtomicU64::new(0),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether `record` matches a rule and is dropped, counting it if so.
+    pub(crate) fn drops(&self, record: &log::Record<'_>) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let mut body = None;
+        self.rules
+            .iter()
+            .find(|rule| {
+                (rule.target.is_some() || rule.body.is_some())
+                    && rule
+                        .target
+                        .as_ref()
+                        .is_none_or(|target| target.is_match(record.target()))
+                    && rule.body.as_ref().is_none_or(|regex| {
+                        regex.is_match(body.get_or_insert_with(|| record.args().to_string()))
+                    })
+            })
+            .map(|rule| rule.dropped.fetch_add(1, Ordering::Relaxed))
+            .is_some()
+    }
+
+    /// Report the number of records dropped by each rule as the `otel_lib.logs.dropped_by_rule` metric, once the
+    /// meter provider is set.
+    pub(crate) fn register_metrics(self: &Arc<Self>) {
+        if self.rules.is_empty() {
+            return;
+        }
+        let drop_rules = self.clone();
+        global::meter("otel-lib")
+            .u64_observable_counter("otel_lib.logs.dropped_by_rule")
+            .with_description("Number of log records dropped by the log drop rules")
+            .with_unit(Unit::new("{record}"))
+            .with_callback(move |observer| {
+                for (index, rule) in (0_i64..).zip(&drop_rules.rules) {
+                    observer.observe(
+                        rule.dropped.load(Ordering::Relaxed),
+                        &[KeyValue::new(DROP_RULE_KEY, index)],
+                    );
+                }
+            })
+            .init();
+    }
+}
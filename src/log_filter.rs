@@ -0,0 +1,164 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Filtering of log records by the sinks they are written to, according to a [`LogFilter`].
+//!
+//! Records reach the log processors without the target of the `log` crate record they were bridged from, so the
+//! bridge makes the target available to the processors while it emits a record.
+
+use std::cell::RefCell;
+
+use env_filter::Filter;
+use log::Level;
+use opentelemetry::{
+    logs::{AnyValue, LogResult, Severity},
+    Key,
+};
+use opentelemetry_sdk::{export::logs::LogData, logs::LogProcessor};
+
+use crate::config::LogFilter;
+
+thread_local! {
+    /// The target of the `log` crate record being emitted on this thread, if any.
+    static CURRENT_TARGET: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Run `emit` with `target` as the target of the records it emits.
+pub(crate) fn with_target<R>(target: &str, emit: impl FnOnce() -> R) -> R {
+    let previous = CURRENT_TARGET.with(|current| current.replace(Some(target.to_owned())));
+    let result = emit();
+    CURRENT_TARGET.with(|current| current.replace(previous));
+    result
+}
+
+/// Whether a record of the `log` crate is being emitted on this thread.
+pub(crate) fn bridging() -> bool {
+    CURRENT_TARGET.with(|current| current.borrow().is_some())
+}
+
+/// A [`LogFilter`] ready to be matched against records.
+#[derive(Debug)]
+pub(crate) struct RecordFilter {
+    severity: Option<Severity>,
+    directives: Option<Filter>,
+    attributes: Vec<(String, String)>,
+}
+
+impl RecordFilter {
+    pub(crate) fn new(filter: &LogFilter) -> Self {
+        RecordFilter {
+            severity: filter.severity,
+            directives: filter
+                .directives
+                .as_deref()
+                .map(|directives| env_filter::Builder::new().parse(directives).build()),
+            attributes: filter
+                .attributes
+                .iter()
+                .map(|attribute| (attribute.key.clone(), attribute.value.clone()))
+                .collect(),
+        }
+    }
+
+    /// Whether a record passes the filter. Severities are given by number, 0 meaning that the record has none, and
+    /// `target` is the target of records of the `log` crate. `attribute` returns the value of an attribute of the
+    /// record, formatted as a string.
+    pub(crate) fn matches(
+        &self,
+        severity_number: i32,
+        target: Option<&str>,
+        attribute: impl Fn(&str) -> Option<String>,
+    ) -> bool {
+        // Records without a severity don't pass a severity filter.
+        if let Some(severity) = self.severity {
+            if severity_number < severity as i32 {
+                return false;
+            }
+        }
+        if let (Some(directives), Some(target)) = (&self.directives, target) {
+            let Some(level) = level(severity_number) else {
+                return false;
+            };
+            if !directives.matches(&log::Record::builder().level(level).target(target).build()) {
+                return false;
+            }
+        }
+        self.attributes
+            .iter()
+            .all(|(key, value)| attribute(key).is_some_and(|actual| actual == *value))
+    }
+
+    /// Whether `data` passes the filter.
+    pub(crate) fn matches_data(&self, data: &LogData) -> bool {
+        CURRENT_TARGET.with(|target| {
+            self.matches(
+                data.record
+                    .severity_number
+                    .map_or(0, |severity| severity as i32),
+                target.borrow().as_deref(),
+                |key| attribute_value(data.record.attributes.as_deref()?, key),
+            )
+        })
+    }
+}
+
+/// The `log` crate level of a severity number.
+fn level(severity_number: i32) -> Option<Level> {
+    match severity_number {
+        1..=4 => Some(Level::Trace),
+        5..=8 => Some(Level::Debug),
+        9..=12 => Some(Level::Info),
+        13..=16 => Some(Level::Warn),
+        17..=24 => Some(Level::Error),
+        _ => None,
+    }
+}
+
+/// The value of the scalar attribute `key` of `attributes`, formatted as a string.
+pub(crate) fn attribute_value(attributes: &[(Key, AnyValue)], key: &str) -> Option<String> {
+    let (_, value) = attributes.iter().find(|(k, _)| k.as_str() == key)?;
+    match value {
+        AnyValue::String(value) => Some(value.as_str().to_owned()),
+        AnyValue::Int(value) => Some(value.to_string()),
+        AnyValue::Double(value) => Some(value.to_string()),
+        AnyValue::Boolean(value) => Some(value.to_string()),
+        AnyValue::Bytes(_) | AnyValue::ListAny(_) | AnyValue::Map(_) => None,
+    }
+}
+
+/// A log processor that only passes the records that match the filter of a sink on to its processor, before they
+/// are queued.
+#[derive(Debug)]
+pub(crate) struct FilteredLogProcessor<P> {
+    inner: P,
+    filter: RecordFilter,
+}
+
+impl<P> FilteredLogProcessor<P> {
+    pub(crate) fn new(inner: P, filter: &LogFilter) -> Self {
+        FilteredLogProcessor {
+            inner,
+            filter: RecordFilter::new(filter),
+        }
+    }
+}
+
+impl<P: LogProcessor> LogProcessor for FilteredLogProcessor<P> {
+    fn emit(&self, data: LogData) {
+        if self.filter.matches_data(&data) {
+            self.inner.emit(data);
+        }
+    }
+
+    fn force_flush(&self) -> LogResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> LogResult<()> {
+        self.inner.shutdown()
+    }
+
+    fn event_enabled(&self, level: Severity, target: &str, name: &str) -> bool {
+        self.inner.event_enabled(level, target, name)
+    }
+}
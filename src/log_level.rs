@@ -0,0 +1,79 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Adjustment of the log level at runtime, so operators can turn on debug logging for a misbehaving module without
+//! restarting the process.
+//!
+//! [`Otel::log_level`](crate::Otel::log_level) returns a [`LogLevelHandle`] that replaces the logging directives of
+//! `Config::level` in the installed logger. The handle can be cloned and handed to e.g. an admin end point.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::global_logger;
+
+/// A handle to the log level of the logger installed by [`Otel`](crate::Otel).
+#[derive(Clone, Debug)]
+pub struct LogLevelHandle {
+    directives: Arc<Mutex<String>>,
+}
+
+impl LogLevelHandle {
+    pub(crate) fn new(directives: String) -> Self {
+        LogLevelHandle {
+            directives: Arc::new(Mutex::new(directives)),
+        }
+    }
+
+    /// The logging directives in effect.
+    pub fn level(&self) -> String {
+        self.directives
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Replace the logging directives, in the format of `Config::level`, e.g. `info,my_crate::db=debug`. Records
+    /// logged from then on are filtered by the new directives. The log filters of the sinks are not affected.
+    ///
+    /// # Errors
+    /// * `LogLevelError` - If `directives` are invalid, or no logger of this library is installed. The directives in
+    ///   effect are kept in that case.
+    pub fn set_level(&self, directives: &str) -> Result<(), LogLevelError> {
+        let filter = env_filter::Builder::new()
+            .try_parse(directives)
+            .map_err(|e| LogLevelError::Invalid(e.to_string()))?
+            .build();
+        let mut current = self
+            .directives
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !global_logger::set_filter(filter) {
+            return Err(LogLevelError::NotInstalled);
+        }
+        directives.clone_into(&mut current);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The error of changing the log level.
+pub enum LogLevelError {
+    /// The directives can't be parsed.
+    Invalid(String),
+    /// No logger of this library is installed, for example as another library installed a global logger first.
+    NotInstalled,
+}
+
+impl fmt::Display for LogLevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogLevelError::Invalid(e) => write!(f, "invalid logging directives: {e}"),
+            LogLevelError::NotInstalled => write!(f, "no logger of otel-lib is installed"),
+        }
+    }
+}
+
+impl std::error::Error for LogLevelError {}
@@ -0,0 +1,90 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::error;
+use opentelemetry::logs::{AnyValue, LogResult, Severity};
+use opentelemetry_sdk::{export::logs::LogData, logs::LogProcessor};
+
+use crate::config::LogRecordLimits;
+
+/// A log processor that enforces limits on records before passing them on to the processor of a target.
+#[derive(Debug)]
+pub(crate) struct LimitedLogProcessor<P> {
+    inner: P,
+    limits: LogRecordLimits,
+    max_attributes: Option<usize>,
+    reported: AtomicBool,
+}
+
+impl<P> LimitedLogProcessor<P> {
+    /// Create a new processor. Records are limited according to `limits`, and attributes beyond the governance cap
+    /// `max_attributes` are dropped and reported as an error.
+    pub(crate) fn new(inner: P, limits: LogRecordLimits, max_attributes: Option<usize>) -> Self {
+        LimitedLogProcessor {
+            inner,
+            limits,
+            max_attributes,
+            reported: AtomicBool::new(false),
+        }
+    }
+}
+
+impl<P: LogProcessor> LogProcessor for LimitedLogProcessor<P> {
+    fn emit(&self, mut data: LogData) {
+        if let Some(attributes) = data.record.attributes.as_mut() {
+            if let Some(max_attributes) = self.max_attributes {
+                if attributes.len() > max_attributes {
+                    attributes.truncate(max_attributes);
+                    // Only report the first record, as the report is a log record itself.
+                    if !self.reported.swap(true, Ordering::Relaxed) {
+                        error!(
+                            "dropping attributes of log records beyond the maximum of {max_attributes} attributes per record"
+                        );
+                    }
+                }
+            }
+            if let Some(attribute_count_limit) = self.limits.attribute_count_limit {
+                attributes.truncate(attribute_count_limit);
+            }
+            if let Some(attribute_value_length_limit) = self.limits.attribute_value_length_limit {
+                for (_, value) in attributes.iter_mut() {
+                    truncate_value(value, attribute_value_length_limit);
+                }
+            }
+        }
+        self.inner.emit(data);
+    }
+
+    fn force_flush(&self) -> LogResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> LogResult<()> {
+        self.inner.shutdown()
+    }
+
+    fn event_enabled(&self, level: Severity, target: &str, name: &str) -> bool {
+        self.inner.event_enabled(level, target, name)
+    }
+}
+
+/// Truncate a string value, or the strings in an array value, to `limit` characters.
+fn truncate_value(value: &mut AnyValue, limit: usize) {
+    match value {
+        AnyValue::String(string) => {
+            if let Some((index, _)) = string.as_str().char_indices().nth(limit) {
+                *string = string.as_str()[..index].to_owned().into();
+            }
+        }
+        AnyValue::ListAny(values) => {
+            for value in values {
+                if matches!(value, AnyValue::String(_)) {
+                    truncate_value(value, limit);
+                }
+            }
+        }
+        _ => {}
+    }
+}
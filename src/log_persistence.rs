@@ -0,0 +1,429 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Persistence of the log batches that fail to export, for edge deployments that lose connectivity for hours.
+//
+// A batch that fails to export is written to the persistence directory of its target as a serialized OTLP request,
+// one file per batch, named by a sequence number. Each export first replays up to `MAX_REPLAYED_BATCHES` persisted
+// batches, oldest first, and stops at the first that fails again. The batch being exported is persisted after them if
+// any are left, so records reach the target in the order they were emitted, and the following exports replay the
+// rest. That way an export doesn't outlast the export timeout of the pipeline after a long outage. Batches persisted
+// when the process exits are replayed after it restarts. The persistence directory is a disk buffer, which discards
+// the oldest batches beyond `max_disk_bytes` and the batches older than `max_disk_age_secs`, and encrypts them with
+// the key of `persistence_key_file`, if set. Its files are read, written and synced on the blocking threads of the
+// runtime, so that a slow disk doesn't stall the exports of other targets.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use log::warn;
+use opentelemetry::{
+    logs::{AnyValue, LogRecord, LogResult, Severity, TraceContext},
+    trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState},
+    Array, InstrumentationLibrary, Key, KeyValue, StringValue, Value,
+};
+use opentelemetry_proto::tonic::{
+    collector::logs::v1::ExportLogsServiceRequest,
+    common::v1::{any_value, AnyValue as ProtoAnyValue, KeyValue as ProtoKeyValue},
+    logs::v1::LogRecord as ProtoLogRecord,
+};
+use opentelemetry_sdk::{
+    export::logs::{LogData, LogExporter},
+    Resource,
+};
+use prost::Message;
+
+use crate::{
+    config::LogsExportTarget,
+    disk_encryption::Cipher,
+    disk_retention::{self, DiskBuffer, Quotas},
+    exporter_metrics::{self, Retry},
+};
+
+/// The maximum number of persisted batches replayed by an export.
+const MAX_REPLAYED_BATCHES: usize = 16;
+
+/// The batches of a target persisted in its persistence directory.
+#[derive(Debug)]
+pub(crate) struct LogPersistence {
+    target: String,
+    buffer: Arc<Mutex<DiskBuffer>>,
+}
+
+impl LogPersistence {
+    fn is_empty(&self) -> bool {
+        self.buffer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_empty()
+    }
+
+    /// Run `f` with the buffer on a blocking thread, as it reads, writes and syncs files.
+    async fn with_buffer<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut DiskBuffer) -> T + Send + 'static,
+    ) -> io::Result<T> {
+        let buffer = self.buffer.clone();
+        disk_retention::blocking(move || {
+            f(&mut buffer.lock().unwrap_or_else(PoisonError::into_inner))
+        })
+        .await
+    }
+
+    /// Persist `batch` after the persisted batches.
+    async fn persist(&self, batch: Vec<LogData>) {
+        let request = ExportLogsServiceRequest {
+            resource_logs: batch.into_iter().map(Into::into).collect(),
+        }
+        .encode_to_vec();
+        if let Err(e) = self.with_buffer(move |buffer| buffer.write(&request)).await {
+            warn!(
+                "unable to persist a failed export to {} due to {e:?}",
+                self.target
+            );
+        }
+    }
+
+    /// Read the oldest persisted batch, if any.
+    async fn oldest(&self) -> Option<io::Result<Vec<LogData>>> {
+        let oldest = match self.with_buffer(DiskBuffer::oldest).await {
+            Ok(oldest) => oldest?,
+            Err(e) => {
+                // The batch is left in place, and replayed by the next export.
+                warn!(
+                    "unable to read the persisted exports to {} due to {e:?}",
+                    self.target
+                );
+                return None;
+            }
+        };
+        Some(oldest.and_then(|bytes| {
+            ExportLogsServiceRequest::decode(bytes.as_slice())
+                .map(decode_request)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }))
+    }
+
+    /// Remove the oldest persisted batch, once it is exported.
+    async fn remove_oldest(&self) {
+        let _ = self.with_buffer(DiskBuffer::remove_oldest).await;
+    }
+
+    /// Discard the oldest persisted batch, as it can't be read.
+    async fn discard_oldest(&self) {
+        let _ = self.with_buffer(DiskBuffer::discard_oldest).await;
+    }
+
+    /// Report the batches that were discarded, once the persisted batches were replayed.
+    fn replayed(&self) {
+        let discarded = self
+            .buffer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take_discarded();
+        if discarded > 0 {
+            warn!(
+                "{discarded} failed exports to {} were discarded rather than persisted while it was failing",
+                self.target
+            );
+        }
+    }
+}
+
+/// The persisted batches of `export_target`, if it has a persistence directory.
+pub(crate) fn open(export_target: &LogsExportTarget) -> Result<Option<LogPersistence>, String> {
+    let Some(dir) = &export_target.persistence_dir else {
+        return Ok(None);
+    };
+    let quotas = Quotas {
+        max_bytes: export_target.max_disk_bytes,
+        max_age: export_target.max_disk_age_secs.map(Duration::from_secs),
+    };
+    let cipher = export_target
+        .persistence_key_file
+        .as_deref()
+        .map(Cipher::load)
+        .transpose()?;
+    DiskBuffer::open("logs", export_target.label(), dir, quotas, cipher)
+        .map(|buffer| {
+            Some(LogPersistence {
+                target: export_target.label().to_owned(),
+                buffer: Arc::new(Mutex::new(buffer)),
+            })
+        })
+        .map_err(|e| {
+            format!(
+                "unable to open persistence directory {} due to {e:?}",
+                dir.display()
+            )
+        })
+}
+
+/// A log exporter that persists the batches that fail to export, and replays them, if configured to.
+#[derive(Debug)]
+pub(crate) struct PersistentLogExporter<E> {
+    inner: E,
+    persistence: Option<LogPersistence>,
+}
+
+impl<E> PersistentLogExporter<E> {
+    pub(crate) fn new(inner: E, persistence: Option<LogPersistence>) -> Self {
+        PersistentLogExporter { inner, persistence }
+    }
+}
+
+#[async_trait]
+impl<E: LogExporter> LogExporter for PersistentLogExporter<E> {
+    async fn export(&mut self, batch: Vec<LogData>) -> LogResult<()> {
+        let Some(persistence) = &self.persistence else {
+            return self.inner.export(batch).await;
+        };
+        if !persistence.is_empty() {
+            let mut replayed = 0;
+            while replayed < MAX_REPLAYED_BATCHES {
+                let Some(persisted) = persistence.oldest().await else {
+                    break;
+                };
+                match persisted {
+                    Ok(persisted) => {
+                        exporter_metrics::record_retry("logs", &persistence.target, Retry::Export);
+                        if let Err(e) = self.inner.export(persisted).await {
+                            if !batch.is_empty() {
+                                persistence.persist(batch).await;
+                            }
+                            return Err(e);
+                        }
+                        replayed += 1;
+                    }
+                    Err(e) => {
+                        warn!(
+                            "discarding a persisted export to {} that can't be read due to {e:?}",
+                            persistence.target
+                        );
+                        persistence.discard_oldest().await;
+                        continue;
+                    }
+                }
+                persistence.remove_oldest().await;
+            }
+            if !persistence.is_empty() {
+                // The following exports replay the rest, and this batch after them.
+                if !batch.is_empty() {
+                    persistence.persist(batch).await;
+                }
+                return Ok(());
+            }
+            persistence.replayed();
+        }
+        if batch.is_empty() {
+            return self.inner.export(batch).await;
+        }
+        // The exporter takes the batch, so a copy is kept to persist it if the export fails.
+        let retained = batch.clone();
+        let result = self.inner.export(batch).await;
+        if result.is_err() {
+            persistence.persist(retained).await;
+        }
+        result
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+}
+
+/// The records of a persisted request.
+fn decode_request(request: ExportLogsServiceRequest) -> Vec<LogData> {
+    let mut batch = Vec::new();
+    for resource_logs in request.resource_logs {
+        let attributes = resource_logs
+            .resource
+            .map(|resource| resource.attributes)
+            .unwrap_or_default();
+        let resource: Cow<'static, Resource> = Cow::Owned(Resource::from_schema_url(
+            decode_key_values(attributes),
+            resource_logs.schema_url,
+        ));
+        for scope_logs in resource_logs.scope_logs {
+            let scope = scope_logs.scope.unwrap_or_default();
+            let instrumentation = InstrumentationLibrary::new(
+                scope.name,
+                (!scope.version.is_empty()).then_some(scope.version),
+                (!scope_logs.schema_url.is_empty()).then_some(scope_logs.schema_url),
+                Some(decode_key_values(scope.attributes)),
+            );
+            for record in scope_logs.log_records {
+                batch.push(LogData {
+                    record: decode_record(record),
+                    resource: resource.clone(),
+                    instrumentation: instrumentation.clone(),
+                });
+            }
+        }
+    }
+    batch
+}
+
+fn decode_record(record: ProtoLogRecord) -> LogRecord {
+    let mut decoded = LogRecord::default();
+    decoded.timestamp = (record.time_unix_nano != 0).then(|| from_nanos(record.time_unix_nano));
+    decoded.observed_timestamp = from_nanos(record.observed_time_unix_nano);
+    decoded.severity_number = severity(record.severity_number);
+    decoded.severity_text =
+        (!record.severity_text.is_empty()).then_some(Cow::Owned(record.severity_text));
+    decoded.body = record.body.and_then(decode_any_value);
+    decoded.attributes = (!record.attributes.is_empty()).then(|| {
+        record
+            .attributes
+            .into_iter()
+            .filter_map(|attribute| {
+                Some((
+                    Key::from(attribute.key),
+                    decode_any_value(attribute.value?)?,
+                ))
+            })
+            .collect()
+    });
+    if let (Ok(trace_id), Ok(span_id)) = (
+        <[u8; 16]>::try_from(record.trace_id.as_slice()),
+        <[u8; 8]>::try_from(record.span_id.as_slice()),
+    ) {
+        let span_context = SpanContext::new(
+            TraceId::from_bytes(trace_id),
+            SpanId::from_bytes(span_id),
+            TraceFlags::new(u8::try_from(record.flags & 0xff).unwrap_or_default()),
+            false,
+            TraceState::default(),
+        );
+        decoded.trace_context = Some(TraceContext::from(&span_context));
+    }
+    decoded
+}
+
+fn from_nanos(nanos: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos)
+}
+
+fn severity(number: i32) -> Option<Severity> {
+    Some(match number {
+        1 => Severity::Trace,
+        2 => Severity::Trace2,
+        3 => Severity::Trace3,
+        4 => Severity::Trace4,
+        5 => Severity::Debug,
+        6 => Severity::Debug2,
+        7 => Severity::Debug3,
+        8 => Severity::Debug4,
+        9 => Severity::Info,
+        10 => Severity::Info2,
+        11 => Severity::Info3,
+        12 => Severity::Info4,
+        13 => Severity::Warn,
+        14 => Severity::Warn2,
+        15 => Severity::Warn3,
+        16 => Severity::Warn4,
+        17 => Severity::Error,
+        18 => Severity::Error2,
+        19 => Severity::Error3,
+        20 => Severity::Error4,
+        21 => Severity::Fatal,
+        22 => Severity::Fatal2,
+        23 => Severity::Fatal3,
+        24 => Severity::Fatal4,
+        _ => return None,
+    })
+}
+
+fn decode_any_value(value: ProtoAnyValue) -> Option<AnyValue> {
+    Some(match value.value? {
+        any_value::Value::StringValue(value) => AnyValue::String(value.into()),
+        any_value::Value::BoolValue(value) => AnyValue::Boolean(value),
+        any_value::Value::IntValue(value) => AnyValue::Int(value),
+        any_value::Value::DoubleValue(value) => AnyValue::Double(value),
+        any_value::Value::BytesValue(value) => AnyValue::Bytes(value),
+        any_value::Value::ArrayValue(array) => AnyValue::ListAny(
+            array
+                .values
+                .into_iter()
+                .filter_map(decode_any_value)
+                .collect(),
+        ),
+        any_value::Value::KvlistValue(list) => AnyValue::Map(
+            list.values
+                .into_iter()
+                .filter_map(|entry| Some((Key::from(entry.key), decode_any_value(entry.value?)?)))
+                .collect::<HashMap<_, _>>(),
+        ),
+    })
+}
+
+/// The attributes of a resource or scope, which are encoded from values rather than log values.
+fn decode_key_values(attributes: Vec<ProtoKeyValue>) -> Vec<KeyValue> {
+    attributes
+        .into_iter()
+        .filter_map(|attribute| {
+            let value = match attribute.value?.value? {
+                any_value::Value::StringValue(value) => Value::from(value),
+                any_value::Value::BoolValue(value) => Value::Bool(value),
+                any_value::Value::IntValue(value) => Value::I64(value),
+                any_value::Value::DoubleValue(value) => Value::F64(value),
+                any_value::Value::ArrayValue(array) => Value::Array(decode_array(array.values)?),
+                any_value::Value::BytesValue(_) | any_value::Value::KvlistValue(_) => return None,
+            };
+            Some(KeyValue::new(attribute.key, value))
+        })
+        .collect()
+}
+
+/// An array of values of the same type.
+fn decode_array(values: Vec<ProtoAnyValue>) -> Option<Array> {
+    let values: Vec<any_value::Value> = values
+        .into_iter()
+        .map(|value| value.value)
+        .collect::<Option<_>>()?;
+    Some(match values.first() {
+        None | Some(any_value::Value::StringValue(_)) => Array::String(
+            values
+                .into_iter()
+                .map(|value| match value {
+                    any_value::Value::StringValue(value) => Some(StringValue::from(value)),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?,
+        ),
+        Some(any_value::Value::BoolValue(_)) => Array::Bool(
+            values
+                .into_iter()
+                .map(|value| match value {
+                    any_value::Value::BoolValue(value) => Some(value),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?,
+        ),
+        Some(any_value::Value::IntValue(_)) => Array::I64(
+            values
+                .into_iter()
+                .map(|value| match value {
+                    any_value::Value::IntValue(value) => Some(value),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?,
+        ),
+        Some(any_value::Value::DoubleValue(_)) => Array::F64(
+            values
+                .into_iter()
+                .map(|value| match value {
+                    any_value::Value::DoubleValue(value) => Some(value),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?,
+        ),
+        Some(_) => return None,
+    })
+}
@@ -0,0 +1,100 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use async_trait::async_trait;
+use opentelemetry::logs::{LogResult, Severity};
+use opentelemetry_sdk::{
+    export::logs::{LogData, LogExporter},
+    logs::LogProcessor,
+};
+
+use crate::exporter_metrics;
+
+/// The number of records of a target that are queued and not yet exported.
+#[derive(Debug)]
+pub(crate) struct QueueLength {
+    target: String,
+    max_queue_size: usize,
+    queued: AtomicUsize,
+}
+
+impl QueueLength {
+    pub(crate) fn new(target: String, max_queue_size: usize) -> Arc<Self> {
+        Arc::new(QueueLength {
+            target,
+            max_queue_size,
+            queued: AtomicUsize::new(0),
+        })
+    }
+}
+
+/// A log processor that drops the records that don't fit in the queue of its batch processor, and counts them as
+/// the `otel_lib.exporter.dropped` metric.
+///
+/// The batch processor of the SDK drops such records too, but only reports them to the global error handler, without
+/// the target. Here, a record is queued from when it is emitted until its batch is handed to the exporter, so at most
+/// `max_queue_size` records are queued, and the batch processor never has to drop any.
+#[derive(Debug)]
+pub(crate) struct QueueLimitedLogProcessor<P> {
+    inner: P,
+    queue: Arc<QueueLength>,
+}
+
+impl<P> QueueLimitedLogProcessor<P> {
+    pub(crate) fn new(inner: P, queue: Arc<QueueLength>) -> Self {
+        QueueLimitedLogProcessor { inner, queue }
+    }
+}
+
+impl<P: LogProcessor> LogProcessor for QueueLimitedLogProcessor<P> {
+    fn emit(&self, data: LogData) {
+        if self.queue.queued.fetch_add(1, Ordering::Relaxed) >= self.queue.max_queue_size {
+            self.queue.queued.fetch_sub(1, Ordering::Relaxed);
+            exporter_metrics::record_dropped(&self.queue.target);
+            return;
+        }
+        self.inner.emit(data);
+    }
+
+    fn force_flush(&self) -> LogResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> LogResult<()> {
+        self.inner.shutdown()
+    }
+
+    fn event_enabled(&self, level: Severity, target: &str, name: &str) -> bool {
+        self.inner.event_enabled(level, target, name)
+    }
+}
+
+/// A log exporter that dequeues the records of its batches from the queue of a [`QueueLimitedLogProcessor`].
+#[derive(Debug)]
+pub(crate) struct DequeuingLogExporter<E> {
+    inner: E,
+    queue: Arc<QueueLength>,
+}
+
+impl<E> DequeuingLogExporter<E> {
+    pub(crate) fn new(inner: E, queue: Arc<QueueLength>) -> Self {
+        DequeuingLogExporter { inner, queue }
+    }
+}
+
+#[async_trait]
+impl<E: LogExporter> LogExporter for DequeuingLogExporter<E> {
+    async fn export(&mut self, batch: Vec<LogData>) -> LogResult<()> {
+        self.queue.queued.fetch_sub(batch.len(), Ordering::Relaxed);
+        self.inner.export(batch).await
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+}
@@ -0,0 +1,128 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Routing of log records to specific sinks, according to the [`LogRoute`]s of the config.
+//!
+//! Each sink decides on its own whether a record is routed to it, so that the records of the `log` crate, events
+//! and forwarded records are routed alike. Records of the `log` crate routed to an audit target are also emitted to
+//! the audit pipeline by the bridge.
+
+use std::sync::Arc;
+
+use opentelemetry::logs::{LogResult, Severity};
+use opentelemetry_sdk::{export::logs::LogData, logs::LogProcessor};
+
+use crate::{
+    config::{LogRoute, LogSink},
+    log_filter::{self, RecordFilter},
+};
+
+/// The [`LogRoute`]s of the config, ready to be matched against records.
+#[derive(Debug, Default)]
+pub(crate) struct Router {
+    routes: Vec<(RecordFilter, Vec<LogSink>)>,
+}
+
+impl Router {
+    pub(crate) fn new(routes: &[LogRoute]) -> Self {
+        Router {
+            routes: routes
+                .iter()
+                .map(|route| (RecordFilter::new(&route.filter), route.sinks.clone()))
+                .collect(),
+        }
+    }
+
+    /// The sinks of a record, or `None` if no route matches it and it goes to the default sinks. The arguments are
+    /// those of [`RecordFilter::matches`].
+    pub(crate) fn route(
+        &self,
+        severity_number: i32,
+        target: Option<&str>,
+        attribute: impl Fn(&str) -> Option<String>,
+    ) -> Option<&[LogSink]> {
+        self.routes
+            .iter()
+            .find(|(filter, _)| filter.matches(severity_number, target, &attribute))
+            .map(|(_, sinks)| sinks.as_slice())
+    }
+
+    /// The sinks of `data`, or `None` if no route matches it.
+    fn route_data(&self, data: &LogData) -> Option<&[LogSink]> {
+        self.routes
+            .iter()
+            .find(|(filter, _)| filter.matches_data(data))
+            .map(|(_, sinks)| sinks.as_slice())
+    }
+
+    /// Whether a record with the sinks `route` is sent to the export target labelled `label`. Records that match no
+    /// route are sent to the log export targets but not to the audit targets.
+    pub(crate) fn routes_to(route: Option<&[LogSink]>, label: &str, audit: bool) -> bool {
+        route.map_or(!audit, |sinks| {
+            sinks
+                .iter()
+                .any(|sink| matches!(sink, LogSink::Target(target) if target == label))
+        })
+    }
+
+    /// Whether any of the sinks `route` are export or audit targets.
+    pub(crate) fn routes_to_targets(route: Option<&[LogSink]>) -> bool {
+        route.is_some_and(|sinks| sinks.iter().any(|sink| matches!(sink, LogSink::Target(_))))
+    }
+}
+
+/// A log processor that only passes the records routed to its export target on to its processor.
+#[derive(Debug)]
+pub(crate) struct RoutedLogProcessor<P> {
+    inner: P,
+    label: String,
+    router: Arc<Router>,
+    audit: bool,
+}
+
+impl<P> RoutedLogProcessor<P> {
+    /// Route the records to the log export target labelled `label`.
+    pub(crate) fn new(inner: P, label: String, router: Arc<Router>) -> Self {
+        RoutedLogProcessor {
+            inner,
+            label,
+            router,
+            audit: false,
+        }
+    }
+
+    /// Route the records to the audit target labelled `label`. Audit records are passed on regardless of the
+    /// routes, only the records of the `log` crate are routed.
+    pub(crate) fn audit(inner: P, label: String, router: Arc<Router>) -> Self {
+        RoutedLogProcessor {
+            inner,
+            label,
+            router,
+            audit: true,
+        }
+    }
+}
+
+impl<P: LogProcessor> LogProcessor for RoutedLogProcessor<P> {
+    fn emit(&self, data: LogData) {
+        if self.audit && !log_filter::bridging() {
+            self.inner.emit(data);
+            return;
+        }
+        if Router::routes_to(self.router.route_data(&data), &self.label, self.audit) {
+            self.inner.emit(data);
+        }
+    }
+
+    fn force_flush(&self) -> LogResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> LogResult<()> {
+        self.inner.shutdown()
+    }
+
+    fn event_enabled(&self, level: Severity, target: &str, name: &str) -> bool {
+        self.inner.event_enabled(level, target, name)
+    }
+}
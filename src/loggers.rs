@@ -1,34 +1,79 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-use std::time::{Duration, SystemTime};
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
 use crate::{
-    config::Config,
-    filtered_log_processor::{FilteredBatchConfig, FilteredBatchLogProcessor},
-    syslog_writer, SERVICE_NAME_KEY,
+    audit, baggage,
+    config::{Config, LogFilter, LogRoute, LogSink, LogsExportTarget},
+    debug_exporter::{CaptureFile, DebugLogExporter},
+    eager_connect_protocol, events,
+    export_failures::ExportFailures,
+    global_logger,
+    headers::ExportHeaders,
+    identity::{DeviceIdentity, IdentityLogExporter},
+    init::{InitComponent, InitReport},
+    limits::{self, LogPipelineLimits},
+    log_bridge_metrics::LogBridgeMetrics,
+    log_drop::DropRules,
+    log_filter::{self, FilteredLogProcessor, RecordFilter},
+    log_limits::LimitedLogProcessor,
+    log_persistence::{self, LogPersistence, PersistentLogExporter},
+    log_queue::{DequeuingLogExporter, QueueLength, QueueLimitedLogProcessor},
+    log_routing::{RoutedLogProcessor, Router},
+    protocol::ProtocolLogExporter,
+    resource_scope::ScopedLogExporter,
+    retry::RetryLogExporter,
+    runtime_targets::{LogTargets, LogTargetsProcessor},
+    schedule::ScheduledLogExporter,
+    simple_log_processor::SimpleLogProcessor,
+    switch::{LogSwitch, SwitchableLogExporter, Switches},
+    syslog_writer::{self, SyslogOptions},
+    tenant_accounting::TenantLogExporter,
+    transport::{self, CaReloading},
+};
+use hyper::header::USER_AGENT;
+use log::{
+    kv::{self, Source, VisitSource},
+    Level,
 };
-use log::Level;
 use opentelemetry::{
-    logs::{AnyValue, LogRecordBuilder, Logger, Severity},
-    KeyValue,
+    logs::{AnyValue, LogError, LogRecordBuilder, Logger, LoggerProvider as _, Severity},
+    Key,
+};
+use opentelemetry_otlp::{
+    ExportConfig, LogExporter, Protocol, WithExportConfig, OTEL_EXPORTER_OTLP_LOGS_ENDPOINT,
+    OTEL_EXPORTER_OTLP_LOGS_TIMEOUT,
 };
-use opentelemetry_otlp::WithExportConfig;
 use opentelemetry_sdk::{
-    logs::{BatchConfigBuilder, BatchLogProcessor, LoggerProvider},
+    logs::{BatchConfigBuilder, BatchLogProcessor, LogProcessor, LoggerProvider},
     runtime, Resource,
 };
 
-pub(crate) struct OtelLogBridge<P, L>
+/// Bridges records of the `log` crate to an OpenTelemetry logger, optionally also writing them to stderr in the
+/// syslog format.
+///
+/// [`Otel`](crate::Otel) installs one of these as the global logger. Applications that manage their own
+/// `LoggerProvider` can build one with [`OtelLogBridge::builder`] and install it with `log::set_boxed_logger`.
+pub struct OtelLogBridge<P, L>
 where
     P: opentelemetry::logs::LoggerProvider<Logger = L> + Send + Sync,
     L: Logger + Send + Sync,
 {
     logger: L,
     std_err_enabled: bool,
+    stderr_filter: RecordFilter,
+    router: Router,
+    drop_rules: Arc<DropRules>,
+    metrics: Arc<LogBridgeMetrics>,
     host_name: String,
     service_name: String,
-    _phantom: std::marker::PhantomData<P>, // P is not used in this struct
+    _phantom: PhantomData<P>, // P is not used in this struct
 }
 
 impl<P, L> log::Log for OtelLogBridge<P, L>
@@ -37,32 +82,35 @@ where
     L: Logger + Send + Sync,
 {
     fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
-        let _ = metadata;
-        true
+        metadata.level() <= log::STATIC_MAX_LEVEL
     }
 
     fn log(&self, record: &log::Record<'_>) {
+        // Compiled out with the `max-level-*` features for the levels they strip.
+        if record.level() > log::STATIC_MAX_LEVEL {
+            return;
+        }
+        self.metrics.received();
+        if self.drop_rules.drops(record) {
+            return;
+        }
         let timestamp = SystemTime::now();
 
-        if self.std_err_enabled {
+        if self.std_err_enabled && self.writes_to_stderr(record) {
+            let start = Instant::now();
             syslog_writer::write_syslog_format(
                 record,
-                &self.service_name,
-                &self.host_name,
-                &timestamp,
+                &SyslogOptions {
+                    service_name: &self.service_name,
+                    host_name: &self.host_name,
+                    timestamp,
+                    trace_id: syslog_writer::current_trace_id(),
+                },
             );
+            self.metrics.formatted(start.elapsed());
         }
 
-        // Propagate to otel logger
-        // TODO: Also emit user-defined attributes as provided by the kv feature of the log crate.
-        self.logger.emit(
-            LogRecordBuilder::new()
-                .with_severity_number(to_otel_severity(record.level()))
-                .with_severity_text(record.level().as_str())
-                .with_timestamp(timestamp)
-                .with_body(AnyValue::from(record.args().to_string()))
-                .build(),
-        );
+        self.emit_otel(record, timestamp);
     }
 
     fn flush(&self) {}
@@ -73,22 +121,176 @@ where
     P: opentelemetry::logs::LoggerProvider<Logger = L> + Send + Sync,
     L: Logger + Send + Sync,
 {
-    pub(crate) fn new(
-        provider: &P,
-        service_name: String,
-        std_err_enabled: bool,
-        host_name: String,
-    ) -> Self {
-        OtelLogBridge {
-            logger: provider.versioned_logger(service_name.clone(), None, None, None),
+    /// Whether `record` is routed to stderr and passes the stderr filter.
+    fn writes_to_stderr(&self, record: &log::Record<'_>) -> bool {
+        let severity_number = to_otel_severity(record.level()) as i32;
+        let attributes = record_attributes(record);
+        let attribute = |key: &str| log_filter::attribute_value(&attributes, key);
+        self.router
+            .route(severity_number, Some(record.target()), attribute)
+            .is_none_or(|sinks| sinks.contains(&LogSink::Stderr))
+            && self
+                .stderr_filter
+                .matches(severity_number, Some(record.target()), attribute)
+    }
+
+    /// Count a record that was not logged as the level filter filtered it out.
+    pub(crate) fn filtered(&self) {
+        self.metrics.filtered();
+    }
+
+    /// Propagate an early `record`, already written to stderr, to the otel logger unless a drop rule drops it.
+    pub(crate) fn replay(&self, record: &log::Record<'_>, timestamp: SystemTime) {
+        if !self.drop_rules.drops(record) {
+            self.emit_otel(record, timestamp);
+        }
+    }
+
+    /// Propagate `record` to the otel logger only, and to the audit pipeline if it is routed to audit targets.
+    fn emit_otel(&self, record: &log::Record<'_>, timestamp: SystemTime) {
+        let mut builder = LogRecordBuilder::new()
+            .with_severity_number(to_otel_severity(record.level()))
+            .with_severity_text(record.level().as_str())
+            .with_timestamp(timestamp)
+            .with_body(AnyValue::from(record.args().to_string()));
+        let attributes = record_attributes(record);
+        let routed_to_targets = Router::routes_to_targets(self.router.route(
+            to_otel_severity(record.level()) as i32,
+            Some(record.target()),
+            |key| log_filter::attribute_value(&attributes, key),
+        ));
+        if !attributes.is_empty() {
+            builder = builder.with_attributes(attributes);
+        }
+        let log_record = builder.build();
+        log_filter::with_target(record.target(), || {
+            if routed_to_targets {
+                audit::emit_routed(log_record.clone());
+            }
+            self.logger.emit(log_record);
+        });
+    }
+
+    /// Create a new bridge builder that emits records to a logger of `provider`.
+    pub fn builder(provider: &P) -> OtelLogBridgeBuilder<'_, P, L> {
+        OtelLogBridgeBuilder {
+            provider,
+            service_name: "App".to_owned(),
+            std_err_enabled: false,
+            stderr_filter: LogFilter::default(),
+            routes: Vec::new(),
+            drop_rules: Arc::default(),
+            metrics: Arc::default(),
+            host_name: None,
+        }
+    }
+}
+
+/// A builder for creating [`OtelLogBridge`] instances.
+pub struct OtelLogBridgeBuilder<'a, P, L>
+where
+    P: opentelemetry::logs::LoggerProvider<Logger = L> + Send + Sync,
+    L: Logger + Send + Sync,
+{
+    provider: &'a P,
+    service_name: String,
+    std_err_enabled: bool,
+    stderr_filter: LogFilter,
+    routes: Vec<LogRoute>,
+    drop_rules: Arc<DropRules>,
+    metrics: Arc<LogBridgeMetrics>,
+    host_name: Option<String>,
+}
+
+impl<P, L> OtelLogBridgeBuilder<'_, P, L>
+where
+    P: opentelemetry::logs::LoggerProvider<Logger = L> + Send + Sync,
+    L: Logger + Send + Sync,
+{
+    /// Set the service name, used as the name of the logger and in records written to stderr. Defaults to "App".
+    #[must_use]
+    pub fn with_service_name(self, service_name: impl Into<String>) -> Self {
+        OtelLogBridgeBuilder {
+            service_name: service_name.into(),
+            ..self
+        }
+    }
+
+    /// Set to true to also write records to stderr in the syslog format. Defaults to false.
+    #[must_use]
+    pub fn with_stderr(self, std_err_enabled: bool) -> Self {
+        OtelLogBridgeBuilder {
             std_err_enabled,
-            host_name,
-            service_name,
-            _phantom: Default::default(),
+            ..self
+        }
+    }
+
+    /// Set the filter of the records written to stderr. Defaults to all records.
+    #[must_use]
+    pub fn with_stderr_filter(self, stderr_filter: LogFilter) -> Self {
+        OtelLogBridgeBuilder {
+            stderr_filter,
+            ..self
+        }
+    }
+
+    /// Set the routes of the records, of which the bridge applies the stderr sink and the audit targets of
+    /// [`Otel`](crate::Otel). Defaults to no routes.
+    #[must_use]
+    pub fn with_routes(self, routes: Vec<LogRoute>) -> Self {
+        OtelLogBridgeBuilder { routes, ..self }
+    }
+
+    /// Set the rules that drop records before they are written to stderr or emitted.
+    #[must_use]
+    pub(crate) fn with_drop_rules(self, drop_rules: Arc<DropRules>) -> Self {
+        OtelLogBridgeBuilder { drop_rules, ..self }
+    }
+
+    /// Set the self-telemetry of the bridge.
+    #[must_use]
+    pub(crate) fn with_metrics(self, metrics: Arc<LogBridgeMetrics>) -> Self {
+        OtelLogBridgeBuilder { metrics, ..self }
+    }
+
+    /// Set the host name used in records written to stderr. Defaults to the host name of the machine.
+    #[must_use]
+    pub fn with_host_name(self, host_name: impl Into<String>) -> Self {
+        OtelLogBridgeBuilder {
+            host_name: Some(host_name.into()),
+            ..self
+        }
+    }
+
+    /// Build the bridge.
+    pub fn build(self) -> OtelLogBridge<P, L> {
+        OtelLogBridge {
+            logger: self
+                .provider
+                .versioned_logger(self.service_name.clone(), None, None, None),
+            std_err_enabled: self.std_err_enabled,
+            stderr_filter: RecordFilter::new(&self.stderr_filter),
+            router: Router::new(&self.routes),
+            drop_rules: self.drop_rules,
+            metrics: self.metrics,
+            host_name: self.host_name.unwrap_or_else(host_name),
+            service_name: self.service_name,
+            _phantom: PhantomData,
         }
     }
 }
 
+/// The host name of the machine, or an empty string if it can't be determined.
+pub(crate) fn host_name() -> String {
+    nix::unistd::gethostname()
+        .map(|hostname| {
+            hostname
+                .into_string()
+                .unwrap_or_else(|hostname| hostname.to_string_lossy().into_owned())
+        })
+        .unwrap_or_default()
+}
+
 const fn to_otel_severity(level: Level) -> Severity {
     match level {
         Level::Error => Severity::Error,
@@ -99,92 +301,435 @@ const fn to_otel_severity(level: Level) -> Severity {
     }
 }
 
-pub(crate) fn init_logs(config: Config) -> Result<LoggerProvider, log::SetLoggerError> {
-    let mut keys = vec![KeyValue::new(SERVICE_NAME_KEY, config.service_name.clone())];
-    if let Some(resource_attributes) = config.resource_attributes {
-        for attribute in resource_attributes {
-            keys.push(KeyValue::new(attribute.key, attribute.value));
-        }
+/// The attributes of `record`: its key-values, followed by the selected baggage entries of the current context.
+fn record_attributes(record: &log::Record<'_>) -> Vec<(Key, AnyValue)> {
+    let mut attributes = key_value_attributes(record.key_values());
+    attributes.extend(baggage::log_attributes());
+    attributes
+}
+
+/// Collects key-values of the `log` crate as log record attributes.
+struct KeyValueAttributes(Vec<(Key, AnyValue)>);
+
+impl<'kvs> VisitSource<'kvs> for KeyValueAttributes {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        self.0
+            .push((Key::new(key.as_str().to_owned()), to_any_value(&value)));
+        Ok(())
     }
-    let mut logger_provider_builder = LoggerProvider::builder()
-        .with_config(opentelemetry_sdk::logs::Config::default().with_resource(Resource::new(keys)));
+}
 
-    let host_name = nix::unistd::gethostname()
-        .map(|hostname| {
-            hostname
-                .into_string()
-                .unwrap_or_else(|hostname| hostname.to_string_lossy().into_owned())
-        })
-        .unwrap_or_default();
+/// The key-values of `source` as log record attributes.
+pub(crate) fn key_value_attributes(source: &dyn Source) -> Vec<(Key, AnyValue)> {
+    let mut attributes = KeyValueAttributes(Vec::with_capacity(source.count()));
+    // Collecting the key-values can't fail.
+    let _ = source.visit(&mut attributes);
+    attributes.0
+}
 
-    if let Some(export_target_list) = config.log_export_targets {
-        for export_target in export_target_list {
-            let exporter = match opentelemetry_otlp::new_exporter()
+/// Booleans, integers, floats and strings keep their type, other values are formatted, as are integers beyond the
+/// range of an `i64`.
+fn to_any_value(value: &kv::Value<'_>) -> AnyValue {
+    if let Some(value) = value.to_bool() {
+        AnyValue::Boolean(value)
+    } else if let Some(value) = value.to_i64() {
+        AnyValue::Int(value)
+    } else if let Some(value) = value.to_borrowed_str() {
+        AnyValue::from(value.to_owned())
+    } else if let (None, Some(value)) = (value.to_u64(), value.to_f64()) {
+        AnyValue::Double(value)
+    } else {
+        AnyValue::from(value.to_string())
+    }
+}
+
+/// Build an OTLP log exporter for `export_target` that talks `protocol`.
+fn build_log_exporter(
+    export_target: &LogsExportTarget,
+    protocol: Protocol,
+    user_agent: &str,
+) -> Result<LogExporter, LogError> {
+    let headers = ExportHeaders::new(
+        export_target.label(),
+        &export_target.headers,
+        export_target.auth_token_file.as_ref(),
+    );
+    match protocol {
+        Protocol::Grpc => {
+            let channel = transport::grpc_channel(
+                &export_target.url,
+                ExportConfig::default().timeout,
+                user_agent,
+                OTEL_EXPORTER_OTLP_LOGS_ENDPOINT,
+                OTEL_EXPORTER_OTLP_LOGS_TIMEOUT,
+                transport::Connector::new(
+                    &export_target.dns,
+                    &export_target.socket_options,
+                    export_target.max_bytes_per_sec,
+                ),
+                &transport::TlsOptions::from(export_target),
+            )
+            .map_err(LogError::Other)?;
+            let mut builder = opentelemetry_otlp::new_exporter()
                 .tonic()
                 .with_endpoint(export_target.url.clone())
-                .build_log_exporter()
-            {
-                Ok(exporter) => exporter,
+                .with_channel(channel);
+            if let Some(headers) = headers {
+                builder = builder.with_interceptor(headers.interceptor());
+            }
+            builder.build_log_exporter()
+        }
+        Protocol::HttpBinary => opentelemetry_otlp::new_exporter()
+            .http()
+            .with_http_client(transport::http_client(
+                transport::Connector::new(
+                    &export_target.dns,
+                    &export_target.socket_options,
+                    export_target.max_bytes_per_sec,
+                ),
+                transport::http_tls(
+                    &export_target.url,
+                    &transport::TlsOptions::from(export_target),
+                )
+                .map_err(LogError::Other)?,
+                ExportConfig::default().timeout,
+                export_target.signer.clone(),
+                headers,
+            ))
+            .with_endpoint(export_target.url.clone())
+            .with_headers(HashMap::from([(
+                USER_AGENT.as_str().to_owned(),
+                user_agent.to_owned(),
+            )]))
+            .build_log_exporter(),
+    }
+}
+
+/// Connect to `export_target` once if it connects eagerly, so that an unreachable or misconfigured target is reported
+/// during initialization rather than on the first export.
+///
+/// # Errors
+/// * The reason the target can't be connected to.
+pub(crate) fn connect_eagerly(export_target: &LogsExportTarget) -> Result<(), String> {
+    if !export_target.eager_connect {
+        return Ok(());
+    }
+    transport::connect_eagerly(
+        &export_target.url,
+        eager_connect_protocol(export_target.protocol, export_target.http_fallback),
+        Duration::from_secs(export_target.timeout),
+        transport::Connector::new(
+            &export_target.dns,
+            &export_target.socket_options,
+            export_target.max_bytes_per_sec,
+        ),
+        &transport::TlsOptions::from(export_target),
+    )
+    .map_err(|e| format!("unable to connect: {e:?}"))
+}
+
+/// Build the exporter for `export_target`, which falls back to OTLP/HTTP if configured to, with the certificate
+/// authorities of the target as they are now.
+fn build_protocol_exporter(
+    export_target: &LogsExportTarget,
+    user_agent: &str,
+) -> Result<ProtocolLogExporter, LogError> {
+    let primary = build_log_exporter(export_target, export_target.protocol, user_agent)?;
+    let fallback = if export_target.http_fallback && export_target.protocol == Protocol::Grpc {
+        Some(build_log_exporter(
+            export_target,
+            Protocol::HttpBinary,
+            user_agent,
+        )?)
+    } else {
+        None
+    };
+    Ok(ProtocolLogExporter::new(
+        export_target.label().to_owned(),
+        primary,
+        fallback,
+    ))
+}
+
+/// Build the exporter of `export_target`, which is rebuilt when the certificate authorities of the target change.
+///
+/// # Errors
+/// * The reason the exporter can't be built.
+pub(crate) fn build_target_exporter(
+    export_target: &LogsExportTarget,
+    config: &Config,
+) -> Result<DebugLogExporter<CaReloading<ProtocolLogExporter>>, String> {
+    let user_agent = transport::user_agent(
+        export_target.user_agent.as_deref(),
+        config.app_identifier.as_deref(),
+    );
+    let exporter = match CaReloading::new(
+        "logs",
+        export_target.label(),
+        &transport::TlsOptions::from(export_target),
+        {
+            let export_target = export_target.clone();
+            move || build_protocol_exporter(&export_target, &user_agent)
+        },
+    ) {
+        Ok(exporter) => exporter,
+        Err(e) => return Err(format!("unable to create exporter: {e:?}")),
+    };
+
+    let capture = export_target.payload_capture.as_ref().and_then(|capture| {
+        CaptureFile::create(export_target.label().to_owned(), capture)
+            .map_err(|e| {
+                eprintln!(
+                    "unable to capture payloads for target [{}] to {}: {:?}",
+                    export_target.label(),
+                    capture.path.display(),
+                    e
+                );
+            })
+            .ok()
+    });
+    Ok(DebugLogExporter::new(
+        exporter,
+        export_target.label().to_owned(),
+        config.pipeline_debug,
+        capture,
+        ExportFailures::new(
+            "logs",
+            export_target.label().to_owned(),
+            config.export_failure_summary_secs,
+        ),
+    ))
+}
+
+/// The exporter of the pipeline of `export_target`, which exports with `exporter` once the records are scoped,
+/// tenanted, persisted, retried and scheduled as configured for the target.
+pub(crate) fn target_exporter(
+    export_target: &LogsExportTarget,
+    config: &Config,
+    exporter: impl opentelemetry_sdk::export::logs::LogExporter + 'static,
+    persistence: Option<LogPersistence>,
+    identity: Option<&Arc<DeviceIdentity>>,
+) -> impl opentelemetry_sdk::export::logs::LogExporter + 'static {
+    IdentityLogExporter::new(
+        ScopedLogExporter::new(ScheduledLogExporter::new(
+            RetryLogExporter::new(
+                PersistentLogExporter::new(
+                    TenantLogExporter::new(
+                        exporter,
+                        config.tenant_attribute.as_deref(),
+                        export_target.label(),
+                    ),
+                    persistence,
+                ),
+                export_target.label(),
+                export_target
+                    .retry
+                    .as_ref()
+                    .filter(|_| export_target.persistence_dir.is_none()),
+            ),
+            export_target.label(),
+            &export_target.schedule,
+        )),
+        identity.cloned(),
+    )
+}
+
+/// The processor of the pipeline of `export_target`, which exports with `exporter`, and its limits.
+pub(crate) fn target_processor(
+    export_target: &LogsExportTarget,
+    config: &Config,
+    exporter: impl opentelemetry_sdk::export::logs::LogExporter + 'static,
+    persistence: Option<LogPersistence>,
+    identity: Option<&Arc<DeviceIdentity>>,
+    router: &Arc<Router>,
+) -> (Box<dyn LogProcessor>, LogPipelineLimits) {
+    let exporter = target_exporter(export_target, config, exporter, persistence, identity);
+
+    if export_target.simple {
+        // Records are exported one at a time as they are emitted, without queueing.
+        let limits = LogPipelineLimits {
+            target: export_target.label().to_owned(),
+            max_queue_size: 0,
+            max_export_batch_size: 1,
+            scheduled_delay: Duration::ZERO,
+            max_export_timeout: Duration::from_secs(export_target.timeout),
+        };
+        let processor = RoutedLogProcessor::new(
+            FilteredLogProcessor::new(
+                LimitedLogProcessor::new(
+                    SimpleLogProcessor::new(Box::new(exporter)),
+                    config.log_record_limits,
+                    config.governance.max_attributes_per_record,
+                ),
+                &export_target.filter,
+            ),
+            export_target.label().to_owned(),
+            router.clone(),
+        );
+        (Box::new(processor), limits)
+    } else {
+        let (max_queue_size, max_export_batch_size) = limits::batch_sizes(None);
+        let limits = LogPipelineLimits {
+            target: export_target.label().to_owned(),
+            max_queue_size,
+            max_export_batch_size,
+            scheduled_delay: Duration::from_secs(export_target.interval_secs),
+            max_export_timeout: Duration::from_secs(export_target.timeout),
+        };
+        let queue = QueueLength::new(export_target.label().to_owned(), max_queue_size);
+        let batch_log_processor = BatchLogProcessor::builder(
+            DequeuingLogExporter::new(exporter, queue.clone()),
+            runtime::Tokio,
+        )
+        .with_batch_config(
+            BatchConfigBuilder::default()
+                .with_max_queue_size(max_queue_size)
+                .with_max_export_batch_size(max_export_batch_size)
+                .with_scheduled_delay(Duration::from_secs(export_target.interval_secs))
+                .with_max_export_timeout(Duration::from_secs(export_target.timeout))
+                .build(),
+        )
+        .build();
+        let processor = RoutedLogProcessor::new(
+            FilteredLogProcessor::new(
+                LimitedLogProcessor::new(
+                    QueueLimitedLogProcessor::new(batch_log_processor, queue),
+                    config.log_record_limits,
+                    config.governance.max_attributes_per_record,
+                ),
+                &export_target.filter,
+            ),
+            export_target.label().to_owned(),
+            router.clone(),
+        );
+        (Box::new(processor), limits)
+    }
+}
+
+pub(crate) fn init_logs(
+    config: Config,
+    resource: Resource,
+    identity: Option<&Arc<DeviceIdentity>>,
+    drop_rules: Arc<DropRules>,
+    bridge_metrics: Arc<LogBridgeMetrics>,
+    switches: &mut Switches,
+    init_report: &mut InitReport,
+) -> Result<(LoggerProvider, Vec<LogPipelineLimits>, Arc<LogTargets>), log::SetLoggerError> {
+    let mut pipeline_limits = Vec::new();
+    let router = Arc::new(Router::new(&config.log_routes));
+    let log_targets = Arc::new(LogTargets::new(router.clone()));
+    let labels: Vec<&str> = [&config.log_export_targets, &config.audit_targets]
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(LogsExportTarget::label)
+        .collect();
+    for route in &config.log_routes {
+        for sink in &route.sinks {
+            if let LogSink::Target(label) = sink {
+                if !labels.contains(&label.as_str()) {
+                    eprintln!("log route sink [{label}] is not a log or audit target");
+                }
+            }
+        }
+    }
+
+    if let Some(export_target_list) = &config.log_export_targets {
+        let mut index = 0;
+        for export_target in export_target_list {
+            if !export_target.enabled {
+                init_report.disabled(InitComponent::LogTarget, export_target.label());
+                continue;
+            }
+            index += 1;
+            if let Some(max_export_targets) = config.governance.max_export_targets {
+                if index > max_export_targets {
+                    eprintln!(
+                        "skipping log target [{}] as the maximum of {} export targets is reached",
+                        export_target.label(),
+                        max_export_targets
+                    );
+                    init_report.skipped(
+                        InitComponent::LogTarget,
+                        export_target.label(),
+                        format!("the maximum of {max_export_targets} export targets is reached"),
+                    );
+                    continue;
+                }
+            }
+            if let Err(e) = connect_eagerly(export_target) {
+                // log error using eprintln as the logger framework is not setup yet!
+                eprintln!("{e} for target [{}]", export_target.label());
+                init_report.failed(InitComponent::LogTarget, Some(export_target.label()), e);
+                continue;
+            }
+            let persistence = match log_persistence::open(export_target) {
+                Ok(persistence) => persistence,
+                Err(e) => {
+                    // log error using eprintln as the logger framework is not setup yet!
+                    eprintln!("{e} for target [{}]", export_target.label());
+                    init_report.failed(InitComponent::LogTarget, Some(export_target.label()), e);
+                    continue;
+                }
+            };
+            let switch = match build_target_exporter(export_target, &config) {
+                Ok(exporter) => {
+                    init_report.initialized(InitComponent::LogTarget, export_target.label());
+                    LogSwitch::new(export_target.url.clone(), exporter)
+                }
                 Err(e) => {
                     // log error using eprintln as the logger framework is not setup yet!
                     eprintln!(
-                        "unable to create exporter for target [{}]: {:?}",
-                        export_target.url, e
+                        "{e} for target [{}], retrying in the background",
+                        export_target.label()
                     );
-                    continue;
+                    init_report.retrying(InitComponent::LogTarget, export_target.label(), e);
+                    let switch =
+                        LogSwitch::pending(export_target.url.clone(), export_target.label());
+                    switches.retry_logs(&switch, export_target.clone(), config.clone());
+                    switch
                 }
             };
-
-            if let Some(export_severity) = export_target.export_severity {
-                let filtered_batch_config = FilteredBatchConfig {
-                    export_severity,
-                    scheduled_delay: Duration::from_secs(export_target.interval_secs),
-                    max_export_timeout: Duration::from_secs(export_target.timeout),
-                    ..Default::default()
-                };
-
-                let filtered_log_processor =
-                    FilteredBatchLogProcessor::builder(exporter, runtime::Tokio)
-                        .with_batch_config(filtered_batch_config)
-                        .build();
-                logger_provider_builder =
-                    logger_provider_builder.with_log_processor(filtered_log_processor);
-            } else {
-                let batch_log_processor = BatchLogProcessor::builder(exporter, runtime::Tokio)
-                    .with_batch_config(
-                        BatchConfigBuilder::default()
-                            .with_scheduled_delay(Duration::from_secs(export_target.interval_secs))
-                            .with_max_export_timeout(Duration::from_secs(export_target.timeout))
-                            .build(),
-                    )
-                    .build();
-                logger_provider_builder =
-                    logger_provider_builder.with_log_processor(batch_log_processor);
-            }
+            switches.add_logs(switch.clone());
+            let (processor, limits) = target_processor(
+                export_target,
+                &config,
+                SwitchableLogExporter::new(switch),
+                persistence,
+                identity,
+                &router,
+            );
+            pipeline_limits.push(limits);
+            log_targets.add(processor);
         }
     }
 
-    let logger_provider = logger_provider_builder.build();
+    let logger_provider = LoggerProvider::builder()
+        .with_config(opentelemetry_sdk::logs::Config::default().with_resource(resource))
+        .with_log_processor(LogTargetsProcessor::new(log_targets.clone()))
+        .build();
+
+    events::set_logger(
+        logger_provider.versioned_logger(config.service_name.clone(), None, None, None),
+        config.span_events_as_logs,
+    );
 
     // Setup Log Bridge to OTEL
-    let otel_log_bridge = OtelLogBridge::new(
-        &logger_provider,
-        config.service_name,
-        config.emit_logs_to_stderr,
-        host_name,
-    );
+    let otel_log_bridge = OtelLogBridge::builder(&logger_provider)
+        .with_service_name(config.service_name)
+        .with_stderr(config.emit_logs_to_stderr)
+        .with_stderr_filter(config.stderr_filter)
+        .with_routes(config.log_routes)
+        .with_drop_rules(drop_rules)
+        .with_metrics(bridge_metrics)
+        .build();
 
     // Setup filtering
     let env_filter = env_filter::Builder::new()
         .parse(config.level.as_str())
         .build();
-    let level_filter = env_filter.filter();
 
-    log::set_boxed_logger(Box::new(env_filter::FilteredLog::new(
-        otel_log_bridge,
-        env_filter,
-    )))?;
-    log::set_max_level(level_filter);
+    global_logger::install(env_filter, otel_log_bridge)?;
 
-    Ok(logger_provider)
+    Ok((logger_provider, pipeline_limits, log_targets))
 }
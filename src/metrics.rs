@@ -0,0 +1,147 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Utilities to record metrics with the meters of [`Otel`](crate::Otel).
+//!
+//! A [`Stopwatch`] measures durations that are robust to the suspends and wall clock changes that are common on
+//! devices. It measures with the monotonic clock, which stops while the device is suspended, and compares it to the
+//! boot time clock, which doesn't, and to the wall clock, which jumps when it is set, e.g. by NTP. Measurements
+//! during which the device was suspended or the wall clock was set are anomalous: they are counted as the
+//! `otel_lib.stopwatch.anomalies` metric instead of being recorded, so they don't pollute histograms.
+
+use std::{
+    fmt,
+    time::{Duration, Instant, SystemTime},
+};
+
+use nix::time::{clock_gettime, ClockId};
+use opentelemetry::{
+    global,
+    metrics::{Histogram, Unit},
+    KeyValue,
+};
+
+/// How much the clocks may disagree over a measurement before it is anomalous.
+const ANOMALY_TOLERANCE: Duration = Duration::from_millis(250);
+
+const ANOMALY_KEY: &str = "anomaly";
+
+/// Measures the duration of an operation, from when it was started.
+#[derive(Clone, Copy, Debug)]
+pub struct Stopwatch {
+    start: Instant,
+    boot_start: Option<Duration>,
+    wall_start: SystemTime,
+}
+
+impl Stopwatch {
+    /// Start measuring.
+    pub fn start() -> Self {
+        Stopwatch {
+            start: Instant::now(),
+            boot_start: boot_time(),
+            wall_start: SystemTime::now(),
+        }
+    }
+
+    /// The time elapsed since the stopwatch was started, with the monotonic clock.
+    ///
+    /// # Errors
+    /// * `DurationAnomaly` - If the device was suspended or the wall clock was set since the stopwatch was started.
+    pub fn elapsed(&self) -> Result<Duration, DurationAnomaly> {
+        let elapsed = self.start.elapsed();
+        let boot_elapsed = self
+            .boot_start
+            .zip(boot_time())
+            .map_or(elapsed, |(start, now)| now.saturating_sub(start));
+        let suspended = boot_elapsed.saturating_sub(elapsed);
+        if suspended > ANOMALY_TOLERANCE {
+            return Err(DurationAnomaly::Suspended { elapsed, suspended });
+        }
+        let wall_consistent = SystemTime::now()
+            .duration_since(self.wall_start)
+            .is_ok_and(|wall_elapsed| wall_elapsed.abs_diff(boot_elapsed) <= ANOMALY_TOLERANCE);
+        if !wall_consistent {
+            return Err(DurationAnomaly::ClockJump { elapsed });
+        }
+        Ok(elapsed)
+    }
+
+    /// Record the time elapsed since the stopwatch was started, in seconds, into `histogram` with `attributes`. An
+    /// anomalous measurement is not recorded, but counted as the `otel_lib.stopwatch.anomalies` metric with an
+    /// `anomaly` attribute of `suspend` or `clock_jump`.
+    ///
+    /// # Errors
+    /// * `DurationAnomaly` - If the device was suspended or the wall clock was set since the stopwatch was started.
+    pub fn record(
+        &self,
+        histogram: &Histogram<f64>,
+        attributes: &[KeyValue],
+    ) -> Result<Duration, DurationAnomaly> {
+        match self.elapsed() {
+            Ok(elapsed) => {
+                histogram.record(elapsed.as_secs_f64(), attributes);
+                Ok(elapsed)
+            }
+            Err(anomaly) => {
+                // Anomalies are rare, and the meter provider may be set after the stopwatch was started.
+                global::meter("otel-lib")
+                    .u64_counter("otel_lib.stopwatch.anomalies")
+                    .with_description(
+                        "Number of durations not recorded as the device was suspended or the wall clock was set",
+                    )
+                    .with_unit(Unit::new("{measurement}"))
+                    .init()
+                    .add(1, &[KeyValue::new(ANOMALY_KEY, anomaly.name())]);
+                Err(anomaly)
+            }
+        }
+    }
+}
+
+/// The time since boot, including the time the device was suspended.
+fn boot_time() -> Option<Duration> {
+    clock_gettime(ClockId::CLOCK_BOOTTIME)
+        .ok()
+        .map(Duration::from)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// A measurement of a [`Stopwatch`] that can't be trusted.
+pub enum DurationAnomaly {
+    /// The device was suspended for `suspended` during the measurement. `elapsed` excludes the suspend.
+    Suspended {
+        elapsed: Duration,
+        suspended: Duration,
+    },
+    /// The wall clock was set during the measurement, so the operation may have been timed out or scheduled by the
+    /// wrong clock. `elapsed` is measured with the monotonic clock.
+    ClockJump { elapsed: Duration },
+}
+
+impl DurationAnomaly {
+    /// The name of the anomaly, as the `anomaly` attribute of the `otel_lib.stopwatch.anomalies` metric.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DurationAnomaly::Suspended { .. } => "suspend",
+            DurationAnomaly::ClockJump { .. } => "clock_jump",
+        }
+    }
+}
+
+impl fmt::Display for DurationAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DurationAnomaly::Suspended { elapsed, suspended } => write!(
+                f,
+                "the device was suspended for {suspended:?} during the measurement of {elapsed:?}"
+            ),
+            DurationAnomaly::ClockJump { elapsed } => write!(
+                f,
+                "the wall clock was set during the measurement of {elapsed:?}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DurationAnomaly {}
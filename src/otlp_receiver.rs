@@ -0,0 +1,313 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::{sync::Arc, time::Duration};
+
+use futures_util::future::join_all;
+use log::{info, warn};
+use opentelemetry_otlp::Protocol;
+use opentelemetry_proto::tonic::{
+    collector::{
+        logs::v1::{
+            logs_service_client::LogsServiceClient,
+            logs_service_server::{LogsService, LogsServiceServer},
+            ExportLogsServiceRequest, ExportLogsServiceResponse,
+        },
+        metrics::v1::{
+            metrics_service_client::MetricsServiceClient,
+            metrics_service_server::{MetricsService, MetricsServiceServer},
+            ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+        },
+    },
+    common::v1::{any_value, KeyValue},
+};
+use tokio::sync::watch;
+use tonic::{
+    transport::{Channel, Server},
+    Request, Response, Status,
+};
+
+use crate::{
+    config::{Config, LogRoute, LogsExportTarget, MetricsExportTarget},
+    headers::ExportHeaders,
+    log_filter::RecordFilter,
+    log_routing::Router,
+    transport::{self, CaReloading, ConnectError, TlsOptions},
+};
+
+/// A local OTLP/gRPC listener that forwards whatever sibling processes send it to the configured export targets,
+/// so that a single process can act as the node-local telemetry funnel.
+pub(crate) struct OtlpReceiver {
+    port: u16,
+    metrics_targets: Vec<MetricsExportTarget>,
+    logs_targets: Vec<LogsExportTarget>,
+    log_routes: Vec<LogRoute>,
+    app_identifier: Option<String>,
+}
+
+impl OtlpReceiver {
+    pub(crate) fn new(port: u16, config: &Config) -> Self {
+        let metrics_targets = config
+            .metrics_export_targets
+            .iter()
+            .flatten()
+            .filter(|target| target.enabled && forwardable(target.label(), target.protocol))
+            .cloned()
+            .collect();
+        let logs_targets = config
+            .log_export_targets
+            .iter()
+            .flatten()
+            .filter(|target| target.enabled && forwardable(target.label(), target.protocol))
+            .cloned()
+            .collect();
+        OtlpReceiver {
+            port,
+            metrics_targets,
+            logs_targets,
+            log_routes: config.log_routes.clone(),
+            app_identifier: config.app_identifier.clone(),
+        }
+    }
+
+    /// Serve OTLP/gRPC on the loopback interface until the server fails, or `shutdown` is set to true.
+    pub(crate) async fn run(
+        &self,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> Result<(), ConnectError> {
+        let mut metrics_forwarder = MetricsForwarder::default();
+        for target in &self.metrics_targets {
+            let channel = connect_lazy(
+                "metrics",
+                target.label(),
+                &target.url,
+                target.timeout,
+                &transport::user_agent(
+                    target.user_agent.as_deref(),
+                    self.app_identifier.as_deref(),
+                ),
+                transport::Connector::new(
+                    &target.dns,
+                    &target.socket_options,
+                    target.max_bytes_per_sec,
+                ),
+                &TlsOptions::from(target),
+            )?;
+            metrics_forwarder.clients.push((
+                target.label().to_owned(),
+                channel,
+                ExportHeaders::new(
+                    target.label(),
+                    &target.headers,
+                    target.auth_token_file.as_ref(),
+                ),
+            ));
+        }
+
+        let mut logs_forwarder = LogsForwarder {
+            router: Router::new(&self.log_routes),
+            ..LogsForwarder::default()
+        };
+        for target in &self.logs_targets {
+            let channel = connect_lazy(
+                "logs",
+                target.label(),
+                &target.url,
+                target.timeout,
+                &transport::user_agent(
+                    target.user_agent.as_deref(),
+                    self.app_identifier.as_deref(),
+                ),
+                transport::Connector::new(
+                    &target.dns,
+                    &target.socket_options,
+                    target.max_bytes_per_sec,
+                ),
+                &TlsOptions::from(target),
+            )?;
+            logs_forwarder.clients.push((
+                target.label().to_owned(),
+                RecordFilter::new(&target.filter),
+                channel,
+                ExportHeaders::new(
+                    target.label(),
+                    &target.headers,
+                    target.auth_token_file.as_ref(),
+                ),
+            ));
+        }
+
+        info!("initializing otlp receiver on port {}", self.port);
+        Ok(Server::builder()
+            .add_service(MetricsServiceServer::new(metrics_forwarder))
+            .add_service(LogsServiceServer::new(logs_forwarder))
+            .serve_with_shutdown(([127, 0, 0, 1], self.port).into(), async move {
+                let _ = shutdown.wait_for(|shutdown| *shutdown).await;
+            })
+            .await?)
+    }
+}
+
+/// Only OTLP/gRPC targets can be forwarded to, as requests are passed on as received.
+fn forwardable(label: &str, protocol: Protocol) -> bool {
+    if protocol != Protocol::Grpc {
+        warn!("otlp receiver will not forward to target {label} as it does not use OTLP/gRPC");
+        return false;
+    }
+    true
+}
+
+/// Create a channel to the `signal` target `label` that connects on first use, and reconnects when the certificate
+/// authorities of the target change.
+fn connect_lazy(
+    signal: &'static str,
+    label: &str,
+    url: &str,
+    timeout: u64,
+    user_agent: &str,
+    connector: transport::Connector,
+    tls: &TlsOptions,
+) -> Result<CaReloading<Channel>, ConnectError> {
+    let url = url.to_owned();
+    let user_agent = user_agent.to_owned();
+    let tls_options = tls.clone();
+    CaReloading::new(signal, label, tls, move || -> Result<_, ConnectError> {
+        let (endpoint, connector) =
+            transport::grpc_endpoint(&url, connector.clone(), &tls_options)?;
+        Ok(transport::connect_lazy(
+            &endpoint
+                .user_agent(user_agent.as_str())?
+                .timeout(Duration::from_secs(timeout)),
+            connector,
+        ))
+    })
+}
+
+#[derive(Default)]
+struct MetricsForwarder {
+    clients: Vec<(String, CaReloading<Channel>, Option<Arc<ExportHeaders>>)>,
+}
+
+#[tonic::async_trait]
+impl MetricsService for MetricsForwarder {
+    async fn export(
+        &self,
+        request: Request<ExportMetricsServiceRequest>,
+    ) -> Result<Response<ExportMetricsServiceResponse>, Status> {
+        let request = request.into_inner();
+        join_all(self.clients.iter().map(|(target, channel, headers)| {
+            let mut client = MetricsServiceClient::new(channel.current().0);
+            let request = forwarded(request.clone(), headers.as_deref());
+            async move {
+                let result = match request {
+                    Ok(request) => client
+                        .export(request)
+                        .await
+                        .map(drop)
+                        .map_err(|e| e.to_string()),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    warn!("unable to forward metrics to {target}: {e}");
+                }
+            }
+        }))
+        .await;
+
+        Ok(Response::new(ExportMetricsServiceResponse {
+            partial_success: None,
+        }))
+    }
+}
+
+#[derive(Default)]
+struct LogsForwarder {
+    clients: Vec<(
+        String,
+        RecordFilter,
+        CaReloading<Channel>,
+        Option<Arc<ExportHeaders>>,
+    )>,
+    router: Router,
+}
+
+#[tonic::async_trait]
+impl LogsService for LogsForwarder {
+    async fn export(
+        &self,
+        request: Request<ExportLogsServiceRequest>,
+    ) -> Result<Response<ExportLogsServiceResponse>, Status> {
+        let request = request.into_inner();
+        join_all(
+            self.clients
+                .iter()
+                .map(|(target, filter, channel, headers)| {
+                    let mut client = LogsServiceClient::new(channel.current().0);
+                    let mut request = request.clone();
+                    retain_matching(&mut request, target, filter, &self.router);
+                    let request = forwarded(request, headers.as_deref());
+                    async move {
+                        let result = match request {
+                            Ok(request) => client
+                                .export(request)
+                                .await
+                                .map(drop)
+                                .map_err(|e| e.to_string()),
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = result {
+                            warn!("unable to forward logs to {target}: {e}");
+                        }
+                    }
+                }),
+        )
+        .await;
+
+        Ok(Response::new(ExportLogsServiceResponse {
+            partial_success: None,
+        }))
+    }
+}
+
+/// The request forwarding `message` to a target, with the headers of the target.
+fn forwarded<T>(message: T, headers: Option<&ExportHeaders>) -> Result<Request<T>, String> {
+    let mut request = Request::new(message);
+    if let Some(headers) = headers {
+        headers.apply(request.metadata_mut())?;
+    }
+    Ok(request)
+}
+
+/// Apply the log routes and a target's log filter to forwarded log records, as the exporters do for the process's
+/// own logs. The records have no `log` crate target, so directives don't apply to them.
+fn retain_matching(
+    request: &mut ExportLogsServiceRequest,
+    label: &str,
+    filter: &RecordFilter,
+    router: &Router,
+) {
+    for resource_logs in &mut request.resource_logs {
+        for scope_logs in &mut resource_logs.scope_logs {
+            scope_logs.log_records.retain(|record| {
+                let attribute = |key: &str| attribute_value(&record.attributes, key);
+                Router::routes_to(
+                    router.route(record.severity_number, None, attribute),
+                    label,
+                    false,
+                ) && filter.matches(record.severity_number, None, attribute)
+            });
+        }
+    }
+}
+
+/// The value of the scalar attribute `key` of `attributes`, formatted as a string.
+fn attribute_value(attributes: &[KeyValue], key: &str) -> Option<String> {
+    let attribute = attributes.iter().find(|attribute| attribute.key == key)?;
+    match attribute.value.as_ref()?.value.as_ref()? {
+        any_value::Value::StringValue(value) => Some(value.clone()),
+        any_value::Value::IntValue(value) => Some(value.to_string()),
+        any_value::Value::DoubleValue(value) => Some(value.to_string()),
+        any_value::Value::BoolValue(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use hyper_0_14::client::connect::{Connected, Connection};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+    time::{Instant, Sleep},
+};
+
+/// Paces the bytes written to the connections of a target to a maximum rate, so that exports don't starve the
+/// other traffic of the device on low bandwidth links.
+#[derive(Debug)]
+pub(crate) struct Pacer {
+    bytes_per_sec: u64,
+    /// The time at which the bytes written so far have been paced out.
+    paced_until: Mutex<Instant>,
+}
+
+impl Pacer {
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        Pacer {
+            bytes_per_sec: bytes_per_sec.max(1),
+            paced_until: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// The most bytes written at once, a tenth of a second worth of the rate.
+    fn burst(&self) -> usize {
+        usize::try_from(self.bytes_per_sec / 10)
+            .unwrap_or(usize::MAX)
+            .max(1)
+    }
+
+    fn paced_until(&self) -> Instant {
+        *self
+            .paced_until
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn consume(&self, bytes: usize) {
+        let nanos = bytes as u128 * 1_000_000_000 / u128::from(self.bytes_per_sec);
+        let mut paced_until = self
+            .paced_until
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *paced_until = (*paced_until).max(Instant::now())
+            + Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX));
+    }
+}
+
+/// A connection whose writes are paced by the [`Pacer`] of its target, if it has one.
+pub(crate) struct PacedStream {
+    inner: TcpStream,
+    pacer: Option<Arc<Pacer>>,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl PacedStream {
+    pub(crate) fn new(inner: TcpStream, pacer: Option<Arc<Pacer>>) -> Self {
+        PacedStream {
+            inner,
+            pacer,
+            delay: None,
+        }
+    }
+}
+
+impl AsyncRead for PacedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for PacedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let Some(pacer) = &this.pacer else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+        loop {
+            if let Some(delay) = &mut this.delay {
+                ready!(delay.as_mut().poll(cx));
+                this.delay = None;
+            }
+            let paced_until = pacer.paced_until();
+            if paced_until > Instant::now() {
+                this.delay = Some(Box::pin(tokio::time::sleep_until(paced_until)));
+                continue;
+            }
+            let len = buf.len().min(pacer.burst());
+            let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..len]))?;
+            pacer.consume(written);
+            return Poll::Ready(Ok(written));
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl Connection for PacedStream {
+    fn connected(&self) -> Connected {
+        self.inner.connected()
+    }
+}
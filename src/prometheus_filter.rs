@@ -0,0 +1,50 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use glob::Pattern;
+use log::error;
+use prometheus::proto::MetricFamily;
+
+/// Selects the metric families served by a prometheus listener, by name.
+///
+/// A family is served if it matches any of the include patterns, or there are none, and none of the exclude
+/// patterns. The `*` and `?` wildcards are supported.
+pub(crate) struct MetricFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl MetricFilter {
+    pub(crate) fn new(include: &[String], exclude: &[String]) -> Self {
+        MetricFilter {
+            include: patterns(include),
+            exclude: patterns(exclude),
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(name)))
+            && !self.exclude.iter().any(|pattern| pattern.matches(name))
+    }
+
+    /// Drop the families that are not selected.
+    pub(crate) fn filter_families(&self, metric_families: &mut Vec<MetricFamily>) {
+        metric_families.retain(|metric_family| self.matches(metric_family.get_name()));
+    }
+}
+
+fn patterns(patterns: &[String]) -> Vec<Pattern> {
+    patterns
+        .iter()
+        .filter_map(|pattern| match Pattern::new(pattern) {
+            Ok(pattern) => Some(pattern),
+            Err(e) => {
+                error!(
+                    "ignoring metric filter pattern {} as it is invalid: {:?}",
+                    pattern, e
+                );
+                None
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,91 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use hyper::body::Bytes;
+use tokio::sync::Mutex;
+
+/// The encoded metrics served by the prometheus end point, and their gzipped form once a scraper asked for it.
+#[derive(Clone)]
+pub(crate) struct Payload {
+    text: Bytes,
+    gzipped: Option<Bytes>,
+}
+
+impl Payload {
+    pub(crate) fn new(text: Vec<u8>) -> Self {
+        Payload {
+            text: Bytes::from(text),
+            gzipped: None,
+        }
+    }
+
+    /// The body of the response, gzipped if `gzip` is true.
+    pub(crate) fn body(&mut self, gzip: bool) -> io::Result<Bytes> {
+        if !gzip {
+            return Ok(self.text.clone());
+        }
+        if let Some(gzipped) = &self.gzipped {
+            return Ok(gzipped.clone());
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&self.text)?;
+        let gzipped = Bytes::from(encoder.finish()?);
+        self.gzipped = Some(gzipped.clone());
+        Ok(gzipped)
+    }
+}
+
+/// Caches the payload for a short time, so scrapers hitting the end point at the same time share one encoding.
+pub(crate) struct PayloadCache {
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, Payload)>>,
+}
+
+impl PayloadCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        PayloadCache {
+            ttl,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// The body of the cached payload, gzipped if `gzip` is true. The payload is encoded with `encode` if it's not
+    /// cached or has expired. Scrapes wait for an encoding in progress rather than encoding again.
+    pub(crate) async fn body<F, E>(&self, gzip: bool, encode: F) -> Result<Bytes, E>
+    where
+        F: std::future::Future<Output = Result<Vec<u8>, E>>,
+        E: From<io::Error>,
+    {
+        let mut cached = self.cached.lock().await;
+        if let Some((encoded_at, payload)) = cached.as_mut() {
+            if encoded_at.elapsed() < self.ttl {
+                return Ok(payload.body(gzip)?);
+            }
+        }
+        let mut payload = Payload::new(encode.await?);
+        let body = payload.body(gzip)?;
+        *cached = Some((Instant::now(), payload));
+        Ok(body)
+    }
+}
+
+/// Returns true if the `Accept-Encoding` header `accept_encoding` accepts gzip.
+pub(crate) fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|coding| {
+        let mut parameters = coding.split(';').map(str::trim);
+        let name = parameters.next().unwrap_or_default();
+        (name.eq_ignore_ascii_case("gzip") || name == "*")
+            && parameters.all(|parameter| {
+                parameter
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_none_or(|q| q > 0.0)
+            })
+    })
+}
@@ -0,0 +1,254 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+};
+
+use glob::Pattern;
+use log::error;
+use prometheus::proto::{Bucket, Gauge, LabelPair, Metric, MetricFamily, MetricType};
+
+const QUANTILE_LABEL: &str = "quantile";
+
+/// Serves selected histograms as additional quantile gauges, for dashboards that can't run `histogram_quantile`.
+///
+/// Quantiles are estimated at scrape time from the increase of the histogram's buckets since the previous scrape, by
+/// linear interpolation within the bucket the quantile falls into, the same way `histogram_quantile` does over a
+/// range. The buckets are cumulative since the process started, so estimating from them as they are would give
+/// lifetime quantiles that stop moving once enough measurements are recorded.
+pub(crate) struct QuantileEmulation {
+    histograms: Vec<Pattern>,
+    quantiles: Vec<f64>,
+    /// The bucket counts and sample count of each series at the previous scrape, by family name and labels.
+    previous: Mutex<HashMap<String, (Vec<u64>, u64)>>,
+}
+
+impl QuantileEmulation {
+    pub(crate) fn new(histograms: &[String], quantiles: &[f64]) -> Self {
+        let histograms = histograms
+            .iter()
+            .filter_map(|histogram| match Pattern::new(histogram) {
+                Ok(pattern) => Some(pattern),
+                Err(e) => {
+                    error!(
+                        "ignoring quantile histogram {} due to an invalid pattern: {:?}",
+                        histogram, e
+                    );
+                    None
+                }
+            })
+            .collect();
+        let quantiles = quantiles
+            .iter()
+            .copied()
+            .filter(|quantile| {
+                let valid = (0.0..=1.0).contains(quantile);
+                if !valid {
+                    error!("ignoring quantile {quantile} as it is not between 0 and 1");
+                }
+                valid
+            })
+            .collect();
+        QuantileEmulation {
+            histograms,
+            quantiles,
+            previous: Mutex::default(),
+        }
+    }
+
+    /// Build a `<histogram>_quantile` gauge family for each selected histogram family in `metric_families`.
+    pub(crate) fn metric_families(&self, metric_families: &[MetricFamily]) -> Vec<MetricFamily> {
+        if self.histograms.is_empty() || self.quantiles.is_empty() {
+            return Vec::new();
+        }
+
+        metric_families
+            .iter()
+            .filter(|metric_family| {
+                metric_family.get_field_type() == MetricType::HISTOGRAM
+                    && self
+                        .histograms
+                        .iter()
+                        .any(|pattern| pattern.matches(metric_family.get_name()))
+            })
+            .map(|metric_family| self.quantile_family(metric_family))
+            .collect()
+    }
+
+    fn quantile_family(&self, histogram_family: &MetricFamily) -> MetricFamily {
+        let mut quantile_family = MetricFamily::default();
+        quantile_family.set_name(format!("{}_quantile", histogram_family.get_name()));
+        quantile_family.set_help(format!(
+            "Quantiles estimated from the buckets of {}",
+            histogram_family.get_name()
+        ));
+        quantile_family.set_field_type(MetricType::GAUGE);
+
+        let mut previous = self.previous.lock().unwrap_or_else(PoisonError::into_inner);
+        for metric in histogram_family.get_metric() {
+            let histogram = metric.get_histogram();
+            let counts: Vec<u64> = histogram
+                .get_bucket()
+                .iter()
+                .map(Bucket::get_cumulative_count)
+                .collect();
+            let sample_count = histogram.get_sample_count();
+            let (window_counts, window_sample_count) = match previous.insert(
+                series_key(histogram_family.get_name(), metric.get_label()),
+                (counts.clone(), sample_count),
+            ) {
+                Some((previous_counts, previous_sample_count)) => window(
+                    &counts,
+                    sample_count,
+                    &previous_counts,
+                    previous_sample_count,
+                ),
+                None => (counts, sample_count),
+            };
+            let buckets: Vec<(f64, u64)> = histogram
+                .get_bucket()
+                .iter()
+                .map(Bucket::get_upper_bound)
+                .zip(window_counts)
+                .collect();
+            for quantile in &self.quantiles {
+                let Some(value) = bucket_quantile(*quantile, &buckets, window_sample_count) else {
+                    continue;
+                };
+
+                let mut quantile_metric = Metric::default();
+                for label in metric.get_label() {
+                    quantile_metric.mut_label().push(label.clone());
+                }
+                let mut quantile_label = LabelPair::default();
+                quantile_label.set_name(QUANTILE_LABEL.to_owned());
+                quantile_label.set_value(quantile.to_string());
+                quantile_metric.mut_label().push(quantile_label);
+                let mut gauge = Gauge::default();
+                gauge.set_value(value);
+                quantile_metric.set_gauge(gauge);
+                quantile_family.mut_metric().push(quantile_metric);
+            }
+        }
+        quantile_family
+    }
+}
+
+/// The key of a series of the family `name` in the counts of the previous scrape.
+fn series_key(name: &str, labels: &[LabelPair]) -> String {
+    let mut key = name.to_owned();
+    for label in labels {
+        for part in ["\0", label.get_name(), "=", label.get_value()] {
+            key.push_str(part);
+        }
+    }
+    key
+}
+
+/// The cumulative bucket counts and sample count of the measurements recorded between two scrapes. If the
+/// histogram was reset in between, e.g. as its buckets changed, the current counts are all since the reset.
+fn window(
+    counts: &[u64],
+    sample_count: u64,
+    previous_counts: &[u64],
+    previous_sample_count: u64,
+) -> (Vec<u64>, u64) {
+    let reset = counts.len() != previous_counts.len()
+        || sample_count < previous_sample_count
+        || counts
+            .iter()
+            .zip(previous_counts)
+            .any(|(count, previous_count)| count < previous_count);
+    if reset {
+        return (counts.to_vec(), sample_count);
+    }
+    (
+        counts
+            .iter()
+            .zip(previous_counts)
+            .map(|(count, previous_count)| count - previous_count)
+            .collect(),
+        sample_count - previous_sample_count,
+    )
+}
+
+/// Estimate `quantile` from cumulative `(upper bound, count)` buckets. Returns `None` if nothing was recorded.
+#[allow(clippy::cast_precision_loss)]
+fn bucket_quantile(quantile: f64, buckets: &[(f64, u64)], sample_count: u64) -> Option<f64> {
+    if sample_count == 0 {
+        return None;
+    }
+
+    let rank = quantile * sample_count as f64;
+    let mut lower_bound = 0.0;
+    let mut lower_count = 0;
+    for (upper_bound, cumulative_count) in buckets.iter().copied() {
+        if upper_bound.is_infinite() {
+            break;
+        }
+        if cumulative_count as f64 >= rank {
+            if cumulative_count == lower_count {
+                return Some(upper_bound);
+            }
+            let fraction = (rank - lower_count as f64) / (cumulative_count - lower_count) as f64;
+            return Some(lower_bound + (upper_bound - lower_bound) * fraction);
+        }
+        lower_bound = upper_bound;
+        lower_count = cumulative_count;
+    }
+
+    // The quantile falls into the +Inf bucket, the highest finite bound is the best estimate.
+    Some(lower_bound)
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::proto::Histogram;
+
+    use super::*;
+
+    fn histogram_family(counts: &[u64]) -> MetricFamily {
+        let mut histogram = Histogram::default();
+        for (upper_bound, count) in [1.0, 2.0, 4.0, f64::INFINITY].into_iter().zip(counts) {
+            let mut bucket = Bucket::default();
+            bucket.set_upper_bound(upper_bound);
+            bucket.set_cumulative_count(*count);
+            histogram.mut_bucket().push(bucket);
+        }
+        histogram.set_sample_count(counts[counts.len() - 1]);
+        let mut metric = Metric::default();
+        metric.set_histogram(histogram);
+        let mut family = MetricFamily::default();
+        family.set_name("latency".to_owned());
+        family.set_field_type(MetricType::HISTOGRAM);
+        family.mut_metric().push(metric);
+        family
+    }
+
+    fn median(emulation: &QuantileEmulation, counts: &[u64]) -> Option<f64> {
+        let families = emulation.metric_families(&[histogram_family(counts)]);
+        families[0]
+            .get_metric()
+            .first()
+            .map(|metric| metric.get_gauge().get_value())
+    }
+
+    #[test]
+    fn quantiles_are_those_of_the_measurements_since_the_previous_scrape() {
+        let emulation = QuantileEmulation::new(&["latency".to_owned()], &[0.5]);
+        // 100 measurements below 1.
+        assert_eq!(median(&emulation, &[100, 100, 100, 100]), Some(0.5));
+        // 100 more between 2 and 4, which the lifetime median would not show.
+        assert_eq!(median(&emulation, &[100, 100, 200, 200]), Some(3.0));
+        // Nothing recorded since.
+        assert_eq!(median(&emulation, &[100, 100, 200, 200]), None);
+    }
+
+    #[test]
+    fn a_reset_histogram_is_estimated_from_its_current_counts() {
+        assert_eq!(window(&[1, 2, 3], 3, &[5, 5, 5], 5), (vec![1, 2, 3], 3));
+        assert_eq!(window(&[6, 8, 9], 9, &[5, 5, 5], 5), (vec![1, 3, 4], 4));
+    }
+}
@@ -0,0 +1,435 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Parsing of metrics in the prometheus text format, as served by the scrape sources, into metric families, so that
+// they are merged with the families of the registries before being served.
+//
+// Samples belong to the family of the preceding `# TYPE` line if their name is one of the family's sample names,
+// e.g. the `_bucket` samples of a histogram, and to an untyped family of their own name otherwise. Untyped families
+// are parsed as gauges, which the encoder serves them as. The samples of a histogram or summary that have the same
+// labels, but for `le` or `quantile`, are one metric.
+
+use std::collections::HashMap;
+
+use prometheus::proto::{
+    Bucket, Counter, Gauge, Histogram, LabelPair, Metric, MetricFamily, MetricType, Quantile,
+    Summary,
+};
+
+const BUCKET_LABEL: &str = "le";
+
+const QUANTILE_LABEL: &str = "quantile";
+
+/// A sample line: its name, labels, value and optional timestamp.
+struct Sample<'a> {
+    name: &'a str,
+    labels: Vec<(String, String)>,
+    value: f64,
+    timestamp_ms: Option<i64>,
+}
+
+/// The metric families of `text`, in the order they first appear. Families without samples are left out.
+///
+/// # Errors
+/// * The first line that is not valid in the text format, and why.
+pub(crate) fn parse(text: &str) -> Result<Vec<MetricFamily>, String> {
+    let mut families = Families::default();
+    let mut current: Option<usize> = None;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let result = if let Some(comment) = line.strip_prefix('#') {
+            parse_comment(comment, &mut families, &mut current)
+        } else {
+            parse_sample(line).map(|sample| families.add(&sample, current))
+        };
+        result.map_err(|e| format!("line {}: {e}", number + 1))?;
+    }
+    Ok(families
+        .families
+        .into_iter()
+        .filter(|family| !family.get_metric().is_empty())
+        .collect())
+}
+
+/// The families being parsed, and the index of the metric of each series of histograms and summaries.
+#[derive(Default)]
+struct Families {
+    families: Vec<MetricFamily>,
+    by_name: HashMap<String, usize>,
+    metrics: HashMap<(usize, Vec<(String, String)>), usize>,
+}
+
+impl Families {
+    /// The index of the family `name`, which is created with `metric_type` if it doesn't exist yet.
+    fn family(&mut self, name: &str, metric_type: MetricType) -> usize {
+        if let Some(index) = self.by_name.get(name) {
+            return *index;
+        }
+        let mut family = MetricFamily::default();
+        family.set_name(name.to_owned());
+        family.set_field_type(metric_type);
+        self.families.push(family);
+        self.by_name
+            .insert(name.to_owned(), self.families.len() - 1);
+        self.families.len() - 1
+    }
+
+    /// Add `sample` to the family of the preceding `# TYPE` line, `current`, if it belongs to it.
+    fn add(&mut self, sample: &Sample<'_>, current: Option<usize>) {
+        let typed = current.and_then(|index| {
+            let family = &self.families[index];
+            let suffix = sample.name.strip_prefix(family.get_name())?;
+            let belongs = match family.get_field_type() {
+                MetricType::HISTOGRAM => matches!(suffix, "_bucket" | "_sum" | "_count"),
+                MetricType::SUMMARY => matches!(suffix, "" | "_sum" | "_count"),
+                MetricType::COUNTER | MetricType::GAUGE | MetricType::UNTYPED => suffix.is_empty(),
+            };
+            belongs.then_some((index, suffix))
+        });
+        let (index, suffix) =
+            typed.unwrap_or_else(|| (self.family(sample.name, MetricType::GAUGE), ""));
+        match self.families[index].get_field_type() {
+            MetricType::COUNTER => {
+                let mut counter = Counter::default();
+                counter.set_value(sample.value);
+                self.new_metric(index, sample).set_counter(counter);
+            }
+            MetricType::GAUGE | MetricType::UNTYPED => {
+                let mut gauge = Gauge::default();
+                gauge.set_value(sample.value);
+                self.new_metric(index, sample).set_gauge(gauge);
+            }
+            MetricType::HISTOGRAM => {
+                add_histogram_sample(self.metric(index, sample), suffix, sample);
+            }
+            MetricType::SUMMARY => add_summary_sample(self.metric(index, sample), suffix, sample),
+        }
+    }
+
+    /// A new metric of the family at `index` with the labels and timestamp of `sample`.
+    fn new_metric(&mut self, index: usize, sample: &Sample<'_>) -> &mut Metric {
+        let metric = self.families[index].mut_metric();
+        metric.push(to_metric(&sample.labels, sample.timestamp_ms));
+        metric.last_mut().unwrap_or_else(|| unreachable!())
+    }
+
+    /// The metric of the histogram or summary at `index` that `sample` is part of.
+    fn metric(&mut self, index: usize, sample: &Sample<'_>) -> &mut Metric {
+        let labels: Vec<_> = sample
+            .labels
+            .iter()
+            .filter(|(name, _)| name != BUCKET_LABEL && name != QUANTILE_LABEL)
+            .cloned()
+            .collect();
+        let family = &mut self.families[index];
+        let metric_index = *self
+            .metrics
+            .entry((index, labels.clone()))
+            .or_insert_with(|| {
+                family
+                    .mut_metric()
+                    .push(to_metric(&labels, sample.timestamp_ms));
+                family.get_metric().len() - 1
+            });
+        &mut family.mut_metric()[metric_index]
+    }
+}
+
+fn to_metric(labels: &[(String, String)], timestamp_ms: Option<i64>) -> Metric {
+    let mut metric = Metric::default();
+    for (name, value) in labels {
+        let mut label = LabelPair::default();
+        label.set_name(name.clone());
+        label.set_value(value.clone());
+        metric.mut_label().push(label);
+    }
+    if let Some(timestamp_ms) = timestamp_ms {
+        metric.set_timestamp_ms(timestamp_ms);
+    }
+    metric
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn add_histogram_sample(metric: &mut Metric, suffix: &str, sample: &Sample<'_>) {
+    if !metric.has_histogram() {
+        metric.set_histogram(Histogram::default());
+    }
+    let histogram = metric.mut_histogram();
+    match suffix {
+        "_bucket" => {
+            let Some(upper_bound) = label_value(&sample.labels, BUCKET_LABEL)
+                .and_then(|upper_bound| parse_value(upper_bound).ok())
+            else {
+                return;
+            };
+            // The encoder adds the +Inf bucket from the sample count.
+            if upper_bound.is_infinite() {
+                return;
+            }
+            let mut bucket = Bucket::default();
+            bucket.set_upper_bound(upper_bound);
+            bucket.set_cumulative_count(sample.value as u64);
+            histogram.mut_bucket().push(bucket);
+        }
+        "_sum" => histogram.set_sample_sum(sample.value),
+        _ => histogram.set_sample_count(sample.value as u64),
+    }
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn add_summary_sample(metric: &mut Metric, suffix: &str, sample: &Sample<'_>) {
+    if !metric.has_summary() {
+        metric.set_summary(Summary::default());
+    }
+    let summary = metric.mut_summary();
+    match suffix {
+        "" => {
+            let Some(quantile) = label_value(&sample.labels, QUANTILE_LABEL)
+                .and_then(|quantile| parse_value(quantile).ok())
+            else {
+                return;
+            };
+            let mut summary_quantile = Quantile::default();
+            summary_quantile.set_quantile(quantile);
+            summary_quantile.set_value(sample.value);
+            summary.mut_quantile().push(summary_quantile);
+        }
+        "_sum" => summary.set_sample_sum(sample.value),
+        _ => summary.set_sample_count(sample.value as u64),
+    }
+}
+
+fn label_value<'a>(labels: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    labels
+        .iter()
+        .find(|(label, _)| label == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Parse a `# HELP` or `# TYPE` line, which starts the family it names. Other comments are ignored.
+fn parse_comment(
+    comment: &str,
+    families: &mut Families,
+    current: &mut Option<usize>,
+) -> Result<(), String> {
+    let comment = comment.trim_start();
+    let (keyword, rest) = comment
+        .split_once(char::is_whitespace)
+        .unwrap_or((comment, ""));
+    let (name, rest) = rest
+        .trim_start()
+        .split_once(char::is_whitespace)
+        .unwrap_or((rest.trim_start(), ""));
+    match keyword {
+        "HELP" => {
+            let index = families.family(name, MetricType::GAUGE);
+            families.families[index].set_help(unescape(rest.trim_start(), false));
+            *current = Some(index);
+        }
+        "TYPE" => {
+            let metric_type = match rest.trim() {
+                "counter" => MetricType::COUNTER,
+                "histogram" => MetricType::HISTOGRAM,
+                "summary" => MetricType::SUMMARY,
+                "gauge" | "untyped" => MetricType::GAUGE,
+                metric_type => return Err(format!("unknown type {metric_type} of {name}")),
+            };
+            let index = families.family(name, metric_type);
+            families.families[index].set_field_type(metric_type);
+            *current = Some(index);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Parse a sample line: `name{label="value",...} value [timestamp]`.
+fn parse_sample(line: &str) -> Result<Sample<'_>, String> {
+    let name_end = line
+        .find(|c: char| c == '{' || c.is_whitespace())
+        .ok_or("sample without a value")?;
+    let (name, mut rest) = line.split_at(name_end);
+    let mut labels = Vec::new();
+    if let Some(label_text) = rest.strip_prefix('{') {
+        rest = label_text;
+        loop {
+            rest = rest.trim_start_matches([' ', ',']);
+            if let Some(after) = rest.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+            let (label, after) = rest.split_once('=').ok_or("label without a value")?;
+            let after = after
+                .trim_start()
+                .strip_prefix('"')
+                .ok_or("label value without quotes")?;
+            let (value, after) = quoted(after)?;
+            labels.push((label.trim().to_owned(), value));
+            rest = after;
+        }
+    }
+    let mut words = rest.split_whitespace();
+    let value = parse_value(words.next().ok_or("sample without a value")?)?;
+    let timestamp_ms = words
+        .next()
+        .map(|timestamp| {
+            timestamp
+                .parse()
+                .map_err(|_| format!("invalid timestamp {timestamp}"))
+        })
+        .transpose()?;
+    Ok(Sample {
+        name,
+        labels,
+        value,
+        timestamp_ms,
+    })
+}
+
+/// The unescaped value of a quoted label value whose opening quote was consumed, and the text after its closing
+/// quote.
+fn quoted(text: &str) -> Result<(String, &str), String> {
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Ok((unescape(&text[..index], true), &text[index + 1..])),
+            _ => {}
+        }
+    }
+    Err("label value without a closing quote".to_owned())
+}
+
+/// Unescape `\\` and `\n`, and `\"` in label values.
+fn unescape(text: &str, label_value: bool) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('\\') | None => unescaped.push('\\'),
+            Some('"') if label_value => unescaped.push('"'),
+            Some(c) => {
+                unescaped.push('\\');
+                unescaped.push(c);
+            }
+        }
+    }
+    unescaped
+}
+
+fn parse_value(value: &str) -> Result<f64, String> {
+    match value {
+        "+Inf" | "Inf" => Ok(f64::INFINITY),
+        "-Inf" => Ok(f64::NEG_INFINITY),
+        "NaN" => Ok(f64::NAN),
+        _ => value.parse().map_err(|_| format!("invalid value {value}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::{Encoder, TextEncoder};
+
+    use super::*;
+
+    const TEXT: &str = r#"# HELP requests_total Requests \\ served.
+# TYPE requests_total counter
+requests_total{method="GET",path="/a\"b\\c\nd"} 3
+requests_total{method="POST",path="/"} 1 1700000000000
+# TYPE latency histogram
+latency_bucket{le="0.5"} 1
+latency_bucket{le="1"} 2
+latency_bucket{le="+Inf"} 3
+latency_sum 2.5
+latency_count 3
+# TYPE rpc summary
+rpc{quantile="0.5"} 0.25
+rpc{quantile="0.9"} NaN
+rpc_sum 7
+rpc_count 10
+# TYPE unused gauge
+temperature -Inf
+"#;
+
+    fn encode(families: &[MetricFamily]) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(families, &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn families_are_parsed_by_type() {
+        let families = parse(TEXT).unwrap();
+        let names: Vec<_> = families.iter().map(MetricFamily::get_name).collect();
+        assert_eq!(names, ["requests_total", "latency", "rpc", "temperature"]);
+
+        let requests = &families[0];
+        assert_eq!(requests.get_field_type(), MetricType::COUNTER);
+        assert_eq!(requests.get_help(), "Requests \\ served.");
+        assert_eq!(requests.get_metric().len(), 2);
+        assert_eq!(
+            requests.get_metric()[0].get_label()[1].get_value(),
+            "/a\"b\\c\nd"
+        );
+        assert_eq!(
+            requests.get_metric()[1].get_timestamp_ms(),
+            1_700_000_000_000
+        );
+
+        let latency = families[1].get_metric()[0].get_histogram();
+        assert_eq!(latency.get_bucket().len(), 2);
+        assert_eq!(latency.get_sample_count(), 3);
+        assert!((latency.get_sample_sum() - 2.5).abs() < f64::EPSILON);
+
+        let rpc = families[2].get_metric()[0].get_summary();
+        assert_eq!(rpc.get_quantile().len(), 2);
+        assert!(rpc.get_quantile()[1].get_value().is_nan());
+        assert_eq!(rpc.get_sample_count(), 10);
+
+        assert_eq!(families[3].get_field_type(), MetricType::GAUGE);
+        assert!(families[3].get_metric()[0]
+            .get_gauge()
+            .get_value()
+            .is_infinite());
+    }
+
+    #[test]
+    fn encoded_families_are_parsed_back() {
+        let encoded = encode(&parse(TEXT).unwrap());
+        assert_eq!(encode(&parse(&encoded).unwrap()), encoded);
+        assert!(encoded.contains("latency_bucket{le=\"+Inf\"} 3\n"));
+        assert_eq!(encoded.matches("le=\"+Inf\"").count(), 1);
+    }
+
+    #[test]
+    fn histogram_series_are_grouped_by_labels() {
+        let text = "# TYPE latency histogram
+latency_bucket{path=\"/a\",le=\"1\"} 1
+latency_bucket{path=\"/b\",le=\"1\"} 2
+latency_count{path=\"/a\"} 1
+latency_count{path=\"/b\"} 2
+";
+        let families = parse(text).unwrap();
+        let metrics = families[0].get_metric();
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[1].get_label()[0].get_value(), "/b");
+        assert_eq!(metrics[1].get_histogram().get_sample_count(), 2);
+    }
+
+    #[test]
+    fn invalid_lines_are_errors() {
+        assert!(parse("requests_total").is_err());
+        assert!(parse("requests_total{method=\"GET} 1").is_err());
+        assert!(parse("requests_total one").is_err());
+        assert!(parse("# TYPE requests_total meter").is_err());
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Exporters that negotiate the OTLP transport with a target on first contact.
+//
+// A target configured for gRPC with `http_fallback` enabled is first exported to over OTLP/gRPC. If the
+// endpoint answers in a way that shows it does not speak gRPC, the same payload is retried over OTLP/HTTP
+// and the protocol that worked is remembered for the lifetime of the exporter.
+
+use std::sync::{Mutex, PoisonError};
+
+use async_trait::async_trait;
+use log::info;
+use opentelemetry::{
+    logs::{LogError, LogResult},
+    metrics::{MetricsError, Result as MetricsResult},
+    ExportError,
+};
+use opentelemetry_otlp::{LogExporter as OtlpLogExporter, MetricsExporter, Protocol};
+use opentelemetry_sdk::{
+    export::logs::{LogData, LogExporter},
+    metrics::{
+        data::{ResourceMetrics, Temporality},
+        exporter::PushMetricsExporter,
+        reader::{AggregationSelector, TemporalitySelector},
+        Aggregation, InstrumentKind,
+    },
+};
+use tonic::Code;
+
+use crate::exporter_metrics::{self, Retry};
+
+/// Part of the message of the status tonic infers from the HTTP status code of a response that is not gRPC.
+const GRPC_STATUS_MISSING: &str = "grpc-status header missing";
+
+/// A metrics exporter for a single target that can fall back from OTLP/gRPC to OTLP/HTTP.
+pub(crate) struct ProtocolMetricsExporter {
+    target: String,
+    primary: MetricsExporter,
+    fallback: Option<MetricsExporter>,
+    negotiated: Mutex<Option<Protocol>>,
+}
+
+impl ProtocolMetricsExporter {
+    /// Create a new exporter. `fallback` is only consulted if `primary` turns out to not speak gRPC.
+    pub(crate) fn new(
+        target: String,
+        primary: MetricsExporter,
+        fallback: Option<MetricsExporter>,
+    ) -> Self {
+        ProtocolMetricsExporter {
+            target,
+            primary,
+            fallback,
+            negotiated: Mutex::new(None),
+        }
+    }
+
+    fn set_negotiated(&self, protocol: Protocol) {
+        *self
+            .negotiated
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(protocol);
+    }
+}
+
+impl TemporalitySelector for ProtocolMetricsExporter {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.primary.temporality(kind)
+    }
+}
+
+impl AggregationSelector for ProtocolMetricsExporter {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.primary.aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl PushMetricsExporter for ProtocolMetricsExporter {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        let Some(fallback) = &self.fallback else {
+            return self.primary.export(metrics).await;
+        };
+
+        let negotiated = *self
+            .negotiated
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match negotiated {
+            Some(Protocol::Grpc) => self.primary.export(metrics).await,
+            Some(Protocol::HttpBinary) => fallback.export(metrics).await,
+            None => match self.primary.export(metrics).await {
+                Ok(()) => {
+                    self.set_negotiated(Protocol::Grpc);
+                    Ok(())
+                }
+                Err(MetricsError::ExportErr(e)) if is_protocol_mismatch(e.as_ref()) => {
+                    exporter_metrics::record_retry(
+                        "metrics",
+                        &self.target,
+                        Retry::ProtocolFallback,
+                    );
+                    fallback.export(metrics).await?;
+                    info!(
+                        "metrics target {} does not speak OTLP/gRPC, using OTLP/HTTP instead",
+                        self.target
+                    );
+                    self.set_negotiated(Protocol::HttpBinary);
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        self.primary.force_flush().await?;
+        if let Some(fallback) = &self.fallback {
+            fallback.force_flush().await?;
+        }
+        Ok(())
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        let result = self.primary.shutdown();
+        if let Some(fallback) = &self.fallback {
+            fallback.shutdown()?;
+        }
+        result
+    }
+}
+
+/// A log exporter for a single target that can fall back from OTLP/gRPC to OTLP/HTTP.
+#[derive(Debug)]
+pub(crate) struct ProtocolLogExporter {
+    target: String,
+    primary: OtlpLogExporter,
+    fallback: Option<OtlpLogExporter>,
+    negotiated: Option<Protocol>,
+}
+
+impl ProtocolLogExporter {
+    /// Create a new exporter. `fallback` is only consulted if `primary` turns out to not speak gRPC.
+    pub(crate) fn new(
+        target: String,
+        primary: OtlpLogExporter,
+        fallback: Option<OtlpLogExporter>,
+    ) -> Self {
+        ProtocolLogExporter {
+            target,
+            primary,
+            fallback,
+            negotiated: None,
+        }
+    }
+}
+
+#[async_trait]
+impl LogExporter for ProtocolLogExporter {
+    async fn export(&mut self, batch: Vec<LogData>) -> LogResult<()> {
+        let Some(fallback) = self.fallback.as_mut() else {
+            return self.primary.export(batch).await;
+        };
+
+        match self.negotiated {
+            Some(Protocol::Grpc) => self.primary.export(batch).await,
+            Some(Protocol::HttpBinary) => fallback.export(batch).await,
+            None => match self.primary.export(batch.clone()).await {
+                Ok(()) => {
+                    self.negotiated = Some(Protocol::Grpc);
+                    Ok(())
+                }
+                Err(LogError::ExportFailed(e)) if is_protocol_mismatch(e.as_ref()) => {
+                    exporter_metrics::record_retry("logs", &self.target, Retry::ProtocolFallback);
+                    fallback.export(batch).await?;
+                    info!(
+                        "logs target {} does not speak OTLP/gRPC, using OTLP/HTTP instead",
+                        self.target
+                    );
+                    self.negotiated = Some(Protocol::HttpBinary);
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.primary.shutdown();
+        if let Some(fallback) = self.fallback.as_mut() {
+            fallback.shutdown();
+        }
+    }
+}
+
+/// Returns true if the gRPC export failed in a way that indicates the endpoint is not a gRPC server,
+/// as opposed to the endpoint being unreachable or rejecting the payload.
+///
+/// Only `Unimplemented`, or a plain HTTP response that lacks the `grpc-status` header, qualify. `Internal` and
+/// `Unknown` are also what a gRPC server reports for its own failures, and `Unavailable` is what a proxy in
+/// front of one answers while it is down, so neither may switch the target to OTLP/HTTP for good.
+fn is_protocol_mismatch(error: &dyn ExportError) -> bool {
+    let error: &(dyn std::error::Error + 'static) = error;
+    match error.downcast_ref::<opentelemetry_otlp::Error>() {
+        Some(opentelemetry_otlp::Error::Status {
+            code: Code::Unimplemented,
+            ..
+        }) => true,
+        Some(opentelemetry_otlp::Error::Status { code, message }) => {
+            *code != Code::Unavailable && message.contains(GRPC_STATUS_MISSING)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(code: Code, message: &str) -> opentelemetry_otlp::Error {
+        opentelemetry_otlp::Error::Status {
+            code,
+            message: message.to_owned(),
+        }
+    }
+
+    #[test]
+    fn unimplemented_is_a_protocol_mismatch() {
+        assert!(is_protocol_mismatch(&status(Code::Unimplemented, "")));
+    }
+
+    #[test]
+    fn a_response_without_grpc_status_is_a_protocol_mismatch() {
+        let message = format!(", detailed error message: {GRPC_STATUS_MISSING}");
+        assert!(is_protocol_mismatch(&status(Code::Internal, &message)));
+        assert!(is_protocol_mismatch(&status(Code::Unknown, &message)));
+    }
+
+    #[test]
+    fn server_failures_are_not_protocol_mismatches() {
+        assert!(!is_protocol_mismatch(&status(Code::Internal, "")));
+        assert!(!is_protocol_mismatch(&status(Code::Unknown, "panicked")));
+        assert!(!is_protocol_mismatch(&status(
+            Code::Unavailable,
+            GRPC_STATUS_MISSING
+        )));
+        assert!(!is_protocol_mismatch(&status(Code::InvalidArgument, "")));
+    }
+
+    #[test]
+    fn other_errors_are_not_protocol_mismatches() {
+        assert!(!is_protocol_mismatch(
+            &opentelemetry_otlp::Error::NoHttpClient
+        ));
+    }
+}
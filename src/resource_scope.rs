@@ -0,0 +1,370 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Secondary resources within one process, e.g. the twins of embedded devices, whose metrics and logs are exported
+//! with their own resource attributes through the export targets of [`Otel`](crate::Otel).
+//!
+//! The resource of a [`ResourceScope`] is the resource of the component merged with the attributes of the scope,
+//! which take precedence. The metrics of its meters are collected by each metrics export target right after the
+//! metrics of the component, and exported to the target as a separate request, so they follow its interval,
+//! temporality and schedule. The records of its loggers go through the log pipeline of the component, with its routes
+//! and the filters of the log targets, and are exported with the resource of the scope. The metrics of scopes are not
+//! served on the prometheus end point, the records of the `log` crate always belong to the component, and the device
+//! identity of scopes is the one read at initialization.
+
+use std::{
+    borrow::Cow,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, PoisonError, RwLock, Weak,
+    },
+};
+
+use async_trait::async_trait;
+use log::warn;
+use opentelemetry::{
+    logs::{AnyValue, LogRecord, LogResult, LoggerProvider as _, Severity},
+    metrics::{Meter, MeterProvider as _, Result as MetricsResult},
+    Key, KeyValue,
+};
+use opentelemetry_sdk::{
+    export::logs::{LogData, LogExporter},
+    logs::{Logger, LoggerProvider},
+    metrics::{
+        data::{ResourceMetrics, Temporality},
+        exporter::PushMetricsExporter,
+        reader::{AggregationSelector, MetricReader, TemporalitySelector},
+        Aggregation, InstrumentKind, ManualReader, Pipeline, SdkMeterProvider,
+    },
+    Resource,
+};
+
+use crate::{
+    instrument_registry::InstrumentRegistry, slo::LatencySlos, tracked_meter,
+    DeltaTemporalitySelector,
+};
+
+/// The attribute identifying the scope of a log record, replaced by the resource of the scope on export.
+const SCOPE_KEY: &str = "otel_lib.resource_scope";
+
+/// The ids of scopes, unique in the process.
+static NEXT_SCOPE_ID: AtomicI64 = AtomicI64::new(0);
+
+/// The resources of the scopes that emit log records, until they are dropped.
+static LOG_SCOPES: RwLock<Vec<(i64, Weak<Resource>)>> = RwLock::new(Vec::new());
+
+/// The metrics export pipelines of a component, which collect the metrics of its scopes.
+#[derive(Debug, Default)]
+pub(crate) struct MetricsScopes {
+    pipelines: RwLock<Vec<Arc<PipelineScopes>>>,
+}
+
+impl MetricsScopes {
+    /// The readers of a new scope, one for each pipeline.
+    fn readers(&self) -> Vec<ScopeReader> {
+        let pipelines = self
+            .pipelines
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        pipelines
+            .iter()
+            .map(|pipeline| {
+                let builder = ManualReader::builder();
+                let reader = Arc::new(match pipeline.temporality {
+                    Some(Temporality::Delta) => builder
+                        .with_temporality_selector(DeltaTemporalitySelector::default())
+                        .build(),
+                    _ => builder.build(),
+                });
+                pipeline
+                    .readers
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push(reader.clone());
+                ScopeReader(reader)
+            })
+            .collect()
+    }
+}
+
+/// The readers of the scopes collected by a metrics export pipeline.
+#[derive(Debug)]
+struct PipelineScopes {
+    temporality: Option<Temporality>,
+    readers: RwLock<Vec<Arc<ManualReader>>>,
+}
+
+/// A reader of a scope, shared by the meter provider of the scope and the pipeline that collects it.
+#[derive(Debug)]
+struct ScopeReader(Arc<ManualReader>);
+
+impl TemporalitySelector for ScopeReader {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.0.temporality(kind)
+    }
+}
+
+impl AggregationSelector for ScopeReader {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.0.aggregation(kind)
+    }
+}
+
+impl MetricReader for ScopeReader {
+    fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+        self.0.register_pipeline(pipeline);
+    }
+
+    fn collect(&self, rm: &mut ResourceMetrics) -> MetricsResult<()> {
+        self.0.collect(rm)
+    }
+
+    fn force_flush(&self) -> MetricsResult<()> {
+        self.0.force_flush()
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.0.shutdown()
+    }
+}
+
+/// A metrics exporter that also exports the metrics of the scopes of the component, each with its resource, after
+/// the metrics of the component.
+pub(crate) struct ScopedMetricsExporter<E> {
+    inner: E,
+    scopes: Arc<PipelineScopes>,
+}
+
+impl<E> ScopedMetricsExporter<E> {
+    /// Wrap the exporter of a pipeline of `temporality`, which collects the scopes of `scopes`.
+    pub(crate) fn new(inner: E, scopes: &MetricsScopes, temporality: Option<Temporality>) -> Self {
+        let pipeline = Arc::new(PipelineScopes {
+            temporality,
+            readers: RwLock::default(),
+        });
+        scopes
+            .pipelines
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(pipeline.clone());
+        ScopedMetricsExporter {
+            inner,
+            scopes: pipeline,
+        }
+    }
+}
+
+impl<E: TemporalitySelector> TemporalitySelector for ScopedMetricsExporter<E> {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.inner.temporality(kind)
+    }
+}
+
+impl<E: AggregationSelector> AggregationSelector for ScopedMetricsExporter<E> {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.inner.aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl<E: PushMetricsExporter> PushMetricsExporter for ScopedMetricsExporter<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        let mut result = self.inner.export(metrics).await;
+
+        let readers = self
+            .scopes
+            .readers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        let mut dropped = Vec::new();
+        for reader in readers {
+            let mut scope_metrics = ResourceMetrics {
+                resource: Resource::empty(),
+                scope_metrics: Vec::new(),
+            };
+            // The reader fails to collect once the meter provider of its scope is dropped.
+            if reader.collect(&mut scope_metrics).is_err() {
+                dropped.push(reader);
+                continue;
+            }
+            if scope_metrics.scope_metrics.is_empty() {
+                continue;
+            }
+            let scope_result = self.inner.export(&mut scope_metrics).await;
+            if result.is_ok() {
+                result = scope_result;
+            }
+        }
+        if !dropped.is_empty() {
+            self.scopes
+                .readers
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .retain(|reader| !dropped.iter().any(|dropped| Arc::ptr_eq(reader, dropped)));
+        }
+        result
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        self.inner.force_flush().await
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// A log exporter that applies the resource of their scope to the records emitted by the loggers of a scope.
+#[derive(Debug)]
+pub(crate) struct ScopedLogExporter<E> {
+    inner: E,
+}
+
+impl<E> ScopedLogExporter<E> {
+    pub(crate) fn new(inner: E) -> Self {
+        ScopedLogExporter { inner }
+    }
+}
+
+#[async_trait]
+impl<E: LogExporter> LogExporter for ScopedLogExporter<E> {
+    async fn export(&mut self, mut batch: Vec<LogData>) -> LogResult<()> {
+        for data in &mut batch {
+            let Some(attributes) = data.record.attributes.as_mut() else {
+                continue;
+            };
+            let Some(index) = attributes
+                .iter()
+                .position(|(key, _)| key.as_str() == SCOPE_KEY)
+            else {
+                continue;
+            };
+            let (_, id) = attributes.remove(index);
+            let AnyValue::Int(id) = id else {
+                continue;
+            };
+            let scopes = LOG_SCOPES.read().unwrap_or_else(PoisonError::into_inner);
+            if let Some(resource) = scopes
+                .iter()
+                .find(|(scope_id, _)| *scope_id == id)
+                .and_then(|(_, resource)| resource.upgrade())
+            {
+                data.resource = Cow::Owned((*resource).clone());
+            }
+        }
+        self.inner.export(batch).await
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+}
+
+/// A logical resource within the component, whose meters and loggers export through the export targets of the
+/// component with the resource of the scope. See [`Otel::resource_scope`](crate::Otel::resource_scope).
+///
+/// Its metrics stop being exported when it is dropped.
+pub struct ResourceScope {
+    id: i64,
+    resource: Arc<Resource>,
+    meter_provider: SdkMeterProvider,
+    logger_provider: Option<LoggerProvider>,
+    instruments: Arc<InstrumentRegistry>,
+    slos: Arc<LatencySlos>,
+}
+
+impl ResourceScope {
+    pub(crate) fn new(
+        resource: Resource,
+        scopes: &MetricsScopes,
+        logger_provider: Option<LoggerProvider>,
+        instruments: Arc<InstrumentRegistry>,
+        slos: Arc<LatencySlos>,
+    ) -> Self {
+        let id = NEXT_SCOPE_ID.fetch_add(1, Ordering::Relaxed);
+        let resource = Arc::new(resource);
+        let mut builder = SdkMeterProvider::builder().with_resource((*resource).clone());
+        for reader in scopes.readers() {
+            builder = builder.with_reader(reader);
+        }
+        if logger_provider.is_some() {
+            let mut log_scopes = LOG_SCOPES.write().unwrap_or_else(PoisonError::into_inner);
+            log_scopes.retain(|(_, resource)| resource.strong_count() > 0);
+            log_scopes.push((id, Arc::downgrade(&resource)));
+        }
+        ResourceScope {
+            id,
+            resource,
+            meter_provider: builder.build(),
+            logger_provider,
+            instruments,
+            slos,
+        }
+    }
+
+    /// The resource of the scope.
+    pub fn resource(&self) -> &Resource {
+        &self.resource
+    }
+
+    /// A meter named `name`, whose instruments are checked like those of [`Otel::meter`](crate::Otel::meter).
+    pub fn meter(&self, name: &str) -> Meter {
+        tracked_meter::tracked_meter(
+            self.meter_provider.meter(name.to_owned()),
+            self.instruments.clone(),
+            self.slos.clone(),
+            None,
+        )
+    }
+
+    /// A logger named `name`, whose records are exported with the resource of the scope.
+    pub fn logger(&self, name: &str) -> ScopedLogger {
+        ScopedLogger {
+            inner: self
+                .logger_provider
+                .as_ref()
+                .map(|logger_provider| logger_provider.logger(name.to_owned())),
+            id: self.id,
+        }
+    }
+}
+
+impl Drop for ResourceScope {
+    fn drop(&mut self) {
+        if let Err(e) = self.meter_provider.shutdown() {
+            warn!("encountered error while shutting down the meter provider of a resource scope: {e:?}");
+        }
+    }
+}
+
+/// A logger of a [`ResourceScope`]. Its records are dropped if the log pipeline of the component is not installed.
+#[derive(Debug)]
+pub struct ScopedLogger {
+    inner: Option<Logger>,
+    id: i64,
+}
+
+impl opentelemetry::logs::Logger for ScopedLogger {
+    fn emit(&self, mut record: LogRecord) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        record
+            .attributes
+            .get_or_insert_with(Vec::new)
+            .push((Key::from_static_str(SCOPE_KEY), AnyValue::Int(self.id)));
+        inner.emit(record);
+    }
+
+    fn event_enabled(&self, level: Severity, target: &str) -> bool {
+        self.inner
+            .as_ref()
+            .is_some_and(|inner| inner.event_enabled(level, target))
+    }
+}
+
+/// The resource of a scope of `resource`, with `attributes`.
+pub(crate) fn scope_resource(resource: &Resource, attributes: &[KeyValue]) -> Resource {
+    resource.merge(&Resource::new(attributes.iter().cloned()))
+}
@@ -0,0 +1,504 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Retries of failed exports, so that a collector that is down for a while, e.g. while it restarts, doesn't lose
+// telemetry.
+//
+// A failed export is buffered rather than retried right away, as the pipelines time out exports that take longer
+// than the timeout of their target. The exports that follow send up to `MAX_REPLAYED_EXPORTS` buffered exports
+// again, oldest first and each as its own request, once the backoff since the last failure elapsed. A buffered
+// export stays in the buffer until it was sent, so an export that the pipeline times out loses none of them. Exports
+// that arrive during the backoff, while buffered exports are pending, are buffered behind them without being sent,
+// and fail with the last error of the target, so that they aren't taken for delivered. Exports that were sent the
+// maximum number of times, or that don't fit in the buffer, are discarded and counted as the
+// `otel_lib.exporter.discarded` metric. Exports buffered when the pipeline shuts down are lost.
+
+use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    error::Error,
+    fmt,
+    hash::BuildHasher,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures_util::lock::Mutex as AsyncMutex;
+use log::warn;
+use opentelemetry::{
+    logs::{LogError, LogResult},
+    metrics::{MetricsError, Result as MetricsResult},
+    ExportError,
+};
+use opentelemetry_sdk::{
+    export::logs::{LogData, LogExporter},
+    metrics::{
+        data::{ResourceMetrics, Temporality},
+        exporter::PushMetricsExporter,
+        reader::{AggregationSelector, TemporalitySelector},
+        Aggregation, InstrumentKind,
+    },
+};
+
+use crate::{
+    config::ExportRetry,
+    exporter_metrics::{self, Discard, Retry},
+};
+
+/// The exporter name of the deferred exports whose last error was not an export error.
+const RETRY_EXPORTER: &str = "retry";
+
+/// The error of an export buffered during the backoff, which is the last error of its target.
+#[derive(Clone, Debug)]
+struct DeferredError {
+    exporter_name: &'static str,
+    message: String,
+}
+
+impl DeferredError {
+    fn new(exporter_name: Option<&'static str>, e: &impl fmt::Display) -> Self {
+        DeferredError {
+            exporter_name: exporter_name.unwrap_or(RETRY_EXPORTER),
+            message: e.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for DeferredError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "deferred until the backoff elapses, last failed with {}",
+            self.message
+        )
+    }
+}
+
+impl Error for DeferredError {}
+
+impl ExportError for DeferredError {
+    // The name of the last error, so that the error handler ignores it too if it was already summarized.
+    fn exporter_name(&self) -> &'static str {
+        self.exporter_name
+    }
+}
+
+/// The maximum number of buffered exports sent again by an export.
+const MAX_REPLAYED_EXPORTS: usize = 16;
+
+/// An export that failed, or was buffered during the backoff without being sent.
+#[derive(Debug)]
+struct Failed<T> {
+    export: T,
+    attempts: u32,
+}
+
+/// The failed exports of a target, and when they are sent again.
+#[derive(Debug)]
+struct Backlog<T> {
+    signal: &'static str,
+    target: String,
+    retry: ExportRetry,
+    exports: VecDeque<Failed<T>>,
+    failures: u32,
+    next_attempt: Option<Instant>,
+    last_error: Option<DeferredError>,
+    discarded: usize,
+}
+
+impl<T> Backlog<T> {
+    fn new(signal: &'static str, target: &str, retry: &ExportRetry) -> Self {
+        Backlog {
+            signal,
+            target: target.to_owned(),
+            retry: retry.clone(),
+            exports: VecDeque::new(),
+            failures: 0,
+            next_attempt: None,
+            last_error: None,
+            discarded: 0,
+        }
+    }
+
+    /// Whether exports are sent now, i.e. the backoff since the last failure elapsed.
+    fn due(&self) -> bool {
+        self.next_attempt
+            .is_none_or(|next_attempt| Instant::now() >= next_attempt)
+    }
+
+    /// Buffer `export`, which was sent `attempts` times, discarding the oldest export if the buffer is full.
+    fn buffer(&mut self, export: T, attempts: u32) {
+        if attempts >= self.retry.max_attempts {
+            self.discard(Discard::MaxAttempts);
+            return;
+        }
+        if self.exports.len() >= self.retry.max_buffered_batches {
+            self.discard(Discard::BufferFull);
+            if self.exports.pop_front().is_none() {
+                return;
+            }
+        }
+        self.exports.push_back(Failed { export, attempts });
+    }
+
+    fn discard(&mut self, discard: Discard) {
+        self.discarded += 1;
+        exporter_metrics::record_discarded(self.signal, &self.target, discard);
+    }
+
+    /// The error of the exports buffered during the backoff, if any are.
+    fn deferred(&self) -> Option<DeferredError> {
+        self.last_error.clone().filter(|_| !self.exports.is_empty())
+    }
+
+    /// Record that the oldest buffered export failed again, discarding it if it was sent the maximum number of times.
+    fn failed_again(&mut self) {
+        let Some(failed) = self.exports.front_mut() else {
+            return;
+        };
+        failed.attempts += 1;
+        if failed.attempts >= self.retry.max_attempts {
+            self.exports.pop_front();
+            self.discard(Discard::MaxAttempts);
+        }
+    }
+
+    /// Record that the target failed with `error`, backing off before the next attempt.
+    fn failed(&mut self, error: DeferredError) {
+        self.failures = self.failures.saturating_add(1);
+        self.next_attempt = Some(Instant::now() + self.backoff());
+        self.last_error = Some(error);
+    }
+
+    /// Record that the target succeeded, reporting the exports that were discarded while it was failing.
+    fn succeeded(&mut self) {
+        self.failures = 0;
+        self.next_attempt = None;
+        self.last_error = None;
+        if self.discarded > 0 {
+            warn!(
+                "{} failed exports to {} were discarded rather than retried while it was failing",
+                self.discarded, self.target
+            );
+            self.discarded = 0;
+        }
+    }
+
+    /// The backoff after the failures in a row so far, doubling with each of them.
+    fn backoff(&self) -> Duration {
+        let max_backoff = Duration::from_millis(self.retry.max_backoff_millis);
+        let backoff = Duration::from_millis(self.retry.initial_backoff_millis)
+            .saturating_mul(2_u32.saturating_pow(self.failures.saturating_sub(1)))
+            .min(max_backoff);
+        let jitter = if self.retry.jitter.is_finite() {
+            self.retry.jitter.clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let factor = 1.0 + jitter * (2.0 * random_fraction() - 1.0);
+        Duration::try_from_secs_f64(backoff.as_secs_f64() * factor).unwrap_or(max_backoff)
+    }
+}
+
+/// A random number between 0 and 1.
+fn random_fraction() -> f64 {
+    let random = RandomState::new().hash_one(Instant::now());
+    f64::from(u32::try_from(random >> 32).unwrap_or_default()) / f64::from(u32::MAX)
+}
+
+/// A metrics exporter that retries the exports that fail, if configured to.
+pub(crate) struct RetryMetricsExporter<E> {
+    inner: E,
+    target: String,
+    backlog: Option<AsyncMutex<Backlog<ResourceMetrics>>>,
+}
+
+impl<E> RetryMetricsExporter<E> {
+    pub(crate) fn new(inner: E, target: &str, retry: Option<&ExportRetry>) -> Self {
+        RetryMetricsExporter {
+            inner,
+            target: target.to_owned(),
+            backlog: retry.map(|retry| AsyncMutex::new(Backlog::new("metrics", target, retry))),
+        }
+    }
+}
+
+impl<E: TemporalitySelector> TemporalitySelector for RetryMetricsExporter<E> {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.inner.temporality(kind)
+    }
+}
+
+impl<E: AggregationSelector> AggregationSelector for RetryMetricsExporter<E> {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.inner.aggregation(kind)
+    }
+}
+
+/// Take the data of `metrics`, to buffer it.
+fn take_metrics(metrics: &mut ResourceMetrics) -> ResourceMetrics {
+    ResourceMetrics {
+        resource: metrics.resource.clone(),
+        scope_metrics: std::mem::take(&mut metrics.scope_metrics),
+    }
+}
+
+#[async_trait]
+impl<E: PushMetricsExporter> PushMetricsExporter for RetryMetricsExporter<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        let Some(backlog) = &self.backlog else {
+            return self.inner.export(metrics).await;
+        };
+        // The lock is held while the buffered exports are sent, so that they are only removed once they were.
+        let mut backlog = backlog.lock().await;
+        if !backlog.due() {
+            if !metrics.scope_metrics.is_empty() {
+                backlog.buffer(take_metrics(metrics), 0);
+            }
+            return backlog
+                .deferred()
+                .map_or(Ok(()), |e| Err(MetricsError::ExportErr(Box::new(e))));
+        }
+
+        let mut result = Ok(());
+        for _ in 0..MAX_REPLAYED_EXPORTS {
+            let Some(failed) = backlog.exports.front_mut() else {
+                break;
+            };
+            if failed.attempts > 0 {
+                exporter_metrics::record_retry("metrics", &self.target, Retry::Export);
+            }
+            if let Err(e) = self.inner.export(&mut failed.export).await {
+                backlog.failed_again();
+                result = Err(e);
+                break;
+            }
+            backlog.exports.pop_front();
+        }
+        if result.is_ok() {
+            if let Err(e) = self.inner.export(metrics).await {
+                if !metrics.scope_metrics.is_empty() {
+                    backlog.buffer(take_metrics(metrics), 1);
+                }
+                result = Err(e);
+            }
+        } else if !metrics.scope_metrics.is_empty() {
+            backlog.buffer(take_metrics(metrics), 0);
+        }
+
+        match &result {
+            Ok(()) => backlog.succeeded(),
+            Err(MetricsError::ExportErr(e)) => {
+                backlog.failed(DeferredError::new(Some(e.exporter_name()), e));
+            }
+            Err(e) => backlog.failed(DeferredError::new(None, e)),
+        }
+        result
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        self.inner.force_flush().await
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// A log exporter that retries the exports that fail, if configured to.
+#[derive(Debug)]
+pub(crate) struct RetryLogExporter<E> {
+    inner: E,
+    backlog: Option<Backlog<Vec<LogData>>>,
+}
+
+impl<E> RetryLogExporter<E> {
+    pub(crate) fn new(inner: E, target: &str, retry: Option<&ExportRetry>) -> Self {
+        RetryLogExporter {
+            inner,
+            backlog: retry.map(|retry| Backlog::new("logs", target, retry)),
+        }
+    }
+}
+
+#[async_trait]
+impl<E: LogExporter> LogExporter for RetryLogExporter<E> {
+    async fn export(&mut self, batch: Vec<LogData>) -> LogResult<()> {
+        let Some(backlog) = &mut self.backlog else {
+            return self.inner.export(batch).await;
+        };
+        if !backlog.due() {
+            if !batch.is_empty() {
+                backlog.buffer(batch, 0);
+            }
+            return backlog
+                .deferred()
+                .map_or(Ok(()), |e| Err(LogError::ExportFailed(Box::new(e))));
+        }
+
+        let mut result = Ok(());
+        for _ in 0..MAX_REPLAYED_EXPORTS {
+            let Some(failed) = backlog.exports.front() else {
+                break;
+            };
+            if failed.attempts > 0 {
+                exporter_metrics::record_retry("logs", &backlog.target, Retry::Export);
+            }
+            // The exporter takes the batch, so it stays buffered until it was sent.
+            if let Err(e) = self.inner.export(failed.export.clone()).await {
+                backlog.failed_again();
+                result = Err(e);
+                break;
+            }
+            backlog.exports.pop_front();
+        }
+        if result.is_ok() {
+            // Likewise, the batch is copied before it is first sent, as it is retried if the export fails.
+            let retained = batch.clone();
+            if let Err(e) = self.inner.export(batch).await {
+                if !retained.is_empty() {
+                    backlog.buffer(retained, 1);
+                }
+                result = Err(e);
+            }
+        } else if !batch.is_empty() {
+            backlog.buffer(batch, 0);
+        }
+
+        match &result {
+            Ok(()) => backlog.succeeded(),
+            Err(LogError::ExportFailed(e)) => {
+                backlog.failed(DeferredError::new(Some(e.exporter_name()), e));
+            }
+            Err(e) => backlog.failed(DeferredError::new(None, e)),
+        }
+        result
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        borrow::Cow,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use opentelemetry::{logs::LogRecord, InstrumentationLibrary};
+    use opentelemetry_sdk::Resource;
+
+    use super::*;
+
+    /// An exporter that records the size of the batches it exports, and fails while `failing` is set.
+    #[derive(Debug, Default)]
+    struct RecordingExporter {
+        failing: Arc<AtomicBool>,
+        exported: Arc<Mutex<Vec<usize>>>,
+    }
+
+    #[async_trait]
+    impl LogExporter for RecordingExporter {
+        async fn export(&mut self, batch: Vec<LogData>) -> LogResult<()> {
+            if self.failing.load(Ordering::SeqCst) {
+                return Err(LogError::Other("collector down".into()));
+            }
+            self.exported.lock().unwrap().push(batch.len());
+            Ok(())
+        }
+    }
+
+    fn batch(len: usize) -> Vec<LogData> {
+        let data = LogData {
+            record: LogRecord::default(),
+            resource: Cow::Owned(Resource::empty()),
+            instrumentation: InstrumentationLibrary::default(),
+        };
+        vec![data; len]
+    }
+
+    fn retry(initial_backoff_millis: u64) -> ExportRetry {
+        ExportRetry {
+            max_attempts: 100,
+            initial_backoff_millis,
+            max_backoff_millis: initial_backoff_millis,
+            jitter: 0.0,
+            max_buffered_batches: 100,
+        }
+    }
+
+    #[test]
+    fn an_export_replays_at_most_max_replayed_exports() {
+        let inner = RecordingExporter::default();
+        let (failing, exported) = (inner.failing.clone(), inner.exported.clone());
+        let mut exporter = RetryLogExporter::new(inner, "target", Some(&retry(0)));
+
+        failing.store(true, Ordering::SeqCst);
+        for _ in 0..MAX_REPLAYED_EXPORTS + 4 {
+            assert!(futures_executor::block_on(exporter.export(batch(1))).is_err());
+        }
+        failing.store(false, Ordering::SeqCst);
+
+        futures_executor::block_on(exporter.export(batch(2))).unwrap();
+        let mut expected = vec![1; MAX_REPLAYED_EXPORTS];
+        expected.push(2);
+        assert_eq!(*exported.lock().unwrap(), expected);
+
+        // The following export replays the rest.
+        exported.lock().unwrap().clear();
+        futures_executor::block_on(exporter.export(batch(3))).unwrap();
+        assert_eq!(*exported.lock().unwrap(), [1, 1, 1, 1, 3]);
+        assert!(exporter.backlog.unwrap().exports.is_empty());
+    }
+
+    #[test]
+    fn exports_during_the_backoff_are_deferred_with_the_last_error() {
+        let inner = RecordingExporter::default();
+        let (failing, exported) = (inner.failing.clone(), inner.exported.clone());
+        let mut exporter = RetryLogExporter::new(inner, "target", Some(&retry(60_000)));
+
+        failing.store(true, Ordering::SeqCst);
+        assert!(futures_executor::block_on(exporter.export(batch(1))).is_err());
+        failing.store(false, Ordering::SeqCst);
+
+        // The target recovered, but isn't sent anything before the backoff elapses.
+        let Err(LogError::ExportFailed(e)) = futures_executor::block_on(exporter.export(batch(1)))
+        else {
+            panic!("the export isn't deferred");
+        };
+        assert_eq!(e.exporter_name(), RETRY_EXPORTER);
+        assert!(e.to_string().contains("collector down"));
+        assert!(exported.lock().unwrap().is_empty());
+        assert_eq!(exporter.backlog.unwrap().exports.len(), 2);
+    }
+
+    #[test]
+    fn exports_are_discarded_after_max_attempts() {
+        let inner = RecordingExporter::default();
+        inner.failing.store(true, Ordering::SeqCst);
+        let mut exporter = RetryLogExporter::new(
+            inner,
+            "target",
+            Some(&ExportRetry {
+                max_attempts: 2,
+                ..retry(0)
+            }),
+        );
+        for _ in 0..3 {
+            assert!(futures_executor::block_on(exporter.export(batch(1))).is_err());
+        }
+        // The first export was sent by the first two exports, and discarded; the third sent the second once.
+        let backlog = exporter.backlog.unwrap();
+        assert_eq!(backlog.discarded, 1);
+        let attempts: Vec<_> = backlog
+            .exports
+            .iter()
+            .map(|failed| failed.attempts)
+            .collect();
+        assert_eq!(attempts, [1, 0]);
+    }
+}
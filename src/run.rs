@@ -0,0 +1,65 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Supervision of the long running tasks of [`Otel::run`](crate::Otel::run): the prometheus end points and the OTLP
+//! receiver.
+
+use tokio::sync::watch;
+
+/// A handle to stop the long running tasks of [`Otel::run`](crate::Otel::run), and await their termination, e.g. from
+/// a signal handler. Stopping is final: once stopped, `Otel::run` returns right away.
+#[derive(Clone, Debug)]
+pub struct RunHandle {
+    stop: watch::Sender<bool>,
+    active: watch::Sender<usize>,
+}
+
+impl Default for RunHandle {
+    fn default() -> Self {
+        RunHandle {
+            stop: watch::Sender::new(false),
+            active: watch::Sender::new(0),
+        }
+    }
+}
+
+impl RunHandle {
+    /// Stop the tasks: the prometheus end points stop accepting connections and return once the scrapes in progress
+    /// are served, and the OTLP receiver stops once the requests in progress are forwarded.
+    pub fn stop(&self) {
+        self.stop.send_replace(true);
+    }
+
+    /// Whether the tasks were stopped.
+    pub fn is_stopped(&self) -> bool {
+        *self.stop.borrow()
+    }
+
+    /// Wait until no `Otel::run` is in progress, i.e. the tasks terminated after they were stopped, or were never
+    /// run.
+    pub async fn stopped(&self) {
+        let mut active = self.active.subscribe();
+        // The sender is owned by self, so it can't be dropped while waiting.
+        let _ = active.wait_for(|active| *active == 0).await;
+    }
+
+    /// A receiver that is set to true when the tasks are stopped.
+    pub(crate) fn subscribe(&self) -> watch::Receiver<bool> {
+        self.stop.subscribe()
+    }
+
+    /// Track a run of the tasks until the returned guard is dropped.
+    pub(crate) fn enter(&self) -> RunGuard<'_> {
+        self.active.send_modify(|active| *active += 1);
+        RunGuard(&self.active)
+    }
+}
+
+/// A run of the tasks in progress, which ends when dropped, including when the future of `Otel::run` is dropped.
+pub(crate) struct RunGuard<'a>(&'a watch::Sender<usize>);
+
+impl Drop for RunGuard<'_> {
+    fn drop(&mut self) {
+        self.0.send_modify(|active| *active -= 1);
+    }
+}
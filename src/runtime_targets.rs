@@ -0,0 +1,709 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Export targets added and removed at runtime, e.g. to point telemetry at a new collector when a device is
+//! re-provisioned, without restarting.
+//!
+//! The meter and logger providers of the SDK can't take readers and processors once built, so a layer is installed at
+//! initialization that targets join and leave. With `runtime_metrics_targets`, the meter provider has a reader of each
+//! temporality for the metrics targets added at runtime, which are collected on the interval of each target. As
+//! collecting delta metrics resets them, the metrics collected for a delta target are also queued for the other delta
+//! targets, which export them on their next interval. The processors of the log targets, configured or added at
+//! runtime, sit behind a single processor of the logger provider.
+//!
+//! Targets added at runtime export like configured targets, except that their exporter must build when they are added,
+//! they can't be switched with `Otel::switch_target`, and the metrics of resource scopes are not exported to them.
+//! They are not reported by `Otel::init_report` and `Otel::limits`.
+
+use std::{
+    any::Any,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError, RwLock, Weak,
+    },
+    time::Duration,
+};
+
+use futures_util::lock::Mutex as AsyncMutex;
+use log::{info, warn};
+use opentelemetry::{
+    global,
+    logs::{LogResult, Severity},
+    metrics::{MetricsError, Result as MetricsResult},
+};
+use opentelemetry_sdk::{
+    export::logs::LogData,
+    logs::LogProcessor,
+    metrics::{
+        data::{
+            self, ExponentialBucket, ExponentialHistogram, ExponentialHistogramDataPoint, Gauge,
+            Histogram, Metric, ResourceMetrics, ScopeMetrics, Sum, Temporality,
+        },
+        exporter::PushMetricsExporter,
+        reader::{AggregationSelector, MetricReader, TemporalitySelector},
+        Aggregation, InstrumentKind, ManualReader, Pipeline,
+    },
+    Resource,
+};
+use tokio::{
+    sync::watch,
+    task::JoinHandle,
+    time::{self, Instant},
+};
+
+use crate::{
+    config::{Config, LogsExportTarget, MetricsExportTarget},
+    identity::{DeviceIdentity, IdentityMetricsExporter},
+    log_persistence,
+    log_routing::Router,
+    loggers,
+    start_time::StartTimeSource,
+    target_health, transport, DeltaTemporalitySelector,
+};
+
+/// How long an export to a metrics target added at runtime may take, as for the periodic readers of the configured
+/// targets.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The most collections queued for a delta target that it didn't export yet, e.g. as its interval is longer than
+/// those of the other delta targets. The oldest collections are dropped beyond.
+const MAX_PENDING_DELTAS: usize = 64;
+
+/// The reason an export target could not be added or removed.
+#[derive(Debug)]
+pub enum RuntimeTargetError {
+    /// The target can't be added, e.g. as it is disabled or the maximum number of export targets is reached.
+    Unsupported(String),
+    /// A target added at runtime already exports the signal to the url.
+    AlreadyExists(String),
+    /// No target added at runtime exports to the url.
+    NotFound(String),
+    /// The exporter of the target could not be built.
+    Build(String),
+}
+
+impl fmt::Display for RuntimeTargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeTargetError::Unsupported(reason) => {
+                write!(f, "unable to add target as {reason}")
+            }
+            RuntimeTargetError::AlreadyExists(url) => {
+                write!(f, "a target added at runtime already exports to {url}")
+            }
+            RuntimeTargetError::NotFound(url) => {
+                write!(f, "no target added at runtime exports to {url}")
+            }
+            RuntimeTargetError::Build(e) => write!(f, "unable to build the target: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeTargetError {}
+
+/// The readers of the metrics targets added at runtime, registered with the meter provider at initialization.
+#[derive(Debug)]
+pub(crate) struct MetricsReaders {
+    cumulative: Arc<ManualReader>,
+    delta: Arc<ManualReader>,
+    /// The collections of the delta reader that each delta target, by id, didn't export yet.
+    pending_deltas: Mutex<Vec<(u64, Vec<ResourceMetrics>)>>,
+}
+
+impl MetricsReaders {
+    pub(crate) fn new() -> Self {
+        MetricsReaders {
+            cumulative: Arc::new(ManualReader::builder().build()),
+            delta: Arc::new(
+                ManualReader::builder()
+                    .with_temporality_selector(DeltaTemporalitySelector::default())
+                    .build(),
+            ),
+            pending_deltas: Mutex::default(),
+        }
+    }
+
+    /// The readers to register with the meter provider.
+    pub(crate) fn readers(&self) -> [RuntimeReader; 2] {
+        [
+            RuntimeReader(self.cumulative.clone()),
+            RuntimeReader(self.delta.clone()),
+        ]
+    }
+
+    fn register_delta(&self, id: u64) {
+        self.pending_deltas
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((id, Vec::new()));
+    }
+
+    fn unregister_delta(&self, id: u64) {
+        self.pending_deltas
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|(target, _)| *target != id);
+    }
+
+    /// The metrics to export to the target `id` of `temporality`, in order.
+    fn collect(&self, id: u64, temporality: Temporality) -> MetricsResult<Vec<ResourceMetrics>> {
+        let mut metrics = ResourceMetrics {
+            resource: Resource::empty(),
+            scope_metrics: Vec::new(),
+        };
+        if temporality != Temporality::Delta {
+            self.cumulative.collect(&mut metrics)?;
+            return Ok(vec![metrics]);
+        }
+        let mut pending_deltas = self
+            .pending_deltas
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.delta.collect(&mut metrics)?;
+        let mut collections = Vec::new();
+        for (target, pending) in pending_deltas.iter_mut() {
+            if *target == id {
+                collections = std::mem::take(pending);
+            } else if !metrics.scope_metrics.is_empty() {
+                if pending.len() == MAX_PENDING_DELTAS {
+                    pending.remove(0);
+                    warn!("dropped delta metrics queued for a metrics target added at runtime as it didn't export them in time");
+                }
+                pending.push(clone_metrics(&metrics));
+            }
+        }
+        collections.push(metrics);
+        Ok(collections)
+    }
+}
+
+/// A reader of the metrics targets added at runtime, shared by the meter provider and the targets.
+#[derive(Debug)]
+pub(crate) struct RuntimeReader(Arc<ManualReader>);
+
+impl TemporalitySelector for RuntimeReader {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.0.temporality(kind)
+    }
+}
+
+impl AggregationSelector for RuntimeReader {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.0.aggregation(kind)
+    }
+}
+
+impl MetricReader for RuntimeReader {
+    fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+        self.0.register_pipeline(pipeline);
+    }
+
+    fn collect(&self, rm: &mut ResourceMetrics) -> MetricsResult<()> {
+        self.0.collect(rm)
+    }
+
+    fn force_flush(&self) -> MetricsResult<()> {
+        self.0.force_flush()
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.0.shutdown()
+    }
+}
+
+/// A copy of `metrics`, whose data points are not `Clone`.
+fn clone_metrics(metrics: &ResourceMetrics) -> ResourceMetrics {
+    ResourceMetrics {
+        resource: metrics.resource.clone(),
+        scope_metrics: metrics
+            .scope_metrics
+            .iter()
+            .map(|scope_metrics| ScopeMetrics {
+                scope: scope_metrics.scope.clone(),
+                metrics: scope_metrics
+                    .metrics
+                    .iter()
+                    .filter_map(|metric| {
+                        let data = metric.data.as_any();
+                        Some(Metric {
+                            name: metric.name.clone(),
+                            description: metric.description.clone(),
+                            unit: metric.unit.clone(),
+                            data: clone_data::<i64>(data)
+                                .or_else(|| clone_data::<u64>(data))
+                                .or_else(|| clone_data::<f64>(data))?,
+                        })
+                    })
+                    .collect(),
+            })
+            .collect(),
+    }
+}
+
+fn clone_data<T: Copy + fmt::Debug + Send + Sync + 'static>(
+    data: &dyn Any,
+) -> Option<Box<dyn data::Aggregation>> {
+    if let Some(gauge) = data.downcast_ref::<Gauge<T>>() {
+        return Some(Box::new(Gauge {
+            data_points: gauge.data_points.clone(),
+        }));
+    }
+    if let Some(sum) = data.downcast_ref::<Sum<T>>() {
+        return Some(Box::new(Sum {
+            data_points: sum.data_points.clone(),
+            temporality: sum.temporality,
+            is_monotonic: sum.is_monotonic,
+        }));
+    }
+    if let Some(histogram) = data.downcast_ref::<Histogram<T>>() {
+        return Some(Box::new(Histogram {
+            data_points: histogram.data_points.clone(),
+            temporality: histogram.temporality,
+        }));
+    }
+    let histogram = data.downcast_ref::<ExponentialHistogram<T>>()?;
+    let clone_bucket = |bucket: &ExponentialBucket| ExponentialBucket {
+        offset: bucket.offset,
+        counts: bucket.counts.clone(),
+    };
+    Some(Box::new(ExponentialHistogram {
+        data_points: histogram
+            .data_points
+            .iter()
+            .map(|point| ExponentialHistogramDataPoint {
+                attributes: point.attributes.clone(),
+                start_time: point.start_time,
+                time: point.time,
+                count: point.count,
+                min: point.min,
+                max: point.max,
+                sum: point.sum,
+                scale: point.scale,
+                zero_count: point.zero_count,
+                positive_bucket: clone_bucket(&point.positive_bucket),
+                negative_bucket: clone_bucket(&point.negative_bucket),
+                zero_threshold: point.zero_threshold,
+                exemplars: point.exemplars.clone(),
+            })
+            .collect(),
+        temporality: histogram.temporality,
+    }))
+}
+
+/// The processors of the log targets, which the targets added at runtime join and leave.
+#[derive(Debug)]
+pub(crate) struct LogTargets {
+    router: Arc<Router>,
+    next_id: AtomicU64,
+    processors: RwLock<Vec<(u64, Box<dyn LogProcessor>)>>,
+}
+
+impl LogTargets {
+    /// The processors of the log targets, whose records are routed by `router`.
+    pub(crate) fn new(router: Arc<Router>) -> Self {
+        LogTargets {
+            router,
+            next_id: AtomicU64::new(0),
+            processors: RwLock::default(),
+        }
+    }
+
+    /// Add the processor of a target, returning its id.
+    pub(crate) fn add(&self, processor: Box<dyn LogProcessor>) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.processors
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((id, processor));
+        id
+    }
+
+    fn remove(&self, id: u64) -> Option<Box<dyn LogProcessor>> {
+        let mut processors = self
+            .processors
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let index = processors.iter().position(|(target, _)| *target == id)?;
+        Some(processors.remove(index).1)
+    }
+}
+
+/// The processor of the logger provider, which passes the records on to the processors of the log targets.
+#[derive(Debug)]
+pub(crate) struct LogTargetsProcessor(Arc<LogTargets>);
+
+impl LogTargetsProcessor {
+    pub(crate) fn new(targets: Arc<LogTargets>) -> Self {
+        LogTargetsProcessor(targets)
+    }
+}
+
+impl LogProcessor for LogTargetsProcessor {
+    fn emit(&self, data: LogData) {
+        let processors = self
+            .0
+            .processors
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(((_, last), others)) = processors.split_last() {
+            for (_, processor) in others {
+                processor.emit(data.clone());
+            }
+            last.emit(data);
+        }
+    }
+
+    fn force_flush(&self) -> LogResult<()> {
+        let processors = self
+            .0
+            .processors
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        processors
+            .iter()
+            .map(|(_, processor)| processor.force_flush())
+            .fold(Ok(()), Result::and)
+    }
+
+    fn shutdown(&mut self) -> LogResult<()> {
+        let mut processors = self
+            .0
+            .processors
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        processors
+            .iter_mut()
+            .map(|(_, processor)| processor.shutdown())
+            .fold(Ok(()), Result::and)
+    }
+
+    fn event_enabled(&self, level: Severity, target: &str, name: &str) -> bool {
+        self.0
+            .processors
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .any(|(_, processor)| processor.event_enabled(level, target, name))
+    }
+}
+
+/// How a target added at runtime is torn down.
+enum Teardown {
+    /// A task that exports the metrics until the sender is set to true.
+    Metrics {
+        id: u64,
+        stop: watch::Sender<bool>,
+        task: JoinHandle<()>,
+    },
+    /// A processor of the log targets.
+    Logs { id: u64 },
+}
+
+struct RuntimeTarget {
+    signal: &'static str,
+    url: String,
+    label: String,
+    teardown: Teardown,
+}
+
+/// The export targets added at runtime.
+pub(crate) struct RuntimeTargets {
+    config: Config,
+    identity: Option<Arc<DeviceIdentity>>,
+    start_time_source: Option<StartTimeSource>,
+    metrics_readers: Option<Arc<MetricsReaders>>,
+    log_targets: Option<Arc<LogTargets>>,
+    /// The number of configured metrics and log targets, which count towards the maximum number of export targets.
+    configured: (usize, usize),
+    next_id: AtomicU64,
+    /// Held while targets are added or removed, so concurrent calls don't race.
+    targets: AsyncMutex<Vec<RuntimeTarget>>,
+}
+
+impl RuntimeTargets {
+    /// The targets added at runtime to the pipelines initialized with `config`, whose metrics targets are collected
+    /// by `metrics_readers` and whose log targets join `log_targets`, if any.
+    pub(crate) fn new(
+        config: Config,
+        identity: Option<Arc<DeviceIdentity>>,
+        start_time_source: Option<StartTimeSource>,
+        metrics_readers: Option<Arc<MetricsReaders>>,
+        log_targets: Option<Arc<LogTargets>>,
+        configured: (usize, usize),
+    ) -> Self {
+        RuntimeTargets {
+            config,
+            identity,
+            start_time_source,
+            metrics_readers,
+            log_targets,
+            configured,
+            next_id: AtomicU64::new(0),
+            targets: AsyncMutex::default(),
+        }
+    }
+
+    /// Fail if a target of `signal` exporting to `url` can't be added next to `targets` and the `configured` targets
+    /// of the signal.
+    fn check(
+        &self,
+        targets: &[RuntimeTarget],
+        signal: &'static str,
+        url: &str,
+        enabled: bool,
+        configured: usize,
+    ) -> Result<(), RuntimeTargetError> {
+        if !enabled {
+            return Err(RuntimeTargetError::Unsupported(
+                "the target is disabled".to_owned(),
+            ));
+        }
+        if targets
+            .iter()
+            .any(|target| target.signal == signal && target.url == url)
+        {
+            return Err(RuntimeTargetError::AlreadyExists(url.to_owned()));
+        }
+        if let Some(max_export_targets) = self.config.governance.max_export_targets {
+            let count = targets
+                .iter()
+                .filter(|target| target.signal == signal)
+                .count();
+            if configured + count >= max_export_targets {
+                return Err(RuntimeTargetError::Unsupported(format!(
+                    "the maximum of {max_export_targets} export targets is reached"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn add_metrics(
+        &self,
+        export_target: MetricsExportTarget,
+    ) -> Result<(), RuntimeTargetError> {
+        let Some(readers) = &self.metrics_readers else {
+            return Err(RuntimeTargetError::Unsupported(
+                "runtime_metrics_targets is not set".to_owned(),
+            ));
+        };
+        let mut targets = self.targets.lock().await;
+        self.check(
+            &targets,
+            "metrics",
+            &export_target.url,
+            export_target.enabled,
+            self.configured.0,
+        )?;
+
+        let config = self.config.clone();
+        let (exporter, export_target) = tokio::task::spawn_blocking(move || {
+            if export_target.eager_connect {
+                transport::connect_eagerly(
+                    &export_target.url,
+                    crate::eager_connect_protocol(
+                        export_target.protocol,
+                        export_target.http_fallback,
+                    ),
+                    Duration::from_secs(export_target.timeout),
+                    transport::Connector::new(
+                        &export_target.dns,
+                        &export_target.socket_options,
+                        export_target.max_bytes_per_sec,
+                    ),
+                    &transport::TlsOptions::from(&export_target),
+                )
+                .map_err(|e| format!("unable to connect: {e:?}"))?;
+            }
+            crate::build_switchable_exporter(&export_target, &config)
+                .map(|exporter| (exporter, export_target))
+                .map_err(|e| format!("{e:?}"))
+        })
+        .await
+        .map_err(|e| RuntimeTargetError::Build(format!("{e:?}")))?
+        .map_err(RuntimeTargetError::Build)?;
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let temporality = export_target.temporality.unwrap_or(Temporality::Cumulative);
+        if temporality == Temporality::Delta {
+            readers.register_delta(id);
+        }
+        let exporter = IdentityMetricsExporter::new(
+            crate::target_pipeline_exporter(
+                exporter,
+                &export_target,
+                &self.config,
+                self.start_time_source,
+            ),
+            self.identity.clone(),
+        );
+        let (stop, stopped) = watch::channel(false);
+        let task = tokio::spawn(export_periodically(
+            readers.clone(),
+            id,
+            temporality,
+            exporter,
+            Duration::from_secs(export_target.interval_secs),
+            stopped,
+        ));
+        info!("added metrics target [{}]", export_target.label());
+        targets.push(RuntimeTarget {
+            signal: "metrics",
+            label: export_target.label().to_owned(),
+            url: export_target.url,
+            teardown: Teardown::Metrics { id, stop, task },
+        });
+        Ok(())
+    }
+
+    pub(crate) async fn add_logs(
+        &self,
+        export_target: LogsExportTarget,
+    ) -> Result<(), RuntimeTargetError> {
+        let Some(log_targets) = &self.log_targets else {
+            return Err(RuntimeTargetError::Unsupported(
+                "the log pipeline is not installed".to_owned(),
+            ));
+        };
+        let mut targets = self.targets.lock().await;
+        self.check(
+            &targets,
+            "logs",
+            &export_target.url,
+            export_target.enabled,
+            self.configured.1,
+        )?;
+
+        let config = self.config.clone();
+        let (exporter, persistence, export_target) = tokio::task::spawn_blocking(move || {
+            loggers::connect_eagerly(&export_target)?;
+            let persistence = log_persistence::open(&export_target)?;
+            let exporter = loggers::build_target_exporter(&export_target, &config)?;
+            Ok::<_, String>((exporter, persistence, export_target))
+        })
+        .await
+        .map_err(|e| RuntimeTargetError::Build(format!("{e:?}")))?
+        .map_err(RuntimeTargetError::Build)?;
+
+        let (processor, _) = loggers::target_processor(
+            &export_target,
+            &self.config,
+            exporter,
+            persistence,
+            self.identity.as_ref(),
+            &log_targets.router,
+        );
+        let id = log_targets.add(processor);
+        info!("added log target [{}]", export_target.label());
+        targets.push(RuntimeTarget {
+            signal: "logs",
+            label: export_target.label().to_owned(),
+            url: export_target.url,
+            teardown: Teardown::Logs { id },
+        });
+        Ok(())
+    }
+
+    /// Remove the targets that export to `url`, once they exported what they collected or queued.
+    pub(crate) async fn remove(&self, url: &str) -> Result<(), RuntimeTargetError> {
+        let mut targets = self.targets.lock().await;
+        let (removed, kept) = std::mem::take(&mut *targets)
+            .into_iter()
+            .partition(|target| target.url == url);
+        *targets = kept;
+        if removed.is_empty() {
+            return Err(RuntimeTargetError::NotFound(url.to_owned()));
+        }
+        for target in removed {
+            self.tear_down(target).await;
+        }
+        Ok(())
+    }
+
+    /// Remove all the targets added at runtime.
+    pub(crate) async fn shutdown(&self) {
+        let mut targets = self.targets.lock().await;
+        for target in std::mem::take(&mut *targets) {
+            self.tear_down(target).await;
+        }
+    }
+
+    async fn tear_down(&self, target: RuntimeTarget) {
+        match target.teardown {
+            Teardown::Metrics { id, stop, task } => {
+                stop.send_replace(true);
+                if let Err(e) = task.await {
+                    warn!(
+                        "encountered error while stopping metrics target [{}]: {e:?}",
+                        target.label
+                    );
+                }
+                if let Some(readers) = &self.metrics_readers {
+                    readers.unregister_delta(id);
+                }
+            }
+            Teardown::Logs { id } => {
+                let processor = self
+                    .log_targets
+                    .as_ref()
+                    .and_then(|log_targets| log_targets.remove(id));
+                if let Some(mut processor) = processor {
+                    // The batch processor blocks until its queue is exported.
+                    let result = tokio::task::spawn_blocking(move || processor.shutdown())
+                        .await
+                        .map_err(|e| format!("{e:?}"))
+                        .and_then(|result| result.map_err(|e| format!("{e:?}")));
+                    if let Err(e) = result {
+                        warn!(
+                            "encountered error while shutting down log target [{}]: {e}",
+                            target.label
+                        );
+                    }
+                }
+            }
+        }
+        target_health::unregister(target.signal, &target.label);
+        info!("removed {} target [{}]", target.signal, target.label);
+    }
+}
+
+/// Export the metrics collected by `readers` for the target `id` of `temporality` with `exporter` every `interval`,
+/// and once more when `stop` is set to true or dropped, before shutting the exporter down.
+async fn export_periodically(
+    readers: Arc<MetricsReaders>,
+    id: u64,
+    temporality: Temporality,
+    exporter: impl PushMetricsExporter,
+    interval: Duration,
+    mut stop: watch::Receiver<bool>,
+) {
+    let mut ticks = time::interval_at(Instant::now() + interval, interval);
+    loop {
+        let stopped = tokio::select! {
+            _ = ticks.tick() => false,
+            _ = stop.wait_for(|stop| *stop) => true,
+        };
+        match readers.collect(id, temporality) {
+            Ok(collections) => {
+                for mut metrics in collections {
+                    let result = time::timeout(EXPORT_TIMEOUT, exporter.export(&mut metrics))
+                        .await
+                        .unwrap_or_else(|_| {
+                            Err(MetricsError::Other("metrics export timed out".to_owned()))
+                        });
+                    if let Err(e) = result {
+                        global::handle_error(e);
+                    }
+                }
+            }
+            Err(e) => global::handle_error(e),
+        }
+        if stopped {
+            break;
+        }
+    }
+    if let Err(e) = exporter.shutdown() {
+        global::handle_error(e);
+    }
+}
@@ -0,0 +1,343 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Export schedules, for deployments where telemetry may only use the uplink at certain times of the day.
+//
+// During the quiet hours of a target, its exports are held in memory instead of being sent. The first export after
+// the quiet hours sends the held data along with its own. Data held when the pipeline shuts down is lost.
+
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use log::warn;
+use opentelemetry::{logs::LogResult, metrics::Result as MetricsResult};
+use opentelemetry_sdk::{
+    export::logs::{LogData, LogExporter},
+    metrics::{
+        data::{ResourceMetrics, ScopeMetrics, Temporality},
+        exporter::PushMetricsExporter,
+        reader::{AggregationSelector, TemporalitySelector},
+        Aggregation, InstrumentKind,
+    },
+};
+
+use crate::{config::ExportSchedule, global_logger};
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+/// The quiet hours of a target, as minutes of the day in UTC.
+#[derive(Debug)]
+struct QuietWindows {
+    windows: Vec<(i64, i64)>,
+}
+
+impl QuietWindows {
+    /// Parse the windows of `schedule`, reporting and ignoring invalid ones.
+    fn new(target: &str, schedule: &ExportSchedule) -> Self {
+        let offset = i64::from(schedule.utc_offset_minutes);
+        let to_utc = |minute: i64| (minute - offset).rem_euclid(MINUTES_PER_DAY);
+        QuietWindows {
+            windows: schedule
+                .quiet_hours
+                .iter()
+                .filter_map(|quiet_hours| {
+                    if let (Some(start), Some(end)) = (
+                        minute_of_day(&quiet_hours.start),
+                        minute_of_day(&quiet_hours.end),
+                    ) {
+                        Some((to_utc(start), to_utc(end)))
+                    } else {
+                        let message = format!(
+                            "ignoring quiet hours {}-{} of target [{target}] as they are not formatted as HH:MM",
+                            quiet_hours.start, quiet_hours.end
+                        );
+                        // The schedules of the log targets configured at startup are built before the logger.
+                        if global_logger::is_installed() {
+                            warn!("{message}");
+                        } else {
+                            // log error using eprintln as the logger framework is not setup yet!
+                            eprintln!("{message}");
+                        }
+                        None
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether exports are held now.
+    fn quiet(&self) -> bool {
+        if self.windows.is_empty() {
+            return false;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs() / 60);
+        let minute = i64::try_from(now).unwrap_or_default() % MINUTES_PER_DAY;
+        self.windows.iter().any(|&(start, end)| {
+            if start <= end {
+                start <= minute && minute < end
+            } else {
+                // The window spans midnight.
+                minute >= start || minute < end
+            }
+        })
+    }
+}
+
+/// Parse a time of the day formatted as `HH:MM` into minutes since midnight.
+fn minute_of_day(time: &str) -> Option<i64> {
+    let (hours, minutes) = time.split_once(':')?;
+    let (hours, minutes): (i64, i64) = (hours.parse().ok()?, minutes.parse().ok()?);
+    ((0..24).contains(&hours) && (0..60).contains(&minutes)).then_some(hours * 60 + minutes)
+}
+
+/// The exports held during quiet hours, of which the oldest are dropped beyond `max_held_exports`.
+#[derive(Debug)]
+struct Held<T> {
+    target: String,
+    exports: VecDeque<T>,
+    max_held_exports: usize,
+    dropped: usize,
+}
+
+impl<T> Held<T> {
+    fn new(target: String, max_held_exports: usize) -> Self {
+        Held {
+            target,
+            exports: VecDeque::new(),
+            max_held_exports,
+            dropped: 0,
+        }
+    }
+
+    fn hold(&mut self, export: T) {
+        if self.exports.len() >= self.max_held_exports {
+            self.exports.pop_front();
+            self.dropped += 1;
+        }
+        self.exports.push_back(export);
+    }
+
+    /// Take the held exports, reporting those that were dropped.
+    fn release(&mut self) -> impl Iterator<Item = T> + '_ {
+        if self.dropped > 0 {
+            warn!(
+                "{} exports to {} held during quiet hours were dropped as the maximum of {} held exports was reached",
+                self.dropped, self.target, self.max_held_exports
+            );
+            self.dropped = 0;
+        }
+        self.exports.drain(..)
+    }
+}
+
+/// A metrics exporter that holds exports during the quiet hours of its target.
+pub(crate) struct ScheduledMetricsExporter<E> {
+    inner: E,
+    quiet_windows: QuietWindows,
+    held: Mutex<Held<ResourceMetrics>>,
+}
+
+impl<E> ScheduledMetricsExporter<E> {
+    pub(crate) fn new(inner: E, target: &str, schedule: &ExportSchedule) -> Self {
+        ScheduledMetricsExporter {
+            inner,
+            quiet_windows: QuietWindows::new(target, schedule),
+            held: Mutex::new(Held::new(target.to_owned(), schedule.max_held_exports)),
+        }
+    }
+}
+
+impl<E: TemporalitySelector> TemporalitySelector for ScheduledMetricsExporter<E> {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.inner.temporality(kind)
+    }
+}
+
+impl<E: AggregationSelector> AggregationSelector for ScheduledMetricsExporter<E> {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.inner.aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl<E: PushMetricsExporter> PushMetricsExporter for ScheduledMetricsExporter<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        // Exports of other resources, i.e. those of resource scopes, are sent apart rather than merged.
+        let mut others = Vec::new();
+        {
+            let mut held = self.held.lock().unwrap_or_else(PoisonError::into_inner);
+            if self.quiet_windows.quiet() {
+                held.hold(ResourceMetrics {
+                    resource: metrics.resource.clone(),
+                    scope_metrics: std::mem::take(&mut metrics.scope_metrics),
+                });
+                return Ok(());
+            }
+            let mut scope_metrics: Vec<ScopeMetrics> = Vec::new();
+            for export in held.release() {
+                if export.resource == metrics.resource {
+                    scope_metrics.extend(export.scope_metrics);
+                } else {
+                    others.push(export);
+                }
+            }
+            if !scope_metrics.is_empty() {
+                scope_metrics.append(&mut metrics.scope_metrics);
+                metrics.scope_metrics = scope_metrics;
+            }
+        }
+        let mut result = self.inner.export(metrics).await;
+        for mut export in others {
+            let other_result = self.inner.export(&mut export).await;
+            if result.is_ok() {
+                result = other_result;
+            }
+        }
+        result
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        self.inner.force_flush().await
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// A log exporter that holds exports during the quiet hours of its target.
+#[derive(Debug)]
+pub(crate) struct ScheduledLogExporter<E> {
+    inner: E,
+    quiet_windows: QuietWindows,
+    held: Held<Vec<LogData>>,
+}
+
+impl<E> ScheduledLogExporter<E> {
+    pub(crate) fn new(inner: E, target: &str, schedule: &ExportSchedule) -> Self {
+        ScheduledLogExporter {
+            inner,
+            quiet_windows: QuietWindows::new(target, schedule),
+            held: Held::new(target.to_owned(), schedule.max_held_exports),
+        }
+    }
+}
+
+#[async_trait]
+impl<E: LogExporter> LogExporter for ScheduledLogExporter<E> {
+    async fn export(&mut self, mut batch: Vec<LogData>) -> LogResult<()> {
+        if self.quiet_windows.quiet() {
+            self.held.hold(batch);
+            return Ok(());
+        }
+        let mut records: Vec<LogData> = self.held.release().flatten().collect();
+        if !records.is_empty() {
+            records.append(&mut batch);
+            batch = records;
+        }
+        self.inner.export(batch).await
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use opentelemetry::{InstrumentationLibrary, KeyValue};
+    use opentelemetry_sdk::{
+        metrics::reader::{DefaultAggregationSelector, DefaultTemporalitySelector},
+        Resource,
+    };
+
+    use super::*;
+
+    /// An exporter that records the resource and the scope names of each export.
+    #[derive(Default)]
+    struct RecordingExporter {
+        exported: Arc<Mutex<Vec<(Resource, Vec<String>)>>>,
+    }
+
+    impl TemporalitySelector for RecordingExporter {
+        fn temporality(&self, kind: InstrumentKind) -> Temporality {
+            DefaultTemporalitySelector::new().temporality(kind)
+        }
+    }
+
+    impl AggregationSelector for RecordingExporter {
+        fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+            DefaultAggregationSelector::new().aggregation(kind)
+        }
+    }
+
+    #[async_trait]
+    impl PushMetricsExporter for RecordingExporter {
+        async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+            let scopes = metrics
+                .scope_metrics
+                .iter()
+                .map(|scope_metrics| scope_metrics.scope.name.to_string())
+                .collect();
+            self.exported
+                .lock()
+                .unwrap()
+                .push((metrics.resource.clone(), scopes));
+            Ok(())
+        }
+
+        async fn force_flush(&self) -> MetricsResult<()> {
+            Ok(())
+        }
+
+        fn shutdown(&self) -> MetricsResult<()> {
+            Ok(())
+        }
+    }
+
+    fn resource(service_name: &'static str) -> Resource {
+        Resource::new([KeyValue::new("service.name", service_name)])
+    }
+
+    fn export(resource: &Resource, scope: &'static str) -> ResourceMetrics {
+        ResourceMetrics {
+            resource: resource.clone(),
+            scope_metrics: vec![ScopeMetrics {
+                scope: InstrumentationLibrary::new(scope, None::<&str>, None::<&str>, None),
+                metrics: Vec::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn exports_of_other_resources_held_during_quiet_hours_are_sent_apart() {
+        let inner = RecordingExporter::default();
+        let exported = inner.exported.clone();
+        let mut exporter =
+            ScheduledMetricsExporter::new(inner, "target", &ExportSchedule::default());
+        let (component, scope) = (resource("component"), resource("scope"));
+
+        exporter.quiet_windows.windows = vec![(0, MINUTES_PER_DAY)];
+        futures_executor::block_on(exporter.export(&mut export(&component, "held"))).unwrap();
+        futures_executor::block_on(exporter.export(&mut export(&scope, "scope held"))).unwrap();
+        assert!(exported.lock().unwrap().is_empty());
+
+        exporter.quiet_windows.windows.clear();
+        futures_executor::block_on(exporter.export(&mut export(&component, "sent"))).unwrap();
+        assert_eq!(
+            *exported.lock().unwrap(),
+            [
+                (component, vec!["held".to_owned(), "sent".to_owned()]),
+                (scope, vec!["scope held".to_owned()]),
+            ]
+        );
+    }
+}
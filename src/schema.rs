@@ -0,0 +1,73 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A JSON Schema of the configuration, for deployment tooling to validate configs before they are shipped to
+//! devices. Available with the `schema` feature.
+//!
+//! The schema describes [`Config`] as [`Config::from_file`] reads it: fields by their names, all of them optional except where
+//! the type has no default, and enums with data as objects keyed by the variant. The types of other crates are
+//! described by the string forms of the OpenTelemetry specification.
+
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+
+use crate::config::Config;
+
+/// The JSON Schema of [`Config`].
+pub fn config_schema() -> RootSchema {
+    schema_for!(Config)
+}
+
+/// The JSON Schema of [`Config`], as pretty printed JSON.
+pub fn config_schema_json() -> String {
+    serde_json::to_string_pretty(&config_schema()).unwrap_or_default()
+}
+
+/// The severity of a log record, `opentelemetry::logs::Severity`.
+#[derive(JsonSchema)]
+#[serde(rename_all = "lowercase")]
+#[allow(dead_code)]
+pub(crate) enum Severity {
+    Trace,
+    Trace2,
+    Trace3,
+    Trace4,
+    Debug,
+    Debug2,
+    Debug3,
+    Debug4,
+    Info,
+    Info2,
+    Info3,
+    Info4,
+    Warn,
+    Warn2,
+    Warn3,
+    Warn4,
+    Error,
+    Error2,
+    Error3,
+    Error4,
+    Fatal,
+    Fatal2,
+    Fatal3,
+    Fatal4,
+}
+
+/// The OTLP transport of an export target, `opentelemetry_otlp::Protocol`.
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+pub(crate) enum Protocol {
+    #[serde(rename = "grpc")]
+    Grpc,
+    #[serde(rename = "http/protobuf")]
+    HttpBinary,
+}
+
+/// The temporality of exported metrics, `opentelemetry_sdk::metrics::data::Temporality`.
+#[derive(JsonSchema)]
+#[serde(rename_all = "lowercase")]
+#[allow(dead_code)]
+pub(crate) enum Temporality {
+    Cumulative,
+    Delta,
+}
@@ -0,0 +1,97 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::{net::SocketAddr, time::Instant};
+
+use axum::{
+    body::HttpBody,
+    extract::{ConnectInfo, Request},
+    http::header::USER_AGENT,
+    middleware::Next,
+    response::Response,
+};
+use log::debug;
+use opentelemetry::{
+    global,
+    metrics::{Counter, Histogram, Unit},
+    KeyValue,
+};
+
+const STATUS_CODE_KEY: &str = "http.response.status_code";
+const SERVER_PORT_KEY: &str = "server.port";
+
+/// Self-telemetry of the prometheus end point, to detect broken or overly aggressive scrapers from the backend.
+pub(crate) struct ScrapeMetrics {
+    scrapes: Counter<u64>,
+    duration: Histogram<f64>,
+    response_size: Histogram<u64>,
+    port: u16,
+    log_scrapes: bool,
+}
+
+impl ScrapeMetrics {
+    /// Create the instruments of the end point on `port`. Each scrape is also logged at debug level if
+    /// `log_scrapes` is true.
+    pub(crate) fn new(port: u16, log_scrapes: bool) -> Self {
+        let meter = global::meter("otel-lib");
+        ScrapeMetrics {
+            scrapes: meter
+                .u64_counter("otel_lib.prometheus.scrapes")
+                .with_description("Number of scrapes of the prometheus end point")
+                .with_unit(Unit::new("{scrape}"))
+                .init(),
+            duration: meter
+                .f64_histogram("otel_lib.prometheus.scrape.duration")
+                .with_description("Time taken to serve a scrape of the prometheus end point")
+                .with_unit(Unit::new("s"))
+                .init(),
+            response_size: meter
+                .u64_histogram("otel_lib.prometheus.scrape.response_size")
+                .with_description("Size of the responses of the prometheus end point")
+                .with_unit(Unit::new("By"))
+                .init(),
+            port,
+            log_scrapes,
+        }
+    }
+
+    /// Serve `request` and record the scrape.
+    pub(crate) async fn observe(&self, request: Request, next: Next) -> Response {
+        let start = Instant::now();
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(peer)| *peer);
+        let user_agent = request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|user_agent| user_agent.to_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+
+        let response = next.run(request).await;
+
+        let duration = start.elapsed();
+        let size = response.body().size_hint().exact();
+        let attributes = [
+            KeyValue::new(STATUS_CODE_KEY, i64::from(response.status().as_u16())),
+            KeyValue::new(SERVER_PORT_KEY, i64::from(self.port)),
+        ];
+        self.scrapes.add(1, &attributes);
+        self.duration.record(duration.as_secs_f64(), &attributes);
+        if let Some(size) = size {
+            self.response_size.record(size, &attributes);
+        }
+        if self.log_scrapes {
+            debug!(
+                "served scrape from {} ({}) with status {} and {} bytes in {:?}",
+                peer.map_or_else(|| "unknown peer".to_owned(), |peer| peer.to_string()),
+                user_agent,
+                response.status(),
+                size.map_or_else(|| "unknown".to_owned(), |size| size.to_string()),
+                duration
+            );
+        }
+        response
+    }
+}
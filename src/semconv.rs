@@ -0,0 +1,202 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Validation against the naming rules of the OpenTelemetry semantic conventions.
+//
+// Names and attribute keys are lowercase, dot separated namespaces of `[a-z][a-z0-9_]*` components, and units are
+// UCUM case sensitive codes such as `s`, `ms`, `By` and `1`, rather than words.
+
+use std::{
+    any::Any,
+    collections::HashSet,
+    sync::{Mutex, PoisonError},
+};
+
+use async_trait::async_trait;
+use log::error;
+use opentelemetry::metrics::Result as MetricsResult;
+use opentelemetry_sdk::{
+    metrics::{
+        data::{ExponentialHistogram, Gauge, Histogram, ResourceMetrics, Sum, Temporality},
+        exporter::PushMetricsExporter,
+        reader::{AggregationSelector, DefaultAggregationSelector, TemporalitySelector},
+        Aggregation, InstrumentKind,
+    },
+    AttributeSet, Resource,
+};
+
+/// The maximum length of a name or attribute key.
+const MAX_NAME_LENGTH: usize = 255;
+
+/// Units commonly written as words, with the UCUM code the semantic conventions use instead.
+const WORD_UNITS: [(&str, &str); 12] = [
+    ("seconds", "s"),
+    ("second", "s"),
+    ("sec", "s"),
+    ("milliseconds", "ms"),
+    ("millisecond", "ms"),
+    ("microseconds", "us"),
+    ("nanoseconds", "ns"),
+    ("bytes", "By"),
+    ("byte", "By"),
+    ("percent", "1"),
+    ("count", "{count}"),
+    ("requests", "{request}"),
+];
+
+/// Returns true if `name` is a lowercase, dot separated namespace.
+fn is_valid_name(name: &str) -> bool {
+    name.len() <= MAX_NAME_LENGTH
+        && name.split('.').all(|component| {
+            let mut chars = component.chars();
+            chars.next().is_some_and(|c| c.is_ascii_lowercase())
+                && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        })
+}
+
+/// Returns the reason `unit` violates the conventions, if it does.
+fn unit_violation(unit: &str) -> Option<String> {
+    if let Some((_, code)) = WORD_UNITS
+        .iter()
+        .find(|(word, _)| word.eq_ignore_ascii_case(unit))
+    {
+        return Some(format!("should be written as {code}"));
+    }
+    if !unit.is_ascii() || unit.chars().any(|c| c.is_ascii_whitespace()) {
+        return Some("is not a UCUM code".to_owned());
+    }
+    None
+}
+
+/// Report resource attributes that violate the conventions.
+pub(crate) fn check_resource(resource: &Resource) {
+    for (key, value) in resource {
+        if !is_valid_name(key.as_str()) {
+            error!(
+                "resource attribute key {} violates the semantic conventions",
+                key.as_str()
+            );
+        }
+        if value.as_str().is_empty() {
+            error!(
+                "resource attribute {} violates the semantic conventions as it is empty",
+                key.as_str()
+            );
+        }
+    }
+}
+
+/// A metrics "exporter" that exports nothing, but reports instrument names, units and attribute keys that violate
+/// the conventions. Each violation is reported once.
+pub(crate) struct SemconvChecker {
+    reported: Mutex<HashSet<String>>,
+}
+
+impl SemconvChecker {
+    pub(crate) fn new() -> Self {
+        SemconvChecker {
+            reported: Mutex::default(),
+        }
+    }
+
+    fn report(reported: &mut HashSet<String>, violation: String) {
+        if !reported.contains(&violation) {
+            error!("{violation}");
+            reported.insert(violation);
+        }
+    }
+}
+
+/// The attribute sets of all data points of `data`, if it holds values of type `T`.
+fn attribute_sets<T: 'static>(data: &dyn Any) -> Vec<&AttributeSet> {
+    if let Some(sum) = data.downcast_ref::<Sum<T>>() {
+        sum.data_points.iter().map(|dp| &dp.attributes).collect()
+    } else if let Some(gauge) = data.downcast_ref::<Gauge<T>>() {
+        gauge.data_points.iter().map(|dp| &dp.attributes).collect()
+    } else if let Some(histogram) = data.downcast_ref::<Histogram<T>>() {
+        histogram
+            .data_points
+            .iter()
+            .map(|dp| &dp.attributes)
+            .collect()
+    } else if let Some(histogram) = data.downcast_ref::<ExponentialHistogram<T>>() {
+        histogram
+            .data_points
+            .iter()
+            .map(|dp| &dp.attributes)
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+impl TemporalitySelector for SemconvChecker {
+    fn temporality(&self, _kind: InstrumentKind) -> Temporality {
+        Temporality::Cumulative
+    }
+}
+
+impl AggregationSelector for SemconvChecker {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        DefaultAggregationSelector::new().aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl PushMetricsExporter for SemconvChecker {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        let mut reported = self.reported.lock().unwrap_or_else(PoisonError::into_inner);
+        for scope_metrics in &metrics.scope_metrics {
+            for metric in &scope_metrics.metrics {
+                if !is_valid_name(&metric.name) {
+                    Self::report(
+                        &mut reported,
+                        format!(
+                            "instrument name {} violates the semantic conventions",
+                            metric.name
+                        ),
+                    );
+                }
+                if let Some(reason) = unit_violation(metric.unit.as_str()) {
+                    Self::report(
+                        &mut reported,
+                        format!(
+                            "unit {} of instrument {} violates the semantic conventions as it {}",
+                            metric.unit.as_str(),
+                            metric.name,
+                            reason
+                        ),
+                    );
+                }
+
+                let data = metric.data.as_any();
+                let attribute_sets = [
+                    attribute_sets::<u64>(data),
+                    attribute_sets::<i64>(data),
+                    attribute_sets::<f64>(data),
+                ];
+                for (key, _) in attribute_sets.iter().flatten().flat_map(|set| set.iter()) {
+                    if !is_valid_name(key.as_str()) {
+                        Self::report(
+                            &mut reported,
+                            format!(
+                                "attribute key {} of instrument {} violates the semantic conventions",
+                                key.as_str(),
+                                metric.name
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        Ok(())
+    }
+}
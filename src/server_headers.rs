@@ -0,0 +1,104 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use axum::{
+    extract::Request,
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// How long browsers may cache the result of a CORS preflight request, in seconds.
+const PREFLIGHT_MAX_AGE_SECS: &str = "600";
+
+/// Security headers added to every response, if enabled.
+const SECURITY_HEADERS: [(header::HeaderName, &str); 5] = [
+    (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+    (header::X_FRAME_OPTIONS, "DENY"),
+    (header::CONTENT_SECURITY_POLICY, "default-src 'none'"),
+    (header::REFERRER_POLICY, "no-referrer"),
+    (header::CACHE_CONTROL, "no-store"),
+];
+
+/// The CORS and security headers of the responses of the http server.
+pub(crate) struct HeaderPolicy {
+    allowed_origins: Vec<String>,
+    security_headers: bool,
+}
+
+impl HeaderPolicy {
+    /// Create a policy that allows cross-origin requests from `allowed_origins`, or from any origin if they contain
+    /// `*`, and adds the standard security headers if `security_headers` is true.
+    pub(crate) fn new(allowed_origins: Vec<String>, security_headers: bool) -> Self {
+        HeaderPolicy {
+            allowed_origins,
+            security_headers,
+        }
+    }
+
+    /// The value of the `Access-Control-Allow-Origin` header for a request from `origin`, if it's allowed.
+    fn allow_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        if self.allowed_origins.iter().any(|allowed| allowed == "*") {
+            return Some(HeaderValue::from_static("*"));
+        }
+        let origin_text = origin.to_str().ok()?;
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(origin_text))
+            .then(|| origin.clone())
+    }
+
+    /// Answer CORS preflight requests, and add the headers of the policy to the responses of other requests.
+    pub(crate) async fn apply(&self, request: Request, next: Next) -> Response {
+        let allow_origin = request
+            .headers()
+            .get(header::ORIGIN)
+            .and_then(|origin| self.allow_origin(origin));
+        let preflight = request.method() == Method::OPTIONS
+            && request
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+
+        let mut response = if preflight {
+            let mut response = StatusCode::NO_CONTENT.into_response();
+            if allow_origin.is_some() {
+                let headers = response.headers_mut();
+                headers.insert(
+                    header::ACCESS_CONTROL_ALLOW_METHODS,
+                    HeaderValue::from_static("GET"),
+                );
+                if let Some(request_headers) = request
+                    .headers()
+                    .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+                {
+                    headers.insert(
+                        header::ACCESS_CONTROL_ALLOW_HEADERS,
+                        request_headers.clone(),
+                    );
+                }
+                headers.insert(
+                    header::ACCESS_CONTROL_MAX_AGE,
+                    HeaderValue::from_static(PREFLIGHT_MAX_AGE_SECS),
+                );
+            }
+            response
+        } else {
+            next.run(request).await
+        };
+
+        let headers = response.headers_mut();
+        if !self.allowed_origins.is_empty() {
+            // The CORS headers depend on the origin of the request.
+            headers.append(header::VARY, HeaderValue::from_static("origin"));
+        }
+        if let Some(allow_origin) = allow_origin {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        }
+        if self.security_headers {
+            for (name, value) in SECURITY_HEADERS {
+                headers.insert(name, HeaderValue::from_static(value));
+            }
+        }
+        response
+    }
+}
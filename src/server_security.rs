@@ -0,0 +1,117 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+    sync::Arc,
+};
+
+use axum::{
+    extract::Request,
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+
+use crate::config::{BasicAuth, PrometheusTls};
+
+/// The credentials the scrapers of the http server must present, if any.
+pub(crate) struct ScrapeAuth {
+    basic: Option<String>,
+    bearer: Option<String>,
+}
+
+impl ScrapeAuth {
+    /// Create the checks of `basic_auth` and `bearer_token`, or `None` if neither is set.
+    pub(crate) fn new(basic_auth: Option<&BasicAuth>, bearer_token: Option<&str>) -> Option<Self> {
+        if basic_auth.is_none() && bearer_token.is_none() {
+            return None;
+        }
+        Some(ScrapeAuth {
+            basic: basic_auth.map(|basic_auth| {
+                STANDARD.encode(format!("{}:{}", basic_auth.username, basic_auth.password))
+            }),
+            bearer: bearer_token.map(str::to_owned),
+        })
+    }
+
+    /// Whether the `Authorization` header `authorization` presents the expected credentials.
+    fn authorized(&self, authorization: &HeaderValue) -> bool {
+        let Some((scheme, credentials)) = authorization
+            .to_str()
+            .ok()
+            .and_then(|authorization| authorization.split_once(' '))
+        else {
+            return false;
+        };
+        let expected = if scheme.eq_ignore_ascii_case("basic") {
+            &self.basic
+        } else if scheme.eq_ignore_ascii_case("bearer") {
+            &self.bearer
+        } else {
+            return false;
+        };
+        expected.as_ref().is_some_and(|expected| {
+            constant_time_eq(expected.as_bytes(), credentials.trim().as_bytes())
+        })
+    }
+
+    /// Reject the requests without the expected credentials with `401 Unauthorized`.
+    pub(crate) async fn apply(&self, request: Request, next: Next) -> Response {
+        if request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .is_some_and(|authorization| self.authorized(authorization))
+        {
+            return next.run(request).await;
+        }
+        let mut response = StatusCode::UNAUTHORIZED.into_response();
+        let headers = response.headers_mut();
+        if self.basic.is_some() {
+            headers.append(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Basic realm=\"metrics\""),
+            );
+        }
+        if self.bearer.is_some() {
+            headers.append(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Bearer realm=\"metrics\""),
+            );
+        }
+        response
+    }
+}
+
+/// Compare `a` and `b` in a time that doesn't depend on where they differ, so the credentials can't be guessed byte
+/// by byte from the time taken to reject them.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Load the certificate chain and private key of `tls` into an acceptor of TLS connections.
+pub(crate) fn tls_acceptor(tls: &PrometheusTls) -> Result<TlsAcceptor, String> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| format!("unable to open {}: {e}", path.display()))
+    };
+    let certs = rustls_pemfile::certs(&mut open(&tls.cert_path)?)
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(|e| format!("invalid certificate in {}: {e}", tls.cert_path.display()))?;
+    if certs.is_empty() {
+        return Err(format!("no certificate in {}", tls.cert_path.display()));
+    }
+    let key = rustls_pemfile::private_key(&mut open(&tls.key_path)?)
+        .map_err(|e| format!("invalid private key in {}: {e}", tls.key_path.display()))?
+        .ok_or_else(|| format!("no private key in {}", tls.key_path.display()))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("unable to use the certificate and private key: {e}"))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -0,0 +1,366 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Export of identical metrics to several OTLP/gRPC targets.
+//
+// Targets that export at the same interval with the same temporality receive identical data. Rather than each of
+// them collecting, converting and serializing the metrics on its own, they share a single reader: each collection is
+// converted to an OTLP request and serialized once, and the serialized request is sent to all targets concurrently.
+
+use std::{
+    sync::{Arc, Mutex, PoisonError, RwLock},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use log::warn;
+use opentelemetry::metrics::{MetricsError, Result as MetricsResult};
+use opentelemetry_otlp::{
+    OTEL_EXPORTER_OTLP_COMPRESSION, OTEL_EXPORTER_OTLP_ENDPOINT, OTEL_EXPORTER_OTLP_HEADERS,
+    OTEL_EXPORTER_OTLP_METRICS_COMPRESSION, OTEL_EXPORTER_OTLP_METRICS_ENDPOINT,
+    OTEL_EXPORTER_OTLP_METRICS_HEADERS, OTEL_EXPORTER_OTLP_METRICS_TIMEOUT,
+    OTEL_EXPORTER_OTLP_TIMEOUT,
+};
+use opentelemetry_proto::tonic::collector::metrics::v1::{
+    ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+};
+use opentelemetry_sdk::metrics::{
+    data::{ResourceMetrics, Temporality},
+    exporter::PushMetricsExporter,
+    reader::{AggregationSelector, DefaultAggregationSelector, TemporalitySelector},
+    Aggregation, InstrumentKind,
+};
+use opentelemetry_sdk::Resource;
+use prost::{
+    bytes::{BufMut, Bytes},
+    Message,
+};
+use tonic::{
+    client::Grpc,
+    codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder},
+    codegen::http::uri::PathAndQuery,
+    transport::Channel,
+    Request, Status,
+};
+
+use crate::{
+    debug_exporter::{log_attempt, metrics_batch, CaptureFile, RateLimiter},
+    export_failures::{ExportFailures, SummarizedError},
+    exporter_metrics,
+    headers::ExportHeaders,
+    switch::drain,
+    target_health,
+    transport::{self, CaReloading, ConnectError, Connector, TlsOptions},
+};
+
+const METRICS_EXPORT_PATH: &str = "/opentelemetry.proto.collector.metrics.v1.MetricsService/Export";
+
+/// Environment variables that change how the OTLP exporters send requests. Targets only share a reader if none of
+/// them are set, as the shared export doesn't honor them.
+const EXPORTER_ENV_VARS: [&str; 8] = [
+    OTEL_EXPORTER_OTLP_ENDPOINT,
+    OTEL_EXPORTER_OTLP_METRICS_ENDPOINT,
+    OTEL_EXPORTER_OTLP_TIMEOUT,
+    OTEL_EXPORTER_OTLP_METRICS_TIMEOUT,
+    OTEL_EXPORTER_OTLP_COMPRESSION,
+    OTEL_EXPORTER_OTLP_METRICS_COMPRESSION,
+    OTEL_EXPORTER_OTLP_HEADERS,
+    OTEL_EXPORTER_OTLP_METRICS_HEADERS,
+];
+
+/// Returns true if targets that receive identical data may share a reader.
+pub(crate) fn sharing_supported() -> bool {
+    EXPORTER_ENV_VARS
+        .iter()
+        .all(|name| std::env::var_os(name).is_none())
+}
+
+/// A codec that sends requests that are already serialized, and decodes the responses.
+#[derive(Clone, Copy, Debug, Default)]
+struct SerializedCodec;
+
+impl Codec for SerializedCodec {
+    type Encode = Bytes;
+    type Decode = ExportMetricsServiceResponse;
+    type Encoder = SerializedCodec;
+    type Decoder = SerializedCodec;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        SerializedCodec
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        SerializedCodec
+    }
+}
+
+impl Encoder for SerializedCodec {
+    type Item = Bytes;
+    type Error = Status;
+
+    fn encode(&mut self, item: Bytes, dst: &mut EncodeBuf<'_>) -> Result<(), Status> {
+        dst.put(item);
+        Ok(())
+    }
+}
+
+impl Decoder for SerializedCodec {
+    type Item = ExportMetricsServiceResponse;
+    type Error = Status;
+
+    fn decode(
+        &mut self,
+        src: &mut DecodeBuf<'_>,
+    ) -> Result<Option<ExportMetricsServiceResponse>, Status> {
+        ExportMetricsServiceResponse::decode(src)
+            .map(Some)
+            .map_err(|e| Status::internal(format!("invalid response: {e}")))
+    }
+}
+
+/// A target of a [`SharedMetricsExporter`].
+pub(crate) struct SharedTarget {
+    label: String,
+    url: String,
+    client: CaReloading<Grpc<Channel>>,
+    rate_limiter: Option<RateLimiter>,
+    capture: Option<Mutex<CaptureFile>>,
+    failures: Option<ExportFailures>,
+    headers: Option<Arc<ExportHeaders>>,
+}
+
+impl SharedTarget {
+    /// Create a target that exports to `url`, connecting with `connector` and, for an `https` url, negotiating TLS
+    /// according to `tls`, reconnecting when its certificate authorities change. Export attempts are only logged if
+    /// `debug` is true.
+    pub(crate) fn new(
+        label: String,
+        url: &str,
+        timeout: Duration,
+        user_agent: &str,
+        debug: bool,
+        capture: Option<CaptureFile>,
+        failures: Option<ExportFailures>,
+        headers: Option<Arc<ExportHeaders>>,
+        connector: Connector,
+        tls: &TlsOptions,
+    ) -> Result<Self, ConnectError> {
+        let client = CaReloading::new("metrics", &label, tls, {
+            let url = url.to_owned();
+            let user_agent = user_agent.to_owned();
+            let tls = tls.clone();
+            move || -> Result<_, ConnectError> {
+                let (endpoint, connector) =
+                    transport::grpc_endpoint(&url, connector.clone(), &tls)?;
+                Ok(Grpc::new(transport::connect_lazy(
+                    &endpoint
+                        .user_agent(user_agent.as_str())?
+                        .timeout(timeout)
+                        .connect_timeout(timeout),
+                    connector,
+                )))
+            }
+        })?;
+        target_health::register("metrics", &label);
+        Ok(SharedTarget {
+            label,
+            url: url.to_owned(),
+            client,
+            rate_limiter: debug.then(RateLimiter::new),
+            capture: capture.map(Mutex::new),
+            failures,
+            headers,
+        })
+    }
+
+    async fn send(&self, request: Bytes, batch: &str) -> Result<(), Status> {
+        if let Some(capture) = &self.capture {
+            capture
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .write(&request);
+        }
+
+        let bytes = request.len();
+        let start = Instant::now();
+        // The channel is replaced when the certificate authorities of the target change.
+        let (mut client, _) = self.client.current();
+        let result = async {
+            client
+                .ready()
+                .await
+                .map_err(|e| Status::unavailable(e.to_string()))?;
+            let mut request = Request::new(request);
+            if let Some(headers) = &self.headers {
+                headers
+                    .apply(request.metadata_mut())
+                    .map_err(Status::invalid_argument)?;
+            }
+            client
+                .unary(
+                    request,
+                    PathAndQuery::from_static(METRICS_EXPORT_PATH),
+                    SerializedCodec,
+                )
+                .await
+        }
+        .await
+        .map(|response| {
+            // The target accepted the request, but may have rejected some of its data points.
+            if let Some(partial_success) = response.into_inner().partial_success {
+                if partial_success.rejected_data_points > 0
+                    || !partial_success.error_message.is_empty()
+                {
+                    warn!(
+                        "metrics export to {} rejected {} data points: {}",
+                        self.label,
+                        partial_success.rejected_data_points,
+                        partial_success.error_message
+                    );
+                }
+            }
+        });
+        if let Some(rate_limiter) = &self.rate_limiter {
+            log_attempt(
+                rate_limiter,
+                "metrics",
+                &self.label,
+                batch,
+                bytes,
+                start.elapsed(),
+                &result,
+            );
+        }
+        exporter_metrics::record_export("metrics", &self.label, start, &result);
+        if let Some(failures) = &self.failures {
+            failures.report(&result);
+        }
+        result
+    }
+}
+
+/// The targets of a [`SharedMetricsExporter`], which may be switched while exports are in flight.
+pub(crate) struct SharedTargets {
+    targets: RwLock<Vec<Arc<SharedTarget>>>,
+}
+
+impl SharedTargets {
+    pub(crate) fn new(targets: Vec<SharedTarget>) -> Arc<Self> {
+        Arc::new(SharedTargets {
+            targets: RwLock::new(targets.into_iter().map(Arc::new).collect()),
+        })
+    }
+
+    fn current(&self) -> Vec<Arc<SharedTarget>> {
+        self.targets
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub(crate) fn exports_to(&self, url: &str) -> bool {
+        self.current().iter().any(|target| target.url == url)
+    }
+
+    /// Replace the target that exports to `old_url` with `target`, once `target` accepted an empty export. Returns
+    /// after the exports in flight to the old target have completed.
+    pub(crate) async fn switch(
+        &self,
+        old_url: &str,
+        target: SharedTarget,
+        resource: &Resource,
+    ) -> Result<(), Status> {
+        let metrics = ResourceMetrics {
+            resource: resource.clone(),
+            scope_metrics: Vec::new(),
+        };
+        let request = ExportMetricsServiceRequest::from(&metrics).encode_to_vec();
+        target
+            .send(Bytes::from(request), &metrics_batch(&metrics))
+            .await?;
+
+        let old = {
+            let mut targets = self.targets.write().unwrap_or_else(PoisonError::into_inner);
+            let Some(index) = targets.iter().position(|target| target.url == old_url) else {
+                return Err(Status::not_found(format!(
+                    "no shared target exports to {old_url}"
+                )));
+            };
+            std::mem::replace(&mut targets[index], Arc::new(target))
+        };
+        drain(&old).await;
+        Ok(())
+    }
+}
+
+/// A metrics exporter that serializes each export once and sends it to several OTLP/gRPC targets.
+pub(crate) struct SharedMetricsExporter {
+    targets: Arc<SharedTargets>,
+    temporality_selector: Box<dyn TemporalitySelector>,
+}
+
+impl SharedMetricsExporter {
+    pub(crate) fn new(
+        targets: Arc<SharedTargets>,
+        temporality_selector: Box<dyn TemporalitySelector>,
+    ) -> Self {
+        SharedMetricsExporter {
+            targets,
+            temporality_selector,
+        }
+    }
+}
+
+impl TemporalitySelector for SharedMetricsExporter {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.temporality_selector.temporality(kind)
+    }
+}
+
+impl AggregationSelector for SharedMetricsExporter {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        DefaultAggregationSelector::new().aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl PushMetricsExporter for SharedMetricsExporter {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        let request = Bytes::from(ExportMetricsServiceRequest::from(&*metrics).encode_to_vec());
+        let batch = metrics_batch(metrics);
+        let targets = self.targets.current();
+        let results = join_all(
+            targets
+                .iter()
+                .map(|target| target.send(request.clone(), &batch)),
+        )
+        .await;
+
+        let mut summarized = true;
+        let failures: Vec<String> = targets
+            .iter()
+            .zip(results)
+            .filter_map(|(target, result)| {
+                let e = result.err()?;
+                summarized &= target.failures.is_some();
+                Some(format!("export to {} failed due to {:?}", target.label, e))
+            })
+            .collect();
+        if failures.is_empty() {
+            Ok(())
+        } else if summarized {
+            Err(SummarizedError::new(&failures.join(", ")).into())
+        } else {
+            Err(MetricsError::Other(failures.join(", ")))
+        }
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Signing of OTLP/HTTP requests, for managed endpoints that require signed requests rather than static headers.
+//!
+//! Set the `signer` of a metrics or log export target to a [`RequestSigner`]. It is called for each request to the
+//! target, after the request is complete, and returns the headers to add to it. With the `sigv4` feature,
+//! [`SigV4Signer`] signs requests with AWS Signature Version 4. OTLP/gRPC requests are not signed.
+
+use std::{fmt, sync::Arc};
+
+use async_trait::async_trait;
+use hyper_0_14::header::{HeaderName, HeaderValue};
+use opentelemetry_http::{Bytes, HttpClient, HttpError, Request, Response};
+
+/// The error of a [`RequestSigner`]. The request is not sent.
+pub type SigningError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A request about to be sent to an export target.
+#[derive(Debug)]
+pub struct SignableRequest<'a> {
+    /// the method, e.g. `POST`.
+    pub method: &'a str,
+    /// the full url of the request.
+    pub url: &'a str,
+    /// the headers of the request, with lowercase names.
+    pub headers: Vec<(&'a str, &'a str)>,
+    /// the serialized OTLP payload.
+    pub body: &'a [u8],
+}
+
+/// Signs the OTLP/HTTP requests to an export target.
+pub trait RequestSigner: fmt::Debug + Send + Sync {
+    /// The headers to add to `request` to sign it, e.g. `authorization`.
+    ///
+    /// # Errors
+    /// * `SigningError` - If the request can't be signed, for example as the credentials are unavailable.
+    fn sign(&self, request: &SignableRequest<'_>) -> Result<Vec<(String, String)>, SigningError>;
+}
+
+/// An HTTP client that signs the requests of an exporter before sending them, if it has a signer.
+#[derive(Debug)]
+pub(crate) struct SigningClient<C> {
+    inner: C,
+    signer: Option<Arc<dyn RequestSigner>>,
+}
+
+impl<C> SigningClient<C> {
+    pub(crate) fn new(inner: C, signer: Option<Arc<dyn RequestSigner>>) -> Self {
+        SigningClient { inner, signer }
+    }
+}
+
+#[async_trait]
+impl<C: HttpClient> HttpClient for SigningClient<C> {
+    async fn send(&self, mut request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+        if let Some(signer) = &self.signer {
+            let url = request.uri().to_string();
+            let headers = signer.sign(&SignableRequest {
+                method: request.method().as_str(),
+                url: &url,
+                headers: request
+                    .headers()
+                    .iter()
+                    .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+                    .collect(),
+                body: request.body(),
+            })?;
+            for (name, value) in headers {
+                request.headers_mut().insert(
+                    HeaderName::from_bytes(name.as_bytes())?,
+                    HeaderValue::from_str(&value)?,
+                );
+            }
+        }
+        self.inner.send(request).await
+    }
+}
+
+#[cfg(feature = "sigv4")]
+pub use self::sigv4::SigV4Signer;
+
+#[cfg(feature = "sigv4")]
+mod sigv4 {
+    use std::{fmt::Write as _, time::SystemTime};
+
+    use hmac::{Hmac, Mac};
+    use hyper_0_14::Uri;
+    use sha2::{Digest, Sha256};
+
+    use super::{RequestSigner, SignableRequest, SigningError};
+
+    const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+    /// Signs requests with AWS Signature Version 4, as required by the OTLP endpoints of AWS services.
+    #[derive(Clone)]
+    pub struct SigV4Signer {
+        /// the access key id of the credentials.
+        pub access_key_id: String,
+        /// the secret access key of the credentials.
+        pub secret_access_key: String,
+        /// the session token of temporary credentials, if any.
+        pub session_token: Option<String>,
+        /// the region of the endpoint, e.g. `us-east-1`.
+        pub region: String,
+        /// the signing name of the service, e.g. `xray` or `logs`.
+        pub service: String,
+    }
+
+    impl std::fmt::Debug for SigV4Signer {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("SigV4Signer")
+                .field("access_key_id", &self.access_key_id)
+                .field("region", &self.region)
+                .field("service", &self.service)
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl RequestSigner for SigV4Signer {
+        fn sign(
+            &self,
+            request: &SignableRequest<'_>,
+        ) -> Result<Vec<(String, String)>, SigningError> {
+            let uri: Uri = request.url.parse()?;
+            let host = uri.authority().ok_or("url has no host")?.as_str();
+            // e.g. 20240131T235959Z, from 2024-01-31T23:59:59Z.
+            let amz_date: String = humantime::format_rfc3339_seconds(SystemTime::now())
+                .to_string()
+                .chars()
+                .filter(|c| *c != '-' && *c != ':')
+                .collect();
+            let date = &amz_date[..8];
+            let payload_hash = hex(&Sha256::digest(request.body));
+
+            let mut headers: Vec<(String, String)> = vec![
+                ("host".to_owned(), host.to_owned()),
+                ("x-amz-content-sha256".to_owned(), payload_hash.clone()),
+                ("x-amz-date".to_owned(), amz_date.clone()),
+            ];
+            if let Some(session_token) = &self.session_token {
+                headers.push(("x-amz-security-token".to_owned(), session_token.clone()));
+            }
+            if let Some((_, content_type)) = request
+                .headers
+                .iter()
+                .find(|(name, _)| *name == "content-type")
+            {
+                headers.push(("content-type".to_owned(), (*content_type).to_owned()));
+            }
+            headers.sort();
+
+            let signed_headers = headers
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+                .join(";");
+            let mut canonical_headers = String::new();
+            for (name, value) in &headers {
+                let _ = writeln!(canonical_headers, "{name}:{}", value.trim());
+            }
+            let canonical_request = format!(
+                "{}\n{}\n{}\n{canonical_headers}\n{signed_headers}\n{payload_hash}",
+                request.method,
+                uri.path(),
+                canonical_query(uri.query().unwrap_or_default()),
+            );
+            let scope = format!("{date}/{}/{}/aws4_request", self.region, self.service);
+            let string_to_sign = format!(
+                "{ALGORITHM}\n{amz_date}\n{scope}\n{}",
+                hex(&Sha256::digest(canonical_request.as_bytes()))
+            );
+
+            let mut key = hmac(
+                format!("AWS4{}", self.secret_access_key).as_bytes(),
+                date.as_bytes(),
+            );
+            for part in [&self.region, &self.service, "aws4_request"] {
+                key = hmac(&key, part.as_bytes());
+            }
+            let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+
+            // The host header is set by the HTTP client.
+            headers.retain(|(name, _)| name != "host" && name != "content-type");
+            headers.push((
+                "authorization".to_owned(),
+                format!(
+                    "{ALGORITHM} Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+                    self.access_key_id
+                ),
+            ));
+            Ok(headers)
+        }
+    }
+
+    /// The query parameters of `query` sorted by name, as already percent-encoded in the url.
+    fn canonical_query(query: &str) -> String {
+        let mut parameters: Vec<(&str, &str)> = query
+            .split('&')
+            .filter(|parameter| !parameter.is_empty())
+            .map(|parameter| parameter.split_once('=').unwrap_or((parameter, "")))
+            .collect();
+        parameters.sort_unstable();
+        parameters
+            .iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+    }
+}
@@ -0,0 +1,56 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::sync::{Mutex, PoisonError};
+
+use opentelemetry::{
+    global,
+    logs::{LogResult, Severity},
+};
+use opentelemetry_sdk::{
+    export::logs::{LogData, LogExporter},
+    logs::LogProcessor,
+};
+
+/// A [`LogProcessor`] that exports each record synchronously as it is emitted. Unlike the batch processors there is no background worker, so nothing is left to flush when the process
+/// exits.
+///
+/// The export blocks the emitting thread. When the exporter relies on the tokio runtime, as the OTLP exporters do,
+/// records must not be emitted from a single threaded runtime.
+#[derive(Debug)]
+pub(crate) struct SimpleLogProcessor {
+    exporter: Mutex<Box<dyn LogExporter>>,
+}
+
+impl SimpleLogProcessor {
+    pub(crate) fn new(exporter: Box<dyn LogExporter>) -> Self {
+        SimpleLogProcessor {
+            exporter: Mutex::new(exporter),
+        }
+    }
+}
+
+impl LogProcessor for SimpleLogProcessor {
+    fn emit(&self, data: LogData) {
+        let mut exporter = self.exporter.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Err(err) = futures_executor::block_on(exporter.export(vec![data])) {
+            global::handle_error(err);
+        }
+    }
+
+    fn force_flush(&self) -> LogResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> LogResult<()> {
+        self.exporter
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .shutdown();
+        Ok(())
+    }
+
+    fn event_enabled(&self, _level: Severity, _target: &str, _name: &str) -> bool {
+        true
+    }
+}
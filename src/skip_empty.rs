@@ -0,0 +1,85 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::any::Any;
+
+use async_trait::async_trait;
+use opentelemetry::metrics::Result as MetricsResult;
+use opentelemetry_sdk::metrics::{
+    data::{ExponentialHistogram, Gauge, Histogram, ResourceMetrics, Sum, Temporality},
+    exporter::PushMetricsExporter,
+    reader::{AggregationSelector, TemporalitySelector},
+    Aggregation, InstrumentKind,
+};
+
+/// A metrics exporter that skips exports without any data points, so an idle component with delta temporality
+/// doesn't send empty requests, waking the network, on every interval.
+pub(crate) struct SkipEmptyMetricsExporter<E> {
+    inner: E,
+    enabled: bool,
+}
+
+impl<E> SkipEmptyMetricsExporter<E> {
+    /// Create a new exporter. All exports are passed on if `enabled` is false.
+    pub(crate) fn new(inner: E, enabled: bool) -> Self {
+        SkipEmptyMetricsExporter { inner, enabled }
+    }
+}
+
+/// Returns true if `data` holds values of type `T` and has data points.
+fn has_data_points<T: 'static>(data: &dyn Any) -> bool {
+    if let Some(sum) = data.downcast_ref::<Sum<T>>() {
+        !sum.data_points.is_empty()
+    } else if let Some(gauge) = data.downcast_ref::<Gauge<T>>() {
+        !gauge.data_points.is_empty()
+    } else if let Some(histogram) = data.downcast_ref::<Histogram<T>>() {
+        !histogram.data_points.is_empty()
+    } else if let Some(histogram) = data.downcast_ref::<ExponentialHistogram<T>>() {
+        !histogram.data_points.is_empty()
+    } else {
+        false
+    }
+}
+
+fn is_empty(metrics: &ResourceMetrics) -> bool {
+    !metrics
+        .scope_metrics
+        .iter()
+        .flat_map(|scope_metrics| &scope_metrics.metrics)
+        .any(|metric| {
+            let data = metric.data.as_any();
+            has_data_points::<u64>(data)
+                || has_data_points::<i64>(data)
+                || has_data_points::<f64>(data)
+        })
+}
+
+impl<E: TemporalitySelector> TemporalitySelector for SkipEmptyMetricsExporter<E> {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.inner.temporality(kind)
+    }
+}
+
+impl<E: AggregationSelector> AggregationSelector for SkipEmptyMetricsExporter<E> {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.inner.aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl<E: PushMetricsExporter> PushMetricsExporter for SkipEmptyMetricsExporter<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        if self.enabled && is_empty(metrics) {
+            return Ok(());
+        }
+        self.inner.export(metrics).await
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        self.inner.force_flush().await
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.inner.shutdown()
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Good and bad event counters of latency SLOs, derived from the histograms of the SLOs as values are recorded, so all
+// services using this crate feed burn-rate alerts with the same metrics. Every value recorded into the histogram of an
+// SLO counts one event of `slo.events`, good if it is at most the target latency, with the attributes of the value
+// and those of the SLO. The objectives are exported as `slo.objective` gauges, so alerts don't need to hardcode them.
+
+use std::sync::Arc;
+
+use log::error;
+use opentelemetry::{
+    global,
+    metrics::{Counter, Histogram, SyncHistogram, Unit},
+    KeyValue,
+};
+
+use crate::config::LatencySlo;
+
+const SLO_NAME_KEY: &str = "slo.name";
+const SLO_OUTCOME_KEY: &str = "slo.outcome";
+
+/// The latency SLOs of the configuration.
+#[derive(Default)]
+pub(crate) struct LatencySlos {
+    slos: Vec<LatencySlo>,
+    events: Option<Counter<u64>>,
+}
+
+impl LatencySlos {
+    /// The valid SLOs of `slos`, counting their events with a counter of the global meter provider. Invalid SLOs are
+    /// reported and ignored.
+    pub(crate) fn new(slos: &[LatencySlo]) -> Self {
+        let slos: Vec<LatencySlo> = slos
+            .iter()
+            .filter(|slo| {
+                let valid = slo.objective > 0.0 && slo.objective < 1.0 && slo.target_latency > 0.0;
+                if !valid {
+                    error!(
+                        "ignoring SLO {} as its objective is not between 0 and 1 or its target latency is not positive",
+                        slo.name
+                    );
+                }
+                valid
+            })
+            .cloned()
+            .collect();
+        if slos.is_empty() {
+            return LatencySlos::default();
+        }
+        let meter = global::meter("otel-lib");
+        let events = meter
+            .u64_counter("slo.events")
+            .with_description("Number of events of latency SLOs, by outcome")
+            .with_unit(Unit::new("{event}"))
+            .init();
+        let objectives: Vec<(f64, KeyValue)> = slos
+            .iter()
+            .map(|slo| (slo.objective, KeyValue::new(SLO_NAME_KEY, slo.name.clone())))
+            .collect();
+        meter
+            .f64_observable_gauge("slo.objective")
+            .with_description("The objective of latency SLOs, as the target ratio of good events")
+            .with_unit(Unit::new("1"))
+            .with_callback(move |observer| {
+                for (objective, name) in &objectives {
+                    observer.observe(*objective, std::slice::from_ref(name));
+                }
+            })
+            .init();
+        LatencySlos {
+            slos,
+            events: Some(events),
+        }
+    }
+
+    /// `histogram` named `name`, also counting the events of the SLOs of that histogram if there are any.
+    pub(crate) fn wrap<T>(&self, name: &str, histogram: Histogram<T>) -> Histogram<T>
+    where
+        T: Latency + Send + Sync + 'static,
+    {
+        let Some(events) = &self.events else {
+            return histogram;
+        };
+        let slos: Vec<(f64, KeyValue)> = self
+            .slos
+            .iter()
+            .filter(|slo| slo.histogram == name)
+            .map(|slo| {
+                (
+                    slo.target_latency,
+                    KeyValue::new(SLO_NAME_KEY, slo.name.clone()),
+                )
+            })
+            .collect();
+        if slos.is_empty() {
+            return histogram;
+        }
+        Histogram::new(Arc::new(SloHistogram {
+            inner: histogram,
+            slos,
+            events: events.clone(),
+        }))
+    }
+}
+
+/// The values of histograms, compared to target latencies.
+pub(crate) trait Latency: Copy {
+    fn as_f64(self) -> f64;
+}
+
+impl Latency for u64 {
+    #[allow(clippy::cast_precision_loss)]
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Latency for f64 {
+    fn as_f64(self) -> f64 {
+        self
+    }
+}
+
+/// A histogram that counts the good and bad events of its SLOs.
+struct SloHistogram<T> {
+    inner: Histogram<T>,
+    /// The target latency and name attribute of each SLO.
+    slos: Vec<(f64, KeyValue)>,
+    events: Counter<u64>,
+}
+
+impl<T: Latency> SyncHistogram<T> for SloHistogram<T> {
+    fn record(&self, value: T, attributes: &[KeyValue]) {
+        self.inner.record(value, attributes);
+        let latency = value.as_f64();
+        for (target_latency, name) in &self.slos {
+            let outcome = if latency <= *target_latency {
+                "good"
+            } else {
+                "bad"
+            };
+            let mut event_attributes = Vec::with_capacity(attributes.len() + 2);
+            event_attributes.extend_from_slice(attributes);
+            event_attributes.push(name.clone());
+            event_attributes.push(KeyValue::new(SLO_OUTCOME_KEY, outcome));
+            self.events.add(1, &event_attributes);
+        }
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::{
+    any::Any,
+    collections::HashMap,
+    fs,
+    sync::{Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use log::error;
+use opentelemetry::metrics::Result as MetricsResult;
+use opentelemetry_sdk::{
+    metrics::{
+        data::{ExponentialHistogram, Histogram, ResourceMetrics, Sum, Temporality},
+        exporter::PushMetricsExporter,
+        reader::{AggregationSelector, TemporalitySelector},
+        Aggregation, InstrumentKind,
+    },
+    AttributeSet,
+};
+
+use crate::config::StartTime;
+
+/// Where the start time of cumulative series comes from, once the configured [`StartTime`] has been resolved.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum StartTimeSource {
+    Fixed(SystemTime),
+    FirstObservation,
+}
+
+/// Resolve the configured start time. A persisted start time is read from its file, or written to it if the file
+/// does not exist yet. If the file can't be used, the error is reported and `process_start` is used instead.
+pub(crate) fn resolve_start_time(
+    start_time: &StartTime,
+    process_start: SystemTime,
+) -> StartTimeSource {
+    match start_time {
+        StartTime::ProcessStart => StartTimeSource::Fixed(process_start),
+        StartTime::FirstObservation => StartTimeSource::FirstObservation,
+        StartTime::Persisted { path } => {
+            if let Ok(contents) = fs::read_to_string(path) {
+                match contents.trim().parse::<u64>() {
+                    Ok(nanos) => {
+                        return StartTimeSource::Fixed(UNIX_EPOCH + Duration::from_nanos(nanos))
+                    }
+                    Err(e) => error!(
+                        "ignoring persisted start time in {} due to: {:?}",
+                        path.display(),
+                        e
+                    ),
+                }
+            }
+            let nanos = process_start
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_nanos());
+            if let Err(e) = fs::write(path, nanos.to_string()) {
+                error!(
+                    "unable to persist start time to {} due to: {:?}",
+                    path.display(),
+                    e
+                );
+            }
+            StartTimeSource::Fixed(process_start)
+        }
+    }
+}
+
+/// A metrics exporter that overrides the start time of cumulative series before passing them on.
+pub(crate) struct StartTimeMetricsExporter<E> {
+    inner: E,
+    source: Option<StartTimeSource>,
+    first_observations: Mutex<HashMap<(String, AttributeSet), SystemTime>>,
+}
+
+impl<E> StartTimeMetricsExporter<E> {
+    /// Create a new exporter. Start times are left as the SDK reports them if `source` is `None`.
+    pub(crate) fn new(inner: E, source: Option<StartTimeSource>) -> Self {
+        StartTimeMetricsExporter {
+            inner,
+            source,
+            first_observations: Mutex::default(),
+        }
+    }
+
+    fn rewrite<T: 'static>(
+        source: StartTimeSource,
+        first_observations: &mut HashMap<(String, AttributeSet), SystemTime>,
+        name: &str,
+        data: &mut dyn Any,
+    ) {
+        let mut start_time = |attributes: &AttributeSet, time: SystemTime| match source {
+            StartTimeSource::Fixed(start_time) => start_time,
+            StartTimeSource::FirstObservation => *first_observations
+                .entry((name.to_owned(), attributes.clone()))
+                .or_insert(time),
+        };
+
+        if let Some(sum) = data.downcast_mut::<Sum<T>>() {
+            if sum.temporality == Temporality::Cumulative {
+                for data_point in &mut sum.data_points {
+                    let time = data_point.time.unwrap_or_else(SystemTime::now);
+                    data_point.start_time = Some(start_time(&data_point.attributes, time));
+                }
+            }
+        } else if let Some(histogram) = data.downcast_mut::<Histogram<T>>() {
+            if histogram.temporality == Temporality::Cumulative {
+                for data_point in &mut histogram.data_points {
+                    data_point.start_time = start_time(&data_point.attributes, data_point.time);
+                }
+            }
+        } else if let Some(histogram) = data.downcast_mut::<ExponentialHistogram<T>>() {
+            if histogram.temporality == Temporality::Cumulative {
+                for data_point in &mut histogram.data_points {
+                    data_point.start_time = start_time(&data_point.attributes, data_point.time);
+                }
+            }
+        }
+    }
+}
+
+impl<E: TemporalitySelector> TemporalitySelector for StartTimeMetricsExporter<E> {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.inner.temporality(kind)
+    }
+}
+
+impl<E: AggregationSelector> AggregationSelector for StartTimeMetricsExporter<E> {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.inner.aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl<E: PushMetricsExporter> PushMetricsExporter for StartTimeMetricsExporter<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        if let Some(source) = self.source {
+            let mut first_observations = self
+                .first_observations
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            for scope_metrics in &mut metrics.scope_metrics {
+                for metric in &mut scope_metrics.metrics {
+                    let data = metric.data.as_mut();
+                    Self::rewrite::<u64>(source, &mut first_observations, &metric.name, data);
+                    Self::rewrite::<i64>(source, &mut first_observations, &metric.name, data);
+                    Self::rewrite::<f64>(source, &mut first_observations, &metric.name, data);
+                }
+            }
+        }
+        self.inner.export(metrics).await
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        self.inner.force_flush().await
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.inner.shutdown()
+    }
+}
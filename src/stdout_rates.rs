@@ -0,0 +1,152 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use async_trait::async_trait;
+use opentelemetry::metrics::{Result as MetricsResult, Unit};
+use opentelemetry_sdk::{
+    metrics::{
+        data::{DataPoint, Gauge, Metric, ResourceMetrics, Sum, Temporality},
+        exporter::PushMetricsExporter,
+        reader::{AggregationSelector, TemporalitySelector},
+        Aggregation, InstrumentKind,
+    },
+    AttributeSet,
+};
+
+/// The value of a counter series at the time of the previous export.
+struct Observation {
+    start_time: Option<SystemTime>,
+    time: SystemTime,
+    value: f64,
+}
+
+/// A metrics exporter that adds a `<counter>.rate` gauge for each cumulative counter, with the per-second rate of each
+/// series over the interval since the previous export, so the metrics written to stdout can be read without a
+/// backend.
+///
+/// A series has no rate on its first export, or after it was reset.
+pub(crate) struct RateMetricsExporter<E> {
+    inner: E,
+    observations: Mutex<HashMap<(Cow<'static, str>, AttributeSet), Observation>>,
+}
+
+impl<E> RateMetricsExporter<E> {
+    pub(crate) fn new(inner: E) -> Self {
+        RateMetricsExporter {
+            inner,
+            observations: Mutex::default(),
+        }
+    }
+}
+
+/// The rate gauge of `metric`, if it is a cumulative counter with series that were exported before.
+fn rates<T: Copy>(
+    observations: &mut HashMap<(Cow<'static, str>, AttributeSet), Observation>,
+    metric: &Metric,
+    sum: &Sum<T>,
+    to_f64: impl Fn(T) -> f64,
+) -> Option<Metric> {
+    if !sum.is_monotonic || sum.temporality != Temporality::Cumulative {
+        return None;
+    }
+
+    let mut data_points = Vec::new();
+    for data_point in &sum.data_points {
+        let Some(time) = data_point.time else {
+            continue;
+        };
+        let observation = Observation {
+            start_time: data_point.start_time,
+            time,
+            value: to_f64(data_point.value),
+        };
+        let key = (metric.name.clone(), data_point.attributes.clone());
+        if let Some(previous) = observations.get(&key) {
+            let elapsed = time
+                .duration_since(previous.time)
+                .map_or(0.0, |elapsed| elapsed.as_secs_f64());
+            if previous.start_time == observation.start_time
+                && observation.value >= previous.value
+                && elapsed > 0.0
+            {
+                data_points.push(DataPoint {
+                    attributes: data_point.attributes.clone(),
+                    start_time: Some(previous.time),
+                    time: Some(time),
+                    value: (observation.value - previous.value) / elapsed,
+                    exemplars: Vec::new(),
+                });
+            }
+        }
+        observations.insert(key, observation);
+    }
+    if data_points.is_empty() {
+        return None;
+    }
+    Some(Metric {
+        name: format!("{}.rate", metric.name).into(),
+        description: format!("Per-second rate of {} over the last interval", metric.name).into(),
+        unit: Unit::new(if metric.unit.as_str().is_empty() {
+            "1/s".to_owned()
+        } else {
+            format!("{}/s", metric.unit.as_str())
+        }),
+        data: Box::new(Gauge { data_points }),
+    })
+}
+
+impl<E: TemporalitySelector> TemporalitySelector for RateMetricsExporter<E> {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.inner.temporality(kind)
+    }
+}
+
+impl<E: AggregationSelector> AggregationSelector for RateMetricsExporter<E> {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.inner.aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl<E: PushMetricsExporter> PushMetricsExporter for RateMetricsExporter<E> {
+    #[allow(clippy::cast_precision_loss)]
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        {
+            let mut observations = self
+                .observations
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            for scope_metrics in &mut metrics.scope_metrics {
+                let mut rate_metrics = Vec::new();
+                for metric in &scope_metrics.metrics {
+                    let data = metric.data.as_any();
+                    let rate_metric = if let Some(sum) = data.downcast_ref::<Sum<u64>>() {
+                        rates(&mut observations, metric, sum, |value| value as f64)
+                    } else if let Some(sum) = data.downcast_ref::<Sum<f64>>() {
+                        rates(&mut observations, metric, sum, |value| value)
+                    } else {
+                        None
+                    };
+                    rate_metrics.extend(rate_metric);
+                }
+                scope_metrics.metrics.extend(rate_metrics);
+            }
+        }
+        self.inner.export(metrics).await
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        self.inner.force_flush().await
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.inner.shutdown()
+    }
+}
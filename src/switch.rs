@@ -0,0 +1,568 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Switching the export target of a pipeline at runtime, for collector migrations without losing telemetry.
+//!
+//! The exporter of each metrics and log export target sits behind a switch. A switch brings up the exporter of the
+//! new target and confirms it with an empty export before routing exports to it. Exports still in flight to the old
+//! target are awaited before its exporter is shut down, and whatever was queued in the meantime goes to the new
+//! target, so nothing is lost.
+//!
+//! A target whose exporter can't be built at startup, e.g. as its certificate is not provisioned yet, sits behind a
+//! pending switch, whose exports fail until the exporter is built. Building is retried in the background with
+//! exponential backoff, and the exporter is switched in once built, unless the target was switched in the meantime.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError, RwLock,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures_util::lock::Mutex as AsyncMutex;
+use log::{info, warn};
+use opentelemetry::{
+    logs::{LogError, LogResult},
+    metrics::{MetricsError, Result as MetricsResult},
+};
+use opentelemetry_sdk::{
+    export::logs::{LogData, LogExporter},
+    metrics::{
+        data::{ResourceMetrics, Temporality},
+        exporter::PushMetricsExporter,
+        reader::{
+            AggregationSelector, DefaultAggregationSelector, DefaultTemporalitySelector,
+            TemporalitySelector,
+        },
+        Aggregation, InstrumentKind,
+    },
+    Resource,
+};
+use tokio::sync::watch;
+
+use crate::{
+    config::{Config, ExportTarget, LogsExportTarget, MetricsExportTarget},
+    exporter_metrics::{self, Retry},
+    loggers,
+    shared_export::SharedTargets,
+};
+
+/// How often a switch checks whether the exports in flight to the old target have completed.
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long to wait before retrying to build the exporter of a pending target, doubled after each failure.
+const RETRY_INITIAL_DELAY: Duration = Duration::from_secs(1);
+
+/// The longest wait between retries to build the exporter of a pending target.
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// The reason a target could not be switched.
+#[derive(Debug)]
+pub enum SwitchTargetError {
+    /// No enabled target of the signal exports to the old url.
+    NotFound(String),
+    /// The new target can't take over from the old target.
+    Unsupported(String),
+    /// The exporter of the new target could not be built.
+    Build(String),
+    /// The confirmation export to the new target failed. The old target is kept.
+    Export(String),
+}
+
+impl fmt::Display for SwitchTargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SwitchTargetError::NotFound(url) => write!(f, "no export target exports to {url}"),
+            SwitchTargetError::Unsupported(reason) => {
+                write!(f, "unable to switch target as {reason}")
+            }
+            SwitchTargetError::Build(e) => write!(f, "unable to build the new target: {e}"),
+            SwitchTargetError::Export(e) => write!(f, "unable to export to the new target: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for SwitchTargetError {}
+
+/// Wait until no exports are in flight with `exporter`, as each of them holds a reference to it.
+pub(crate) async fn drain<T: ?Sized>(exporter: &Arc<T>) {
+    while Arc::strong_count(exporter) > 1 {
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+}
+
+/// The exporter of a target whose exporter is not built yet. Its exports fail.
+#[derive(Debug)]
+struct PendingExporter {
+    label: String,
+}
+
+impl PendingExporter {
+    fn reason(&self) -> String {
+        format!("the exporter of target [{}] is not built yet", self.label)
+    }
+}
+
+impl TemporalitySelector for PendingExporter {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        DefaultTemporalitySelector::new().temporality(kind)
+    }
+}
+
+impl AggregationSelector for PendingExporter {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        DefaultAggregationSelector::new().aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl PushMetricsExporter for PendingExporter {
+    async fn export(&self, _metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        Err(MetricsError::Other(self.reason()))
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LogExporter for PendingExporter {
+    async fn export(&mut self, _batch: Vec<LogData>) -> LogResult<()> {
+        Err(LogError::Other(self.reason().into()))
+    }
+}
+
+/// Build an exporter with `build` until it succeeds, waiting longer after each failure, or until `stop` is set,
+/// `pending` is cleared or the target is dropped. `build` runs on a blocking thread, as it reads files such as
+/// certificates.
+async fn retry_build<E: Send + 'static>(
+    signal: &'static str,
+    label: &str,
+    pending: impl Fn() -> Option<bool>,
+    mut stop: Option<watch::Receiver<bool>>,
+    build: impl Fn() -> Result<E, String> + Send + Sync + 'static,
+) -> Option<E> {
+    let build = Arc::new(build);
+    let mut delay = RETRY_INITIAL_DELAY;
+    loop {
+        let sleep = tokio::time::sleep(delay);
+        match &mut stop {
+            Some(stop) => {
+                tokio::select! {
+                    () = sleep => {}
+                    _ = stop.wait_for(|stop| *stop) => return None,
+                }
+            }
+            None => sleep.await,
+        }
+        if pending() != Some(true) {
+            return None;
+        }
+        exporter_metrics::record_retry(signal, label, Retry::Build);
+        let build = build.clone();
+        let built = tokio::task::spawn_blocking(move || build())
+            .await
+            .unwrap_or_else(|e| Err(format!("{e:?}")));
+        match built {
+            Ok(exporter) => return Some(exporter),
+            Err(e) => {
+                delay = (delay * 2).min(RETRY_MAX_DELAY);
+                warn!("unable to build exporter for target [{label}] due to {e}, retrying in {delay:?}");
+            }
+        }
+    }
+}
+
+/// The switch of a metrics export target.
+pub(crate) struct MetricsSwitch {
+    url: Mutex<String>,
+    exporter: RwLock<Arc<dyn PushMetricsExporter>>,
+    pending: AtomicBool,
+}
+
+impl MetricsSwitch {
+    pub(crate) fn new(url: String, exporter: impl PushMetricsExporter) -> Arc<Self> {
+        Arc::new(MetricsSwitch {
+            url: Mutex::new(url),
+            exporter: RwLock::new(Arc::new(exporter)),
+            pending: AtomicBool::new(false),
+        })
+    }
+
+    /// The switch of `label`, exporting to `url`, whose exporter is not built yet.
+    pub(crate) fn pending(url: String, label: &str) -> Arc<Self> {
+        Arc::new(MetricsSwitch {
+            url: Mutex::new(url),
+            exporter: RwLock::new(Arc::new(PendingExporter {
+                label: label.to_owned(),
+            })),
+            pending: AtomicBool::new(true),
+        })
+    }
+
+    /// Switch to `exporter` without confirmation, if the switch is still pending. Returns whether it switched.
+    fn install(&self, exporter: Arc<dyn PushMetricsExporter>) -> bool {
+        let mut current = self
+            .exporter
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let pending = self.pending.swap(false, Ordering::SeqCst);
+        if pending {
+            *current = exporter;
+        }
+        pending
+    }
+
+    fn exports_to(&self, url: &str) -> bool {
+        *self.url.lock().unwrap_or_else(PoisonError::into_inner) == url
+    }
+
+    async fn switch(
+        &self,
+        url: String,
+        exporter: Arc<dyn PushMetricsExporter>,
+        resource: &Resource,
+    ) -> Result<(), SwitchTargetError> {
+        exporter
+            .export(&mut ResourceMetrics {
+                resource: resource.clone(),
+                scope_metrics: Vec::new(),
+            })
+            .await
+            .map_err(|e| SwitchTargetError::Export(format!("{e:?}")))?;
+
+        let old = {
+            let mut current = self
+                .exporter
+                .write()
+                .unwrap_or_else(PoisonError::into_inner);
+            self.pending.store(false, Ordering::SeqCst);
+            std::mem::replace(&mut *current, exporter)
+        };
+        *self.url.lock().unwrap_or_else(PoisonError::into_inner) = url;
+        drain(&old).await;
+        if let Err(e) = old.shutdown() {
+            warn!("encountered error while shutting down the old metrics target: {e:?}");
+        }
+        Ok(())
+    }
+}
+
+/// A metrics exporter that exports to whichever target its switch currently points to. The temporality is that of
+/// the target the pipeline was built for, as the SDK doesn't expect it to change.
+pub(crate) struct SwitchableMetricsExporter {
+    switch: Arc<MetricsSwitch>,
+    temporality_selector: Box<dyn TemporalitySelector>,
+}
+
+impl SwitchableMetricsExporter {
+    pub(crate) fn new(
+        switch: Arc<MetricsSwitch>,
+        temporality_selector: Box<dyn TemporalitySelector>,
+    ) -> Self {
+        SwitchableMetricsExporter {
+            switch,
+            temporality_selector,
+        }
+    }
+
+    fn exporter(&self) -> Arc<dyn PushMetricsExporter> {
+        self.switch
+            .exporter
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl TemporalitySelector for SwitchableMetricsExporter {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.temporality_selector.temporality(kind)
+    }
+}
+
+impl AggregationSelector for SwitchableMetricsExporter {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        DefaultAggregationSelector::new().aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl PushMetricsExporter for SwitchableMetricsExporter {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        self.exporter().export(metrics).await
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        self.exporter().force_flush().await
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.exporter().shutdown()
+    }
+}
+
+/// The switch of a log export target.
+pub(crate) struct LogSwitch {
+    url: Mutex<String>,
+    exporter: AsyncMutex<Box<dyn LogExporter>>,
+    pending: AtomicBool,
+}
+
+impl LogSwitch {
+    pub(crate) fn new(url: String, exporter: impl LogExporter + 'static) -> Arc<Self> {
+        Arc::new(LogSwitch {
+            url: Mutex::new(url),
+            exporter: AsyncMutex::new(Box::new(exporter)),
+            pending: AtomicBool::new(false),
+        })
+    }
+
+    /// The switch of `label`, exporting to `url`, whose exporter is not built yet.
+    pub(crate) fn pending(url: String, label: &str) -> Arc<Self> {
+        Arc::new(LogSwitch {
+            url: Mutex::new(url),
+            exporter: AsyncMutex::new(Box::new(PendingExporter {
+                label: label.to_owned(),
+            })),
+            pending: AtomicBool::new(true),
+        })
+    }
+
+    /// Switch to `exporter` without confirmation, if the switch is still pending. Returns whether it switched.
+    async fn install(&self, exporter: Box<dyn LogExporter>) -> bool {
+        let mut current = self.exporter.lock().await;
+        let pending = self.pending.swap(false, Ordering::SeqCst);
+        if pending {
+            *current = exporter;
+        }
+        pending
+    }
+
+    fn exports_to(&self, url: &str) -> bool {
+        *self.url.lock().unwrap_or_else(PoisonError::into_inner) == url
+    }
+
+    async fn switch(
+        &self,
+        url: String,
+        mut exporter: Box<dyn LogExporter>,
+    ) -> Result<(), SwitchTargetError> {
+        exporter
+            .export(Vec::new())
+            .await
+            .map_err(|e| SwitchTargetError::Export(format!("{e:?}")))?;
+
+        // Exports hold the lock until they complete, so the export in flight to the old target is drained here.
+        let mut current = self.exporter.lock().await;
+        self.pending.store(false, Ordering::SeqCst);
+        let mut old = std::mem::replace(&mut *current, exporter);
+        *self.url.lock().unwrap_or_else(PoisonError::into_inner) = url;
+        drop(current);
+        old.shutdown();
+        Ok(())
+    }
+}
+
+impl fmt::Debug for LogSwitch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogSwitch")
+            .field("url", &self.url)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A log exporter that exports to whichever target its switch currently points to.
+#[derive(Debug)]
+pub(crate) struct SwitchableLogExporter {
+    switch: Arc<LogSwitch>,
+}
+
+impl SwitchableLogExporter {
+    pub(crate) fn new(switch: Arc<LogSwitch>) -> Self {
+        SwitchableLogExporter { switch }
+    }
+}
+
+#[async_trait]
+impl LogExporter for SwitchableLogExporter {
+    async fn export(&mut self, batch: Vec<LogData>) -> LogResult<()> {
+        self.switch.exporter.lock().await.export(batch).await
+    }
+
+    fn shutdown(&mut self) {
+        futures_executor::block_on(self.switch.exporter.lock()).shutdown();
+    }
+}
+
+/// The switches of all export targets.
+#[derive(Default)]
+pub(crate) struct Switches {
+    metrics: Vec<Arc<MetricsSwitch>>,
+    shared_metrics: Vec<Arc<SharedTargets>>,
+    logs: Vec<Arc<LogSwitch>>,
+    /// Set to true to stop retrying to build the exporters of pending targets.
+    stop: Option<watch::Receiver<bool>>,
+}
+
+impl Switches {
+    /// Switches whose pending targets stop retrying when `stop` is set to true.
+    pub(crate) fn new(stop: watch::Receiver<bool>) -> Self {
+        Switches {
+            stop: Some(stop),
+            ..Switches::default()
+        }
+    }
+
+    /// Retry to build the exporter of `export_target` in the background, and switch `switch` to it once built.
+    pub(crate) fn retry_metrics(
+        &self,
+        switch: &Arc<MetricsSwitch>,
+        export_target: MetricsExportTarget,
+        config: Config,
+    ) {
+        let weak_switch = Arc::downgrade(switch);
+        let stop = self.stop.clone();
+        tokio::spawn(async move {
+            let label = export_target.label().to_owned();
+            let pending = || {
+                weak_switch
+                    .upgrade()
+                    .map(|switch| switch.pending.load(Ordering::SeqCst))
+            };
+            let built = retry_build("metrics", &label, pending, stop, move || {
+                crate::build_switchable_exporter(&export_target, &config)
+                    .map_err(|e| format!("{e:?}"))
+            })
+            .await;
+            if let Some((exporter, switch)) = built.zip(weak_switch.upgrade()) {
+                if switch.install(Arc::new(exporter)) {
+                    info!("built exporter for target [{label}]");
+                }
+            }
+        });
+    }
+
+    /// Retry to build the exporter of `export_target` in the background, and switch `switch` to it once built.
+    pub(crate) fn retry_logs(
+        &self,
+        switch: &Arc<LogSwitch>,
+        export_target: LogsExportTarget,
+        config: Config,
+    ) {
+        let weak_switch = Arc::downgrade(switch);
+        let stop = self.stop.clone();
+        tokio::spawn(async move {
+            let label = export_target.label().to_owned();
+            let pending = || {
+                weak_switch
+                    .upgrade()
+                    .map(|switch| switch.pending.load(Ordering::SeqCst))
+            };
+            let built = retry_build("logs", &label, pending, stop, move || {
+                loggers::build_target_exporter(&export_target, &config)
+            })
+            .await;
+            if let Some((exporter, switch)) = built.zip(weak_switch.upgrade()) {
+                if switch.install(Box::new(exporter)).await {
+                    info!("built exporter for target [{label}]");
+                }
+            }
+        });
+    }
+
+    pub(crate) fn add_metrics(&mut self, switch: Arc<MetricsSwitch>) {
+        self.metrics.push(switch);
+    }
+
+    pub(crate) fn add_shared_metrics(&mut self, targets: Arc<SharedTargets>) {
+        self.shared_metrics.push(targets);
+    }
+
+    pub(crate) fn add_logs(&mut self, switch: Arc<LogSwitch>) {
+        self.logs.push(switch);
+    }
+
+    /// Switch the target that exports to `old_url` over to `new_target`, which is built according to `config` and
+    /// confirmed with an export of `resource`.
+    pub(crate) async fn switch(
+        &self,
+        config: &Config,
+        resource: &Resource,
+        old_url: &str,
+        new_target: ExportTarget,
+    ) -> Result<(), SwitchTargetError> {
+        match new_target {
+            ExportTarget::Metrics(new_target) => {
+                self.switch_metrics(config, resource, old_url, new_target)
+                    .await
+            }
+            ExportTarget::Logs(new_target) => self.switch_logs(config, old_url, new_target).await,
+        }?;
+        info!("switched export target {old_url}");
+        Ok(())
+    }
+
+    async fn switch_metrics(
+        &self,
+        config: &Config,
+        resource: &Resource,
+        old_url: &str,
+        new_target: MetricsExportTarget,
+    ) -> Result<(), SwitchTargetError> {
+        if let Some(switch) = self
+            .metrics
+            .iter()
+            .find(|switch| switch.exports_to(old_url))
+        {
+            let exporter = crate::build_switchable_exporter(&new_target, config)
+                .map_err(|e| SwitchTargetError::Build(format!("{}: {e}", new_target.label())))?;
+            return switch
+                .switch(new_target.url.clone(), Arc::new(exporter), resource)
+                .await;
+        }
+        if let Some(targets) = self
+            .shared_metrics
+            .iter()
+            .find(|targets| targets.exports_to(old_url))
+        {
+            if !crate::shareable(&new_target) {
+                return Err(SwitchTargetError::Unsupported(format!(
+                    "{old_url} shares its data with other targets and {} is not an OTLP/gRPC target without fallback",
+                    new_target.label()
+                )));
+            }
+            let target = crate::build_shared_target(&new_target, config)
+                .map_err(|e| SwitchTargetError::Build(format!("{}: {e}", new_target.label())))?;
+            return targets
+                .switch(old_url, target, resource)
+                .await
+                .map_err(|e| SwitchTargetError::Export(format!("{e:?}")));
+        }
+        Err(SwitchTargetError::NotFound(old_url.to_owned()))
+    }
+
+    async fn switch_logs(
+        &self,
+        config: &Config,
+        old_url: &str,
+        new_target: LogsExportTarget,
+    ) -> Result<(), SwitchTargetError> {
+        let Some(switch) = self.logs.iter().find(|switch| switch.exports_to(old_url)) else {
+            return Err(SwitchTargetError::NotFound(old_url.to_owned()));
+        };
+        let exporter = loggers::build_target_exporter(&new_target, config)
+            .map_err(|e| SwitchTargetError::Build(format!("{}: {e}", new_target.label())))?;
+        switch
+            .switch(new_target.url.clone(), Box::new(exporter))
+            .await
+    }
+}
@@ -1,29 +1,86 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-use std::time::SystemTime;
+use std::{fmt::Write as _, time::SystemTime};
 
 use humantime::format_rfc3339_millis;
-use log::Record;
-
-pub(crate) fn write_syslog_format(
-    record: &Record<'_>,
-    service_name: &str,
-    host_name: &str,
-    timestamp: &SystemTime,
-) {
-    // Write to stderr
-    // TODO: check if there is any benefit to buffering this write, given the trade-off of missing logs if the app panics.
+use log::{
+    kv::{self, Key, Value, VisitSource},
+    Record,
+};
+use opentelemetry::{
+    trace::{TraceContextExt, TraceId},
+    Context,
+};
+
+/// The parts of a syslog formatted line that don't come from the log record.
+#[derive(Clone, Copy, Debug)]
+pub struct SyslogOptions<'a> {
+    /// name of the component, for example "App"
+    pub service_name: &'a str,
+    /// name of the host the component runs on
+    pub host_name: &'a str,
+    /// time the record was logged
+    pub timestamp: SystemTime,
+    /// id of the trace of the span that was active when the record was logged, if any
+    pub trace_id: Option<TraceId>,
+}
+
+/// The id of the trace of the span active in the current context, if any.
+pub fn current_trace_id() -> Option<TraceId> {
+    Context::map_current(|cx| {
+        cx.has_active_span()
+            .then(|| cx.span().span_context().trace_id())
+    })
+}
+
+/// Format `record` as a single line in the [syslog format](https://www.rfc-editor.org/rfc/rfc5424#page-8), without a
+/// trailing newline. The thread id is that of the calling thread. If a trace id is given, it is included as
+/// `trace="<id>"` so that local logs can be correlated with traces in the backend. The key-values of the record
+/// follow as `<key>="<value>"` parameters of the structured data.
+pub fn format_syslog(record: &Record<'_>, opts: &SyslogOptions<'_>) -> String {
     let level = to_syslog_level(record.level());
-    let timestamp = format_rfc3339_millis(*timestamp);
+    let timestamp = format_rfc3339_millis(opts.timestamp);
     let thread_id = nix::unistd::gettid().as_raw();
     let module = record.target();
-    eprintln!(
-        r#"<{level}>{timestamp} {} [{} tid="{thread_id}" module="{module}"] - {}"#,
-        service_name,
-        host_name,
+    let trace = opts
+        .trace_id
+        .map(|trace_id| format!(r#" trace="{trace_id}""#))
+        .unwrap_or_default();
+    let mut key_values = KeyValueParams(String::new());
+    // Formatting the key-values can't fail.
+    let _ = record.key_values().visit(&mut key_values);
+    let key_values = key_values.0;
+    format!(
+        r#"<{level}>{timestamp} {} [{} tid="{thread_id}" module="{module}"{trace}{key_values}] - {}"#,
+        opts.service_name,
+        opts.host_name,
         record.args()
-    );
+    )
+}
+
+/// Formats key-values of the `log` crate as parameters of syslog structured data, escaping `"`, `\` and `]` in
+/// their values.
+struct KeyValueParams(String);
+
+impl<'kvs> VisitSource<'kvs> for KeyValueParams {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let _ = write!(self.0, r#" {key}=""#);
+        for c in value.to_string().chars() {
+            if matches!(c, '"' | '\\' | ']') {
+                self.0.push('\\');
+            }
+            self.0.push(c);
+        }
+        self.0.push('"');
+        Ok(())
+    }
+}
+
+pub(crate) fn write_syslog_format(record: &Record<'_>, opts: &SyslogOptions<'_>) {
+    // Write to stderr
+    // TODO: check if there is any benefit to buffering this write, given the trade-off of missing logs if the app panics.
+    eprintln!("{}", format_syslog(record, opts));
 }
 
 const fn to_syslog_level(level: log::Level) -> i8 {
@@ -0,0 +1,90 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// The health of the export targets, served on the `/healthz` and `/readyz` routes of the prometheus end points so
+// probes and operators can detect a broken telemetry pipeline. Targets are registered as their exporter is built, and
+// every export to them is recorded, whether or not the self-diagnostics metrics are set up yet.
+
+use std::{
+    collections::BTreeMap,
+    sync::{PoisonError, RwLock},
+    time::SystemTime,
+};
+
+use serde::Serialize;
+
+/// The health of the targets, by signal and label.
+static TARGETS: RwLock<BTreeMap<(&'static str, String), Health>> = RwLock::new(BTreeMap::new());
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Health {
+    last_success: Option<SystemTime>,
+    consecutive_failures: u32,
+}
+
+/// The health of the targets, as served.
+#[derive(Debug, Serialize)]
+pub(crate) struct HealthReport {
+    status: &'static str,
+    targets: Vec<TargetReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct TargetReport {
+    signal: &'static str,
+    target: String,
+    last_success: Option<String>,
+    consecutive_failures: u32,
+}
+
+/// Register `target` of `signal`, which is reported before its first export.
+pub(crate) fn register(signal: &'static str, target: &str) {
+    TARGETS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry((signal, target.to_owned()))
+        .or_default();
+}
+
+/// Record an export of `signal` to `target`, which succeeded if `success`.
+pub(crate) fn record(signal: &'static str, target: &str, success: bool) {
+    let mut targets = TARGETS.write().unwrap_or_else(PoisonError::into_inner);
+    let health = targets.entry((signal, target.to_owned())).or_default();
+    if success {
+        health.last_success = Some(SystemTime::now());
+        health.consecutive_failures = 0;
+    } else {
+        health.consecutive_failures = health.consecutive_failures.saturating_add(1);
+    }
+}
+
+/// The health of the targets, which are ready unless one of them failed `unready_after_failures` times in a row.
+pub(crate) fn report(unready_after_failures: u32) -> (bool, HealthReport) {
+    let targets = TARGETS.read().unwrap_or_else(PoisonError::into_inner);
+    let ready = targets
+        .values()
+        .all(|health| health.consecutive_failures < unready_after_failures);
+    let report = HealthReport {
+        status: if ready { "ready" } else { "unready" },
+        targets: targets
+            .iter()
+            .map(|((signal, target), health)| TargetReport {
+                signal,
+                target: target.clone(),
+                last_success: health
+                    .last_success
+                    .map(|time| humantime::format_rfc3339_seconds(time).to_string()),
+                consecutive_failures: health.consecutive_failures,
+            })
+            .collect(),
+    };
+    (ready, report)
+}
+
+/// Stop reporting `target` of `signal`, once it is removed.
+pub(crate) fn unregister(signal: &'static str, target: &str) {
+    TARGETS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&(signal, target.to_owned()));
+}
@@ -0,0 +1,252 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// Accounting of the telemetry exported for each tenant, so operators of shared agents can attribute the cost of
+// telemetry to the tenants that emit it.
+//
+// The tenant of a log record or data point is the value of the tenant attribute among its attributes, or else among
+// the attributes of its resource, e.g. of a resource scope. The records and data points of each tenant that were
+// exported successfully are counted as the `otel_lib.exporter.tenant.records` metric, and their serialized size as
+// `otel_lib.exporter.tenant.size`, excluding the resource and scope they share with others. Telemetry without the
+// tenant attribute, and the metrics of otel-lib itself, are not accounted.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use opentelemetry::{logs::LogResult, metrics::Result as MetricsResult};
+use opentelemetry_proto::tonic::{
+    collector::metrics::v1::ExportMetricsServiceRequest,
+    common::v1::{any_value, KeyValue as ProtoKeyValue},
+    logs::v1::ResourceLogs,
+    metrics::v1::metric::Data,
+};
+use opentelemetry_sdk::{
+    export::logs::{LogData, LogExporter},
+    metrics::{
+        data::{ResourceMetrics, Temporality},
+        exporter::PushMetricsExporter,
+        reader::{AggregationSelector, TemporalitySelector},
+        Aggregation, InstrumentKind,
+    },
+};
+use prost::Message;
+
+use crate::exporter_metrics;
+
+/// The scope of the metrics of otel-lib, which are not accounted to tenants, as they describe the pipelines.
+const SELF_SCOPE: &str = "otel-lib";
+
+/// The records and bytes exported for each tenant.
+#[derive(Debug, Default)]
+struct Usage(HashMap<String, (u64, u64)>);
+
+impl Usage {
+    /// Account a record of `bytes` with `attributes`, of a resource with `resource_attributes`.
+    fn add(
+        &mut self,
+        key: &str,
+        attributes: &[ProtoKeyValue],
+        resource_attributes: &[ProtoKeyValue],
+        bytes: usize,
+    ) {
+        let Some(tenant) = tenant(key, attributes).or_else(|| tenant(key, resource_attributes))
+        else {
+            return;
+        };
+        let (records, total) = self.0.entry(tenant).or_default();
+        *records += 1;
+        *total += bytes as u64;
+    }
+
+    fn record(self, signal: &'static str, targets: &[String]) {
+        for (tenant, (records, bytes)) in self.0 {
+            for target in targets {
+                exporter_metrics::record_tenant_export(signal, target, &tenant, records, bytes);
+            }
+        }
+    }
+}
+
+/// The value of the attribute `key` among `attributes`, if any.
+fn tenant(key: &str, attributes: &[ProtoKeyValue]) -> Option<String> {
+    let value = attributes
+        .iter()
+        .find(|attribute| attribute.key == key)?
+        .value
+        .as_ref()?
+        .value
+        .as_ref()?;
+    Some(match value {
+        any_value::Value::StringValue(value) => value.clone(),
+        any_value::Value::BoolValue(value) => value.to_string(),
+        any_value::Value::IntValue(value) => value.to_string(),
+        any_value::Value::DoubleValue(value) => value.to_string(),
+        _ => return None,
+    })
+}
+
+/// The usage of the tenants in `metrics`.
+fn metrics_usage(key: &str, metrics: &ResourceMetrics) -> Usage {
+    let mut usage = Usage::default();
+    let request = ExportMetricsServiceRequest::from(metrics);
+    for resource_metrics in &request.resource_metrics {
+        let resource_attributes = resource_metrics
+            .resource
+            .as_ref()
+            .map_or(&[][..], |resource| &resource.attributes);
+        for metric in resource_metrics
+            .scope_metrics
+            .iter()
+            .filter(|scope_metrics| {
+                scope_metrics
+                    .scope
+                    .as_ref()
+                    .is_none_or(|scope| scope.name != SELF_SCOPE)
+            })
+            .flat_map(|scope_metrics| &scope_metrics.metrics)
+        {
+            let mut add = |attributes: &[ProtoKeyValue], bytes| {
+                usage.add(key, attributes, resource_attributes, bytes);
+            };
+            match &metric.data {
+                Some(Data::Gauge(gauge)) => gauge
+                    .data_points
+                    .iter()
+                    .for_each(|point| add(&point.attributes, point.encoded_len())),
+                Some(Data::Sum(sum)) => sum
+                    .data_points
+                    .iter()
+                    .for_each(|point| add(&point.attributes, point.encoded_len())),
+                Some(Data::Histogram(histogram)) => histogram
+                    .data_points
+                    .iter()
+                    .for_each(|point| add(&point.attributes, point.encoded_len())),
+                Some(Data::ExponentialHistogram(histogram)) => histogram
+                    .data_points
+                    .iter()
+                    .for_each(|point| add(&point.attributes, point.encoded_len())),
+                Some(Data::Summary(summary)) => summary
+                    .data_points
+                    .iter()
+                    .for_each(|point| add(&point.attributes, point.encoded_len())),
+                None => {}
+            }
+        }
+    }
+    usage
+}
+
+/// The usage of the tenants in `batch`.
+fn logs_usage(key: &str, batch: &[LogData]) -> Usage {
+    let mut usage = Usage::default();
+    for resource_logs in batch.iter().cloned().map(ResourceLogs::from) {
+        let resource_attributes = resource_logs
+            .resource
+            .as_ref()
+            .map_or(&[][..], |resource| &resource.attributes);
+        for record in resource_logs
+            .scope_logs
+            .iter()
+            .flat_map(|scope_logs| &scope_logs.log_records)
+        {
+            usage.add(
+                key,
+                &record.attributes,
+                resource_attributes,
+                record.encoded_len(),
+            );
+        }
+    }
+    usage
+}
+
+/// A metrics exporter that accounts the data points it exports to `targets` for each tenant, if a tenant attribute
+/// is configured.
+pub(crate) struct TenantMetricsExporter<E> {
+    inner: E,
+    key: Option<String>,
+    targets: Vec<String>,
+}
+
+impl<E> TenantMetricsExporter<E> {
+    pub(crate) fn new(inner: E, key: Option<&str>, targets: Vec<String>) -> Self {
+        TenantMetricsExporter {
+            inner,
+            key: key.map(str::to_owned),
+            targets,
+        }
+    }
+}
+
+impl<E: TemporalitySelector> TemporalitySelector for TenantMetricsExporter<E> {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.inner.temporality(kind)
+    }
+}
+
+impl<E: AggregationSelector> AggregationSelector for TenantMetricsExporter<E> {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.inner.aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl<E: PushMetricsExporter> PushMetricsExporter for TenantMetricsExporter<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> MetricsResult<()> {
+        let Some(key) = &self.key else {
+            return self.inner.export(metrics).await;
+        };
+        let usage = metrics_usage(key, metrics);
+        let result = self.inner.export(metrics).await;
+        if result.is_ok() {
+            usage.record("metrics", &self.targets);
+        }
+        result
+    }
+
+    async fn force_flush(&self) -> MetricsResult<()> {
+        self.inner.force_flush().await
+    }
+
+    fn shutdown(&self) -> MetricsResult<()> {
+        self.inner.shutdown()
+    }
+}
+
+/// A log exporter that accounts the records it exports to `target` for each tenant, if a tenant attribute is
+/// configured.
+#[derive(Debug)]
+pub(crate) struct TenantLogExporter<E> {
+    inner: E,
+    key: Option<String>,
+    target: String,
+}
+
+impl<E> TenantLogExporter<E> {
+    pub(crate) fn new(inner: E, key: Option<&str>, target: &str) -> Self {
+        TenantLogExporter {
+            inner,
+            key: key.map(str::to_owned),
+            target: target.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl<E: LogExporter> LogExporter for TenantLogExporter<E> {
+    async fn export(&mut self, batch: Vec<LogData>) -> LogResult<()> {
+        let Some(key) = &self.key else {
+            return self.inner.export(batch).await;
+        };
+        let usage = logs_usage(key, &batch);
+        let result = self.inner.export(batch).await;
+        if result.is_ok() {
+            usage.record("logs", std::slice::from_ref(&self.target));
+        }
+        result
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+}
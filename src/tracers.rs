@@ -0,0 +1,261 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+// The trace pipeline: spans created with the tracers of `opentelemetry::global::tracer` are batched and exported
+// to each trace export target over OTLP/gRPC.
+//
+// A tracer provider has a single sampler, so the provider samples the traces that the most permissive target keeps,
+// and the processor of each target passes on the traces its own sampler keeps. Ratios are applied to the trace id,
+// so the traces kept by a target with a smaller ratio are a subset of those kept by the provider.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures_util::future::BoxFuture;
+use log::error;
+use opentelemetry::{
+    global,
+    trace::{TraceError, TraceId, TraceResult},
+    Context,
+};
+use opentelemetry_otlp::{
+    WithExportConfig, OTEL_EXPORTER_OTLP_TRACES_ENDPOINT, OTEL_EXPORTER_OTLP_TRACES_TIMEOUT,
+};
+use opentelemetry_sdk::{
+    export::trace::{ExportResult, SpanData, SpanExporter},
+    runtime,
+    trace::{BatchConfigBuilder, BatchSpanProcessor, Sampler, Span, SpanProcessor, TracerProvider},
+    Resource,
+};
+
+use crate::{
+    config::{Config, DnsConfig, SocketOptions, TraceSampler, TracesExportTarget},
+    events::SpanEventsProcessor,
+    export_failures::{ExportFailures, SummarizedError},
+    exporter_metrics,
+    identity::{DeviceIdentity, IdentitySpanExporter},
+    init::{InitComponent, InitReport},
+    target_health,
+    transport::{self, CaReloading, TlsOptions},
+};
+
+/// Initialize the trace pipeline and install its tracer provider globally, if any trace targets are configured.
+pub(crate) fn init_traces(
+    config: &Config,
+    resource: Resource,
+    identity: Option<&Arc<DeviceIdentity>>,
+    init_report: &mut InitReport,
+) -> Option<TracerProvider> {
+    let trace_targets = config.trace_export_targets.as_ref()?;
+    let trace_targets: Vec<&TracesExportTarget> = trace_targets
+        .iter()
+        .filter(|target| {
+            if !target.enabled {
+                init_report.disabled(InitComponent::TraceTarget, target.label());
+            }
+            target.enabled
+        })
+        .collect();
+    let mut tracer_provider_builder = TracerProvider::builder().with_config(
+        opentelemetry_sdk::trace::config()
+            .with_resource(resource)
+            .with_sampler(Sampler::ParentBased(Box::new(provider_sampler(
+                trace_targets.iter().map(|target| target.sampler),
+            )))),
+    );
+    for trace_target in trace_targets {
+        let exporter = match build_span_exporter(trace_target, config) {
+            Ok(exporter) => exporter,
+            Err(e) => {
+                error!(
+                    "unable to create exporter for trace target [{}]: {:?}",
+                    trace_target.label(),
+                    e
+                );
+                init_report.failed(InitComponent::TraceTarget, Some(trace_target.label()), e);
+                continue;
+            }
+        };
+        init_report.initialized(InitComponent::TraceTarget, trace_target.label());
+        let exporter = IdentitySpanExporter::new(exporter, identity.cloned());
+        let batch_span_processor = BatchSpanProcessor::builder(exporter, runtime::Tokio)
+            .with_batch_config(
+                BatchConfigBuilder::default()
+                    .with_scheduled_delay(Duration::from_secs(trace_target.interval_secs))
+                    .with_max_export_timeout(Duration::from_secs(trace_target.timeout))
+                    .build(),
+            )
+            .build();
+        tracer_provider_builder = tracer_provider_builder.with_span_processor(
+            SampledSpanProcessor::new(batch_span_processor, trace_target.sampler),
+        );
+    }
+    if config.span_events_as_logs {
+        tracer_provider_builder =
+            tracer_provider_builder.with_span_processor(SpanEventsProcessor::new());
+    }
+
+    let tracer_provider = tracer_provider_builder.build();
+    global::set_tracer_provider(tracer_provider.clone());
+    Some(tracer_provider)
+}
+
+/// The sampler of the provider, which keeps the traces kept by any of `samplers`.
+fn provider_sampler(samplers: impl Iterator<Item = TraceSampler>) -> Sampler {
+    let mut ratio: f64 = 0.0;
+    for sampler in samplers {
+        ratio = ratio.max(match sampler {
+            TraceSampler::AlwaysOn => 1.0,
+            TraceSampler::AlwaysOff => 0.0,
+            TraceSampler::TraceIdRatio(ratio) => ratio,
+        });
+    }
+    if ratio >= 1.0 {
+        Sampler::AlwaysOn
+    } else if ratio <= 0.0 {
+        Sampler::AlwaysOff
+    } else {
+        Sampler::TraceIdRatioBased(ratio)
+    }
+}
+
+/// Build the exporter of `trace_target`.
+fn build_span_exporter(
+    trace_target: &TracesExportTarget,
+    config: &Config,
+) -> TraceResult<SummarizedSpanExporter> {
+    let user_agent = transport::user_agent(None, config.app_identifier.as_deref());
+    let exporter = CaReloading::new(
+        "traces",
+        trace_target.label(),
+        &TlsOptions::from(trace_target),
+        {
+            let trace_target = trace_target.clone();
+            move || build_otlp_span_exporter(&trace_target, &user_agent)
+        },
+    )?;
+    target_health::register("traces", trace_target.label());
+    Ok(SummarizedSpanExporter {
+        inner: exporter,
+        label: trace_target.label().into(),
+        failures: ExportFailures::new(
+            "traces",
+            trace_target.label().to_owned(),
+            config.export_failure_summary_secs,
+        )
+        .map(Arc::new),
+    })
+}
+
+/// Build the OTLP/gRPC exporter of `trace_target`, which trusts the certificate authorities of its CA certificate
+/// files as they are now.
+fn build_otlp_span_exporter(
+    trace_target: &TracesExportTarget,
+    user_agent: &str,
+) -> TraceResult<opentelemetry_otlp::SpanExporter> {
+    let channel = transport::grpc_channel(
+        &trace_target.url,
+        Duration::from_secs(trace_target.timeout),
+        user_agent,
+        OTEL_EXPORTER_OTLP_TRACES_ENDPOINT,
+        OTEL_EXPORTER_OTLP_TRACES_TIMEOUT,
+        transport::Connector::new(&DnsConfig::default(), &SocketOptions::default(), None),
+        &TlsOptions::from(trace_target),
+    )
+    .map_err(TraceError::Other)?;
+    opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(trace_target.url.clone())
+        .with_channel(channel)
+        .build_span_exporter()
+}
+
+/// A span exporter whose failed exports are summarized, if `failures` is set, and whose exports are recorded as
+/// self-diagnostics metrics.
+#[derive(Debug)]
+struct SummarizedSpanExporter {
+    inner: CaReloading<opentelemetry_otlp::SpanExporter>,
+    label: Arc<str>,
+    failures: Option<Arc<ExportFailures>>,
+}
+
+impl SpanExporter for SummarizedSpanExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let start = Instant::now();
+        let export = self.inner.export(batch);
+        let label = self.label.clone();
+        let failures = self.failures.clone();
+        Box::pin(async move {
+            let result = export.await;
+            exporter_metrics::record_export("traces", &label, start, &result);
+            let Some(failures) = failures else {
+                return result;
+            };
+            failures.report(&result);
+            result.map_err(|e| TraceError::from(SummarizedError::new(&e)))
+        })
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown();
+    }
+
+    fn force_flush(&mut self) -> BoxFuture<'static, ExportResult> {
+        self.inner.force_flush()
+    }
+}
+
+/// A span processor that only passes the spans of the traces kept by the sampler of a target on to its processor.
+#[derive(Debug)]
+struct SampledSpanProcessor<P> {
+    inner: P,
+    sampler: TraceSampler,
+}
+
+impl<P> SampledSpanProcessor<P> {
+    fn new(inner: P, sampler: TraceSampler) -> Self {
+        SampledSpanProcessor { inner, sampler }
+    }
+
+    /// Whether the sampler keeps the trace `trace_id`, as decided by the trace id ratio sampler of the SDK.
+    fn keeps(&self, trace_id: TraceId) -> bool {
+        match self.sampler {
+            TraceSampler::AlwaysOn => true,
+            TraceSampler::AlwaysOff => false,
+            TraceSampler::TraceIdRatio(ratio) if ratio >= 1.0 => true,
+            TraceSampler::TraceIdRatio(ratio) => {
+                #[allow(
+                    clippy::cast_precision_loss,
+                    clippy::cast_possible_truncation,
+                    clippy::cast_sign_loss
+                )]
+                let upper_bound = (ratio.max(0.0) * (1_u64 << 63) as f64) as u64;
+                let low =
+                    u64::from_be_bytes(trace_id.to_bytes()[8..].try_into().unwrap_or_default());
+                low >> 1 < upper_bound
+            }
+        }
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for SampledSpanProcessor<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        if self.keeps(span.span_context.trace_id()) {
+            self.inner.on_end(span);
+        }
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        self.inner.shutdown()
+    }
+}
@@ -0,0 +1,158 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A layer of `tracing_subscriber` that sends the events of the `tracing` crate through the same pipeline as the
+//! records of the `log` crate, for services that use the macros of `tracing`.
+//!
+//! Events are passed to the logger installed by [`Otel`](crate::Otel) as records of their level and target, so the
+//! global `level`, the stderr output in the syslog format, the log routes and drop rules and the filters of the log
+//! targets apply to them as configured. The message of a record is the message of its event, prefixed with the
+//! spans the event is in and followed by its other fields, e.g. `request{id=7}: connected peer="10.0.0.2"`.
+//!
+//! Install it with the registry of `tracing_subscriber`, after initializing [`Otel`](crate::Otel):
+//! `tracing::subscriber::set_global_default(Registry::default().with(OtelTracingLayer::new()))`.
+
+use std::fmt::{self, Write as _};
+
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// The fields of a span, formatted as `key=value` pairs when the span was created or recorded.
+struct SpanFields(String);
+
+/// Formats the fields of an event or span: the `message` field as is, the others as `key=value` pairs.
+#[derive(Default)]
+struct FieldFormatter {
+    message: String,
+    fields: String,
+}
+
+impl FieldFormatter {
+    fn separator(&mut self) {
+        if !self.fields.is_empty() {
+            self.fields.push(' ');
+        }
+    }
+}
+
+impl Visit for FieldFormatter {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.record_debug(field, &value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            self.separator();
+            let _ = write!(self.fields, "{}={value:?}", field.name());
+        }
+    }
+}
+
+/// A layer that sends the events of the `tracing` crate to the logger of the `log` crate installed by
+/// [`Otel`](crate::Otel).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OtelTracingLayer {
+    _private: (),
+}
+
+impl OtelTracingLayer {
+    /// Create a layer.
+    pub fn new() -> Self {
+        OtelTracingLayer::default()
+    }
+}
+
+impl<S> Layer<S> for OtelTracingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attributes: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut formatter = FieldFormatter::default();
+        attributes.record(&mut formatter);
+        span.extensions_mut().insert(SpanFields(formatter.fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() else {
+            return;
+        };
+        let mut formatter = FieldFormatter {
+            message: String::new(),
+            fields: std::mem::take(fields),
+        };
+        values.record(&mut formatter);
+        *fields = formatter.fields;
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let event_metadata = event.metadata();
+        let level = to_log_level(*event_metadata.level());
+        let metadata = log::Metadata::builder()
+            .level(level)
+            .target(event_metadata.target())
+            .build();
+        let logger = log::logger();
+        if level > log::max_level() || !logger.enabled(&metadata) {
+            return;
+        }
+
+        let mut message = String::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                message.push_str(span.name());
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    if !fields.is_empty() {
+                        let _ = write!(message, "{{{fields}}}");
+                    }
+                }
+                message.push_str(": ");
+            }
+        }
+        let mut formatter = FieldFormatter::default();
+        event.record(&mut formatter);
+        message.push_str(&formatter.message);
+        if !formatter.fields.is_empty() {
+            if !formatter.message.is_empty() {
+                message.push(' ');
+            }
+            message.push_str(&formatter.fields);
+        }
+
+        logger.log(
+            &log::Record::builder()
+                .metadata(metadata)
+                .args(format_args!("{message}"))
+                .module_path(event_metadata.module_path())
+                .file(event_metadata.file())
+                .line(event_metadata.line())
+                .build(),
+        );
+    }
+}
+
+const fn to_log_level(level: Level) -> log::Level {
+    match level {
+        Level::ERROR => log::Level::Error,
+        Level::WARN => log::Level::Warn,
+        Level::INFO => log::Level::Info,
+        Level::DEBUG => log::Level::Debug,
+        Level::TRACE => log::Level::Trace,
+    }
+}
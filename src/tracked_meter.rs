@@ -0,0 +1,162 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::{any::Any, borrow::Cow, sync::Arc};
+
+use opentelemetry::metrics::{
+    Callback, CallbackRegistration, Counter, Histogram, InstrumentProvider, Meter,
+    ObservableCounter, ObservableGauge, ObservableUpDownCounter, Observer, Result, Unit,
+    UpDownCounter,
+};
+
+use crate::{instrument_registry::InstrumentRegistry, slo::LatencySlos};
+
+/// Creates the instruments of a meter, registering them for duplicate detection and optionally prefixing their
+/// names, e.g. `myapp.subsys.requests` for `requests`.
+struct TrackedInstruments {
+    meter: Meter,
+    registry: Arc<InstrumentRegistry>,
+    slos: Arc<LatencySlos>,
+    prefix: Option<String>,
+}
+
+/// A meter that creates its instruments with `meter`, registering them with `registry` and prefixing their names
+/// with `prefix` and a dot, if any. Its histograms count the events of their latency SLOs in `slos`.
+pub(crate) fn tracked_meter(
+    meter: Meter,
+    registry: Arc<InstrumentRegistry>,
+    slos: Arc<LatencySlos>,
+    prefix: Option<&str>,
+) -> Meter {
+    Meter::new(Arc::new(TrackedInstruments {
+        meter,
+        registry,
+        slos,
+        prefix: prefix.map(|prefix| prefix.trim_end_matches('.').to_owned()),
+    }))
+}
+
+impl TrackedInstruments {
+    /// The full name of the instrument created as `name`, after registering it.
+    fn register(
+        &self,
+        name: Cow<'static, str>,
+        kind: &'static str,
+        unit: Option<&Unit>,
+    ) -> Result<Cow<'static, str>> {
+        let name = match &self.prefix {
+            Some(prefix) => Cow::Owned(format!("{prefix}.{name}")),
+            None => name,
+        };
+        self.registry.register(&name, kind, unit)?;
+        Ok(name)
+    }
+}
+
+/// Forward the creation of synchronous instruments to the meter, with the full name.
+macro_rules! sync_instruments {
+    ($($kind:ident -> $instrument:ty),* $(,)?) => {
+        $(
+            fn $kind(
+                &self,
+                name: Cow<'static, str>,
+                description: Option<Cow<'static, str>>,
+                unit: Option<Unit>,
+            ) -> Result<$instrument> {
+                let name = self.register(name, stringify!($kind), unit.as_ref())?;
+                let mut builder = self.meter.$kind(name);
+                if let Some(description) = description {
+                    builder = builder.with_description(description);
+                }
+                if let Some(unit) = unit {
+                    builder = builder.with_unit(unit);
+                }
+                builder.try_init()
+            }
+        )*
+    };
+}
+
+/// Forward the creation of histograms to the meter, with the full name, counting the events of their latency SLOs.
+macro_rules! histogram_instruments {
+    ($($kind:ident -> $measurement:ty),* $(,)?) => {
+        $(
+            fn $kind(
+                &self,
+                name: Cow<'static, str>,
+                description: Option<Cow<'static, str>>,
+                unit: Option<Unit>,
+            ) -> Result<Histogram<$measurement>> {
+                let name = self.register(name, stringify!($kind), unit.as_ref())?;
+                let mut builder = self.meter.$kind(name.clone());
+                if let Some(description) = description {
+                    builder = builder.with_description(description);
+                }
+                if let Some(unit) = unit {
+                    builder = builder.with_unit(unit);
+                }
+                Ok(self.slos.wrap(&name, builder.try_init()?))
+            }
+        )*
+    };
+}
+
+/// Forward the creation of asynchronous instruments to the meter, with the full name and the callbacks.
+macro_rules! async_instruments {
+    ($($kind:ident -> $instrument:ty, $measurement:ty),* $(,)?) => {
+        $(
+            fn $kind(
+                &self,
+                name: Cow<'static, str>,
+                description: Option<Cow<'static, str>>,
+                unit: Option<Unit>,
+                callbacks: Vec<Callback<$measurement>>,
+            ) -> Result<$instrument> {
+                let name = self.register(name, stringify!($kind), unit.as_ref())?;
+                let mut builder = self.meter.$kind(name);
+                if let Some(description) = description {
+                    builder = builder.with_description(description);
+                }
+                if let Some(unit) = unit {
+                    builder = builder.with_unit(unit);
+                }
+                for callback in callbacks {
+                    builder = builder.with_callback(callback);
+                }
+                builder.try_init()
+            }
+        )*
+    };
+}
+
+impl InstrumentProvider for TrackedInstruments {
+    sync_instruments! {
+        u64_counter -> Counter<u64>,
+        f64_counter -> Counter<f64>,
+        i64_up_down_counter -> UpDownCounter<i64>,
+        f64_up_down_counter -> UpDownCounter<f64>,
+    }
+
+    histogram_instruments! {
+        u64_histogram -> u64,
+        f64_histogram -> f64,
+    }
+
+    async_instruments! {
+        u64_observable_counter -> ObservableCounter<u64>, u64,
+        f64_observable_counter -> ObservableCounter<f64>, f64,
+        i64_observable_up_down_counter -> ObservableUpDownCounter<i64>, i64,
+        f64_observable_up_down_counter -> ObservableUpDownCounter<f64>, f64,
+        u64_observable_gauge -> ObservableGauge<u64>, u64,
+        i64_observable_gauge -> ObservableGauge<i64>, i64,
+        f64_observable_gauge -> ObservableGauge<f64>, f64,
+    }
+
+    fn register_callback(
+        &self,
+        instruments: &[Arc<dyn Any>],
+        callback: Box<dyn Fn(&dyn Observer) + Send + Sync>,
+    ) -> Result<Box<dyn CallbackRegistration>> {
+        self.meter.register_callback(instruments, callback)
+    }
+}
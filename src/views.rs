@@ -0,0 +1,160 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    sync::{Mutex, PoisonError},
+};
+
+use glob::Pattern;
+use log::error;
+use opentelemetry::Key;
+use opentelemetry_sdk::metrics::{Aggregation, Instrument, InstrumentKind, Stream, View};
+
+use crate::config::{HistogramAggregation, MetricView};
+
+/// The SDK's default explicit bucket boundaries, used when only min/max recording is customized.
+const DEFAULT_HISTOGRAM_BOUNDARIES: [f64; 15] = [
+    0.0, 5.0, 10.0, 25.0, 50.0, 75.0, 100.0, 250.0, 500.0, 750.0, 1000.0, 2500.0, 5000.0, 7500.0,
+    10000.0,
+];
+
+/// A configured view with its instrument name pattern compiled.
+struct CompiledView {
+    pattern: Pattern,
+    view: MetricView,
+}
+
+/// Build a single SDK view out of the configured metric views and instrument cap.
+///
+/// The SDK applies every matching view to an instrument, producing one stream per view. To let a specific view
+/// override a broad one instead, views are matched in order and only the first matching view is applied.
+/// Views with an invalid instrument name pattern or aggregation are reported and ignored.
+///
+/// Instruments created after `max_instruments` distinct instruments are dropped, so they don't take up memory.
+pub(crate) fn build_view(
+    metric_views: Vec<MetricView>,
+    max_instruments: Option<usize>,
+) -> impl View {
+    let views: Vec<CompiledView> = metric_views
+        .into_iter()
+        .filter_map(|view| {
+            let pattern = match Pattern::new(&view.instrument_name) {
+                Ok(pattern) => pattern,
+                Err(e) => {
+                    error!(
+                        "ignoring view for {} due to an invalid pattern: {:?}",
+                        view.instrument_name, e
+                    );
+                    return None;
+                }
+            };
+            if view.rename.is_some() && view.instrument_name.contains(['*', '?']) {
+                error!(
+                    "ignoring view for {} as instruments matched by wildcards can't be renamed",
+                    view.instrument_name
+                );
+                return None;
+            }
+            if view.bucket_boundaries.is_some()
+                && matches!(
+                    view.histogram_aggregation,
+                    Some(HistogramAggregation::Base2Exponential { .. })
+                )
+            {
+                error!(
+                    "ignoring view for {} as exponential histograms don't have bucket boundaries",
+                    view.instrument_name
+                );
+                return None;
+            }
+            if let Err(e) = to_aggregation(&view).validate() {
+                error!(
+                    "ignoring view for {} due to an invalid aggregation: {:?}",
+                    view.instrument_name, e
+                );
+                return None;
+            }
+            Some(CompiledView { pattern, view })
+        })
+        .collect();
+
+    // The view is consulted once per reader for each instrument, so instruments are counted by name.
+    let instruments: Mutex<HashSet<Cow<'static, str>>> = Mutex::default();
+    let dropped: Mutex<HashSet<Cow<'static, str>>> = Mutex::default();
+
+    move |instrument: &Instrument| -> Option<Stream> {
+        if let Some(max_instruments) = max_instruments {
+            let mut instruments = instruments.lock().unwrap_or_else(PoisonError::into_inner);
+            if !instruments.contains(&instrument.name) {
+                if instruments.len() >= max_instruments {
+                    if dropped
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .insert(instrument.name.clone())
+                    {
+                        error!(
+                            "dropping instrument {} as the maximum of {} instruments is reached",
+                            instrument.name, max_instruments
+                        );
+                    }
+                    return Some(Stream::new().aggregation(Aggregation::Drop));
+                }
+                instruments.insert(instrument.name.clone());
+            }
+        }
+
+        let view = &views
+            .iter()
+            .find(|view| view.pattern.matches(&instrument.name))?
+            .view;
+
+        if view.drop {
+            return Some(Stream::new().aggregation(Aggregation::Drop));
+        }
+        let mut stream = Stream::new()
+            .name(
+                view.rename
+                    .clone()
+                    .map_or_else(|| instrument.name.clone(), Cow::Owned),
+            )
+            .description(instrument.description.clone())
+            .unit(instrument.unit.clone());
+        if instrument.kind == Some(InstrumentKind::Histogram) {
+            stream = stream.aggregation(to_aggregation(view));
+        }
+        if let Some(allowed_attribute_keys) = &view.allowed_attribute_keys {
+            stream =
+                stream.allowed_attribute_keys(allowed_attribute_keys.iter().cloned().map(Key::new));
+        }
+        Some(stream)
+    }
+}
+
+/// The aggregation a view applies to histograms.
+fn to_aggregation(view: &MetricView) -> Aggregation {
+    match view.histogram_aggregation {
+        None | Some(HistogramAggregation::ExplicitBucket) => {
+            if view.record_min_max && view.bucket_boundaries.is_none() {
+                Aggregation::Default
+            } else {
+                Aggregation::ExplicitBucketHistogram {
+                    boundaries: view
+                        .bucket_boundaries
+                        .clone()
+                        .unwrap_or_else(|| DEFAULT_HISTOGRAM_BOUNDARIES.to_vec()),
+                    record_min_max: view.record_min_max,
+                }
+            }
+        }
+        Some(HistogramAggregation::Base2Exponential {
+            max_size,
+            max_scale,
+        }) => Aggregation::Base2ExponentialHistogram {
+            max_size,
+            max_scale,
+            record_min_max: view.record_min_max,
+        },
+    }
+}